@@ -1,5 +1,7 @@
 use fabricia_backend::{
-	config::BackendConfig, db::service::DatabaseConfig, redis::RedisConfig, target::TargetConfig,
+	branch::BranchConfig, config::BackendConfig, db::service::DatabaseConfig,
+	job_prefetch::PrefetchConfig, job_queue::JobQueueConfig, redis::RedisConfig,
+	target::TargetConfig,
 };
 use serde::{Deserialize, Serialize};
 
@@ -9,7 +11,14 @@ pub struct AxisConfig {
 	pub database: DatabaseConfig,
 	pub redis: RedisConfig,
 	pub target: Vec<TargetConfig>,
+	#[serde(default)]
+	pub job_queue: JobQueueConfig,
+	#[serde(default)]
+	pub branch: BranchConfig,
 	pub runners: usize,
+	/// Prefetching and concurrency of each runner.
+	#[serde(default)]
+	pub prefetch: PrefetchConfig,
 }
 
 impl TryFrom<AxisConfig> for BackendConfig {
@@ -20,6 +29,8 @@ impl TryFrom<AxisConfig> for BackendConfig {
 			database: config.database,
 			redis: config.redis,
 			target: config.target,
+			job_queue: config.job_queue,
+			branch: config.branch,
 		})
 	}
 }
@@ -9,7 +9,7 @@ use bus::AxisBusFactory;
 use clap::Parser;
 use config::AxisConfig;
 use fabricia_axis_jobrunner::JobRunner;
-use fabricia_backend::BackendServices;
+use fabricia_backend::{BackendServices, config::BackendConfig};
 use tokio::net::{TcpListener, UnixListener};
 use tracing::info;
 
@@ -34,7 +34,8 @@ async fn main() -> Result<()> {
 	)?;
 
 	let config_path = &args.config;
-	let config = toml::from_str::<AxisConfig>(&fs::read_to_string(config_path)?)?;
+	let mut config = toml::from_str::<AxisConfig>(&fs::read_to_string(config_path)?)?;
+	BackendConfig::override_from_env(&mut config.database, &mut config.redis);
 	info!("loaded configuration from file: {:?}", config_path);
 
 	info!("initializing backend services ...");
@@ -47,7 +48,7 @@ async fn main() -> Result<()> {
 		.await?,
 	);
 	info!("initializing runner service ...");
-	let runner = JobRunner::new(backend_services.clone())?;
+	let runner = JobRunner::new(backend_services.clone(), config.prefetch)?;
 	let services = AxisServices {
 		config: Arc::new(config),
 		backend: backend_services,
@@ -56,12 +57,15 @@ async fn main() -> Result<()> {
 	services_ref.set(services.clone()).unwrap();
 
 	tokio::spawn(bus::handle_bus_message(services.clone()));
-	for i in 0..=services.config.runners {
-		tokio::spawn(services.runner.clone().run(i));
-	}
+	let runners = (0..=services.config.runners)
+		.map(|i| tokio::spawn(services.runner.clone().run(i)))
+		.collect::<Vec<_>>();
 	tokio::spawn(services.runner.clone().run_watcher(services.config.runners));
+	tokio::spawn(services.runner.clone().run_subscriber());
 
 	let listen_addr = services.config.http.listen.clone();
+	let backend = services.backend.clone();
+	let runner = services.runner.clone();
 	let router = routes::make_router(services)?;
 	if let Some(path) = listen_addr.strip_prefix("unix://") {
 		let path = PathBuf::from(path);
@@ -70,18 +74,35 @@ async fn main() -> Result<()> {
 
 		let listener = UnixListener::bind(&path)?;
 		info!("listening on UDS: {:?}", path);
-		axum::serve(listener, router).await?;
+		axum::serve(listener, router)
+			.with_graceful_shutdown(shutdown_signal())
+			.await?;
 	} else if let Some(addr) = listen_addr.strip_prefix("tcp://") {
 		let listener = TcpListener::bind(addr).await?;
 		info!("listening on TCP {}", listener.local_addr()?);
-		axum::serve(listener, router).await.unwrap();
+		axum::serve(listener, router)
+			.with_graceful_shutdown(shutdown_signal())
+			.await
+			.unwrap();
 	} else {
 		bail!("unsupported http.listen schema")
 	}
 
+	info!("shutting down ...");
+	runner.shutdown();
+	// prefetched jobs are released before the database is closed
+	for handle in runners {
+		handle.await?;
+	}
+	runner.unregister().await?;
+	backend.shutdown().await?;
 	Ok(())
 }
 
+async fn shutdown_signal() {
+	_ = tokio::signal::ctrl_c().await;
+}
+
 #[derive(Debug, Clone)]
 pub struct AxisServices {
 	pub config: Arc<AxisConfig>,
@@ -1,23 +1,38 @@
 use std::sync::Arc;
 
 use anyhow::Result;
-use fabricia_backend::{BackendServices, job_queue::JobCommand};
-use tokio::sync::Notify;
+use fabricia_backend::{
+	BackendServices,
+	job_notifier::ANY_KIND,
+	job_prefetch::{JobPrefetcher, PrefetchConfig},
+	job_queue::{Job, JobCommand},
+	trace::TraceContext,
+};
+use futures::StreamExt;
+use tokio::sync::{Notify, watch};
 use tracing::{Instrument, debug, error, info, info_span};
 
 #[derive(Debug)]
 pub struct JobRunner {
 	/// Notifier to resume the dispatcher immediately.
 	notifier: Notify,
+	/// Set to stop running jobs, and release prefetched ones.
+	shutdown: watch::Sender<bool>,
 	/// Backend services
 	backend: Arc<BackendServices>,
+	prefetch: PrefetchConfig,
+	/// Identifier of this worker, recorded on claimed jobs.
+	worker: String,
 }
 
 impl JobRunner {
-	pub fn new(backend: Arc<BackendServices>) -> Result<Self> {
+	pub fn new(backend: Arc<BackendServices>, prefetch: PrefetchConfig) -> Result<Self> {
 		Ok(Self {
 			notifier: Notify::const_new(),
+			shutdown: watch::Sender::new(false),
 			backend,
+			prefetch,
+			worker: worker_id(),
 		})
 	}
 
@@ -25,24 +40,58 @@ impl JobRunner {
 	pub async fn run(self: Arc<Self>, index: usize) {
 		info!("job runner started");
 		loop {
-			self.notifier.notified().await;
+			tokio::select! {
+				biased;
+				_ = self.stopped() => break,
+				_ = self.notifier.notified() => {}
+			}
 			debug!("notified to resume");
 
-			let result = async {
-				while let Some(job) = self.backend.job_queue.fetch_and_start().await? {
-					let mut db = self.backend.database.get().await?;
-					self.exec(job.command)
-						.instrument(info_span!("execute job", job = %job.id))
-						.await?;
-					self.backend.job_queue.finish_job(&mut db, job.id).await?;
-				}
-				Ok::<_, anyhow::Error>(())
-			}
-			.await;
+			let prefetcher = JobPrefetcher::new(self.backend.job_queue.clone(), self.prefetch)
+				.with_worker(self.worker.clone());
+			let result = prefetcher.run(|job| self.handle(job), self.stopped()).await;
 			if let Err(error) = result {
 				error!(?error, "job runner error")
 			}
 		}
+		info!("job runner stopped");
+	}
+
+	/// Completes once [JobRunner::shutdown] is called.
+	async fn stopped(&self) {
+		_ = self.shutdown.subscribe().wait_for(|stopped| *stopped).await;
+	}
+
+	/// Executes a started job, and finishes or fails it.
+	async fn handle(&self, job: Job) -> fabricia_backend::Result<()> {
+		let mut db = self.backend.database.get().await?;
+		if !self
+			.backend
+			.job_queue
+			.check_guard(&mut db, &job.command)
+			.await?
+		{
+			return self.backend.job_queue.skip_job(&mut db, job.id).await;
+		}
+		drop(db);
+
+		// continue the trace of the operation enqueuing the job
+		let context = job
+			.trace_context
+			.map(|parent| parent.child())
+			.unwrap_or_else(TraceContext::new_root);
+		let span = info_span!(
+			"execute job",
+			job = %job.id,
+			trace_id = %context.trace_id(),
+			span_id = %context.span_id(),
+		);
+		// panics of the job are caught, and fail the job
+		let handler = context
+			.scope(self.exec(job.command.clone()))
+			.instrument(span);
+		self.backend.job_queue.run_job(&job, handler).await?;
+		Ok(())
 	}
 
 	#[tracing::instrument(level = "debug", name = "job_watcher", skip(self))]
@@ -50,6 +99,18 @@ impl JobRunner {
 		info!("job watcher started");
 		loop {
 			let result = async {
+				self.backend.job_queue.register_worker(&self.worker).await?;
+
+				let timed_out = self.backend.job_queue.fail_timed_out().await?;
+				if timed_out != 0 {
+					info!(timed_out, "failed runaway jobs");
+				}
+				self.backend.job_queue.reclaim_lost_jobs().await?;
+
+				self.backend.branch.sweep_deleted().await?;
+				self.backend.job_queue.check_stale().await?;
+				self.backend.job_queue.check_empty().await?;
+
 				let count = self.backend.job_queue.count_pending(runners).await?;
 				for _ in 0..count {
 					self.notify_one();
@@ -65,6 +126,33 @@ impl JobRunner {
 		}
 	}
 
+	/// Wakes up job runners on notifications of enqueued jobs.
+	#[tracing::instrument(level = "debug", name = "job_subscriber", skip(self))]
+	pub async fn run_subscriber(self: Arc<Self>) {
+		loop {
+			let result = async {
+				let mut notifications = self.backend.job_queue.notifier().subscribe().await?;
+				info!("job subscriber started");
+				while let Some(kind) = notifications.next().await {
+					debug!(%kind, "notified of enqueued job");
+					if kind == ANY_KIND {
+						// notifications may have been missed
+						self.notify_all();
+					} else {
+						self.notify_one();
+					}
+				}
+				Ok::<_, anyhow::Error>(())
+			}
+			.await;
+			if let Err(error) = result {
+				error!(?error, "job subscriber error")
+			}
+			// reconnect after a while
+			tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+		}
+	}
+
 	pub fn notify_one(&self) {
 		self.notifier.notify_one();
 	}
@@ -73,11 +161,35 @@ impl JobRunner {
 		self.notifier.notify_waiters();
 	}
 
+	/// Stops runners processing jobs, releasing their prefetched jobs.
+	///
+	/// Runners return from [JobRunner::run] after their running jobs finish.
+	pub fn shutdown(&self) {
+		self.shutdown.send_replace(true);
+	}
+
+	/// Unregisters this worker from the job queue.
+	///
+	/// This should be called after runners are stopped.
+	pub async fn unregister(&self) -> Result<()> {
+		self.backend
+			.job_queue
+			.unregister_worker(&self.worker)
+			.await?;
+		Ok(())
+	}
+
 	/// Runs a job command.
 	async fn exec(&self, job: JobCommand) -> Result<()> {
 		match job {
-			JobCommand::SyncBranch(branch) => todo!(),
+			JobCommand::SyncBranch { .. } => todo!(),
 		}
 		Ok(())
 	}
 }
+
+/// Returns the identifier of this worker process.
+fn worker_id() -> String {
+	let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "axis".to_string());
+	format!("{}-{}", host, std::process::id())
+}
@@ -0,0 +1,10 @@
+-- Dead-lettered jobs
+CREATE TABLE "job_dead_letter"(
+	"id" UUID NOT NULL PRIMARY KEY,
+	"kind" VARCHAR NOT NULL,
+	"data" JSONB NOT NULL,
+	"priority" SMALLINT NOT NULL,
+	"error" VARCHAR NOT NULL,
+	"failed_at" TIMESTAMP NOT NULL
+);
+CREATE INDEX "job_dead_letter_failed_at" ON "job_dead_letter" ("failed_at");
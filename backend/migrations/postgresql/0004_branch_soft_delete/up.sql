@@ -0,0 +1,5 @@
+-- Soft deletion of branches
+ALTER TABLE "branch" ADD COLUMN "deleted_at" TIMESTAMP NULL DEFAULT NULL;
+-- Branch of jobs
+ALTER TABLE "job_queue" ADD COLUMN "branch" BIGINT NULL DEFAULT NULL;
+CREATE INDEX "job_queue_branch" ON "job_queue" ("branch");
@@ -0,0 +1,2 @@
+ALTER TABLE "job_dead_letter" DROP COLUMN "data_bin";
+ALTER TABLE "job_queue" DROP COLUMN "data_bin";
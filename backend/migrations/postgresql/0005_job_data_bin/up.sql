@@ -0,0 +1,3 @@
+-- Binary encoded job data
+ALTER TABLE "job_queue" ADD COLUMN "data_bin" BYTEA NULL DEFAULT NULL;
+ALTER TABLE "job_dead_letter" ADD COLUMN "data_bin" BYTEA NULL DEFAULT NULL;
@@ -0,0 +1,2 @@
+ALTER TABLE "job_dead_letter" DROP COLUMN "compressed";
+ALTER TABLE "job_queue" DROP COLUMN "compressed";
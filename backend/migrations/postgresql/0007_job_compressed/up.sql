@@ -0,0 +1,3 @@
+-- Compressed job data
+ALTER TABLE "job_queue" ADD COLUMN "compressed" BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE "job_dead_letter" ADD COLUMN "compressed" BOOLEAN NOT NULL DEFAULT FALSE;
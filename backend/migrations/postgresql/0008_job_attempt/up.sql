@@ -0,0 +1,10 @@
+-- Attempt history of jobs
+CREATE TABLE "job_attempt"(
+	"job" UUID NOT NULL,
+	"attempt" INT NOT NULL,
+	"started_at" TIMESTAMP NOT NULL,
+	"finished_at" TIMESTAMP NULL DEFAULT NULL,
+	"outcome" SMALLINT NOT NULL DEFAULT 0,
+	"error" VARCHAR NULL DEFAULT NULL,
+	PRIMARY KEY ("job", "attempt")
+);
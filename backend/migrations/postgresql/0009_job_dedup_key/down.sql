@@ -0,0 +1,2 @@
+DROP INDEX "job_queue_dedup_key";
+ALTER TABLE "job_queue" DROP COLUMN "dedup_key";
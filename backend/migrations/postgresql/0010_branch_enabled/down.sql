@@ -0,0 +1 @@
+ALTER TABLE "branch" DROP COLUMN "enabled";
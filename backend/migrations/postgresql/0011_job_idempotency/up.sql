@@ -0,0 +1,6 @@
+-- Producer-chosen idempotency keys of jobs
+CREATE TABLE "job_idempotency"(
+	"idempotency_key" VARCHAR NOT NULL PRIMARY KEY,
+	"job" UUID NOT NULL,
+	"created_at" TIMESTAMP NOT NULL
+);
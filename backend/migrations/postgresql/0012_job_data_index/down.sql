@@ -0,0 +1 @@
+DROP INDEX "job_queue_data";
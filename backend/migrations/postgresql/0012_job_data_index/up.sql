@@ -0,0 +1,2 @@
+-- Containment queries on job data
+CREATE INDEX "job_queue_data" ON "job_queue" USING GIN ("data" jsonb_path_ops);
@@ -0,0 +1,2 @@
+-- W3C trace context of the operation enqueuing the job
+ALTER TABLE "job_queue" ADD COLUMN "trace_context" VARCHAR NULL DEFAULT NULL;
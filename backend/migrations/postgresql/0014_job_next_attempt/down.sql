@@ -0,0 +1 @@
+ALTER TABLE "job_queue" DROP COLUMN "next_attempt_at";
@@ -0,0 +1,4 @@
+ALTER TABLE "branch" DROP COLUMN "created_at";
+ALTER TABLE "branch" DROP COLUMN "updated_at";
+ALTER TABLE "branch" DROP COLUMN "created_by";
+ALTER TABLE "branch" DROP COLUMN "updated_by";
@@ -0,0 +1,2 @@
+ALTER TABLE "job_attempt" DROP COLUMN "error_code";
+ALTER TABLE "job_dead_letter" DROP COLUMN "error_code";
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS "job_worker";
+ALTER TABLE "job_queue" DROP COLUMN "claimed_by";
@@ -0,0 +1,7 @@
+-- Workers claiming jobs
+ALTER TABLE "job_queue" ADD COLUMN "claimed_by" VARCHAR NULL DEFAULT NULL;
+CREATE TABLE "job_worker"(
+	"id" VARCHAR NOT NULL PRIMARY KEY,
+	"registered_at" TIMESTAMP NOT NULL,
+	"heartbeat_at" TIMESTAMP NOT NULL
+);
@@ -0,0 +1,3 @@
+-- Most recent failure of synchronizing branches
+ALTER TABLE "branch" ADD COLUMN "last_error" VARCHAR NULL DEFAULT NULL;
+ALTER TABLE "branch" ADD COLUMN "last_error_code" SMALLINT NULL DEFAULT NULL;
@@ -0,0 +1,2 @@
+-- Tenants of jobs, for sharing claims across tenants
+ALTER TABLE "job_queue" ADD COLUMN "tenant" VARCHAR NOT NULL DEFAULT '';
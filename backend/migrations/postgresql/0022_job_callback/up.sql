@@ -0,0 +1,2 @@
+-- URLs notified when jobs finish
+ALTER TABLE "job_queue" ADD COLUMN "callback_url" VARCHAR NULL;
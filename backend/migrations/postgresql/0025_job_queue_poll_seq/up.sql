@@ -0,0 +1,3 @@
+-- jobs within a priority are claimed in the order of `seq`
+DROP INDEX "job_queue_poll";
+CREATE INDEX "job_queue_poll" ON "job_queue" ("kind", ("started_at" IS NULL), "priority" DESC, "seq");
@@ -0,0 +1,7 @@
+-- Dependencies between jobs
+CREATE TABLE `job_dependency`(
+	`job` UUID NOT NULL,
+	`depends_on` UUID NOT NULL,
+	PRIMARY KEY (`job`, `depends_on`)
+);
+CREATE INDEX `job_dependency_depends_on` ON `job_dependency` (`depends_on`);
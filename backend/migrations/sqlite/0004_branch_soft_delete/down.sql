@@ -0,0 +1,3 @@
+DROP INDEX `job_queue_branch`;
+ALTER TABLE `job_queue` DROP COLUMN `branch`;
+ALTER TABLE `branch` DROP COLUMN `deleted_at`;
@@ -0,0 +1,2 @@
+ALTER TABLE `job_queue` DROP COLUMN `last_error`;
+ALTER TABLE `job_queue` DROP COLUMN `attempts`;
@@ -0,0 +1,3 @@
+-- Retries of jobs
+ALTER TABLE `job_queue` ADD COLUMN `attempts` INT NOT NULL DEFAULT 0;
+ALTER TABLE `job_queue` ADD COLUMN `last_error` VARCHAR NULL DEFAULT NULL;
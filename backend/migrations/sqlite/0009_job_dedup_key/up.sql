@@ -0,0 +1,3 @@
+-- Deduplication of jobs
+ALTER TABLE `job_queue` ADD COLUMN `dedup_key` VARCHAR NULL DEFAULT NULL;
+CREATE UNIQUE INDEX `job_queue_dedup_key` ON `job_queue` (`dedup_key`);
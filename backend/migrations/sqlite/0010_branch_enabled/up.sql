@@ -0,0 +1,2 @@
+-- Branches disabled by maintainers
+ALTER TABLE `branch` ADD COLUMN `enabled` BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1,2 @@
+-- SQLite has no GIN indexes, queries on job data scan jobs of the kind
+SELECT 1;
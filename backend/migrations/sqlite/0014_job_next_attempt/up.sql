@@ -0,0 +1,2 @@
+-- Earliest time to retry a failed job
+ALTER TABLE `job_queue` ADD COLUMN `next_attempt_at` TIMESTAMP NULL DEFAULT NULL;
@@ -0,0 +1,5 @@
+-- Audit fields of branches
+ALTER TABLE `branch` ADD COLUMN `created_at` TIMESTAMP NULL DEFAULT NULL;
+ALTER TABLE `branch` ADD COLUMN `updated_at` TIMESTAMP NULL DEFAULT NULL;
+ALTER TABLE `branch` ADD COLUMN `created_by` VARCHAR NULL DEFAULT NULL;
+ALTER TABLE `branch` ADD COLUMN `updated_by` VARCHAR NULL DEFAULT NULL;
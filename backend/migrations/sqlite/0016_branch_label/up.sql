@@ -0,0 +1,7 @@
+-- Labels of branches
+CREATE TABLE `branch_label`(
+	`branch` BIGINT NOT NULL,
+	`label` VARCHAR NOT NULL,
+	PRIMARY KEY (`branch`, `label`)
+);
+CREATE INDEX `branch_label_label` ON `branch_label` (`label`);
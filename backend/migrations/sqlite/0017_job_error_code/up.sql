@@ -0,0 +1,3 @@
+-- Categories of job failures
+ALTER TABLE `job_attempt` ADD COLUMN `error_code` SMALLINT NULL DEFAULT NULL;
+ALTER TABLE `job_dead_letter` ADD COLUMN `error_code` SMALLINT NULL DEFAULT NULL;
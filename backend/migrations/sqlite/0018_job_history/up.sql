@@ -0,0 +1,12 @@
+-- Archived finished jobs
+CREATE TABLE `job_history`(
+	`id` UUID NOT NULL PRIMARY KEY,
+	`kind` VARCHAR NOT NULL,
+	`data` JSONB NOT NULL,
+	`data_bin` BLOB NULL DEFAULT NULL,
+	`compressed` BOOLEAN NOT NULL DEFAULT FALSE,
+	`priority` SMALLINT NOT NULL,
+	`outcome` SMALLINT NOT NULL,
+	`finished_at` TIMESTAMP NOT NULL
+);
+CREATE INDEX `job_history_finished_at` ON `job_history` (`finished_at`);
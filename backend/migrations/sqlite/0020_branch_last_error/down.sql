@@ -0,0 +1,2 @@
+ALTER TABLE `branch` DROP COLUMN `last_error`;
+ALTER TABLE `branch` DROP COLUMN `last_error_code`;
@@ -0,0 +1,2 @@
+-- ordering of jobs within a priority, negative for jobs enqueued at the front
+ALTER TABLE `job_queue` ADD COLUMN `seq` BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1 @@
+ALTER TABLE `branch` DROP COLUMN `sync_cursor`;
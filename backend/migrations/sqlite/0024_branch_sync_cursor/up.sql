@@ -0,0 +1,2 @@
+-- Where incremental synchronizations of branches left off
+ALTER TABLE `branch` ADD COLUMN `sync_cursor` VARCHAR NULL DEFAULT NULL;
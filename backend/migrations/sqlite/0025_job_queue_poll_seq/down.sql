@@ -0,0 +1,2 @@
+DROP INDEX `job_queue_poll`;
+CREATE INDEX `job_queue_poll` ON `job_queue` (`kind`, (`started_at` IS NULL), `priority` DESC);
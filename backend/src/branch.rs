@@ -1,27 +1,44 @@
-use std::sync::Arc;
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	sync::Arc,
+};
 
 use diesel::{
-	ExpressionMethods, OptionalExtension, QueryDsl, delete, insert_into,
+	BoolExpressionMethods, EscapeExpressionMethods, ExpressionMethods, IntoSql, OptionalExtension,
+	QueryDsl, TextExpressionMethods, delete, insert_into,
 	prelude::{AsChangeset, Identifiable},
+	sql_types::Bool,
 	update,
 };
+pub use fabricia_common_model::branch::{BranchConfigInfo, BranchConfigUpdate, BranchFilter};
 use fabricia_common_model::branch::{BranchStatus, TrackingMode};
+use json_patch::{Patch, PatchErrorKind};
 use kstring::KString;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use time::{Duration, PrimitiveDateTime};
 use tracing::info;
 
 use crate::{
 	Result,
 	db::{
-		schema::{self, branch::dsl},
+		BoxedSqlConn,
+		schema::{self, branch::dsl, branch_label},
 		service::DatabaseService,
 	},
-	job_queue::{JobCommand, JobQueue},
+	job_queue::{JobCommand, JobCommandBuilder, JobErrorCode, JobQueue, JobRef},
 };
 
 pub type BranchRef = i64;
 
+/// Most recent failure of synchronizing a branch.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct BranchSyncError {
+	/// Category of the failure, or `None` if it is not categorized.
+	pub code: Option<JobErrorCode>,
+	pub message: String,
+}
+
 /// State of a branch.
 ///
 /// Stored as a tiny unsigned column. Unknown values are decoded as suspended.
@@ -135,28 +152,97 @@ impl From<SqlTrackingMode> for TrackingMode {
 	}
 }
 
+/// Configuration for [`BranchService`].
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BranchConfig {
+	/// Time in seconds to keep deleted branches restorable.
+	///
+	/// Deleted branches are removed by [BranchService::sweep_deleted]
+	/// after this period.
+	#[serde(default = "default_deletion_grace_period")]
+	pub deletion_grace_period: u64,
+}
+
+impl Default for BranchConfig {
+	fn default() -> Self {
+		Self {
+			deletion_grace_period: default_deletion_grace_period(),
+		}
+	}
+}
+
+fn default_deletion_grace_period() -> u64 {
+	24 * 60 * 60
+}
+
 #[derive(Debug)]
 pub struct BranchService {
 	db: Arc<DatabaseService>,
 	job_queue: Arc<JobQueue>,
+	config: BranchConfig,
 }
 
 impl BranchService {
-	pub fn new(db: Arc<DatabaseService>, job_queue: Arc<JobQueue>) -> Self {
-		Self { db, job_queue }
+	pub fn new(db: Arc<DatabaseService>, job_queue: Arc<JobQueue>, config: BranchConfig) -> Self {
+		Self {
+			db,
+			job_queue,
+			config,
+		}
 	}
 
 	/// Tracks a new branch.
-	pub async fn track(&self, name: &str, info: BranchConfigInfo) -> Result<()> {
+	///
+	/// `actor` is the principal tracking the branch, if known.
+	/// Returns warnings of the configuration.
+	///
+	/// A deleted branch with the same name is removed if its grace period
+	/// has expired. Otherwise, it must be restored or swept first, and
+	/// [BranchError::DeletedBranchExists] is returned.
+	pub async fn track(
+		&self,
+		name: &str,
+		info: BranchConfigInfo,
+		actor: Option<&str>,
+	) -> Result<Vec<String>> {
+		let warnings = validate_config(&info)?;
 		let mut conn = self.db.get().await?;
 		let branch = name.to_owned();
+		let cutoff = self.deletion_cutoff();
 
 		conn.transaction::<(), crate::BackendError, _>(async |conn| {
+			// a deleted branch with the same name blocks the unique index
+			let restorable = conn
+				.get_result::<_, i64>(
+					dsl::branch
+						.filter(dsl::name.eq(&branch))
+						.filter(dsl::deleted_at.ge(cutoff))
+						.count(),
+				)
+				.await?;
+			if restorable != 0 {
+				return Err(BranchError::DeletedBranchExists(KString::from_ref(&branch)).into());
+			}
+			let expired = dsl::branch
+				.filter(dsl::name.eq(&branch))
+				.filter(dsl::deleted_at.lt(cutoff));
+			conn.execute(
+				delete(branch_label::table)
+					.filter(branch_label::branch.eq_any(expired.select(dsl::id))),
+			)
+			.await?;
+			conn.execute(delete(expired)).await?;
+
 			let base = match info.base {
-				Some(base) => Some(self.find_id_or_err(base).await?),
+				Some(base) => Some(Self::find_id_or_err_with(conn, &base).await?),
 				None => None,
 			};
-			let priority = info.priority.unwrap_or(100) as u16;
+			let priority = info
+				.priority
+				.or(BranchConfigInfo::defaults().priority)
+				.unwrap_or_default();
+			let time = self.job_queue.clock().now();
 
 			let id = conn
 				.get_result::<_, i64>(
@@ -169,12 +255,20 @@ impl BranchService {
 							dsl::tracking.eq(SqlTrackingMode::from(
 								info.tracking_mode.unwrap_or(TrackingMode::Auto),
 							) as i16),
+							dsl::enabled.eq(info.enabled.unwrap_or(true)),
+							dsl::created_at.eq(time),
+							dsl::updated_at.eq(time),
+							dsl::created_by.eq(actor),
+							dsl::updated_by.eq(actor),
 						))
 						.returning(dsl::id),
 				)
 				.await?;
+			if let Some(labels) = &info.labels {
+				Self::write_labels(conn, id, labels).await?;
+			}
 			self.job_queue
-				.enqueue_with_priority(conn, JobCommand::SyncBranch(id), priority)
+				.enqueue_with_priority(conn, JobCommand::sync_branch(id), priority)
 				.await?;
 
 			Ok(())
@@ -182,21 +276,32 @@ impl BranchService {
 		.await?;
 		info!(branch, "tracked branch");
 
-		Ok(())
+		Ok(warnings)
 	}
 
 	pub async fn find_id<S: AsRef<str>>(&self, name: S) -> Result<Option<BranchRef>> {
 		let mut conn = self.db.get().await?;
+		Self::find_id_with(&mut conn, name.as_ref()).await
+	}
+
+	async fn find_id_with(conn: &mut BoxedSqlConn, name: &str) -> Result<Option<BranchRef>> {
 		Ok(conn
 			.get_result(
 				dsl::branch
-					.filter(dsl::name.eq(name.as_ref()))
+					.filter(dsl::name.eq(name))
+					.filter(dsl::deleted_at.is_null())
 					.select(dsl::id),
 			)
 			.await
 			.optional()?)
 	}
 
+	async fn find_id_or_err_with(conn: &mut BoxedSqlConn, name: &str) -> Result<BranchRef> {
+		Ok(Self::find_id_with(conn, name)
+			.await?
+			.ok_or_else(|| BranchError::BranchNameNotFound(KString::from_ref(name)))?)
+	}
+
 	pub async fn find_id_or_err<S: AsRef<str>>(&self, name: S) -> Result<BranchRef> {
 		Ok(self
 			.find_id(&name)
@@ -204,16 +309,99 @@ impl BranchService {
 			.ok_or_else(|| BranchError::BranchNameNotFound(KString::from_ref(name.as_ref())))?)
 	}
 
-	/// Untracks a new branch.
+	/// Enqueues a synchronization job for a branch.
+	///
+	/// The job is enqueued with the priority of the branch.
+	pub async fn sync(&self, id: BranchRef) -> Result<JobRef> {
+		let mut conn = self.db.get().await?;
+
+		let priority = conn
+			.get_result::<_, i16>(
+				dsl::branch
+					.filter(dsl::id.eq(id))
+					.filter(dsl::deleted_at.is_null())
+					.select(dsl::priority),
+			)
+			.await
+			.optional()?
+			.ok_or(BranchError::BranchNotFound(id))?;
+		let command = JobCommandBuilder::sync_branch().branch(id).build()?;
+		let job = self
+			.job_queue
+			.enqueue_with_priority(&mut conn, command, priority as u16)
+			.await?;
+		info!(id, %job, "enqueued branch synchronization");
+
+		Ok(job)
+	}
+
+	/// Enqueues a full synchronization job for a branch.
+	///
+	/// The sync cursor of the branch is reset, so that the synchronization
+	/// starts over instead of resuming from where the last one left off.
+	pub async fn sync_full(&self, id: BranchRef) -> Result<JobRef> {
+		self.set_sync_cursor(id, None).await?;
+		self.sync(id).await
+	}
+
+	/// Returns where the last synchronization of a branch left off.
+	///
+	/// Synchronizations should fetch only data newer than the cursor, or
+	/// everything if it is `None`.
+	pub async fn get_sync_cursor(&self, id: BranchRef) -> Result<Option<String>> {
+		let mut conn = self.db.get().await?;
+		Ok(conn
+			.get_result::<_, Option<String>>(
+				dsl::branch
+					.filter(dsl::id.eq(id))
+					.filter(dsl::deleted_at.is_null())
+					.select(dsl::sync_cursor),
+			)
+			.await
+			.optional()?
+			.ok_or(BranchError::BranchNotFound(id))?)
+	}
+
+	/// Records where a synchronization of a branch left off.
+	///
+	/// This should be called at the end of a successful synchronization.
+	pub async fn set_sync_cursor(&self, id: BranchRef, cursor: Option<&str>) -> Result<()> {
+		let mut conn = self.db.get().await?;
+		let updated = conn
+			.execute(
+				update(dsl::branch)
+					.filter(dsl::id.eq(id))
+					.filter(dsl::deleted_at.is_null())
+					.set(dsl::sync_cursor.eq(cursor)),
+			)
+			.await?;
+		if updated == 0 {
+			return Err(BranchError::BranchNotFound(id).into());
+		}
+		Ok(())
+	}
+
+	/// Untracks a branch.
+	///
+	/// The branch is soft-deleted and its pending jobs are cancelled.
+	/// It can be restored with [BranchService::restore] until swept.
 	pub async fn untrack(&self, id: BranchRef) -> Result<()> {
 		let mut conn = self.db.get().await?;
 
 		conn.transaction::<(), crate::BackendError, _>(async |conn| {
 			non_zero_or_not_found(
-				conn.execute(delete(dsl::branch).filter(dsl::id.eq(id)))
-					.await?,
+				conn.execute(
+					update(dsl::branch)
+						.filter(dsl::id.eq(id))
+						.filter(dsl::deleted_at.is_null())
+						.set(dsl::deleted_at.eq(self.job_queue.clock().now())),
+				)
+				.await?,
 				id,
 			)?;
+			self.job_queue
+				.cancel_pending_for_branches(conn, &[id])
+				.await?;
 
 			Ok(())
 		})
@@ -223,31 +411,569 @@ impl BranchService {
 		Ok(())
 	}
 
-	pub async fn update_config(&self, id: BranchRef, info: &BranchConfigInfo) -> Result<()> {
+	/// Returns the oldest deletion time of restorable branches.
+	fn deletion_cutoff(&self) -> PrimitiveDateTime {
+		self.job_queue.clock().now() - Duration::seconds(self.config.deletion_grace_period as i64)
+	}
+
+	/// Restores a deleted branch within the grace period.
+	///
+	/// The restored branch is marked dirty and synchronized again,
+	/// as its pending jobs were cancelled on deletion.
+	pub async fn restore(&self, name: &str) -> Result<BranchRef> {
+		let mut conn = self.db.get().await?;
+		let cutoff = self.deletion_cutoff();
+
+		let id = conn
+			.transaction::<BranchRef, crate::BackendError, _>(async |conn| {
+				let (id, priority) = conn
+					.get_result::<_, (BranchRef, i16)>(
+						dsl::branch
+							.filter(dsl::name.eq(name))
+							.filter(dsl::deleted_at.ge(cutoff))
+							.select((dsl::id, dsl::priority)),
+					)
+					.await
+					.optional()?
+					.ok_or_else(|| BranchError::BranchNameNotFound(KString::from_ref(name)))?;
+				conn.execute(update(dsl::branch).filter(dsl::id.eq(id)).set((
+					dsl::deleted_at.eq(None::<PrimitiveDateTime>),
+					dsl::status.eq(SqlBranchStatus::Dirty as i16),
+				)))
+				.await?;
+				self.job_queue
+					.enqueue_with_priority(conn, JobCommand::sync_branch(id), priority as u16)
+					.await?;
+				Ok(id)
+			})
+			.await?;
+		info!(id, name, "restored branch");
+
+		Ok(id)
+	}
+
+	/// Removes branches deleted before the grace period.
+	///
+	/// Returns the count of removed branches.
+	pub async fn sweep_deleted(&self) -> Result<usize> {
+		let mut conn = self.db.get().await?;
+		let cutoff = self.deletion_cutoff();
+
+		let count = conn
+			.transaction::<usize, crate::BackendError, _>(async |conn| {
+				let expired = dsl::branch.filter(dsl::deleted_at.lt(cutoff));
+				conn.execute(
+					delete(branch_label::table)
+						.filter(branch_label::branch.eq_any(expired.select(dsl::id))),
+				)
+				.await?;
+				Ok(conn.execute(delete(expired)).await?)
+			})
+			.await?;
+		if count != 0 {
+			info!(count, "swept deleted branches");
+		}
+		Ok(count)
+	}
+
+	/// Deletes all branches matching a filter.
+	///
+	/// Branches are soft-deleted, and their pending jobs are cancelled.
+	/// Returns the count of deleted branches.
+	pub async fn delete_many(&self, filter: &BranchFilter) -> Result<usize> {
+		let mut conn = self.db.get().await?;
+
+		let count = conn
+			.transaction::<usize, crate::BackendError, _>(async |conn| {
+				let ids = Self::filter_ids(conn, filter).await?;
+				if ids.is_empty() {
+					return Ok(0);
+				}
+
+				conn.execute(
+					update(dsl::branch)
+						.filter(dsl::id.eq_any(&ids))
+						.set(dsl::deleted_at.eq(self.job_queue.clock().now())),
+				)
+				.await?;
+				self.job_queue
+					.cancel_pending_for_branches(conn, &ids)
+					.await?;
+				Ok(ids.len())
+			})
+			.await?;
+		info!(count, "deleted branches");
+
+		Ok(count)
+	}
+
+	/// Lists IDs of branches matching a query, ordered by name.
+	pub async fn list_ids(&self, query: &BranchQuery) -> Result<Vec<BranchRef>> {
+		let mut conn = self.db.get().await?;
+		Self::query_ids(&mut conn, query).await
+	}
+
+	/// Counts branches matching a query, ignoring its pagination.
+	pub async fn count(&self, query: &BranchQuery) -> Result<usize> {
+		let query = BranchQuery {
+			limit: None,
+			offset: 0,
+			after: None,
+			..query.clone()
+		};
+		// branches are few, so matching IDs are simply loaded
+		Ok(self.list_ids(&query).await?.len())
+	}
+
+	async fn query_ids(conn: &mut BoxedSqlConn, query: &BranchQuery) -> Result<Vec<BranchRef>> {
+		let status = query.status.map(|status| status as i16);
+
+		// unused conditions are made always true, so that the query type is static
+		Ok(conn
+			.load(
+				dsl::branch
+					.filter(dsl::deleted_at.is_null())
+					.filter(
+						dsl::enabled
+							.eq(query.enabled.unwrap_or_default())
+							.or(query.enabled.is_none().into_sql::<Bool>()),
+					)
+					.filter(
+						dsl::status
+							.eq(status.unwrap_or_default())
+							.or(status.is_none().into_sql::<Bool>()),
+					)
+					.filter(
+						dsl::id
+							.eq_any(
+								branch_label::table
+									.filter(
+										branch_label::label
+											.eq(query.label.clone().unwrap_or_default()),
+									)
+									.select(branch_label::branch),
+							)
+							.or(query.label.is_none().into_sql::<Bool>()),
+					)
+					.filter(
+						dsl::name
+							.gt(query.after.clone().unwrap_or_default())
+							.or(query.after.is_none().into_sql::<Bool>()),
+					)
+					.order(dsl::name.asc())
+					.limit(query.limit.map(i64::from).unwrap_or(i64::MAX))
+					.offset(i64::from(query.offset))
+					.select(dsl::id),
+			)
+			.await?)
+	}
+
+	async fn filter_ids(conn: &mut BoxedSqlConn, filter: &BranchFilter) -> Result<Vec<BranchRef>> {
+		let (names, ids, prefix) = match filter {
+			BranchFilter::Names(names) => (Some(names.as_slice()), None, None),
+			BranchFilter::Refs(ids) => (None, Some(ids.as_slice()), None),
+			BranchFilter::NamePrefix(prefix) => (None, None, Some(escape_like(prefix) + "%")),
+		};
+
+		// unused conditions are made always true, so that the query type is static
+		Ok(conn
+			.load(
+				dsl::branch
+					.filter(dsl::deleted_at.is_null())
+					.filter(
+						dsl::name
+							.eq_any(names.unwrap_or_default().iter().map(KString::as_str))
+							.or(names.is_none().into_sql::<Bool>()),
+					)
+					.filter(
+						dsl::id
+							.eq_any(ids.unwrap_or_default())
+							.or(ids.is_none().into_sql::<Bool>()),
+					)
+					.filter(
+						dsl::name
+							.like(prefix.clone().unwrap_or_default())
+							.escape('\\')
+							.or(prefix.is_none().into_sql::<Bool>()),
+					)
+					.select(dsl::id),
+			)
+			.await?)
+	}
+
+	/// Returns the current configuration of a branch.
+	///
+	/// All fields of the returned configuration are set.
+	/// When the branch has no base branch, `base` is set to an empty string.
+	pub async fn get_config(&self, id: BranchRef) -> Result<BranchConfigInfo> {
+		let mut conn = self.db.get().await?;
+		Self::load_config(&mut conn, id).await
+	}
+
+	async fn load_config(conn: &mut BoxedSqlConn, id: BranchRef) -> Result<BranchConfigInfo> {
+		let (base, priority, tracking, enabled) = conn
+			.get_result::<_, (Option<BranchRef>, i16, i16, bool)>(
+				dsl::branch
+					.filter(dsl::id.eq(id))
+					.filter(dsl::deleted_at.is_null())
+					.select((dsl::base, dsl::priority, dsl::tracking, dsl::enabled)),
+			)
+			.await
+			.optional()?
+			.ok_or(BranchError::BranchNotFound(id))?;
+		let base = match base {
+			Some(base) => KString::from(
+				conn.get_result::<_, String>(
+					dsl::branch.filter(dsl::id.eq(base)).select(dsl::name),
+				)
+				.await?,
+			),
+			None => KString::from_static(""),
+		};
+
+		Ok(BranchConfigInfo {
+			base: Some(base),
+			priority: Some(priority as u16),
+			tracking_mode: Some(SqlTrackingMode::from(tracking).into()),
+			enabled: Some(enabled),
+			labels: Some(Self::load_labels(conn, id).await?),
+		})
+	}
+
+	/// Returns the most recent failure of synchronizing a branch.
+	///
+	/// Returns `None` if the most recent synchronization has succeeded.
+	pub async fn get_last_error(&self, id: BranchRef) -> Result<Option<BranchSyncError>> {
+		let mut conn = self.db.get().await?;
+		let (message, code) = conn
+			.get_result::<_, (Option<String>, Option<i16>)>(
+				dsl::branch
+					.filter(dsl::id.eq(id))
+					.filter(dsl::deleted_at.is_null())
+					.select((dsl::last_error, dsl::last_error_code)),
+			)
+			.await
+			.optional()?
+			.ok_or(BranchError::BranchNotFound(id))?;
+		Ok(message.map(|message| BranchSyncError {
+			code: code.map(JobErrorCode::from),
+			message,
+		}))
+	}
+
+	/// Returns labels of a branch.
+	pub async fn get_labels(&self, id: BranchRef) -> Result<BTreeSet<String>> {
+		let mut conn = self.db.get().await?;
+		Self::load_labels(&mut conn, id).await
+	}
+
+	async fn load_labels(conn: &mut BoxedSqlConn, id: BranchRef) -> Result<BTreeSet<String>> {
+		Ok(conn
+			.load::<_, String>(
+				branch_label::table
+					.filter(branch_label::branch.eq(id))
+					.select(branch_label::label),
+			)
+			.await?
+			.into_iter()
+			.collect())
+	}
+
+	/// Replaces all labels of a branch.
+	async fn write_labels(
+		conn: &mut BoxedSqlConn,
+		id: BranchRef,
+		labels: &BTreeSet<String>,
+	) -> Result<()> {
+		conn.execute(delete(branch_label::table).filter(branch_label::branch.eq(id)))
+			.await?;
+		for label in labels {
+			conn.execute(
+				insert_into(branch_label::table)
+					.values((branch_label::branch.eq(id), branch_label::label.eq(label))),
+			)
+			.await?;
+		}
+		Ok(())
+	}
+
+	/// Merges fields set in `info` into the configuration of a branch.
+	///
+	/// `actor` is the principal updating the configuration, if known.
+	/// Returns warnings of the configuration.
+	pub async fn update_config(
+		&self,
+		id: BranchRef,
+		info: &BranchConfigInfo,
+		actor: Option<&str>,
+	) -> Result<Vec<String>> {
 		let mut conn = self.db.get().await?;
+		conn.transaction::<_, crate::BackendError, _>(async |conn| {
+			self.write_config(conn, id, info, actor).await
+		})
+		.await
+	}
+
+	/// Updates the configuration of a branch, only if the current configuration
+	/// matches `expected`.
+	///
+	/// Fields unset in `expected` are not compared.
+	/// If the current configuration does not match, [BranchError::ConfigConflict]
+	/// is returned and nothing is changed.
+	pub async fn update_config_cas(
+		&self,
+		id: BranchRef,
+		expected: &BranchConfigInfo,
+		desired: &BranchConfigInfo,
+		actor: Option<&str>,
+	) -> Result<Vec<String>> {
+		let mut conn = self.db.get().await?;
+
+		let warnings = conn
+			.transaction::<_, crate::BackendError, _>(async |conn| {
+				Self::lock_row(conn, id).await?;
+
+				let current = Self::load_config(conn, id).await?;
+				if !current.matches(expected) {
+					return Err(BranchError::ConfigConflict(id).into());
+				}
+				self.write_config(conn, id, desired, actor).await
+			})
+			.await?;
+		info!(id, "updated branch config with compare-and-swap");
+
+		Ok(warnings)
+	}
+
+	/// Applies a JSON Patch (RFC 6902) to the configuration of a branch.
+	///
+	/// The patch is applied to the complete configuration, as returned by
+	/// [BranchService::get_config]. The patched document then replaces
+	/// the current configuration, with removed fields reset to their defaults.
+	///
+	/// Nothing is changed if any operation fails.
+	pub async fn patch_config(
+		&self,
+		id: BranchRef,
+		patch: &Patch,
+		actor: Option<&str>,
+	) -> Result<Vec<String>> {
+		let mut conn = self.db.get().await?;
+
+		let warnings = conn
+			.transaction::<_, crate::BackendError, _>(async |conn| {
+				Self::lock_row(conn, id).await?;
+
+				let current = Self::load_config(conn, id).await?;
+				let mut document = serde_json::to_value(&current)?;
+				json_patch::patch(&mut document, patch).map_err(|error| match error.kind {
+					PatchErrorKind::TestFailed => BranchError::PatchTestFailed(error.to_string()),
+					_ => BranchError::InvalidPatch(error.to_string()),
+				})?;
+				let patched = serde_json::from_value::<BranchConfigInfo>(document)
+					.map_err(|error| BranchError::InvalidConfig(error.to_string()))?;
+
+				self.write_config(conn, id, &patched.or_defaults(), actor)
+					.await
+			})
+			.await?;
+		info!(id, "patched branch config");
+
+		Ok(warnings)
+	}
+
+	/// Applies a JSON Merge Patch (RFC 7396) to the configuration of a branch.
+	///
+	/// Fields omitted in `patch` are left unchanged, and fields set to null are
+	/// reset to their defaults. The merged configuration is validated before
+	/// being written.
+	pub async fn merge_patch_config(
+		&self,
+		id: BranchRef,
+		patch: &serde_json::Value,
+		actor: Option<&str>,
+	) -> Result<Vec<String>> {
+		let mut conn = self.db.get().await?;
+
+		let warnings = conn
+			.transaction::<_, crate::BackendError, _>(async |conn| {
+				self.merge_patch_with(conn, id, patch, actor).await
+			})
+			.await?;
+		info!(id, "merge-patched branch config");
+
+		Ok(warnings)
+	}
+
+	/// Applies a JSON Merge Patch to the configuration of all branches
+	/// matching a query, in a transaction.
+	///
+	/// Branches of which patched configurations are invalid are left unchanged,
+	/// and reported in [BulkUpdateResult::failures]. Other branches are
+	/// updated as with [BranchService::merge_patch_config].
+	pub async fn merge_patch_many(
+		&self,
+		query: &BranchQuery,
+		patch: &serde_json::Value,
+		actor: Option<&str>,
+	) -> Result<BulkUpdateResult> {
+		let mut conn = self.db.get().await?;
+
+		let result = conn
+			.transaction::<_, crate::BackendError, _>(async |conn| {
+				let ids = Self::query_ids(conn, query).await?;
+				let branches = conn
+					.load::<_, (BranchRef, String)>(
+						dsl::branch
+							.filter(dsl::id.eq_any(&ids))
+							.order(dsl::name.asc())
+							.select((dsl::id, dsl::name)),
+					)
+					.await?;
+
+				let mut result = BulkUpdateResult::default();
+				for (id, name) in branches {
+					// failed branches are rolled back to the savepoint
+					let outcome = conn
+						.transaction::<_, crate::BackendError, _>(async |conn| {
+							self.merge_patch_with(conn, id, patch, actor).await
+						})
+						.await;
+					match outcome {
+						Ok(_) => {
+							info!(target: "audit", id, %name, ?actor, "merge-patched branch config");
+							result.updated += 1;
+						}
+						Err(crate::BackendError::BranchError(error)) => {
+							result.failures.insert(name, error.to_string());
+						}
+						Err(error) => return Err(error),
+					}
+				}
+				Ok(result)
+			})
+			.await?;
+		info!(
+			updated = result.updated,
+			failed = result.failures.len(),
+			"merge-patched branch configs"
+		);
+
+		Ok(result)
+	}
+
+	/// Merges a patch into the configuration of a branch, in a transaction.
+	async fn merge_patch_with(
+		&self,
+		conn: &mut BoxedSqlConn,
+		id: BranchRef,
+		patch: &serde_json::Value,
+		actor: Option<&str>,
+	) -> Result<Vec<String>> {
+		Self::lock_row(conn, id).await?;
+
+		let current = Self::load_config(conn, id).await?;
+		let mut document = serde_json::to_value(&current)?;
+		json_patch::merge(&mut document, patch);
+		let merged = serde_json::from_value::<BranchConfigInfo>(document)
+			.map_err(|error| BranchError::InvalidConfig(error.to_string()))?;
+
+		self.write_config(conn, id, &merged.or_defaults(), actor)
+			.await
+	}
+
+	/// Locks the row of a branch until the end of the current transaction.
+	///
+	/// This is done with a no-op write, so that following reads and writes
+	/// in the same transaction are atomic.
+	async fn lock_row(conn: &mut BoxedSqlConn, id: BranchRef) -> Result<()> {
+		non_zero_or_not_found(
+			conn.execute(
+				update(dsl::branch)
+					.filter(dsl::id.eq(id))
+					.filter(dsl::deleted_at.is_null())
+					.set(dsl::priority.eq(dsl::priority)),
+			)
+			.await?,
+			id,
+		)?;
+		Ok(())
+	}
+
+	/// Writes fields set in `info` into the configuration of a branch.
+	///
+	/// If the branch is disabled by this, its pending jobs are cancelled.
+	/// Running jobs are left to finish.
+	///
+	/// Returns warnings of the configuration.
+	async fn write_config(
+		&self,
+		conn: &mut BoxedSqlConn,
+		id: BranchRef,
+		info: &BranchConfigInfo,
+		actor: Option<&str>,
+	) -> Result<Vec<String>> {
+		let warnings = validate_config(info)?;
 		let base = match &info.base {
 			Some(base) => {
 				if base.is_empty() {
 					Some(None)
 				} else {
-					Some(Some(self.find_id_or_err(base).await?))
+					Some(Some(Self::find_id_or_err_with(conn, base).await?))
 				}
 			}
 			None => None,
 		};
 
+		let config = SqlBranchConfig {
+			id,
+			base,
+			priority: info.priority.map(|pri| pri as i16),
+			tracking: info.tracking_mode.map(|mode| mode as i16),
+			enabled: info.enabled,
+			updated_at: self.job_queue.clock().now(),
+			updated_by: Some(actor),
+		};
+
+		let disabling = info.enabled == Some(false)
+			&& conn
+				.get_result::<_, bool>(dsl::branch.filter(dsl::id.eq(id)).select(dsl::enabled))
+				.await
+				.optional()?
+				.unwrap_or_default();
+
 		non_zero_or_not_found(
-			conn.execute(update(dsl::branch).set(SqlBranchConfig {
-				id,
-				base,
-				priority: info.priority.map(|pri| pri as i16),
-				tracking: info.tracking_mode.map(|mode| mode as i16),
-			}))
+			conn.execute(
+				update(
+					dsl::branch
+						.filter(dsl::id.eq(id))
+						.filter(dsl::deleted_at.is_null()),
+				)
+				.set(config),
+			)
 			.await?,
 			id,
 		)?;
-		Ok(())
+		if let Some(labels) = &info.labels {
+			Self::write_labels(conn, id, labels).await?;
+		}
+		if disabling {
+			self.job_queue
+				.cancel_pending_for_branches(conn, &[id])
+				.await?;
+			info!(id, "disabled branch");
+		}
+		Ok(warnings)
+	}
+}
+
+/// Rejects configurations with errors, and returns warnings.
+fn validate_config(info: &BranchConfigInfo) -> Result<Vec<String>, BranchError> {
+	let validation = info.validate();
+	if !validation.is_valid() {
+		return Err(BranchError::InvalidConfig(validation.errors.join("; ")));
 	}
+	Ok(validation.warnings)
 }
 
 #[derive(Debug, Error)]
@@ -256,6 +982,28 @@ pub enum BranchError {
 	BranchNameNotFound(KString),
 	#[error("branch {0} not found")]
 	BranchNotFound(BranchRef),
+	#[error("configuration of branch {0} does not match the expected one")]
+	ConfigConflict(BranchRef),
+	#[error("test operation of JSON patch failed: {0}")]
+	PatchTestFailed(String),
+	#[error("invalid JSON patch: {0}")]
+	InvalidPatch(String),
+	#[error("invalid branch config: {0}")]
+	InvalidConfig(String),
+	#[error("branch {0} is deleted and can still be restored")]
+	DeletedBranchExists(KString),
+}
+
+/// Escapes wildcards in a pattern of `LIKE`.
+fn escape_like(text: &str) -> String {
+	let mut escaped = String::with_capacity(text.len());
+	for ch in text.chars() {
+		if matches!(ch, '%' | '_' | '\\') {
+			escaped.push('\\');
+		}
+		escaped.push(ch);
+	}
+	escaped
 }
 
 fn non_zero_or_not_found(val: usize, id: BranchRef) -> Result<(), BranchError> {
@@ -266,35 +1014,70 @@ fn non_zero_or_not_found(val: usize, id: BranchRef) -> Result<(), BranchError> {
 	}
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Default)]
-pub struct BranchConfigInfo {
-	/// Name of the base branch of this branch.
-	///
-	/// Set this to empty string to remove base branch.
-	pub base: Option<KString>,
-	pub priority: Option<u16>,
-	pub tracking_mode: Option<TrackingMode>,
+/// Query of branches to list.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct BranchQuery {
+	/// Only lists branches enabled or disabled.
+	pub enabled: Option<bool>,
+	/// Only lists branches in the state.
+	pub status: Option<SqlBranchStatus>,
+	/// Only lists branches with the label.
+	pub label: Option<String>,
+	/// Maximum count of branches to list.
+	pub limit: Option<u32>,
+	/// Count of branches to skip.
+	pub offset: u32,
+	/// Only lists branches with names ordered after this, for keyset pagination.
+	pub after: Option<String>,
+}
+
+/// Result of [BranchService::merge_patch_many].
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct BulkUpdateResult {
+	/// Count of updated branches.
+	pub updated: usize,
+	/// Errors of branches left unchanged, by name.
+	pub failures: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Identifiable, AsChangeset)]
 #[diesel(table_name = schema::branch)]
-pub struct SqlBranchConfig {
+pub struct SqlBranchConfig<'a> {
 	id: BranchRef,
 	base: Option<Option<BranchRef>>,
 	priority: Option<i16>,
 	tracking: Option<i16>,
+	enabled: Option<bool>,
+	updated_at: PrimitiveDateTime,
+	updated_by: Option<Option<&'a str>>,
 }
 
 #[cfg(test)]
 mod test {
-	use diesel::QueryDsl;
+	use diesel::{ExpressionMethods, QueryDsl, update};
+
+	use json_patch::Patch;
+	use serde_json::json;
+	use time::{Duration, PrimitiveDateTime};
 
-	use crate::{db::schema::branch::dsl, job_queue::JobCommand, test::test_env};
+	use crate::{
+		BackendError, BackendServices,
+		branch::{
+			BranchConfigInfo, BranchError, BranchFilter, BranchQuery, BranchSyncError,
+			BulkUpdateResult, SqlBranchStatus,
+		},
+		db::schema::branch::dsl,
+		job_queue::{JobCommand, JobErrorCode, JobFailure},
+		test::{TestEnv, test_env},
+	};
 
 	#[tokio::test]
 	async fn test_track() {
 		let env = test_env().await;
-		env.branch.track("test", Default::default()).await.unwrap();
+		env.branch
+			.track("test", Default::default(), None)
+			.await
+			.unwrap();
 
 		// assert object
 		let mut db = env.database.get().await.unwrap();
@@ -308,6 +1091,714 @@ mod test {
 
 		// assert sync job
 		let job = env.job_queue.fetch_and_start().await.unwrap().unwrap();
-		assert_eq!(job.command, JobCommand::SyncBranch(1));
+		assert_eq!(job.command, JobCommand::sync_branch(1));
+	}
+
+	#[tokio::test]
+	async fn test_last_error() {
+		let env = test_env().await;
+		env.branch
+			.track("test", Default::default(), None)
+			.await
+			.unwrap();
+		let id = env.branch.find_id_or_err("test").await.unwrap();
+		assert_eq!(env.branch.get_last_error(id).await.unwrap(), None);
+
+		let job = env.job_queue.fetch_and_start().await.unwrap().unwrap();
+		let failure = JobFailure::new(JobErrorCode::Network, "remote hung up");
+		let mut db = env.database.get().await.unwrap();
+		assert!(
+			env.job_queue
+				.fail_job_with(&mut db, job.id, &failure)
+				.await
+				.unwrap()
+		);
+		drop(db);
+		assert_eq!(
+			env.branch.get_last_error(id).await.unwrap(),
+			Some(BranchSyncError {
+				code: Some(JobErrorCode::Network),
+				message: "remote hung up".to_string(),
+			})
+		);
+
+		// the retry succeeds
+		let job = env.job_queue.fetch_and_start().await.unwrap().unwrap();
+		let mut db = env.database.get().await.unwrap();
+		env.job_queue.finish_job(&mut db, job.id).await.unwrap();
+		drop(db);
+		assert_eq!(env.branch.get_last_error(id).await.unwrap(), None);
+	}
+
+	#[tokio::test]
+	async fn test_sync_cursor() {
+		let env = test_env().await;
+		env.branch
+			.track("test", Default::default(), None)
+			.await
+			.unwrap();
+		let id = env.branch.find_id_or_err("test").await.unwrap();
+		assert_eq!(env.branch.get_sync_cursor(id).await.unwrap(), None);
+
+		// a sync persists the cursor, and the next one reads it back
+		env.branch.set_sync_cursor(id, Some("rev-1")).await.unwrap();
+		assert_eq!(
+			env.branch.get_sync_cursor(id).await.unwrap().as_deref(),
+			Some("rev-1")
+		);
+
+		// a full sync resets the cursor
+		env.branch.sync_full(id).await.unwrap();
+		assert_eq!(env.branch.get_sync_cursor(id).await.unwrap(), None);
+
+		assert!(matches!(
+			env.branch.set_sync_cursor(42, Some("rev-1")).await,
+			Err(BackendError::BranchError(BranchError::BranchNotFound(42)))
+		));
+	}
+
+	#[tokio::test]
+	async fn test_audit_fields() {
+		let env = test_env().await;
+		env.branch
+			.track("test", Default::default(), Some("alice"))
+			.await
+			.unwrap();
+		let id = env.branch.find_id_or_err("test").await.unwrap();
+
+		let audit = async || {
+			let mut db = env.database.get().await.unwrap();
+			db.get_result::<_, (
+				Option<PrimitiveDateTime>,
+				Option<PrimitiveDateTime>,
+				Option<String>,
+				Option<String>,
+			)>(dsl::branch.filter(dsl::id.eq(id)).select((
+				dsl::created_at,
+				dsl::updated_at,
+				dsl::created_by,
+				dsl::updated_by,
+			)))
+			.await
+			.unwrap()
+		};
+		let (created_at, updated_at, created_by, updated_by) = audit().await;
+		assert!(created_at.is_some());
+		assert_eq!(updated_at, created_at);
+		assert_eq!(created_by.as_deref(), Some("alice"));
+		assert_eq!(updated_by.as_deref(), Some("alice"));
+
+		let info = BranchConfigInfo {
+			priority: Some(120),
+			..Default::default()
+		};
+		env.branch
+			.update_config(id, &info, Some("bob"))
+			.await
+			.unwrap();
+		let (new_created_at, new_updated_at, created_by, updated_by) = audit().await;
+		assert_eq!(new_created_at, created_at);
+		assert!(new_updated_at >= updated_at);
+		assert_eq!(created_by.as_deref(), Some("alice"));
+		assert_eq!(updated_by.as_deref(), Some("bob"));
+	}
+
+	#[tokio::test]
+	async fn test_update_config_cas() {
+		let env = test_env().await;
+		env.branch
+			.track("test", Default::default(), None)
+			.await
+			.unwrap();
+		let id = env.branch.find_id_or_err("test").await.unwrap();
+
+		let expected = env.branch.get_config(id).await.unwrap();
+		let desired = BranchConfigInfo {
+			priority: Some(120),
+			..Default::default()
+		};
+		env.branch
+			.update_config_cas(id, &expected, &desired, None)
+			.await
+			.unwrap();
+		assert_eq!(env.branch.get_config(id).await.unwrap().priority, Some(120));
+	}
+
+	#[tokio::test]
+	async fn test_update_config_cas_conflict() {
+		let env = test_env().await;
+		env.branch
+			.track("test", Default::default(), None)
+			.await
+			.unwrap();
+		let id = env.branch.find_id_or_err("test").await.unwrap();
+
+		let expected = BranchConfigInfo {
+			priority: Some(50),
+			..Default::default()
+		};
+		let desired = BranchConfigInfo {
+			priority: Some(120),
+			..Default::default()
+		};
+		assert!(matches!(
+			env.branch
+				.update_config_cas(id, &expected, &desired, None)
+				.await,
+			Err(BackendError::BranchError(BranchError::ConfigConflict(_)))
+		));
+		assert_eq!(env.branch.get_config(id).await.unwrap().priority, Some(100));
+	}
+
+	#[tokio::test]
+	async fn test_update_config_warning() {
+		let env = test_env().await;
+		env.branch
+			.track("test", Default::default(), None)
+			.await
+			.unwrap();
+		let id = env.branch.find_id_or_err("test").await.unwrap();
+
+		let info = BranchConfigInfo {
+			priority: Some(2000),
+			..Default::default()
+		};
+		let warnings = env.branch.update_config(id, &info, None).await.unwrap();
+		assert!(!warnings.is_empty());
+		assert_eq!(
+			env.branch.get_config(id).await.unwrap().priority,
+			Some(2000)
+		);
+	}
+
+	#[tokio::test]
+	async fn test_disable_cancels_pending_jobs() {
+		let env = test_env().await;
+		env.branch
+			.track("test", Default::default(), None)
+			.await
+			.unwrap();
+		let id = env.branch.find_id_or_err("test").await.unwrap();
+
+		let info = BranchConfigInfo {
+			enabled: Some(false),
+			..Default::default()
+		};
+		env.branch.update_config(id, &info, None).await.unwrap();
+		assert_eq!(
+			env.branch.get_config(id).await.unwrap().enabled,
+			Some(false)
+		);
+		assert!(env.job_queue.fetch_and_start().await.unwrap().is_none());
+	}
+
+	#[tokio::test]
+	async fn test_update_config_invalid() {
+		let env = test_env().await;
+		env.branch
+			.track("test", Default::default(), None)
+			.await
+			.unwrap();
+		let id = env.branch.find_id_or_err("test").await.unwrap();
+
+		let info = BranchConfigInfo {
+			base: Some("bad..name".into()),
+			..Default::default()
+		};
+		assert!(matches!(
+			env.branch.update_config(id, &info, None).await,
+			Err(BackendError::BranchError(BranchError::InvalidConfig(_)))
+		));
+		assert_eq!(
+			env.branch.get_config(id).await.unwrap().base,
+			Some("".into())
+		);
+	}
+
+	fn make_patch(value: serde_json::Value) -> Patch {
+		serde_json::from_value(value).unwrap()
+	}
+
+	#[tokio::test]
+	async fn test_patch_config_add() {
+		let env = test_env().await;
+		env.branch
+			.track("test", Default::default(), None)
+			.await
+			.unwrap();
+		let id = env.branch.find_id_or_err("test").await.unwrap();
+
+		let patch = make_patch(json!([{ "op": "add", "path": "/priority", "value": 120 }]));
+		env.branch.patch_config(id, &patch, None).await.unwrap();
+		assert_eq!(env.branch.get_config(id).await.unwrap().priority, Some(120));
+	}
+
+	#[tokio::test]
+	async fn test_patch_config_replace() {
+		let env = test_env().await;
+		let info = BranchConfigInfo {
+			priority: Some(120),
+			..Default::default()
+		};
+		env.branch.track("test", info, None).await.unwrap();
+		let id = env.branch.find_id_or_err("test").await.unwrap();
+
+		let patch = make_patch(json!([
+			{ "op": "test", "path": "/priority", "value": 120 },
+			{ "op": "replace", "path": "/priority", "value": 150 },
+		]));
+		env.branch.patch_config(id, &patch, None).await.unwrap();
+		assert_eq!(env.branch.get_config(id).await.unwrap().priority, Some(150));
+	}
+
+	#[tokio::test]
+	async fn test_patch_config_remove() {
+		let env = test_env().await;
+		env.branch
+			.track("base", Default::default(), None)
+			.await
+			.unwrap();
+		let info = BranchConfigInfo {
+			base: Some("base".into()),
+			priority: Some(120),
+			..Default::default()
+		};
+		env.branch.track("test", info, None).await.unwrap();
+		let id = env.branch.find_id_or_err("test").await.unwrap();
+
+		let patch = make_patch(json!([
+			{ "op": "remove", "path": "/base" },
+			{ "op": "remove", "path": "/priority" },
+		]));
+		env.branch.patch_config(id, &patch, None).await.unwrap();
+		let config = env.branch.get_config(id).await.unwrap();
+		assert_eq!(config.base, Some("".into()));
+		assert_eq!(config.priority, Some(100));
+	}
+
+	#[tokio::test]
+	async fn test_patch_config_test_failed() {
+		let env = test_env().await;
+		env.branch
+			.track("test", Default::default(), None)
+			.await
+			.unwrap();
+		let id = env.branch.find_id_or_err("test").await.unwrap();
+
+		let patch = make_patch(json!([
+			{ "op": "test", "path": "/priority", "value": 50 },
+			{ "op": "replace", "path": "/priority", "value": 120 },
+		]));
+		assert!(matches!(
+			env.branch.patch_config(id, &patch, None).await,
+			Err(BackendError::BranchError(BranchError::PatchTestFailed(_)))
+		));
+		assert_eq!(env.branch.get_config(id).await.unwrap().priority, Some(100));
+	}
+
+	#[tokio::test]
+	async fn test_merge_patch_config_update() {
+		let env = test_env().await;
+		env.branch
+			.track("test", Default::default(), None)
+			.await
+			.unwrap();
+		let id = env.branch.find_id_or_err("test").await.unwrap();
+
+		env.branch
+			.merge_patch_config(id, &json!({ "priority": 120 }), None)
+			.await
+			.unwrap();
+		assert_eq!(env.branch.get_config(id).await.unwrap().priority, Some(120));
+	}
+
+	#[tokio::test]
+	async fn test_merge_patch_config_remove() {
+		let env = test_env().await;
+		env.branch
+			.track("base", Default::default(), None)
+			.await
+			.unwrap();
+		let info = BranchConfigInfo {
+			base: Some("base".into()),
+			priority: Some(120),
+			..Default::default()
+		};
+		env.branch.track("test", info, None).await.unwrap();
+		let id = env.branch.find_id_or_err("test").await.unwrap();
+
+		env.branch
+			.merge_patch_config(id, &json!({ "base": null }), None)
+			.await
+			.unwrap();
+		let config = env.branch.get_config(id).await.unwrap();
+		assert_eq!(config.base, Some("".into()));
+		// omitted fields are preserved
+		assert_eq!(config.priority, Some(120));
+	}
+
+	#[tokio::test]
+	async fn test_merge_patch_config_invalid() {
+		let env = test_env().await;
+		env.branch
+			.track("test", Default::default(), None)
+			.await
+			.unwrap();
+		let id = env.branch.find_id_or_err("test").await.unwrap();
+
+		assert!(matches!(
+			env.branch
+				.merge_patch_config(id, &json!({ "priority": "urgent" }), None)
+				.await,
+			Err(BackendError::BranchError(BranchError::InvalidConfig(_)))
+		));
+		assert!(matches!(
+			env.branch
+				.merge_patch_config(id, &json!({ "base": "bad..name" }), None)
+				.await,
+			Err(BackendError::BranchError(BranchError::InvalidConfig(_)))
+		));
+		assert_eq!(env.branch.get_config(id).await.unwrap().priority, Some(100));
+	}
+
+	#[tokio::test]
+	async fn test_sync() {
+		let env = test_env().await;
+		let info = BranchConfigInfo {
+			priority: Some(120),
+			..Default::default()
+		};
+		env.branch.track("test", info, None).await.unwrap();
+		let id = env.branch.find_id_or_err("test").await.unwrap();
+
+		let job = env.branch.sync(id).await.unwrap();
+		let info = env.job_queue.get_job(job).await.unwrap().unwrap();
+		assert_eq!(info.kind, "SyncBranch");
+		assert_eq!(info.data, json!({ "branch": id }));
+		assert_eq!(info.priority, 120);
+		assert!(info.started_at.is_none());
+	}
+
+	#[tokio::test]
+	async fn test_delete_many_by_prefix() {
+		let env = test_env().await;
+		for name in ["feat-a", "feat-b", "feat", "main"] {
+			env.branch
+				.track(name, Default::default(), None)
+				.await
+				.unwrap();
+		}
+
+		let count = env
+			.branch
+			.delete_many(&BranchFilter::NamePrefix("feat-".to_string()))
+			.await
+			.unwrap();
+		assert_eq!(count, 2);
+		assert!(env.branch.find_id("feat-a").await.unwrap().is_none());
+		assert!(env.branch.find_id("feat-b").await.unwrap().is_none());
+		let feat = env.branch.find_id("feat").await.unwrap().unwrap();
+		let main = env.branch.find_id("main").await.unwrap().unwrap();
+
+		// only jobs of the remaining branches are left
+		let mut commands = Vec::new();
+		while let Some(job) = env.job_queue.fetch_and_start().await.unwrap() {
+			commands.push(job.command);
+		}
+		commands.sort_by_key(|command| command.branch());
+		assert_eq!(
+			commands,
+			vec![JobCommand::sync_branch(feat), JobCommand::sync_branch(main)]
+		);
+	}
+
+	async fn track_for_query(env: &BackendServices) {
+		for name in ["a", "b", "c", "d"] {
+			let info = BranchConfigInfo {
+				enabled: Some(name != "b"),
+				..Default::default()
+			};
+			env.branch.track(name, info, None).await.unwrap();
+		}
+
+		let mut db = env.database.get().await.unwrap();
+		db.execute(
+			update(dsl::branch)
+				.filter(dsl::name.eq_any(["b", "c"]))
+				.set(dsl::status.eq(SqlBranchStatus::Error as i16)),
+		)
+		.await
+		.unwrap();
+	}
+
+	async fn list_names(env: &BackendServices, query: BranchQuery) -> Vec<String> {
+		let ids = env.branch.list_ids(&query).await.unwrap();
+		let mut db = env.database.get().await.unwrap();
+		db.load(
+			dsl::branch
+				.filter(dsl::id.eq_any(ids))
+				.order(dsl::name.asc())
+				.select(dsl::name),
+		)
+		.await
+		.unwrap()
+	}
+
+	#[tokio::test]
+	async fn test_list_ids_after() {
+		let env = test_env().await;
+		track_for_query(&env).await;
+
+		let query = BranchQuery {
+			after: Some("b".to_string()),
+			limit: Some(1),
+			..Default::default()
+		};
+		assert_eq!(list_names(&env, query.clone()).await, ["c"]);
+		assert_eq!(env.branch.count(&query).await.unwrap(), 4);
+	}
+
+	#[tokio::test]
+	async fn test_list_ids_enabled() {
+		let env = test_env().await;
+		track_for_query(&env).await;
+
+		let query = BranchQuery {
+			enabled: Some(false),
+			..Default::default()
+		};
+		assert_eq!(list_names(&env, query).await, ["b"]);
+		let query = BranchQuery {
+			enabled: Some(true),
+			..Default::default()
+		};
+		assert_eq!(list_names(&env, query).await, ["a", "c", "d"]);
+	}
+
+	#[tokio::test]
+	async fn test_list_ids_status() {
+		let env = test_env().await;
+		track_for_query(&env).await;
+
+		let query = BranchQuery {
+			status: Some(SqlBranchStatus::Error),
+			..Default::default()
+		};
+		assert_eq!(list_names(&env, query).await, ["b", "c"]);
+		let query = BranchQuery {
+			status: Some(SqlBranchStatus::Dirty),
+			..Default::default()
+		};
+		assert_eq!(list_names(&env, query).await, ["a", "d"]);
+	}
+
+	#[tokio::test]
+	async fn test_list_ids_combined() {
+		let env = test_env().await;
+		track_for_query(&env).await;
+
+		let query = BranchQuery {
+			enabled: Some(true),
+			status: Some(SqlBranchStatus::Error),
+			..Default::default()
+		};
+		assert_eq!(list_names(&env, query).await, ["c"]);
+		let query = BranchQuery {
+			enabled: Some(true),
+			limit: Some(1),
+			offset: 1,
+			..Default::default()
+		};
+		assert_eq!(list_names(&env, query).await, ["c"]);
+	}
+
+	#[tokio::test]
+	async fn test_labels() {
+		let env = test_env().await;
+		for (name, labels) in [
+			("main", &["stable", "arch=amd64"][..]),
+			("feat", &["arch=amd64"][..]),
+			("old", &[][..]),
+		] {
+			let info = BranchConfigInfo {
+				labels: Some(labels.iter().map(|label| label.to_string()).collect()),
+				..Default::default()
+			};
+			env.branch.track(name, info, None).await.unwrap();
+		}
+
+		let query = BranchQuery {
+			label: Some("arch=amd64".to_string()),
+			..Default::default()
+		};
+		assert_eq!(list_names(&env, query).await, ["feat", "main"]);
+		let query = BranchQuery {
+			label: Some("stable".to_string()),
+			..Default::default()
+		};
+		assert_eq!(list_names(&env, query).await, ["main"]);
+
+		let feat = env.branch.find_id_or_err("feat").await.unwrap();
+		let info = BranchConfigInfo {
+			labels: Some(["stable".to_string()].into()),
+			..Default::default()
+		};
+		env.branch.update_config(feat, &info, None).await.unwrap();
+		assert_eq!(
+			env.branch.get_labels(feat).await.unwrap(),
+			["stable".to_string()].into()
+		);
+		let query = BranchQuery {
+			label: Some("stable".to_string()),
+			..Default::default()
+		};
+		assert_eq!(list_names(&env, query).await, ["feat", "main"]);
+	}
+
+	#[tokio::test]
+	async fn test_merge_patch_many() {
+		let env = test_env().await;
+		for (name, labels) in [
+			("main", &["stable"][..]),
+			("lts", &["stable"][..]),
+			("feat", &[][..]),
+		] {
+			let info = BranchConfigInfo {
+				labels: Some(labels.iter().map(|label| label.to_string()).collect()),
+				..Default::default()
+			};
+			env.branch.track(name, info, None).await.unwrap();
+		}
+		let priority = async |name: &str| {
+			let id = env.branch.find_id_or_err(name).await.unwrap();
+			env.branch.get_config(id).await.unwrap().priority
+		};
+
+		let query = BranchQuery {
+			label: Some("stable".to_string()),
+			..Default::default()
+		};
+		let result = env
+			.branch
+			.merge_patch_many(&query, &json!({ "priority": 150 }), Some("alice"))
+			.await
+			.unwrap();
+		assert_eq!(
+			result,
+			BulkUpdateResult {
+				updated: 2,
+				failures: Default::default()
+			}
+		);
+		assert_eq!(priority("main").await, Some(150));
+		assert_eq!(priority("lts").await, Some(150));
+		assert_eq!(priority("feat").await, Some(100));
+
+		// invalid patches are reported per branch, leaving them unchanged
+		let result = env
+			.branch
+			.merge_patch_many(&query, &json!({ "priority": "urgent" }), None)
+			.await
+			.unwrap();
+		assert_eq!(result.updated, 0);
+		assert_eq!(result.failures.keys().collect::<Vec<_>>(), ["lts", "main"]);
+		assert_eq!(priority("main").await, Some(150));
+	}
+
+	#[tokio::test]
+	async fn test_labels_invalid() {
+		let env = test_env().await;
+		let info = BranchConfigInfo {
+			labels: Some(["bad label".to_string()].into()),
+			..Default::default()
+		};
+		let error = env.branch.track("test", info, None).await.unwrap_err();
+		assert!(matches!(
+			error,
+			BackendError::BranchError(BranchError::InvalidConfig(_))
+		));
+		assert!(env.branch.find_id("test").await.unwrap().is_none());
+	}
+
+	#[tokio::test]
+	async fn test_restore_within_grace_period() {
+		let env = TestEnv::builder().with_branches(["main"]).build().await;
+		let id = env.branch.find_id_or_err("main").await.unwrap();
+		// the job of tracking
+		env.job_queue.fetch_and_start().await.unwrap().unwrap();
+
+		env.branch.untrack(id).await.unwrap();
+		assert!(env.branch.find_id("main").await.unwrap().is_none());
+		assert_eq!(env.branch.sweep_deleted().await.unwrap(), 0);
+
+		assert_eq!(env.branch.restore("main").await.unwrap(), id);
+		assert_eq!(env.branch.find_id("main").await.unwrap(), Some(id));
+		let job = env.job_queue.fetch_and_start().await.unwrap().unwrap();
+		assert_eq!(job.command, JobCommand::sync_branch(id));
+	}
+
+	#[tokio::test]
+	async fn test_track_deleted_name() {
+		let env = TestEnv::builder().with_branches(["main"]).build().await;
+		let id = env.branch.find_id_or_err("main").await.unwrap();
+		env.branch.untrack(id).await.unwrap();
+
+		// the deleted branch is kept within the grace period
+		assert!(matches!(
+			env.branch.track("main", Default::default(), None).await,
+			Err(BackendError::BranchError(BranchError::DeletedBranchExists(
+				_
+			)))
+		));
+		assert_eq!(env.branch.restore("main").await.unwrap(), id);
+		env.branch.untrack(id).await.unwrap();
+
+		let mut db = env.database.get().await.unwrap();
+		db.execute(
+			update(dsl::branch)
+				.filter(dsl::id.eq(id))
+				.set(dsl::deleted_at.eq(env.job_queue.clock().now() - Duration::days(2))),
+		)
+		.await
+		.unwrap();
+		drop(db);
+
+		env.branch
+			.track("main", Default::default(), None)
+			.await
+			.unwrap();
+		let new_id = env.branch.find_id_or_err("main").await.unwrap();
+		assert_ne!(new_id, id);
+		assert!(matches!(
+			env.branch.restore("main").await,
+			Err(BackendError::BranchError(BranchError::BranchNameNotFound(
+				_
+			)))
+		));
+	}
+
+	#[tokio::test]
+	async fn test_sweep_after_grace_period() {
+		let env = TestEnv::builder().with_branches(["main"]).build().await;
+		let id = env.branch.find_id_or_err("main").await.unwrap();
+		env.branch.untrack(id).await.unwrap();
+
+		let mut db = env.database.get().await.unwrap();
+		db.execute(
+			update(dsl::branch)
+				.filter(dsl::id.eq(id))
+				.set(dsl::deleted_at.eq(env.job_queue.clock().now() - Duration::days(2))),
+		)
+		.await
+		.unwrap();
+		drop(db);
+
+		assert_eq!(env.branch.sweep_deleted().await.unwrap(), 1);
+		assert!(matches!(
+			env.branch.restore("main").await,
+			Err(BackendError::BranchError(BranchError::BranchNameNotFound(
+				_
+			)))
+		));
 	}
 }
@@ -1,10 +1,193 @@
+use std::{collections::HashSet, fmt::Display};
+
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+	branch::BranchConfig, db::service::DatabaseConfig, job_queue::JobQueueConfig,
+	redis::RedisConfig, target::TargetConfig,
+};
 
-use crate::{db::service::DatabaseConfig, redis::RedisConfig, target::TargetConfig};
+/// Environment variable overriding [`DatabaseConfig::url`].
+pub const ENV_DATABASE_URL: &str = "FABRICIA_DATABASE_URL";
+/// Environment variable overriding [`RedisConfig::url`].
+pub const ENV_REDIS_URL: &str = "FABRICIA_REDIS_URL";
 
 #[derive(Debug, PartialEq, Eq, Clone, Hash, Deserialize, Serialize)]
 pub struct BackendConfig {
 	pub database: DatabaseConfig,
 	pub redis: RedisConfig,
 	pub target: Vec<TargetConfig>,
+	#[serde(default)]
+	pub job_queue: JobQueueConfig,
+	#[serde(default)]
+	pub branch: BranchConfig,
+}
+
+impl BackendConfig {
+	/// Checks the configuration, reporting all invalid settings at once.
+	///
+	/// This is called by [`BackendServices::new`](crate::BackendServices::new)
+	/// before connecting to anything.
+	pub fn validate(&self) -> Result<(), ConfigError> {
+		let mut problems = Vec::new();
+		let mut invalid = |setting: &str, reason: &str| {
+			problems.push(format!("{setting}: {reason}"));
+		};
+
+		let database = &self.database;
+		if database.url.is_empty() {
+			invalid("database.url", "is required");
+		} else if !["postgres://", "postgresql://", "sqlite://"]
+			.iter()
+			.any(|scheme| database.url.starts_with(scheme))
+		{
+			invalid("database.url", "must be a PostgreSQL or SQLite URL");
+		}
+		if database.max_connections == 0 {
+			invalid("database.max-connections", "must be positive");
+		} else if database.url == "sqlite://:memory:" && database.max_connections != 1 {
+			invalid("database.max-connections", "must be 1 for in-memory SQLite");
+		}
+		if database.warmup_connections > database.max_connections {
+			invalid(
+				"database.warmup-connections",
+				"must not exceed database.max-connections",
+			);
+		}
+
+		if self.redis.url.is_empty() {
+			invalid("redis.url", "is required");
+		}
+		if self.redis.max_connections == 0 {
+			invalid("redis.max-connections", "must be positive");
+		}
+
+		let mut targets = HashSet::new();
+		for target in &self.target {
+			if target.name.is_empty() {
+				invalid("target.name", "is required");
+			} else if !targets.insert(&target.name) {
+				invalid("target.name", &format!("{} is duplicated", target.name));
+			}
+		}
+
+		let job_queue = &self.job_queue;
+		if job_queue.max_attempts == 0 {
+			invalid("job-queue.max-attempts", "must be positive");
+		}
+		if job_queue.retry_delay > job_queue.max_retry_delay {
+			invalid(
+				"job-queue.retry-delay",
+				"must not exceed job-queue.max-retry-delay",
+			);
+		}
+		if job_queue.max_claims_per_worker == Some(0) {
+			invalid("job-queue.max-claims-per-worker", "must be positive");
+		}
+
+		if problems.is_empty() {
+			Ok(())
+		} else {
+			Err(ConfigError(problems))
+		}
+	}
+
+	/// Overrides settings with environment variables, see [ENV_DATABASE_URL]
+	/// and [ENV_REDIS_URL].
+	///
+	/// This is for secrets which should not be written to configuration files.
+	pub fn override_from_env(database: &mut DatabaseConfig, redis: &mut RedisConfig) {
+		Self::override_with(database, redis, |name| std::env::var(name).ok());
+	}
+
+	fn override_with<F: Fn(&str) -> Option<String>>(
+		database: &mut DatabaseConfig,
+		redis: &mut RedisConfig,
+		lookup: F,
+	) {
+		if let Some(url) = lookup(ENV_DATABASE_URL) {
+			database.url = url;
+		}
+		if let Some(url) = lookup(ENV_REDIS_URL) {
+			redis.url = url;
+		}
+	}
+}
+
+/// Invalid settings found by [`BackendConfig::validate`].
+#[derive(Debug, PartialEq, Eq, Clone, Error)]
+pub struct ConfigError(pub Vec<String>);
+
+impl Display for ConfigError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "invalid configuration:")?;
+		for problem in &self.0 {
+			write!(f, "\n- {problem}")?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::collections::HashMap;
+
+	use crate::{
+		BackendError, BackendServices,
+		config::{BackendConfig, ConfigError, ENV_DATABASE_URL},
+		test::{TestEnvBuilder, TestingBusFactory},
+	};
+
+	#[test]
+	fn test_validate() {
+		let config = TestEnvBuilder::config();
+		assert_eq!(config.validate(), Ok(()));
+
+		let mut config = TestEnvBuilder::config();
+		config.database.url = String::new();
+		config.database.warmup_connections = 2;
+		config.job_queue.max_attempts = 0;
+		let error = config.validate().unwrap_err();
+		assert_eq!(
+			error,
+			ConfigError(vec![
+				"database.url: is required".to_string(),
+				"database.warmup-connections: must not exceed database.max-connections".to_string(),
+				"job-queue.max-attempts: must be positive".to_string(),
+			])
+		);
+		assert_eq!(
+			error.to_string(),
+			"invalid configuration:\n- database.url: is required\n\
+			- database.warmup-connections: must not exceed database.max-connections\n\
+			- job-queue.max-attempts: must be positive"
+		);
+	}
+
+	#[tokio::test]
+	async fn test_fail_fast() {
+		let mut config = TestEnvBuilder::config();
+		config.redis.url = String::new();
+		let Err(BackendError::ConfigError(error)) =
+			BackendServices::new(config, TestingBusFactory).await
+		else {
+			panic!("invalid configuration is accepted");
+		};
+		assert_eq!(
+			error,
+			ConfigError(vec!["redis.url: is required".to_string()])
+		);
+	}
+
+	#[test]
+	fn test_override_from_env() {
+		let vars = HashMap::from([(ENV_DATABASE_URL, "sqlite://data.db")]);
+		let mut config = TestEnvBuilder::config();
+		BackendConfig::override_with(&mut config.database, &mut config.redis, |name| {
+			vars.get(name).map(|value| value.to_string())
+		});
+		assert_eq!(config.database.url, "sqlite://data.db");
+		assert_eq!(config.redis.url, "redis://127.0.0.1");
+	}
 }
@@ -20,6 +20,7 @@ use diesel_async::{
 };
 use diesel_migrations::{EmbeddedMigrations, MigrationHarness, embed_migrations};
 use futures::future::{BoxFuture, FutureExt, ready};
+use tracing::warn;
 
 pub mod schema;
 pub mod service;
@@ -60,6 +61,19 @@ impl BoxedSqlConn {
 		}
 	}
 
+	/// Executes multiple SQL statements, without any parameters.
+	///
+	/// Dispatches [SimpleConnection::batch_execute].
+	pub fn batch_execute<'conn>(
+		&'conn mut self,
+		query: &'conn str,
+	) -> BoxFuture<'conn, Result<(), diesel::result::Error>> {
+		match self {
+			BoxedSqlConn::Pg(conn) => conn.batch_execute(query).boxed(),
+			BoxedSqlConn::Sqlite(conn) => ready(conn.batch_execute(query)).boxed(),
+		}
+	}
+
 	pub fn is_broken(&mut self) -> bool {
 		match self {
 			BoxedSqlConn::Pg(conn) => conn.is_broken(),
@@ -118,8 +132,37 @@ impl BoxedSqlConn {
 			}
 		}
 	}
+
+	/// Runs `callback` in a transaction, and reruns it if the transaction fails
+	/// with a retryable error, e.g. a serialization failure or a deadlock.
+	///
+	/// The callback is retried at most [MAX_TRANSACTION_RETRIES] times,
+	/// with exponential backoff starting from 10 milliseconds.
+	pub async fn retry_on_serialization<R, F>(&mut self, mut callback: F) -> crate::Result<R>
+	where
+		F: AsyncFnMut(&mut Self) -> crate::Result<R>,
+		R: Send,
+	{
+		let mut retries = 0;
+		loop {
+			let result = self
+				.transaction::<R, crate::BackendError, _>(async |conn| callback(conn).await)
+				.await;
+			match result {
+				Err(error) if error.is_retryable() && retries < MAX_TRANSACTION_RETRIES => {
+					retries += 1;
+					warn!(%error, retries, "retrying transaction");
+					tokio::time::sleep(std::time::Duration::from_millis(10 << (retries - 1))).await;
+				}
+				result => return result,
+			}
+		}
+	}
 }
 
+/// Maximum count of retries of [BoxedSqlConn::retry_on_serialization].
+pub const MAX_TRANSACTION_RETRIES: u32 = 3;
+
 impl<'query> BoxedSqlConn {
 	/// Executes the given command, returning the number of rows affected.
 	///
@@ -357,11 +400,14 @@ pub fn run_migrations_sqlite(
 }
 
 #[cfg(test)]
-
 pub(crate) mod test {
-	use diesel::Connection;
+	use std::time::Duration;
 
-	use super::*;
+	use diesel::{Connection, SqliteConnection, sqlite::Sqlite};
+
+	// not a glob import, so that query methods resolve to those of BoxedSqlConn
+	// instead of the diesel traits imported by the parent module
+	use super::{BoxedSqlConn, MAX_TRANSACTION_RETRIES, SQLITE_MIGRATIONS, run_migrations, schema};
 
 	pub fn make_empty_test_db() -> BoxedSqlConn {
 		BoxedSqlConn::Sqlite(SqliteConnection::establish(":memory:").unwrap())
@@ -372,4 +418,289 @@ pub(crate) mod test {
 		let db = make_empty_test_db();
 		run_migrations(db).unwrap();
 	}
+
+	#[tokio::test]
+	async fn test_schema_version() {
+		let env = crate::test::test_env().await;
+		let version = env.database.schema_version().await.unwrap().unwrap();
+		let latest = diesel::migration::MigrationSource::<Sqlite>::migrations(&SQLITE_MIGRATIONS)
+			.unwrap()
+			.iter()
+			.map(|migration| migration.name().version().as_owned())
+			.max()
+			.unwrap();
+		assert_eq!(version, latest.to_string());
+	}
+
+	#[tokio::test]
+	async fn test_warmup() {
+		let env = crate::test::TestEnv::builder()
+			.with_config(|config| {
+				config.database.url = crate::test::temp_database_url();
+				config.database.max_connections = 3;
+			})
+			.build()
+			.await;
+		env.database.warmup(2).await.unwrap();
+		assert_eq!(env.database.idle_connections(), 2);
+		// capped at the maximum size of the pool
+		env.database.warmup(5).await.unwrap();
+		assert_eq!(env.database.idle_connections(), 3);
+	}
+
+	#[tokio::test]
+	async fn test_classify_errors() {
+		use diesel::{ExpressionMethods, QueryDsl, insert_into};
+
+		use crate::{BackendError, db::service::DatabaseError};
+
+		let env = crate::test::test_env().await;
+		let mut db = env.database.get().await.unwrap();
+		let insert = || {
+			insert_into(schema::branch::table).values((
+				schema::branch::name.eq("main"),
+				schema::branch::tracking.eq(0),
+			))
+		};
+		db.execute(insert()).await.unwrap();
+
+		let error = BackendError::from(db.execute(insert()).await.unwrap_err());
+		assert!(matches!(
+			error,
+			BackendError::DatabaseError(DatabaseError::UniqueViolation(_))
+		));
+		assert!(!error.is_retryable());
+
+		let error = db
+			.get_result::<_, String>(
+				schema::branch::table
+					.filter(schema::branch::name.eq("feat"))
+					.select(schema::branch::name),
+			)
+			.await
+			.unwrap_err();
+		assert!(matches!(
+			BackendError::from(error),
+			BackendError::DatabaseError(DatabaseError::NotFound)
+		));
+
+		// as reported by PostgreSQL
+		let error = diesel::result::Error::DatabaseError(
+			diesel::result::DatabaseErrorKind::Unknown,
+			Box::new("deadlock detected".to_string()),
+		);
+		let error = BackendError::from(error);
+		assert!(matches!(
+			error,
+			BackendError::DatabaseError(DatabaseError::Deadlock(_))
+		));
+		assert!(error.is_retryable());
+	}
+
+	#[tokio::test]
+	async fn test_retry_on_serialization() {
+		use diesel::{ExpressionMethods, QueryDsl, insert_into};
+
+		use crate::db::service::DatabaseError;
+
+		let env = crate::test::test_env().await;
+		let mut db = env.database.get().await.unwrap();
+		let mut runs = 0;
+		let id = db
+			.retry_on_serialization(async |conn| {
+				runs += 1;
+				let id = conn
+					.get_result::<_, i64>(
+						insert_into(schema::branch::table)
+							.values((
+								schema::branch::name.eq("main"),
+								schema::branch::tracking.eq(0),
+							))
+							.returning(schema::branch::id),
+					)
+					.await?;
+				if runs == 1 {
+					return Err(DatabaseError::SerializationFailure("injected".into()).into());
+				}
+				Ok(id)
+			})
+			.await
+			.unwrap();
+		assert_eq!(runs, 2);
+
+		// the insertion of the failed run is rolled back
+		let ids = db
+			.load::<_, i64>(schema::branch::table.select(schema::branch::id))
+			.await
+			.unwrap();
+		assert_eq!(ids, [id]);
+
+		let mut runs = 0;
+		let error = db
+			.retry_on_serialization(async |_| {
+				runs += 1;
+				Err::<(), _>(DatabaseError::SerializationFailure("injected".into()).into())
+			})
+			.await
+			.unwrap_err();
+		assert!(error.is_retryable());
+		assert_eq!(runs, MAX_TRANSACTION_RETRIES + 1);
+	}
+
+	/// Set `FABRICIA_TEST_POSTGRES_URL` to run this test on a PostgreSQL server.
+	#[tokio::test]
+	async fn test_retry_on_deadlock_postgres() {
+		use diesel::{ExpressionMethods, delete, insert_into, update};
+		use tokio::sync::Barrier;
+
+		let Ok(url) = std::env::var("FABRICIA_TEST_POSTGRES_URL") else {
+			return;
+		};
+		let env = crate::test::TestEnv::builder()
+			.with_config(move |config| {
+				config.database.url = url;
+				config.database.max_connections = 3;
+			})
+			.build()
+			.await;
+		let mut db = env.database.get().await.unwrap();
+		// the database is kept between runs
+		db.execute(
+			delete(schema::branch::table)
+				.filter(schema::branch::name.eq_any(["deadlock-1", "deadlock-2"])),
+		)
+		.await
+		.unwrap();
+		db.execute(insert_into(schema::branch::table).values(&vec![
+			(
+				schema::branch::name.eq("deadlock-1"),
+				schema::branch::tracking.eq(0),
+			),
+			(
+				schema::branch::name.eq("deadlock-2"),
+				schema::branch::tracking.eq(0),
+			),
+		]))
+		.await
+		.unwrap();
+		drop(db);
+
+		// both transactions lock one row, and then wait for the other
+		let barrier = Barrier::new(2);
+		let run = async |first: &str, second: &str| {
+			let mut db = env.database.get().await.unwrap();
+			let mut runs = 0;
+			db.retry_on_serialization(async |conn| {
+				runs += 1;
+				for (i, name) in [first, second].into_iter().enumerate() {
+					conn.execute(
+						update(schema::branch::table)
+							.filter(schema::branch::name.eq(name))
+							.set(schema::branch::tracking.eq(1)),
+					)
+					.await?;
+					if i == 0 && runs == 1 {
+						barrier.wait().await;
+					}
+				}
+				Ok(())
+			})
+			.await
+			.unwrap();
+			runs
+		};
+		let (a, b) = tokio::join!(
+			run("deadlock-1", "deadlock-2"),
+			run("deadlock-2", "deadlock-1")
+		);
+		// the transaction aborted by PostgreSQL succeeds on retry
+		assert_eq!(a + b, 3);
+	}
+
+	#[tokio::test]
+	async fn test_advisory_lock() {
+		let env = crate::test::test_env().await;
+		let db = &env.database;
+
+		let (tx, rx) = tokio::sync::oneshot::channel();
+		let first = db.with_advisory_lock(1, async move || {
+			rx.await.unwrap();
+			1
+		});
+		let second = async move {
+			let result = db.with_advisory_lock(1, async || 2).await.unwrap();
+			// another key is not blocked
+			let other = db.with_advisory_lock(2, async || 3).await.unwrap();
+			tx.send(()).unwrap();
+			(result, other)
+		};
+		let (first, second) = tokio::join!(first, second);
+		assert_eq!(first.unwrap(), Some(1));
+		assert_eq!(second, (None, Some(3)));
+
+		// the lock is released after the section
+		assert_eq!(db.with_advisory_lock(1, async || 4).await.unwrap(), Some(4));
+	}
+
+	#[tokio::test]
+	async fn test_advisory_lock_cancelled() {
+		let env = crate::test::test_env().await;
+		let db = &env.database;
+
+		let section = db.with_advisory_lock(1, async || std::future::pending::<()>().await);
+		assert!(
+			tokio::time::timeout(Duration::from_millis(50), section)
+				.await
+				.is_err()
+		);
+		assert_eq!(db.with_advisory_lock(1, async || 1).await.unwrap(), Some(1));
+	}
+
+	/// Set `FABRICIA_TEST_POSTGRES_URL` to run this test on a PostgreSQL server.
+	#[tokio::test]
+	async fn test_advisory_lock_postgres() {
+		let Ok(url) = std::env::var("FABRICIA_TEST_POSTGRES_URL") else {
+			return;
+		};
+		let env = crate::test::TestEnv::builder()
+			.with_config(move |config| {
+				config.database.url = url;
+				config.database.max_connections = 3;
+			})
+			.build()
+			.await;
+		let db = &env.database;
+
+		let (tx, rx) = tokio::sync::oneshot::channel();
+		let first = db.with_advisory_lock(1, async move || {
+			rx.await.unwrap();
+			1
+		});
+		let second = async move {
+			let result = db.with_advisory_lock(1, async || 2).await.unwrap();
+			tx.send(()).unwrap();
+			result
+		};
+		let (first, second) = tokio::join!(first, second);
+		assert_eq!(first.unwrap(), Some(1));
+		assert_eq!(second, None);
+
+		// a cancelled section closes its session, which is released by the
+		// server shortly afterwards
+		let section = db.with_advisory_lock(1, async || std::future::pending::<()>().await);
+		assert!(
+			tokio::time::timeout(Duration::from_millis(50), section)
+				.await
+				.is_err()
+		);
+		let mut result = None;
+		for _ in 0..50 {
+			result = db.with_advisory_lock(1, async || 3).await.unwrap();
+			if result.is_some() {
+				break;
+			}
+			tokio::time::sleep(Duration::from_millis(20)).await;
+		}
+		assert_eq!(result, Some(3));
+	}
 }
@@ -19,6 +19,34 @@ diesel::table! {
 		tracking -> SmallInt,
 		/// Count of tracked packages in this branch.
 		total_srcpkgs -> Int4,
+		/// Time when this branch is deleted.
+		///
+		/// Deleted branches are kept in this table, but must be
+		/// treated as non-existent.
+		deleted_at -> Nullable<Timestamp>,
+		/// Whether this branch is enabled.
+		enabled -> Bool,
+		/// Time when this branch is tracked.
+		///
+		/// This is null for branches tracked before auditing.
+		created_at -> Nullable<Timestamp>,
+		/// Time when the configuration of this branch is last updated.
+		updated_at -> Nullable<Timestamp>,
+		/// Principal tracking this branch, if known.
+		created_by -> Nullable<Varchar>,
+		/// Principal last updating the configuration of this branch, if known.
+		updated_by -> Nullable<Varchar>,
+		/// Error of the most recent failed synchronization.
+		///
+		/// This is cleared when a synchronization succeeds.
+		last_error -> Nullable<Varchar>,
+		/// Code [crate::job_queue::JobErrorCode] of `last_error`, if categorized.
+		last_error_code -> Nullable<SmallInt>,
+		/// Opaque position where the last synchronization left off.
+		///
+		/// This is read at the start of a synchronization and written at
+		/// the end, so that the next one only fetches new data.
+		sync_cursor -> Nullable<Varchar>,
 	}
 }
 
@@ -33,12 +61,154 @@ diesel::table! {
 		/// the job is enqueued.
 		id -> XUuid,
 		kind -> VarChar,
+		/// Data of this job, or null if `data_bin` is set.
 		data -> XJson,
+		/// Binary encoded data of this job.
+		///
+		/// The first byte is the format, see [crate::job_queue::JobDataEncoding].
+		data_bin -> Nullable<Binary>,
+		/// Whether `data_bin` is compressed with zstd.
+		compressed -> Bool,
 		priority -> Int2,
 		/// Started time of this job.
 		///
 		/// This column is null when and only when the job is not started.
 		started_at -> Nullable<Timestamp>,
+		/// Branch this job works on, if any.
+		branch -> Nullable<BigInt>,
+		/// Count of failed attempts of this job.
+		attempts -> Int4,
+		/// Error of the most recent failed attempt.
+		last_error -> Nullable<VarChar>,
+		/// Key to deduplicate pending and running jobs.
+		dedup_key -> Nullable<VarChar>,
+		/// W3C `traceparent` of the operation enqueuing this job.
+		trace_context -> Nullable<VarChar>,
+		/// Earliest time to retry this job after a failed attempt.
+		///
+		/// Jobs are not started before this time.
+		next_attempt_at -> Nullable<Timestamp>,
+		/// Worker running this job, if known.
+		///
+		/// This is null for pending jobs.
+		claimed_by -> Nullable<VarChar>,
+		/// Tenant owning this job, or empty if none.
+		tenant -> VarChar,
+		/// URL notified when this job finishes, see [crate::job_callback].
+		callback_url -> Nullable<VarChar>,
+		/// Ordering of this job within its priority, before the ID.
+		///
+		/// This is zero, or negative for jobs enqueued at the front.
+		seq -> BigInt,
+	}
+}
+
+diesel::table! {
+	/// Table for workers claiming jobs.
+	job_worker (id) {
+		id -> VarChar,
+		registered_at -> Timestamp,
+		/// Time of the most recent heartbeat of this worker.
+		heartbeat_at -> Timestamp,
+	}
+}
+
+diesel::table! {
+	use crate::db::utils::*;
+	use diesel::sql_types::*;
+
+	/// Table for jobs failed permanently.
+	job_dead_letter (id) {
+		/// ID of the job in [job_queue].
+		id -> XUuid,
+		kind -> VarChar,
+		data -> XJson,
+		data_bin -> Nullable<Binary>,
+		compressed -> Bool,
+		priority -> Int2,
+		/// Reason of the failure.
+		error -> VarChar,
+		/// Category [crate::job_queue::JobErrorCode] of the failure, if categorized.
+		error_code -> Nullable<Int2>,
+		failed_at -> Timestamp,
+	}
+}
+
+diesel::table! {
+	use crate::db::utils::*;
+	use diesel::sql_types::*;
+
+	/// Table for dependencies between jobs.
+	///
+	/// A job is not started until all jobs it depends on are finished.
+	/// Rows are removed when the depended job finishes.
+	job_dependency (job, depends_on) {
+		job -> XUuid,
+		depends_on -> XUuid,
+	}
+}
+
+diesel::table! {
+	use crate::db::utils::*;
+	use diesel::sql_types::*;
+
+	/// Table for attempts of jobs.
+	///
+	/// Rows are kept after the job finishes.
+	job_attempt (job, attempt) {
+		job -> XUuid,
+		/// Number of this attempt, starting from 1.
+		attempt -> Int4,
+		started_at -> Timestamp,
+		finished_at -> Nullable<Timestamp>,
+		/// Outcome [crate::job_queue::AttemptOutcome].
+		outcome -> Int2,
+		error -> Nullable<VarChar>,
+		/// Category [crate::job_queue::JobErrorCode] of the error, if categorized.
+		error_code -> Nullable<Int2>,
+	}
+}
+
+diesel::table! {
+	use crate::db::utils::*;
+	use diesel::sql_types::*;
+
+	/// Table for finished jobs, archived by [crate::job_queue::FinishPolicy::Archive].
+	job_history (id) {
+		/// ID of the job in [job_queue].
+		id -> XUuid,
+		kind -> VarChar,
+		data -> XJson,
+		data_bin -> Nullable<Binary>,
+		compressed -> Bool,
+		priority -> Int2,
+		/// Outcome [crate::job_queue::AttemptOutcome] of the last attempt.
+		outcome -> Int2,
+		finished_at -> Timestamp,
+	}
+}
+
+diesel::table! {
+	/// Table for labels of branches.
+	///
+	/// A label is either a plain tag or a `key=value` pair.
+	branch_label (branch, label) {
+		branch -> BigInt,
+		label -> Varchar,
+	}
+}
+
+diesel::table! {
+	use crate::db::utils::*;
+	use diesel::sql_types::*;
+
+	/// Table for idempotency keys of jobs.
+	///
+	/// Rows are kept after the job finishes, until pruned.
+	job_idempotency (idempotency_key) {
+		idempotency_key -> VarChar,
+		job -> XUuid,
+		created_at -> Timestamp,
 	}
 }
 
@@ -75,3 +245,6 @@ diesel::table! {
 		data -> XJson,
 	}
 }
+
+diesel::allow_tables_to_appear_in_same_query!(job_queue, job_dependency);
+diesel::allow_tables_to_appear_in_same_query!(branch, branch_label);
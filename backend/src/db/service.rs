@@ -1,8 +1,19 @@
-use std::fmt::Debug;
+use std::{
+	collections::HashSet,
+	fmt::Debug,
+	sync::{
+		Mutex,
+		atomic::{AtomicBool, Ordering},
+	},
+};
 
 use deadpool::managed::{Manager, Object, Pool, PoolError, RecycleError, RecycleResult};
-use diesel::{Connection, ConnectionError, SqliteConnection};
+use diesel::{
+	Connection, ConnectionError, QueryableByName, SqliteConnection, sql_query,
+	sql_types::{BigInt, Bool, Nullable, Text},
+};
 use diesel_async::{AsyncConnection, AsyncPgConnection};
+use futures::future::try_join_all;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use time::Duration;
@@ -29,15 +40,29 @@ pub struct DatabaseConfig {
 	/// When using `sqlite://:memory:`, this must be set to 1.
 	#[serde(default = "default_max_conns")]
 	pub max_connections: usize,
+	/// The number of connections opened on startup, see [`DatabaseService::warmup`].
+	#[serde(default)]
+	pub warmup_connections: usize,
+	/// Time in seconds to wait for connections in use, when closing the pool.
+	#[serde(default = "default_close_timeout")]
+	pub close_timeout: u64,
 }
 
 fn default_max_conns() -> usize {
 	3
 }
 
+fn default_close_timeout() -> u64 {
+	30
+}
+
 /// Database connection service.
 pub struct DatabaseService {
 	pool: Pool<SqlConnectionManager>,
+	/// Set when closing, so that no connections are handed out meanwhile.
+	closing: AtomicBool,
+	/// Advisory locks held in this process, for databases without advisory locks.
+	local_locks: Mutex<HashSet<i64>>,
 }
 
 impl DatabaseService {
@@ -64,13 +89,17 @@ impl DatabaseService {
 			info!("database migrations completed");
 		}
 
-		let db = Self { pool };
+		let db = Self {
+			pool,
+			closing: AtomicBool::new(false),
+			local_locks: Mutex::new(HashSet::new()),
+		};
 
 		// for tests, the above migrations are not enough
 		// because in memory SQLite database get cleared
 		// after re-establishing the connection
 		#[cfg(test)]
-		{
+		if !db.is_postgres() {
 			let mut conn = db.get().await?;
 			super::run_migrations_sqlite(&mut conn).map_err(DatabaseError::MigrationError)?;
 		}
@@ -79,8 +108,197 @@ impl DatabaseService {
 	}
 
 	pub async fn get(&self) -> Result<SqlConnRef> {
+		if self.closing.load(Ordering::Acquire) {
+			return Err(DatabaseError::PoolError(PoolError::Closed).into());
+		}
 		Ok(self.pool.get().await.map_err(DatabaseError::from)?)
 	}
+
+	/// Opens up to `n` connections in advance, so that early requests
+	/// do not wait for connecting.
+	///
+	/// `n` is capped at [`DatabaseConfig::max_connections`].
+	pub async fn warmup(&self, n: usize) -> Result<()> {
+		let n = n.min(self.pool.status().max_size);
+		// connections are held together, so that each get opens a new one
+		let conns = try_join_all((0..n).map(|_| self.get())).await?;
+		drop(conns);
+		info!(n, "database connections warmed up");
+		Ok(())
+	}
+
+	/// Returns the count of open connections not in use.
+	pub fn idle_connections(&self) -> usize {
+		self.pool.status().available
+	}
+
+	/// Stops handing out connections, waits for all connections in use to be
+	/// returned, then closes the pool.
+	///
+	/// If connections are still in use after [`DatabaseConfig::close_timeout`],
+	/// the pool is closed anyway and [`DatabaseError::CloseTimeout`] is returned.
+	/// After closing, acquiring connections fails.
+	pub async fn close(&self) -> Result<()> {
+		self.closing.store(true, Ordering::Release);
+		let timeout = std::time::Duration::from_secs(self.config().close_timeout);
+		let returned = tokio::time::timeout(timeout, async {
+			loop {
+				let status = self.pool.status();
+				if status.available == status.size {
+					break;
+				}
+				tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+			}
+		})
+		.await;
+		self.pool.close();
+		if returned.is_err() {
+			let status = self.pool.status();
+			let in_use = status.size.saturating_sub(status.available);
+			warn!(
+				in_use,
+				"database connections not returned, pool closed anyway"
+			);
+			return Err(DatabaseError::CloseTimeout(in_use).into());
+		}
+		info!("database connection pool closed");
+		Ok(())
+	}
+
+	/// Checks if the pool is closed.
+	pub fn is_closed(&self) -> bool {
+		self.pool.is_closed()
+	}
+
+	pub fn config(&self) -> &DatabaseConfig {
+		&self.pool.manager().0
+	}
+
+	/// Checks if the database is PostgreSQL.
+	pub fn is_postgres(&self) -> bool {
+		let url = &self.config().url;
+		url.starts_with("postgresql://") || url.starts_with("postgres://")
+	}
+
+	/// Returns the version of the latest applied migration.
+	pub async fn schema_version(&self) -> Result<Option<String>> {
+		#[derive(QueryableByName)]
+		struct SchemaVersion {
+			#[diesel(sql_type = Nullable<Text>)]
+			version: Option<String>,
+		}
+
+		let mut conn = self.get().await?;
+		let result = conn
+			.get_result::<_, SchemaVersion>(sql_query(
+				"SELECT MAX(version) AS version FROM __diesel_schema_migrations",
+			))
+			.await?;
+		Ok(result.version)
+	}
+
+	/// Runs `f` only if the advisory lock `key` can be acquired, and releases
+	/// the lock afterwards.
+	///
+	/// This is for tasks which must run on only one instance at a time.
+	/// Returns `None` without running `f` if the lock is held by others.
+	///
+	/// With PostgreSQL, `pg_try_advisory_lock` is used, and a connection is
+	/// held while running `f`. If `f` panics or the future is cancelled, the
+	/// connection is closed to release the lock. Other databases are not shared
+	/// between instances, so the lock is only exclusive in this process.
+	pub async fn with_advisory_lock<F, R>(&self, key: i64, f: F) -> Result<Option<R>>
+	where
+		F: AsyncFnOnce() -> R,
+	{
+		if !self.is_postgres() {
+			let Some(_guard) = LocalLockGuard::acquire(&self.local_locks, key) else {
+				return Ok(None);
+			};
+			return Ok(Some(f().await));
+		}
+
+		#[derive(QueryableByName)]
+		struct AdvisoryLock {
+			#[diesel(sql_type = Bool)]
+			locked: bool,
+		}
+
+		let mut conn = self.get().await?;
+		let lock = conn
+			.get_result::<_, AdvisoryLock>(
+				sql_query("SELECT pg_try_advisory_lock($1) AS locked").bind::<BigInt, _>(key),
+			)
+			.await?;
+		if !lock.locked {
+			return Ok(None);
+		}
+
+		let mut guard = PgLockGuard(Some(conn));
+		let result = f().await;
+		let unlock = guard
+			.conn()
+			.get_result::<_, AdvisoryLock>(
+				sql_query("SELECT pg_advisory_unlock($1) AS locked").bind::<BigInt, _>(key),
+			)
+			.await;
+		match unlock {
+			Ok(_) => guard.release(),
+			Err(error) => {
+				warn!(key, %error, "failed to release advisory lock, dropping the connection")
+			}
+		}
+		Ok(Some(result))
+	}
+}
+
+/// A PostgreSQL session holding advisory locks.
+///
+/// Unless [`PgLockGuard::release`] is called after unlocking, the connection
+/// is closed on drop, as closing the session releases all its advisory locks.
+struct PgLockGuard(Option<SqlConnRef>);
+
+impl PgLockGuard {
+	fn conn(&mut self) -> &mut SqlConnRef {
+		self.0.as_mut().unwrap()
+	}
+
+	/// Returns the connection to the pool.
+	fn release(mut self) {
+		self.0.take();
+	}
+}
+
+impl Drop for PgLockGuard {
+	fn drop(&mut self) {
+		if let Some(conn) = self.0.take() {
+			drop(Object::take(conn));
+		}
+	}
+}
+
+/// A process-local advisory lock, released on drop.
+struct LocalLockGuard<'a> {
+	locks: &'a Mutex<HashSet<i64>>,
+	key: i64,
+}
+
+impl<'a> LocalLockGuard<'a> {
+	fn acquire(locks: &'a Mutex<HashSet<i64>>, key: i64) -> Option<Self> {
+		// the guard must not be built while the set is locked, as dropping it
+		// locks the set again
+		if locks.lock().unwrap().insert(key) {
+			Some(Self { locks, key })
+		} else {
+			None
+		}
+	}
+}
+
+impl Drop for LocalLockGuard<'_> {
+	fn drop(&mut self) {
+		self.locks.lock().unwrap().remove(&self.key);
+	}
 }
 
 impl Debug for DatabaseService {
@@ -129,7 +347,7 @@ impl Manager for SqlConnectionManager {
 			if std::thread::panicking() || obj.is_broken() {
 				return Err(RecycleError::Message("Broken connection".into()));
 			}
-			obj.ping().await.map_err(DatabaseError::QueryError)?;
+			obj.ping().await.map_err(DatabaseError::from)?;
 			Ok(())
 		}
 	}
@@ -140,11 +358,25 @@ pub enum DatabaseError {
 	#[error("connection error: {0}")]
 	ConnectionError(#[from] ConnectionError),
 	#[error("query error: {0}")]
-	QueryError(#[from] diesel::result::Error),
+	QueryError(diesel::result::Error),
+	#[error("record not found")]
+	NotFound,
+	#[error("unique constraint violated: {0}")]
+	UniqueViolation(String),
+	#[error("foreign key constraint violated: {0}")]
+	ForeignKeyViolation(String),
+	/// The transaction conflicted with a concurrent one, and may succeed on retry.
+	#[error("serialization failure: {0}")]
+	SerializationFailure(String),
+	/// The transaction was aborted to resolve a deadlock, and may succeed on retry.
+	#[error("deadlock detected: {0}")]
+	Deadlock(String),
 	#[error("connection pool error: {0:?}")]
 	PoolError(PoolError<()>),
 	#[error("connection pool build error: {0}")]
 	PoolBuildError(#[from] deadpool::managed::BuildError),
+	#[error("{0} connections still in use when closing the pool")]
+	CloseTimeout(usize),
 	#[error("async-await joining error: {0}")]
 	JoinError(#[from] tokio::task::JoinError),
 	#[error("failed to apply migration: {0}")]
@@ -154,6 +386,42 @@ pub enum DatabaseError {
 	UnknownUrlSchema(String),
 }
 
+impl DatabaseError {
+	/// Checks if the failed operation may succeed on retry.
+	pub fn is_retryable(&self) -> bool {
+		matches!(self, Self::SerializationFailure(_) | Self::Deadlock(_))
+	}
+}
+
+/// The message of PostgreSQL errors with SQLSTATE 40P01.
+const PG_DEADLOCK_MESSAGE: &str = "deadlock detected";
+
+/// Classifies common query errors, so that callers need not inspect them.
+impl From<diesel::result::Error> for DatabaseError {
+	fn from(value: diesel::result::Error) -> Self {
+		use diesel::result::{DatabaseErrorKind, Error};
+
+		match value {
+			Error::NotFound => Self::NotFound,
+			Error::DatabaseError(kind, info) => {
+				let message = info.message().to_string();
+				match kind {
+					DatabaseErrorKind::UniqueViolation => Self::UniqueViolation(message),
+					DatabaseErrorKind::ForeignKeyViolation => Self::ForeignKeyViolation(message),
+					DatabaseErrorKind::SerializationFailure => Self::SerializationFailure(message),
+					// diesel does not expose SQLSTATE, and reports deadlocks (40P01)
+					// of PostgreSQL as unknown errors
+					DatabaseErrorKind::Unknown if message == PG_DEADLOCK_MESSAGE => {
+						Self::Deadlock(info.details().unwrap_or(&message).to_string())
+					}
+					kind => Self::QueryError(Error::DatabaseError(kind, info)),
+				}
+			}
+			error => Self::QueryError(error),
+		}
+	}
+}
+
 impl From<PoolError<DatabaseError>> for DatabaseError {
 	fn from(value: PoolError<DatabaseError>) -> Self {
 		Self::PoolError(match value {
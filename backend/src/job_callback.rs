@@ -0,0 +1,325 @@
+//! Completion callbacks of jobs.
+//!
+//! Jobs enqueued with [`EnqueueOptions::callback_url`](crate::job_queue::EnqueueOptions::callback_url)
+//! are reported to the URL with a `POST` request once they finish, either
+//! successfully, skipped, or dead-lettered. Continued jobs are reported when
+//! the last continuation finishes.
+
+use std::{collections::BTreeSet, fmt::Debug, sync::Arc, time::Duration};
+
+use futures::{FutureExt, future::BoxFuture};
+use hmac::{Hmac, Mac};
+use reqwest::{Url, header::CONTENT_TYPE, redirect};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tracing::{info, warn};
+
+use crate::job_queue::{AttemptOutcome, JobQueueError, JobRef};
+
+/// Header of signatures of callback bodies.
+///
+/// The value is `sha256=` followed by the hex-encoded HMAC-SHA256 of the body,
+/// keyed with [`JobQueueConfig::callback_secret`](crate::job_queue::JobQueueConfig::callback_secret).
+pub const SIGNATURE_HEADER: &str = "x-fabricia-signature";
+
+/// Body of a callback, as JSON.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CallbackBody {
+	pub id: JobRef,
+	pub kind: String,
+	pub outcome: CallbackOutcome,
+	/// Error of the last attempt, if the job did not succeed.
+	pub error: Option<String>,
+}
+
+/// How a job finished.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CallbackOutcome {
+	Succeeded,
+	Skipped,
+	/// The job failed, and has been dead-lettered.
+	Failed,
+}
+
+impl From<AttemptOutcome> for CallbackOutcome {
+	fn from(value: AttemptOutcome) -> Self {
+		match value {
+			AttemptOutcome::Succeeded => Self::Succeeded,
+			AttemptOutcome::Skipped => Self::Skipped,
+			AttemptOutcome::Running | AttemptOutcome::Failed => Self::Failed,
+		}
+	}
+}
+
+/// A callback to be sent once the transaction finishing the job is done.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) struct PendingCallback {
+	pub url: String,
+	pub body: CallbackBody,
+}
+
+/// Sender of callback requests.
+pub trait CallbackSender
+where
+	Self: Send + Sync + Debug,
+{
+	/// Posts a JSON body to the URL, with the signature if any.
+	///
+	/// Returns an error if the request fails or the response is not successful.
+	fn post(
+		&self,
+		url: &str,
+		body: Vec<u8>,
+		signature: Option<String>,
+	) -> BoxFuture<'static, Result<(), String>>;
+}
+
+/// [CallbackSender] with an HTTP client.
+///
+/// Redirects are not followed, so that callbacks never reach hosts
+/// outside of [`JobQueueConfig::callback_hosts`](crate::job_queue::JobQueueConfig::callback_hosts).
+#[derive(Debug, Clone)]
+pub struct HttpCallbackSender {
+	http: reqwest::Client,
+}
+
+impl HttpCallbackSender {
+	pub fn new() -> Self {
+		let http = reqwest::Client::builder()
+			.redirect(redirect::Policy::none())
+			.timeout(Duration::from_secs(10))
+			.build()
+			.expect("failed to build HTTP client");
+		Self { http }
+	}
+}
+
+impl Default for HttpCallbackSender {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl CallbackSender for HttpCallbackSender {
+	fn post(
+		&self,
+		url: &str,
+		body: Vec<u8>,
+		signature: Option<String>,
+	) -> BoxFuture<'static, Result<(), String>> {
+		let mut request = self
+			.http
+			.post(url)
+			.header(CONTENT_TYPE, "application/json")
+			.body(body);
+		if let Some(signature) = signature {
+			request = request.header(SIGNATURE_HEADER, signature);
+		}
+		async move {
+			request
+				.send()
+				.await
+				.and_then(|response| response.error_for_status())
+				.map_err(|error| error.to_string())?;
+			Ok(())
+		}
+		.boxed()
+	}
+}
+
+/// Checks if a callback URL is an HTTP(S) URL of an allowed host.
+///
+/// No host is allowed if `allowed_hosts` is empty.
+pub fn check_url(url: &str, allowed_hosts: &BTreeSet<String>) -> Result<(), JobQueueError> {
+	let parsed = Url::parse(url)
+		.map_err(|error| JobQueueError::CallbackNotAllowed(format!("{url}: {error}")))?;
+	if !matches!(parsed.scheme(), "http" | "https") {
+		return Err(JobQueueError::CallbackNotAllowed(format!(
+			"{url}: scheme is not HTTP"
+		)));
+	}
+	match parsed.host_str() {
+		Some(host) if allowed_hosts.contains(host) => Ok(()),
+		_ => Err(JobQueueError::CallbackNotAllowed(format!(
+			"{url}: host is not allowed"
+		))),
+	}
+}
+
+/// Signs a body, returning the value of [SIGNATURE_HEADER].
+pub fn sign(secret: &str, body: &[u8]) -> String {
+	let mut mac =
+		Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+	mac.update(body);
+	format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Sends a callback in the background.
+///
+/// Failed deliveries are retried up to `retries` times, with the delay
+/// doubled after each retry.
+pub(crate) fn spawn_delivery(
+	sender: Arc<dyn CallbackSender>,
+	callback: PendingCallback,
+	secret: Option<&str>,
+	retries: u32,
+	mut retry_delay: Duration,
+) {
+	let body = match serde_json::to_vec(&callback.body) {
+		Ok(body) => body,
+		Err(error) => {
+			warn!(id = %callback.body.id, %error, "failed to encode job callback");
+			return;
+		}
+	};
+	let signature = secret.map(|secret| sign(secret, &body));
+	tokio::spawn(async move {
+		let id = callback.body.id;
+		for attempt in 0..=retries {
+			match sender
+				.post(&callback.url, body.clone(), signature.clone())
+				.await
+			{
+				Ok(()) => {
+					info!(%id, url = callback.url, "sent job callback");
+					return;
+				}
+				Err(error) => {
+					warn!(%id, url = callback.url, attempt, error, "failed to send job callback");
+				}
+			}
+			if attempt < retries {
+				tokio::time::sleep(retry_delay).await;
+				retry_delay = retry_delay.saturating_mul(2);
+			}
+		}
+		warn!(%id, url = callback.url, "gave up sending job callback");
+	});
+}
+
+#[cfg(test)]
+mod test {
+	use std::collections::BTreeSet;
+
+	use axum::{Router, body::Bytes, extract::State, http::HeaderMap, routing::post};
+	use tokio::{net::TcpListener, sync::mpsc};
+
+	use crate::{
+		BackendError,
+		job_callback::{CallbackBody, CallbackOutcome, SIGNATURE_HEADER, check_url, sign},
+		job_queue::{EnqueueOptions, JobCommand, JobQueue, JobQueueConfig, JobQueueError},
+		test::test_env,
+	};
+
+	type Received = (Bytes, Option<String>);
+
+	async fn record(
+		State(tx): State<mpsc::UnboundedSender<Received>>,
+		headers: HeaderMap,
+		body: Bytes,
+	) {
+		let signature = headers
+			.get(SIGNATURE_HEADER)
+			.and_then(|value| value.to_str().ok())
+			.map(str::to_string);
+		tx.send((body, signature)).unwrap();
+	}
+
+	/// Starts a server recording bodies and signatures of callbacks.
+	async fn mock_server() -> (String, mpsc::UnboundedReceiver<Received>) {
+		let (tx, rx) = mpsc::unbounded_channel();
+		let app = Router::new().route("/done", post(record)).with_state(tx);
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let url = format!("http://{}/done", listener.local_addr().unwrap());
+		tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+		(url, rx)
+	}
+
+	#[test]
+	fn test_check_url() {
+		let hosts = BTreeSet::from(["hooks.example.com".to_string()]);
+		assert!(check_url("https://hooks.example.com/done", &hosts).is_ok());
+		assert!(check_url("http://hooks.example.com:8080/done", &hosts).is_ok());
+		for url in [
+			"https://internal.example.com/done",
+			"ftp://hooks.example.com/done",
+			"not a url",
+		] {
+			assert!(matches!(
+				check_url(url, &hosts),
+				Err(JobQueueError::CallbackNotAllowed(_))
+			));
+		}
+		assert!(check_url("https://hooks.example.com/done", &BTreeSet::new()).is_err());
+	}
+
+	#[tokio::test]
+	async fn test_callback() {
+		let (url, mut rx) = mock_server().await;
+		let env = test_env().await;
+		let config = JobQueueConfig {
+			callback_hosts: BTreeSet::from(["127.0.0.1".to_string()]),
+			callback_secret: Some("secret".to_string()),
+			max_attempts: 1,
+			..Default::default()
+		};
+		let jq = JobQueue::new(env.database.clone(), config);
+
+		let mut db = env.database.get().await.unwrap();
+		let options = EnqueueOptions {
+			callback_url: Some("http://10.0.0.1/done".to_string()),
+			..Default::default()
+		};
+		assert!(matches!(
+			jq.enqueue_with(&mut db, JobCommand::sync_branch(1), options)
+				.await,
+			Err(BackendError::JobQueueError(
+				JobQueueError::CallbackNotAllowed(_)
+			))
+		));
+		for branch in [1, 2] {
+			let options = EnqueueOptions {
+				callback_url: Some(url.clone()),
+				..Default::default()
+			};
+			jq.enqueue_with(&mut db, JobCommand::sync_branch(branch), options)
+				.await
+				.unwrap();
+		}
+		drop(db);
+
+		let finished = jq.fetch_and_start().await.unwrap().unwrap();
+		let failed = jq.fetch_and_start().await.unwrap().unwrap();
+		let mut db = env.database.get().await.unwrap();
+		jq.finish_job(&mut db, finished.id).await.unwrap();
+		jq.fail_job(&mut db, failed.id, "boom").await.unwrap();
+		drop(db);
+
+		let mut bodies = Vec::new();
+		for _ in 0..2 {
+			let (body, signature) = rx.recv().await.unwrap();
+			assert_eq!(signature, Some(sign("secret", &body)));
+			bodies.push(serde_json::from_slice::<CallbackBody>(&body).unwrap());
+		}
+		bodies.sort_by_key(|body| body.id);
+		assert_eq!(
+			bodies,
+			[
+				CallbackBody {
+					id: finished.id,
+					kind: "SyncBranch".to_string(),
+					outcome: CallbackOutcome::Succeeded,
+					error: None,
+				},
+				CallbackBody {
+					id: failed.id,
+					kind: "SyncBranch".to_string(),
+					outcome: CallbackOutcome::Failed,
+					error: Some("boom".to_string()),
+				},
+			]
+		);
+	}
+}
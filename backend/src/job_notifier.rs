@@ -0,0 +1,435 @@
+//! Wakeup mechanism of job workers.
+
+use std::{fmt::Debug, sync::Arc, time::Duration};
+
+use diesel::{sql_query, sql_types::Text};
+use futures::{
+	FutureExt, StreamExt,
+	future::{BoxFuture, ready},
+	stream::{self, BoxStream},
+};
+use kstring::KString;
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio_postgres::{AsyncMessage, NoTls};
+use tracing::{info, warn};
+
+use crate::{
+	Result,
+	db::{BoxedSqlConn, service::DatabaseService},
+	job_queue::JobQueueError,
+};
+
+/// Kind notified when jobs of any kind may have been enqueued.
+///
+/// This is yielded by subscriptions after notifications may have been missed,
+/// e.g. on reconnecting, so that workers poll the queue immediately.
+pub const ANY_KIND: &str = "*";
+
+/// Notifies job workers of newly enqueued jobs.
+///
+/// Notifications are hints for workers to poll the job queue.
+/// They may be lost or duplicated, so workers should still poll periodically.
+pub trait JobNotifier
+where
+	Self: Send + Sync + Debug,
+{
+	/// Notifies subscribers that a job of `kind` is enqueued on `conn`.
+	///
+	/// If `conn` is in a transaction, notifiers sending through the database
+	/// should send on it, so that the notification is delivered only if the
+	/// job is committed.
+	fn notify<'a>(&'a self, conn: &'a mut BoxedSqlConn, kind: &str) -> BoxFuture<'a, Result<()>>;
+
+	/// Subscribes to notifications.
+	///
+	/// Returns a stream of kinds of enqueued jobs, or [ANY_KIND].
+	fn subscribe(&self) -> BoxFuture<'_, Result<BoxStream<'static, KString>>>;
+
+	/// Waits for sent notifications to be delivered.
+	///
+	/// This is called on shutdown. By default, notifications are sent
+	/// before [JobNotifier::notify] returns, so there is nothing to wait for.
+	fn flush(&self) -> BoxFuture<'_, Result<()>> {
+		ready(Ok(())).boxed()
+	}
+}
+
+/// In-process [JobNotifier].
+///
+/// Only subscribers in the same process are notified.
+#[derive(Debug, Clone)]
+pub struct LocalJobNotifier {
+	sender: broadcast::Sender<KString>,
+}
+
+impl LocalJobNotifier {
+	pub fn new() -> Self {
+		Self {
+			sender: broadcast::Sender::new(64),
+		}
+	}
+}
+
+impl Default for LocalJobNotifier {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl JobNotifier for LocalJobNotifier {
+	fn notify<'a>(&'a self, _: &'a mut BoxedSqlConn, kind: &str) -> BoxFuture<'a, Result<()>> {
+		// no subscribers is not an error
+		_ = self.sender.send(KString::from_ref(kind));
+		ready(Ok(())).boxed()
+	}
+
+	fn subscribe(&self) -> BoxFuture<'_, Result<BoxStream<'static, KString>>> {
+		let receiver = self.sender.subscribe();
+		let stream = stream::unfold(receiver, |mut receiver| async move {
+			loop {
+				match receiver.recv().await {
+					Ok(kind) => return Some((kind, receiver)),
+					// a missed notification is fine, as long as the subscriber wakes up
+					Err(broadcast::error::RecvError::Lagged(_)) => continue,
+					Err(broadcast::error::RecvError::Closed) => return None,
+				}
+			}
+		});
+		ready(Ok(stream.boxed())).boxed()
+	}
+}
+
+/// State of the connection of a [PgJobNotifier] subscription.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ListenState {
+	/// Connecting, or waiting to reconnect.
+	Connecting,
+	/// Listening to notifications.
+	Listening,
+	/// There is no subscription.
+	Closed,
+}
+
+/// PostgreSQL [JobNotifier] with `LISTEN` and `NOTIFY`.
+///
+/// Notifications are sent on the connection enqueuing the job, so jobs
+/// enqueued in a rolled back transaction are never notified.
+///
+/// Subscriptions reconnect with exponential backoff when the connection is lost,
+/// e.g. on database restarts, and yield [ANY_KIND] once reconnected.
+#[derive(Debug)]
+pub struct PgJobNotifier {
+	db: Arc<DatabaseService>,
+	state: watch::Sender<ListenState>,
+	backoff: (Duration, Duration),
+}
+
+impl PgJobNotifier {
+	/// Channel of notifications.
+	pub const CHANNEL: &str = "fabricia_job";
+
+	pub fn new(db: Arc<DatabaseService>) -> Self {
+		Self {
+			db,
+			state: watch::Sender::new(ListenState::Closed),
+			backoff: (Duration::from_millis(500), Duration::from_secs(30)),
+		}
+	}
+
+	/// Sets the initial and maximum delays between reconnecting.
+	pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+		self.backoff = (initial, max);
+		self
+	}
+
+	/// Returns the state of the connection of the most recent subscription.
+	pub fn state(&self) -> ListenState {
+		*self.state.borrow()
+	}
+
+	/// Watches changes of [PgJobNotifier::state].
+	pub fn watch_state(&self) -> watch::Receiver<ListenState> {
+		self.state.subscribe()
+	}
+
+	/// Connects and listens to notifications.
+	///
+	/// The returned stream ends when the connection is lost.
+	async fn connect(url: &str) -> Result<BoxStream<'static, KString>> {
+		// connections in the pool are shared, so a dedicated one is used
+		let (client, mut connection) = tokio_postgres::connect(url, NoTls)
+			.await
+			.map_err(|error| JobQueueError::Notifier(error.to_string()))?;
+
+		let (sender, receiver) = mpsc::unbounded_channel();
+		let mut messages = stream::poll_fn(move |cx| connection.poll_message(cx));
+		tokio::spawn(async move {
+			while let Some(message) = messages.next().await {
+				match message {
+					Ok(AsyncMessage::Notification(notification)) => {
+						let kind = KString::from_ref(notification.payload());
+						if sender.send(kind).is_err() {
+							break;
+						}
+					}
+					Ok(_) => {}
+					Err(error) => {
+						warn!(%error, "job notification connection failed");
+						break;
+					}
+				}
+			}
+		});
+
+		client
+			.batch_execute(&format!("LISTEN {}", Self::CHANNEL))
+			.await
+			.map_err(|error| JobQueueError::Notifier(error.to_string()))?;
+
+		// the client is kept alive with the stream
+		let stream = stream::unfold((receiver, client), |(mut receiver, client)| async move {
+			let kind = receiver.recv().await?;
+			Some((kind, (receiver, client)))
+		});
+		Ok(stream.boxed())
+	}
+}
+
+impl JobNotifier for PgJobNotifier {
+	fn notify<'a>(&'a self, conn: &'a mut BoxedSqlConn, kind: &str) -> BoxFuture<'a, Result<()>> {
+		let kind = kind.to_owned();
+		async move {
+			// notifications in a transaction are delivered on commit
+			conn.execute(
+				sql_query("SELECT pg_notify($1, $2)")
+					.bind::<Text, _>(Self::CHANNEL)
+					.bind::<Text, _>(kind),
+			)
+			.await?;
+			Ok(())
+		}
+		.boxed()
+	}
+
+	fn subscribe(&self) -> BoxFuture<'_, Result<BoxStream<'static, KString>>> {
+		async move {
+			let url = self.db.config().url.clone();
+			// the first connection is established before returning,
+			// so that configuration errors are reported to the caller
+			self.state.send_replace(ListenState::Connecting);
+			let first = Self::connect(&url)
+				.await
+				.inspect_err(|_| _ = self.state.send_replace(ListenState::Closed))?;
+			info!("subscribed to job notifications");
+			let connect = move || {
+				let url = url.clone();
+				async move { Self::connect(&url).await }.boxed()
+			};
+			Ok(reconnecting(
+				first,
+				connect,
+				self.backoff,
+				self.state.clone(),
+			))
+		}
+		.boxed()
+	}
+}
+
+/// Forwards notifications from `first`, and from new connections made with
+/// `connect` whenever the previous connection is lost.
+///
+/// Failed connections are retried with exponential backoff between `backoff.0`
+/// and `backoff.1`. [ANY_KIND] is yielded after each reconnection.
+fn reconnecting<C>(
+	first: BoxStream<'static, KString>,
+	mut connect: C,
+	backoff: (Duration, Duration),
+	state: watch::Sender<ListenState>,
+) -> BoxStream<'static, KString>
+where
+	C: FnMut() -> BoxFuture<'static, Result<BoxStream<'static, KString>>> + Send + 'static,
+{
+	let (sender, receiver) = mpsc::unbounded_channel();
+	tokio::spawn(async move {
+		let mut connection = first;
+		state.send_replace(ListenState::Listening);
+		loop {
+			while let Some(kind) = connection.next().await {
+				if sender.send(kind).is_err() {
+					state.send_replace(ListenState::Closed);
+					return;
+				}
+			}
+			warn!("job notification connection lost, reconnecting");
+			state.send_replace(ListenState::Connecting);
+
+			let mut delay = backoff.0;
+			connection = loop {
+				tokio::time::sleep(delay).await;
+				if sender.is_closed() {
+					state.send_replace(ListenState::Closed);
+					return;
+				}
+				match connect().await {
+					Ok(connection) => break connection,
+					Err(error) => {
+						warn!(%error, ?delay, "failed to reconnect job notifications");
+						delay = (delay * 2).min(backoff.1);
+					}
+				}
+			};
+			info!("reconnected job notifications");
+			state.send_replace(ListenState::Listening);
+			// notifications may have been missed while disconnected
+			if sender.send(KString::from_static(ANY_KIND)).is_err() {
+				state.send_replace(ListenState::Closed);
+				return;
+			}
+		}
+	});
+
+	stream::unfold(receiver, |mut receiver| async move {
+		let kind = receiver.recv().await?;
+		Some((kind, receiver))
+	})
+	.boxed()
+}
+
+#[cfg(test)]
+mod test {
+	use std::{
+		collections::VecDeque,
+		sync::{Arc, Mutex},
+		time::Duration,
+	};
+
+	use futures::{
+		FutureExt, StreamExt,
+		stream::{self, BoxStream},
+	};
+	use kstring::KString;
+	use tokio::sync::{mpsc, watch};
+
+	use crate::{
+		BackendError,
+		job_notifier::{ANY_KIND, ListenState, reconnecting},
+		job_queue::{JobCommand, JobQueueError},
+		test::test_env,
+	};
+
+	#[tokio::test]
+	async fn test_local_notifier() {
+		let env = test_env().await;
+		let mut subscriber = env.job_queue.notifier().subscribe().await.unwrap();
+
+		let mut db = env.database.get().await.unwrap();
+		env.job_queue
+			.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+
+		let kind = tokio::time::timeout(Duration::from_secs(1), subscriber.next())
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(kind, "SyncBranch");
+	}
+
+	/// Set `FABRICIA_TEST_POSTGRES_URL` to run this test on a PostgreSQL server.
+	#[tokio::test]
+	async fn test_pg_notifier_transaction() {
+		let Ok(url) = std::env::var("FABRICIA_TEST_POSTGRES_URL") else {
+			return;
+		};
+		let env = crate::test::TestEnv::builder()
+			.with_config(move |config| {
+				config.database.url = url;
+				config.database.max_connections = 3;
+			})
+			.build()
+			.await;
+		let mut subscriber = env.job_queue.notifier().subscribe().await.unwrap();
+		let mut db = env.database.get().await.unwrap();
+
+		// jobs of rolled back transactions are not notified
+		let result = db
+			.transaction::<(), BackendError, _>(async |conn| {
+				env.job_queue
+					.enqueue(conn, JobCommand::sync_branch(1))
+					.await?;
+				Err(JobQueueError::Draining.into())
+			})
+			.await;
+		assert!(result.is_err());
+		let notified = tokio::time::timeout(Duration::from_millis(200), subscriber.next()).await;
+		assert!(notified.is_err());
+
+		db.transaction::<(), BackendError, _>(async |conn| {
+			env.job_queue
+				.enqueue(conn, JobCommand::sync_branch(1))
+				.await?;
+			Ok(())
+		})
+		.await
+		.unwrap();
+		let kind = tokio::time::timeout(Duration::from_secs(1), subscriber.next())
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(kind, "SyncBranch");
+	}
+
+	#[tokio::test]
+	async fn test_reconnect() {
+		fn connection() -> (mpsc::UnboundedSender<KString>, BoxStream<'static, KString>) {
+			let (sender, receiver) = mpsc::unbounded_channel();
+			let stream = stream::unfold(receiver, |mut receiver| async move {
+				let kind = receiver.recv().await?;
+				Some((kind, receiver))
+			});
+			(sender, stream.boxed())
+		}
+
+		let (first_sender, first) = connection();
+		let (second_sender, second) = connection();
+		// the first reconnection attempt fails
+		let connections = Arc::new(Mutex::new(VecDeque::from([None, Some(second)])));
+		let connect = move || {
+			let connection = connections.lock().unwrap().pop_front().flatten();
+			async move {
+				connection.ok_or_else(|| {
+					BackendError::from(JobQueueError::Notifier("refused".to_string()))
+				})
+			}
+			.boxed()
+		};
+		let state = watch::Sender::new(ListenState::Connecting);
+		let mut states = state.subscribe();
+		let backoff = (Duration::from_millis(10), Duration::from_millis(50));
+		let mut subscriber = reconnecting(first, connect, backoff, state);
+
+		async fn next(subscriber: &mut BoxStream<'static, KString>) -> KString {
+			tokio::time::timeout(Duration::from_secs(1), subscriber.next())
+				.await
+				.unwrap()
+				.unwrap()
+		}
+
+		first_sender
+			.send(KString::from_static("SyncBranch"))
+			.unwrap();
+		assert_eq!(next(&mut subscriber).await, "SyncBranch");
+		assert_eq!(*states.borrow_and_update(), ListenState::Listening);
+
+		// the connection is dropped
+		drop(first_sender);
+		assert_eq!(next(&mut subscriber).await, ANY_KIND);
+		assert_eq!(*states.borrow_and_update(), ListenState::Listening);
+
+		second_sender
+			.send(KString::from_static("SyncBranch"))
+			.unwrap();
+		assert_eq!(next(&mut subscriber).await, "SyncBranch");
+	}
+}
@@ -0,0 +1,331 @@
+//! Prefetching jobs for workers.
+
+use std::{collections::VecDeque, pin::pin, sync::Arc};
+
+use futures::{StreamExt, stream::FuturesUnordered};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info};
+
+use crate::{
+	Result,
+	job_queue::{ClaimOptions, Job, JobQueue},
+};
+
+/// Configuration for [`JobPrefetcher`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PrefetchConfig {
+	/// Maximum count of jobs claimed ahead of processing.
+	///
+	/// If zero, jobs are claimed only when they can be processed immediately.
+	#[serde(default)]
+	pub prefetch: usize,
+	/// Maximum count of jobs processed concurrently.
+	#[serde(default = "default_concurrency")]
+	pub concurrency: usize,
+}
+
+impl Default for PrefetchConfig {
+	fn default() -> Self {
+		Self {
+			prefetch: 0,
+			concurrency: default_concurrency(),
+		}
+	}
+}
+
+fn default_concurrency() -> usize {
+	1
+}
+
+/// Claims jobs ahead of processing, so that workers are kept busy
+/// without a round trip to the queue between jobs.
+///
+/// At most [`PrefetchConfig::concurrency`] jobs are processed at a time,
+/// and at most [`PrefetchConfig::prefetch`] more are claimed and buffered.
+/// The buffer is backfilled as jobs complete.
+#[derive(Debug)]
+pub struct JobPrefetcher {
+	job_queue: Arc<JobQueue>,
+	config: PrefetchConfig,
+	worker: Option<String>,
+}
+
+impl JobPrefetcher {
+	pub fn new(job_queue: Arc<JobQueue>, config: PrefetchConfig) -> Self {
+		Self {
+			job_queue,
+			config,
+			worker: None,
+		}
+	}
+
+	/// Records claimed jobs as held by a worker registered with
+	/// [JobQueue::register_worker].
+	pub fn with_worker(mut self, worker: String) -> Self {
+		self.worker = Some(worker);
+		self
+	}
+
+	/// Processes jobs with `handler` until the queue is empty, or `shutdown` completes.
+	///
+	/// The handler is responsible for finishing or failing its job.
+	/// Errors of handlers are logged and do not stop processing.
+	///
+	/// On shutdown, or if claiming fails, buffered jobs are returned to the
+	/// queue with [JobQueue::unclaim_job], and running handlers are waited for.
+	/// The first error of claiming or unclaiming is then returned.
+	///
+	/// Returns the count of handled jobs.
+	pub async fn run<H, F, S>(&self, mut handler: H, shutdown: S) -> Result<usize>
+	where
+		H: FnMut(Job) -> F,
+		F: Future<Output = Result<()>>,
+		S: Future<Output = ()>,
+	{
+		let concurrency = self.config.concurrency.max(1);
+		let mut shutdown = pin!(shutdown);
+		let mut buffer = VecDeque::new();
+		let mut running = FuturesUnordered::new();
+		let mut handled = 0;
+		let mut result = Ok(());
+		let options = ClaimOptions {
+			worker: self.worker.as_deref(),
+			..Default::default()
+		};
+
+		loop {
+			let wanted =
+				(self.config.prefetch + concurrency).saturating_sub(running.len() + buffer.len());
+			if wanted != 0 {
+				match self
+					.job_queue
+					.fetch_and_start_batch_with(wanted, &options)
+					.await
+				{
+					Ok(jobs) => {
+						debug!(wanted, claimed = jobs.len(), "prefetched jobs");
+						buffer.extend(jobs);
+					}
+					Err(error) => {
+						error!(?error, "failed to prefetch jobs, stopping");
+						result = Err(error);
+						break;
+					}
+				}
+			}
+			while running.len() < concurrency {
+				let Some(job) = buffer.pop_front() else {
+					break;
+				};
+				running.push(handler(job));
+			}
+			if running.is_empty() {
+				return Ok(handled);
+			}
+
+			tokio::select! {
+				Some(result) = running.next() => {
+					handled += 1;
+					if let Err(error) = result {
+						error!(?error, "job handler error");
+					}
+				}
+				_ = &mut shutdown => break,
+			}
+		}
+
+		info!(count = buffer.len(), "unclaiming prefetched jobs");
+		for job in buffer {
+			if let Err(error) = self.job_queue.unclaim_job(job.id).await {
+				error!(id = %job.id, ?error, "failed to unclaim prefetched job");
+				if result.is_ok() {
+					result = Err(error);
+				}
+			}
+		}
+		while let Some(result) = running.next().await {
+			handled += 1;
+			if let Err(error) = result {
+				error!(?error, "job handler error");
+			}
+		}
+		result.map(|()| handled)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::{
+		sync::{
+			Arc,
+			atomic::{AtomicUsize, Ordering},
+		},
+		time::Duration,
+	};
+
+	use diesel::{ExpressionMethods, insert_into};
+	use serde_json::json;
+	use tokio::sync::Notify;
+	use uuid::Uuid;
+
+	use crate::{
+		BackendServices,
+		db::{
+			schema::job_queue::dsl,
+			utils::{XJsonVal, XUuidVal},
+		},
+		job_prefetch::{JobPrefetcher, PrefetchConfig},
+		job_queue::{Job, JobCommand, JobFilter},
+		test::test_env,
+	};
+
+	async fn enqueue(env: &BackendServices, count: i64) {
+		let mut db = env.database.get().await.unwrap();
+		for branch in 0..count {
+			env.job_queue
+				.enqueue(&mut db, JobCommand::sync_branch(branch))
+				.await
+				.unwrap();
+		}
+	}
+
+	async fn handle(env: &BackendServices, job: Job) -> crate::Result<()> {
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		let mut db = env.database.get().await?;
+		env.job_queue.finish_job(&mut db, job.id).await
+	}
+
+	/// Runs the prefetcher until the queue is empty, and returns the maximum
+	/// count of handlers running at a time.
+	async fn run_concurrency(env: &BackendServices, config: PrefetchConfig) -> usize {
+		let prefetcher = JobPrefetcher::new(env.job_queue.clone(), config);
+		let running = AtomicUsize::new(0);
+		let peak = AtomicUsize::new(0);
+		let handled = prefetcher
+			.run(
+				|job| {
+					let running = &running;
+					let peak = &peak;
+					async move {
+						let count = running.fetch_add(1, Ordering::SeqCst) + 1;
+						peak.fetch_max(count, Ordering::SeqCst);
+						let result = handle(env, job).await;
+						running.fetch_sub(1, Ordering::SeqCst);
+						result
+					}
+				},
+				std::future::pending(),
+			)
+			.await
+			.unwrap();
+		assert_eq!(handled, 8);
+		peak.into_inner()
+	}
+
+	#[tokio::test]
+	async fn test_prefetch_throughput() {
+		let env = test_env().await;
+
+		enqueue(&env, 8).await;
+		assert_eq!(run_concurrency(&env, PrefetchConfig::default()).await, 1);
+		enqueue(&env, 8).await;
+		let config = PrefetchConfig {
+			prefetch: 4,
+			concurrency: 4,
+		};
+		// handlers overlap, instead of waiting for each other
+		assert_eq!(run_concurrency(&env, config).await, 4);
+		assert_eq!(env.job_queue.count_pending(1).await.unwrap(), 0);
+	}
+
+	#[tokio::test]
+	async fn test_prefetch_fetch_error() {
+		let env = test_env().await;
+		enqueue(&env, 3).await;
+		// the payload of this job cannot be loaded, failing the next claim
+		let mut db = env.database.get().await.unwrap();
+		db.execute(insert_into(dsl::job_queue).values((
+			dsl::id.eq(XUuidVal(Uuid::now_v7())),
+			dsl::kind.eq("SyncBranch"),
+			dsl::data.eq(XJsonVal(json!({ "$payload": "missing" }))),
+			dsl::priority.eq(0),
+		)))
+		.await
+		.unwrap();
+		drop(db);
+
+		let config = PrefetchConfig {
+			prefetch: 1,
+			concurrency: 2,
+		};
+		let prefetcher = JobPrefetcher::new(env.job_queue.clone(), config);
+		let finished = AtomicUsize::new(0);
+		let result = prefetcher
+			.run(
+				|job| {
+					let finished = &finished;
+					async move {
+						handle(&env, job).await?;
+						finished.fetch_add(1, Ordering::SeqCst);
+						Ok(())
+					}
+				},
+				std::future::pending(),
+			)
+			.await;
+		assert!(result.is_err());
+
+		// both running handlers are completed, and the buffered job is unclaimed
+		assert_eq!(finished.into_inner(), 2);
+		let jobs = env
+			.job_queue
+			.list_jobs(&JobFilter::default())
+			.await
+			.unwrap();
+		assert_eq!(jobs.len(), 2);
+		assert!(
+			jobs.iter()
+				.all(|job| job.started_at.is_none() && job.attempts == 0)
+		);
+	}
+
+	#[tokio::test]
+	async fn test_prefetch_shutdown() {
+		let env = test_env().await;
+		enqueue(&env, 6).await;
+
+		let started = Arc::new(Notify::new());
+		let config = PrefetchConfig {
+			prefetch: 2,
+			concurrency: 1,
+		};
+		let prefetcher = JobPrefetcher::new(env.job_queue.clone(), config);
+		let handled = prefetcher
+			.run(
+				|job| {
+					started.notify_one();
+					handle(&env, job)
+				},
+				started.notified(),
+			)
+			.await
+			.unwrap();
+		assert_eq!(handled, 1);
+
+		// the running job is finished, and prefetched ones can be claimed again
+		// without being charged an attempt
+		let jobs = env
+			.job_queue
+			.list_jobs(&JobFilter::default())
+			.await
+			.unwrap();
+		assert_eq!(jobs.len(), 5);
+		assert!(
+			jobs.iter()
+				.all(|job| job.started_at.is_none() && job.attempts == 0)
+		);
+		let jobs = env.job_queue.fetch_and_start_batch(10).await.unwrap();
+		assert_eq!(jobs.len(), 5);
+	}
+}
@@ -1,14 +1,31 @@
-use std::sync::Arc;
+use std::{
+	collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+	fmt::{Debug, Display},
+	io::{Read, Write},
+	panic::AssertUnwindSafe,
+	sync::{
+		Arc, Mutex,
+		atomic::{self, AtomicBool},
+	},
+	time::Instant,
+};
 
 use diesel::{
-	BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl, delete, insert_into,
+	BoolExpressionMethods, ExpressionMethods, IntoSql, OptionalExtension, QueryDsl, Queryable,
+	QueryableByName, Selectable, delete,
+	dsl::count_star,
+	insert_into, sql_query,
+	sql_types::{Bool, Text},
 	update,
 };
+use fabricia_common_model::priority::Priority;
+use flate2::{read::GzDecoder, write::GzEncoder};
+use futures::FutureExt;
 use kstring::KString;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use thiserror::Error;
-use time::{OffsetDateTime, PrimitiveDateTime};
-use tracing::{info, warn};
+use time::{OffsetDateTime, PrimitiveDateTime, UtcOffset};
+use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
 use crate::{
@@ -16,20 +33,48 @@ use crate::{
 	branch::BranchRef,
 	db::{
 		BoxedSqlConn,
-		schema::job_queue::dsl,
+		schema::{
+			branch, job_attempt, job_dead_letter, job_dependency, job_history, job_idempotency,
+			job_queue::dsl, job_worker,
+		},
 		service::DatabaseService,
-		utils::{XJsonVal, XUuidVal},
+		utils::{XJsonVal, XUuid, XUuidVal},
 	},
+	job_callback::{
+		self, CallbackBody, CallbackOutcome, CallbackSender, HttpCallbackSender, PendingCallback,
+	},
+	job_notifier::{JobNotifier, LocalJobNotifier},
+	payload_store::{InlinePayloadStore, PayloadStore},
+	trace::TraceContext,
 };
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 #[serde(tag = "t", content = "c", rename = "kebab-case")]
 pub enum JobCommand {
 	/// Synchronize metadata of a branch.
-	SyncBranch(BranchRef),
+	SyncBranch {
+		branch: BranchRef,
+		/// Position to resume from, for syncs processed in chunks.
+		///
+		/// Handlers re-enqueue the command with an advanced cursor through
+		/// [JobQueue::continue_job], so that a single job does not run unboundedly.
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		cursor: Option<String>,
+	},
 }
 
 impl JobCommand {
+	/// Kinds of all known commands.
+	pub const KINDS: &[&str] = &["SyncBranch"];
+
+	/// Creates a command synchronizing a branch from the beginning.
+	pub fn sync_branch(branch: BranchRef) -> Self {
+		Self::SyncBranch {
+			branch,
+			cursor: None,
+		}
+	}
+
 	pub fn serialize(&self) -> serde_json::Result<(KString, serde_json::Value)> {
 		let mut value = serde_json::to_value(self)?;
 		Ok((
@@ -38,10 +83,110 @@ impl JobCommand {
 		))
 	}
 
-	pub fn deserialize(kind: &str, value: serde_json::Value) -> serde_json::Result<Self> {
+	pub fn deserialize(kind: &str, mut value: serde_json::Value) -> serde_json::Result<Self> {
+		// data of SyncBranch was the bare branch before cursors were added,
+		// and may still be stored in the queue
+		if kind == "SyncBranch" && value.is_number() {
+			value = serde_json::json!({ "branch": value });
+		}
 		let value = serde_json::json!({ "t": kind, "c": value });
 		serde_json::from_value(value)
 	}
+
+	/// Returns the kind of this command, as stored in the queue.
+	pub fn kind(&self) -> &'static str {
+		match self {
+			JobCommand::SyncBranch { .. } => "SyncBranch",
+		}
+	}
+
+	/// Returns the branch this command works on, if any.
+	pub fn branch(&self) -> Option<BranchRef> {
+		match self {
+			JobCommand::SyncBranch { branch, .. } => Some(*branch),
+		}
+	}
+
+	/// Returns the precondition of running this command, if any.
+	///
+	/// Workers check the precondition with [JobQueue::check_guard] after
+	/// starting the job. If it does not hold, the job should be finished with
+	/// [JobQueue::skip_job] without being executed.
+	pub fn guard(&self) -> Option<JobGuard> {
+		match self {
+			JobCommand::SyncBranch { branch, .. } => Some(JobGuard::BranchEnabled(*branch)),
+		}
+	}
+}
+
+/// Builder of [JobCommand]s, validating their fields.
+///
+/// Commands built from user input should be built with this rather than
+/// constructed directly, so that invalid commands are rejected before
+/// they are enqueued.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobCommandBuilder {
+	kind: KString,
+	branch: Option<BranchRef>,
+	cursor: Option<String>,
+}
+
+impl JobCommandBuilder {
+	/// Creates a builder of a command of `kind`, see [JobCommand::KINDS].
+	pub fn new(kind: impl Into<KString>) -> Self {
+		Self {
+			kind: kind.into(),
+			branch: None,
+			cursor: None,
+		}
+	}
+
+	/// Creates a builder of a [JobCommand::SyncBranch] command.
+	pub fn sync_branch() -> Self {
+		Self::new(KString::from_static("SyncBranch"))
+	}
+
+	/// Sets the branch the command works on.
+	pub fn branch(mut self, branch: BranchRef) -> Self {
+		self.branch = Some(branch);
+		self
+	}
+
+	/// Sets the position to resume from.
+	pub fn cursor(mut self, cursor: impl Into<String>) -> Self {
+		self.cursor = Some(cursor.into());
+		self
+	}
+
+	/// Validates the fields and builds the command.
+	pub fn build(self) -> Result<JobCommand, JobQueueError> {
+		let invalid = |reason: &str| JobQueueError::InvalidCommand(reason.to_string());
+		match self.kind.as_str() {
+			"SyncBranch" => {
+				let branch = self.branch.ok_or_else(|| invalid("branch is required"))?;
+				if branch <= 0 {
+					return Err(invalid("branch must be positive"));
+				}
+				if self.cursor.as_ref().is_some_and(|cursor| cursor.is_empty()) {
+					return Err(invalid("cursor must not be empty"));
+				}
+				Ok(JobCommand::SyncBranch {
+					branch,
+					cursor: self.cursor,
+				})
+			}
+			kind => Err(JobQueueError::InvalidCommand(format!(
+				"unknown job kind {kind}"
+			))),
+		}
+	}
+}
+
+/// Precondition of running a job.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum JobGuard {
+	/// The branch exists and is enabled.
+	BranchEnabled(BranchRef),
 }
 
 pub type JobRef = Uuid;
@@ -50,203 +195,6235 @@ pub type JobRef = Uuid;
 pub struct Job {
 	pub id: JobRef,
 	pub command: JobCommand,
+	/// Trace context of the operation enqueuing this job.
+	pub trace_context: Option<TraceContext>,
 }
 
-#[derive(Debug)]
-pub struct JobQueue {
-	db: Arc<DatabaseService>,
+impl Job {
+	/// Returns the branch to synchronize, if this is a [JobCommand::SyncBranch] job.
+	pub fn as_sync_branch(&self) -> Option<BranchRef> {
+		match &self.command {
+			JobCommand::SyncBranch { branch, .. } => Some(*branch),
+		}
+	}
+
+	/// Deserializes the data of this job, as stored in the queue, into `T`.
+	///
+	/// This is for code handling jobs of several kinds generically, such as
+	/// observers, which should not parse the stored JSON by themselves.
+	pub fn payload<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+		let (_, value) = self.command.serialize()?;
+		serde_json::from_value(value)
+	}
 }
 
-impl JobQueue {
-	pub fn new(db: Arc<DatabaseService>) -> Self {
-		Self { db }
+/// Options for enqueuing a job.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct EnqueueOptions {
+	/// Priority of the job.
+	///
+	/// Jobs with higher priorities are claimed sooner.
+	/// If `None`, [`JobQueueConfig::default_priority`] is used.
+	pub priority: Option<u16>,
+	/// Jobs that must finish before this job becomes eligible to start.
+	///
+	/// Jobs that have already finished are ignored. If any of them is
+	/// dead-lettered, enqueuing fails with [JobQueueError::DependencyFailed],
+	/// and if any is dead-lettered later, this job is dead-lettered with it.
+	pub depends_on: Vec<JobRef>,
+	/// Whether to compress the job data.
+	///
+	/// If `None`, data is compressed when it is larger than
+	/// [`JobQueueConfig::compress_threshold`].
+	pub compress: Option<bool>,
+	/// Key to deduplicate the job.
+	///
+	/// If a job with the same key is still in the queue, no job is enqueued
+	/// and the ID of the existing job is returned.
+	pub dedup_key: Option<String>,
+	/// Producer-chosen key for idempotent enqueuing.
+	///
+	/// If a job has ever been enqueued with the same key, no job is enqueued
+	/// and the ID of that job is returned, even if it has finished.
+	/// Keys are kept until [JobQueue::prune_idempotency_keys].
+	pub idempotency_key: Option<String>,
+	/// Trace context of the operation enqueuing the job.
+	///
+	/// If `None`, [TraceContext::current] is used.
+	pub trace_context: Option<TraceContext>,
+	/// Tenant owning the job.
+	///
+	/// Claims are shared across tenants as configured with
+	/// [`JobQueueConfig::tenant_weights`].
+	pub tenant: Option<String>,
+	/// URL notified when the job finishes, see [crate::job_callback].
+	///
+	/// The host must be in [`JobQueueConfig::callback_hosts`].
+	pub callback_url: Option<String>,
+	/// Whether to enqueue before pending jobs of the same priority.
+	pub front: bool,
+}
+
+/// A pending job exported by [JobQueue::export].
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ExportedJob {
+	pub id: JobRef,
+	pub kind: String,
+	pub data: serde_json::Value,
+	pub priority: u16,
+	/// Time when the job was enqueued, from the timestamp of its ID.
+	///
+	/// This is informational, and ignored on import.
+	#[serde(default, with = "time::serde::rfc3339::option")]
+	pub created_at: Option<OffsetDateTime>,
+	#[serde(default)]
+	pub branch: Option<BranchRef>,
+	#[serde(default)]
+	pub dedup_key: Option<String>,
+	#[serde(default)]
+	pub trace_context: Option<String>,
+	/// Pending jobs this job depends on.
+	#[serde(default)]
+	pub depends_on: Vec<JobRef>,
+	#[serde(default)]
+	pub tenant: Option<String>,
+	/// Count of failed attempts.
+	#[serde(default)]
+	pub attempts: u32,
+	#[serde(default)]
+	pub last_error: Option<String>,
+	/// Earliest time to retry the job after a failed attempt.
+	#[serde(default, with = "time::serde::rfc3339::option")]
+	pub next_attempt_at: Option<OffsetDateTime>,
+	#[serde(default)]
+	pub callback_url: Option<String>,
+	/// Ordering of the job within its priority, see [EnqueueOptions::front].
+	#[serde(default)]
+	pub seq: i64,
+}
+
+/// Columns of jobs exported by [JobQueue::export].
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::db::schema::job_queue)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+struct SqlExportedJob {
+	id: XUuidVal,
+	kind: String,
+	data: XJsonVal,
+	data_bin: Option<Vec<u8>>,
+	compressed: bool,
+	priority: i16,
+	branch: Option<BranchRef>,
+	dedup_key: Option<String>,
+	trace_context: Option<String>,
+	tenant: String,
+	attempts: i32,
+	last_error: Option<String>,
+	next_attempt_at: Option<PrimitiveDateTime>,
+	callback_url: Option<String>,
+	seq: i64,
+}
+
+/// Tables processed by [JobQueue::maintain].
+const MAINTAINED_TABLES: &[&str] = &[
+	"job_queue",
+	"job_attempt",
+	"job_dependency",
+	"job_dead_letter",
+	"job_history",
+];
+
+/// Report of [JobQueue::maintain].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MaintenanceReport {
+	/// Whether tables were vacuumed in addition to being analyzed.
+	pub vacuumed: bool,
+	/// Time spent on each table.
+	pub tables: Vec<(&'static str, std::time::Duration)>,
+}
+
+/// Outcome of [JobQueue::enqueue_with].
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum EnqueueOutcome {
+	/// A new job was enqueued.
+	Enqueued { id: JobRef },
+	/// A job with the same dedup or idempotency key exists, and no job was enqueued.
+	Deduplicated { id: JobRef },
+}
+
+impl EnqueueOutcome {
+	/// Returns the ID of the enqueued or existing job.
+	pub fn id(&self) -> JobRef {
+		match *self {
+			Self::Enqueued { id } | Self::Deduplicated { id } => id,
+		}
+	}
+
+	/// Checks if a new job was enqueued.
+	pub fn is_enqueued(&self) -> bool {
+		matches!(self, Self::Enqueued { .. })
 	}
+}
 
-	pub async fn enqueue(&self, conn: &mut BoxedSqlConn, job: JobCommand) -> Result<()> {
-		self.enqueue_with_priority(conn, job, 100).await
+impl Default for EnqueueOptions {
+	fn default() -> Self {
+		Self {
+			priority: None,
+			depends_on: Vec::new(),
+			compress: None,
+			dedup_key: None,
+			idempotency_key: None,
+			trace_context: None,
+			tenant: None,
+			callback_url: None,
+			front: false,
+		}
 	}
+}
 
-	pub async fn enqueue_with_priority(
-		&self,
-		conn: &mut BoxedSqlConn,
-		job: JobCommand,
-		priority: u16,
-	) -> Result<()> {
-		let id = Uuid::now_v7();
-		let (kind, job_data) = job.serialize()?;
+/// Options of claiming jobs.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct ClaimOptions<'a> {
+	/// Only claims jobs of these kinds.
+	///
+	/// If `None`, jobs of all kinds are claimed.
+	pub kinds: Option<&'a [KString]>,
+	/// Only claims jobs of at least this priority.
+	///
+	/// This is for workers reserved for urgent jobs, which should never be
+	/// blocked behind a backlog of low-priority jobs.
+	pub min_priority: Option<i16>,
+	/// Worker claiming jobs, recorded as [JobInfo::claimed_by].
+	pub worker: Option<&'a str>,
+}
 
-		let id = conn
-			.get_result::<_, XUuidVal>(
-				insert_into(dsl::job_queue)
-					.values((
-						dsl::id.eq(XUuidVal(id)),
-						dsl::kind.eq(kind.as_str()),
-						dsl::data.eq(XJsonVal(job_data)),
-						dsl::priority.eq(priority as i16),
-					))
-					.returning(dsl::id),
-			)
-			.await?;
-		let id = id.0;
-		info!(%kind, %id, "enqueued job");
+/// Filter of listing jobs.
+///
+/// Unset fields match all jobs.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct JobFilter {
+	pub kind: Option<String>,
+	pub status: Option<JobStatus>,
+	/// Minimum priority, inclusive.
+	pub min_priority: Option<i16>,
+	/// Maximum priority, inclusive.
+	pub max_priority: Option<i16>,
+	/// Branch the jobs work on.
+	pub branch: Option<BranchRef>,
+	/// Minimum time since the jobs were enqueued.
+	pub min_age: Option<time::Duration>,
+	/// Maximum time since the jobs were enqueued.
+	pub max_age: Option<time::Duration>,
+	/// Maximum count of jobs to list.
+	pub limit: u32,
+	/// Only lists jobs enqueued after this job, for keyset pagination.
+	pub after: Option<JobRef>,
+}
 
-		// TODO: notify a job worker
+impl Default for JobFilter {
+	fn default() -> Self {
+		Self {
+			kind: None,
+			status: None,
+			min_priority: None,
+			max_priority: None,
+			branch: None,
+			min_age: None,
+			max_age: None,
+			limit: 100,
+			after: None,
+		}
+	}
+}
 
+impl JobFilter {
+	/// Checks if the filter is consistent.
+	pub fn validate(&self) -> Result<(), JobQueueError> {
+		if let Some((min, max)) = self
+			.min_priority
+			.zip(self.max_priority)
+			.filter(|(min, max)| min > max)
+		{
+			return Err(JobQueueError::InvalidFilter(format!(
+				"minimum priority {min} is larger than maximum priority {max}"
+			)));
+		}
+		if let Some((min, max)) = self
+			.min_age
+			.zip(self.max_age)
+			.filter(|(min, max)| min > max)
+		{
+			return Err(JobQueueError::InvalidFilter(format!(
+				"minimum age {min} is larger than maximum age {max}"
+			)));
+		}
+		if [self.min_age, self.max_age]
+			.into_iter()
+			.flatten()
+			.any(|age| age.is_negative())
+		{
+			return Err(JobQueueError::InvalidFilter(
+				"age must not be negative".to_string(),
+			));
+		}
 		Ok(())
 	}
+}
 
-	pub async fn fetch_and_start(&self) -> Result<Option<Job>> {
-		let mut conn = self.db.get().await?;
+/// Summary of retries of jobs in the queue.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct RetryStats {
+	/// Count of jobs by the number of their next or running attempt.
+	///
+	/// Jobs never failed are on their first attempt.
+	pub attempts: BTreeMap<u32, usize>,
+	/// Kinds with the most failed attempts, and the count of failed attempts.
+	///
+	/// Kinds without failed attempts are omitted.
+	pub top_kinds: Vec<(String, u64)>,
+}
 
-		loop {
-			let time = OffsetDateTime::now_utc();
-			let time = PrimitiveDateTime::new(time.date(), time.time());
+/// Status of a job in the queue.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum JobStatus {
+	Pending,
+	Running,
+}
 
-			// find a pending job
-			// for jobs with the same priority, we order them with ID.
-			// because ID are UUID v7, this is equivalent to ordering with
-			// insertion time
-			let result = conn
-				.get_result::<_, (XUuidVal, String, XJsonVal)>(
-					dsl::job_queue
-						.limit(1)
-						.filter(dsl::started_at.is_null())
-						.order((dsl::priority.desc(), dsl::id.asc()))
-						.select((dsl::id, dsl::kind, dsl::data)),
-				)
-				.await
-				.optional()?;
-			if let Some((id, kind, data)) = result {
-				let cols = conn
-					.execute(
-						update(dsl::job_queue)
-							.filter(dsl::id.eq(id).and(dsl::started_at.is_null()))
-							.set(dsl::started_at.eq(time)),
-					)
-					.await?;
-				#[cfg(test)]
-				assert!(cols != 0);
-				if cols == 0 {
-					warn!(%id, "SQL lightweight job queue polling hit contented");
-					continue;
-				}
-				info!(%id, "polled lightweight job");
-				let cmd = JobCommand::deserialize(&kind, data.0)?;
-				return Ok(Some(Job {
-					id: id.0,
-					command: cmd,
-				}));
-			} else {
-				return Ok(None);
-			}
+/// Information about a job in the queue.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct JobInfo {
+	pub id: JobRef,
+	pub kind: String,
+	pub data: serde_json::Value,
+	pub priority: u16,
+	/// Started time of the job, or `None` if the job is pending.
+	pub started_at: Option<PrimitiveDateTime>,
+	/// Count of failed attempts.
+	pub attempts: u32,
+	/// Error of the most recent failed attempt.
+	pub last_error: Option<String>,
+	/// Trace context of the operation enqueuing this job.
+	pub trace_context: Option<TraceContext>,
+	/// Worker running this job, if known.
+	pub claimed_by: Option<String>,
+}
+
+/// Reason of a job being claimed or not, returned by [JobQueue::explain_claim].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ClaimExplanation {
+	/// The job is eligible, and would be claimed by workers accepting its kind.
+	///
+	/// Jobs of higher priorities, and tenants as configured with
+	/// [`JobQueueConfig::tenant_weights`], may still be claimed first.
+	Claimable,
+	/// The job has been claimed.
+	Running { claimed_by: Option<String> },
+	/// The queue is draining, see [JobQueue::drain].
+	Draining,
+	/// The job has failed, and is not retried until the time.
+	RetryScheduled { next_attempt_at: PrimitiveDateTime },
+	/// The job waits for these jobs to finish.
+	DependenciesPending { depends_on: Vec<JobRef> },
+	/// The job is of a kind unknown to this version, and left for workers knowing it.
+	///
+	/// See [UnknownKindPolicy::Skip].
+	UnknownKind,
+}
+
+/// A worker registered with [JobQueue::register_worker].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct WorkerInfo {
+	pub id: String,
+	pub registered_at: PrimitiveDateTime,
+	/// Time of the most recent heartbeat.
+	pub heartbeat_at: PrimitiveDateTime,
+	/// Count of jobs being run by this worker.
+	pub running: usize,
+}
+
+/// An attempt of a job.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct JobAttempt {
+	/// Number of this attempt, starting from 1.
+	pub attempt: u32,
+	pub started_at: PrimitiveDateTime,
+	/// Finished time of this attempt, or `None` if it is running.
+	pub finished_at: Option<PrimitiveDateTime>,
+	pub outcome: AttemptOutcome,
+	pub error: Option<String>,
+	/// Category of the error, or `None` if the failure is not categorized.
+	pub error_code: Option<JobErrorCode>,
+}
+
+/// Category of a job failure.
+///
+/// Stored as a tiny unsigned column. Unknown values are decoded as internal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[repr(u8)]
+pub enum JobErrorCode {
+	/// Bugs or unexpected states of the worker.
+	Internal = 0,
+	/// Failures of remote services or connections, which are usually transient.
+	Network = 1,
+	/// Invalid jobs or inputs, which are unlikely to succeed on retry.
+	Validation = 2,
+}
+
+impl From<i16> for JobErrorCode {
+	fn from(value: i16) -> Self {
+		match value {
+			1 => Self::Network,
+			2 => Self::Validation,
+			_ => Self::Internal,
 		}
 	}
+}
 
-	pub async fn finish_job(&self, conn: &mut BoxedSqlConn, id: JobRef) -> Result<()> {
-		let cols = conn
-			.execute(
-				delete(dsl::job_queue)
-					.filter(dsl::id.eq(XUuidVal(id)).and(dsl::started_at.is_not_null())),
-			)
-			.await?;
-		if cols == 0 {
-			warn!(%id, "job has been aborted or finished by another worker");
-			return Err(JobQueueError::JobAborted(id).into());
+/// A structured failure of a job.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct JobFailure {
+	/// Machine-readable category of the failure.
+	pub code: JobErrorCode,
+	/// Human-readable message of the failure.
+	pub message: String,
+}
+
+impl JobFailure {
+	pub fn new<S: Into<String>>(code: JobErrorCode, message: S) -> Self {
+		Self {
+			code,
+			message: message.into(),
 		}
-		Ok(())
 	}
+}
 
-	/// Returns the approximate count of pending jobs.
-	pub async fn count_pending(&self, max: usize) -> Result<usize> {
-		let mut conn = self.db.get().await?;
+/// Outcome of an attempt of a job.
+///
+/// Stored as a tiny unsigned column. Unknown values are decoded as failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum AttemptOutcome {
+	Running = 0,
+	Succeeded = 1,
+	Failed = 2,
+	/// The job was not executed, as its precondition did not hold.
+	Skipped = 3,
+}
 
-		let count: i64 = conn
-			.get_result(
-				dsl::job_queue
-					.count()
-					.filter(dsl::started_at.is_not_null())
-					.limit(max.try_into().unwrap()),
-			)
-			.await?;
-		Ok(count.try_into().unwrap())
+impl From<i16> for AttemptOutcome {
+	fn from(value: i16) -> Self {
+		match value {
+			0 => Self::Running,
+			1 => Self::Succeeded,
+			3 => Self::Skipped,
+			_ => Self::Failed,
+		}
 	}
 }
 
-#[derive(Debug, Error)]
-pub enum JobQueueError {
-	#[error("job {0} has been aborted")]
-	JobAborted(JobRef),
+type SqlJobInfo = (
+	String,
+	SqlJobData,
+	i16,
+	Option<PrimitiveDateTime>,
+	i32,
+	Option<String>,
+	Option<String>,
+	Option<String>,
+);
+
+type SqlClaimedJob = (XUuidVal, String, SqlJobData, i32, Option<String>);
+
+/// Configuration for [`JobQueue`].
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct JobQueueConfig {
+	/// Maximum running time in seconds, by job kind.
+	///
+	/// Jobs running longer than this are considered runaway,
+	/// and are moved to the dead-letter queue by [`JobQueue::fail_timed_out`].
+	/// Kinds not listed here are limited by [`JobQueueConfig::default_timeout`].
+	#[serde(default)]
+	pub timeouts: BTreeMap<KString, u64>,
+	/// Maximum running time in seconds of kinds not in [`JobQueueConfig::timeouts`].
+	///
+	/// If unset, such jobs may run for unlimited time.
+	#[serde(default)]
+	pub default_timeout: Option<u64>,
+	/// Maximum running time in milliseconds of handlers, by job kind.
+	///
+	/// Handlers run by [`JobQueue::run_job`] longer than this are cancelled,
+	/// and their jobs are failed to be retried. Unlike [`JobQueueConfig::timeouts`],
+	/// this is enforced by the worker, and should be shorter than those.
+	/// Kinds not listed here are limited by
+	/// [`JobQueueConfig::default_handler_timeout_ms`].
+	#[serde(default)]
+	pub handler_timeouts_ms: BTreeMap<KString, u64>,
+	/// Maximum running time in milliseconds of handlers of kinds not in
+	/// [`JobQueueConfig::handler_timeouts_ms`].
+	///
+	/// If unset, such handlers may run for unlimited time.
+	#[serde(default)]
+	pub default_handler_timeout_ms: Option<u64>,
+	/// Weights of tenants in claiming jobs, see [`EnqueueOptions::tenant`].
+	///
+	/// If not empty, claims are shared across tenants with pending jobs
+	/// in proportion to their weights, and jobs are ordered by priority
+	/// only within a tenant.
+	/// Tenants not listed here, including jobs without tenants, have
+	/// [`JobQueueConfig::default_tenant_weight`].
+	#[serde(default)]
+	pub tenant_weights: BTreeMap<KString, u32>,
+	#[serde(default = "default_tenant_weight")]
+	pub default_tenant_weight: u32,
+	/// Encoding of data of newly enqueued jobs.
+	#[serde(default)]
+	pub data_encoding: JobDataEncoding,
+	/// Maximum count of attempts of a job.
+	///
+	/// Jobs failed this many times are moved to the dead-letter queue
+	/// by [`JobQueue::fail_job`].
+	#[serde(default = "default_max_attempts")]
+	pub max_attempts: u32,
+	/// Minimum size of job data in bytes to be compressed.
+	///
+	/// If unset, job data is only compressed on request.
+	#[serde(default)]
+	pub compress_threshold: Option<usize>,
+	/// Minimum size of job data in bytes to be offloaded to the payload store,
+	/// see [`JobQueue::with_payload_store`].
+	///
+	/// If unset, job data is always kept in the queue.
+	#[serde(default)]
+	pub offload_threshold: Option<usize>,
+	/// Codec of compressing newly enqueued jobs.
+	///
+	/// Jobs are always decompressed with the codec they were compressed with,
+	/// so the codec can be changed at any time.
+	#[serde(default)]
+	pub compression: CompressionCodec,
+	/// Threshold in milliseconds to warn about slow job claims.
+	///
+	/// Both the claim query and the whole claim loop of
+	/// [`JobQueue::fetch_and_start`] are checked.
+	/// If unset, no warnings are emitted.
+	#[serde(default)]
+	pub slow_claim_threshold_ms: Option<u64>,
+	/// Order of jobs with the same priority.
+	#[serde(default)]
+	pub ordering: JobOrdering,
+	/// Delay in seconds before retrying a job failed for the first time.
+	///
+	/// The delay is doubled for each subsequent failure, up to
+	/// [`JobQueueConfig::max_retry_delay`].
+	/// If zero, failed jobs are retried immediately.
+	#[serde(default)]
+	pub retry_delay: u64,
+	/// Maximum delay in seconds before retrying a failed job.
+	#[serde(default = "default_max_retry_delay")]
+	pub max_retry_delay: u64,
+	/// Randomization of retry delays.
+	#[serde(default)]
+	pub retry_jitter: RetryJitter,
+	/// Handling of jobs of kinds unknown to this version.
+	#[serde(default)]
+	pub unknown_kinds: UnknownKindPolicy,
+	/// Priority of jobs enqueued by [`JobQueue::enqueue`].
+	///
+	/// Jobs with higher priorities are claimed sooner. Producers with
+	/// explicit priorities should use [`JobQueue::enqueue_with_priority`].
+	#[serde(default = "default_priority")]
+	pub default_priority: u16,
+	/// Handling of finished jobs.
+	#[serde(default)]
+	pub on_finish: FinishPolicy,
+	/// Maximum age in seconds of the oldest pending job before alerting.
+	///
+	/// See [`JobQueue::set_stale_threshold`]. If unset, no alerts are raised.
+	#[serde(default)]
+	pub stale_threshold: Option<u64>,
+	/// Time in seconds the queue must stay empty before
+	/// [`JobObserver::on_queue_empty`] is called, see [`JobQueue::check_empty`].
+	#[serde(default)]
+	pub empty_debounce: u64,
+	/// Maximum count of pending jobs.
+	///
+	/// Enqueueing into a full queue fails with [JobQueueError::QueueFull],
+	/// so that producers back off. If unset, the queue is unbounded.
+	#[serde(default)]
+	pub max_pending: Option<u64>,
+	/// Time in seconds producers are advised to wait before retrying
+	/// to enqueue into a full queue.
+	#[serde(default = "default_queue_full_retry_after")]
+	pub queue_full_retry_after: u64,
+	/// Maximum count of jobs a single worker may hold at a time.
+	///
+	/// Workers claiming with [`ClaimOptions::worker`] get no more jobs while
+	/// they hold this many, so that faster workers do not starve others.
+	/// If unset, workers are not limited.
+	#[serde(default)]
+	pub max_claims_per_worker: Option<u32>,
+	/// Checks if indexes used to claim jobs exist on startup.
+	///
+	/// See [`JobQueue::check_indexes`].
+	#[serde(default)]
+	pub check_indexes: bool,
+	/// Hosts which callback URLs may point to, see [`EnqueueOptions::callback_url`].
+	///
+	/// Jobs with callbacks to other hosts are rejected. If empty, no callbacks
+	/// are allowed.
+	#[serde(default)]
+	pub callback_hosts: BTreeSet<String>,
+	/// Key to sign bodies of callbacks with, see [`job_callback::SIGNATURE_HEADER`].
+	///
+	/// If unset, callbacks are not signed.
+	#[serde(default)]
+	pub callback_secret: Option<String>,
+	/// Count of retries of failed callbacks.
+	#[serde(default = "default_callback_retries")]
+	pub callback_retries: u32,
+	/// Delay in milliseconds before the first retry of a failed callback,
+	/// doubled after each retry.
+	#[serde(default = "default_callback_retry_delay_ms")]
+	pub callback_retry_delay_ms: u64,
+	/// Time in seconds without a heartbeat before a worker is considered lost.
+	///
+	/// Jobs claimed by lost workers are released by [`JobQueue::reclaim_lost_jobs`].
+	/// This should be several times the heartbeat interval of workers.
+	/// If unset, jobs are never reclaimed.
+	#[serde(default)]
+	pub worker_timeout: Option<u64>,
 }
 
-#[cfg(test)]
-mod test {
-	use diesel::QueryDsl;
+/// Handling of jobs finished by [JobQueue::finish_job] or [JobQueue::skip_job].
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum FinishPolicy {
+	/// Deleted, leaving only their attempts.
+	#[default]
+	Delete,
+	/// Moved to the history table, keeping the full record of every job.
+	Archive,
+}
 
-	use crate::{db::schema::job_queue::dsl, job_queue::JobCommand, test::test_env};
+/// Handling of jobs of kinds not in [JobCommand::KINDS].
+///
+/// Such jobs are usually enqueued by newer producers during rolling upgrades.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnknownKindPolicy {
+	/// Never claimed, and left for workers knowing the kind.
+	#[default]
+	Skip,
+	/// Claimed and moved to the dead-letter queue.
+	Quarantine,
+}
 
-	#[tokio::test]
-	async fn test_enqueue() {
-		let env = test_env().await;
-		let mut db = env.database.get().await.unwrap();
-		env.job_queue
-			.enqueue(&mut db, JobCommand::SyncBranch(1))
-			.await
-			.unwrap();
+/// Randomization of retry delays.
+///
+/// Jobs failed at the same time, e.g. during an upstream outage, would
+/// otherwise be retried in a synchronized wave.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum RetryJitter {
+	/// Retried exactly after the delay.
+	#[default]
+	None,
+	/// Retried after a random time between zero and the delay.
+	Full,
+	/// Retried after a random time between half the delay and the delay.
+	Equal,
+}
+
+impl RetryJitter {
+	/// Randomizes a delay in milliseconds.
+	fn apply(self, delay: u64) -> u64 {
+		match self {
+			Self::None => delay,
+			Self::Full => rand::random_range(0..=delay),
+			Self::Equal => delay / 2 + rand::random_range(0..=delay - delay / 2),
+		}
 	}
+}
 
-	#[tokio::test]
-	async fn test_enqueue_fetch() {
-		let env = test_env().await;
-		let mut db = env.database.get().await.unwrap();
-		let jq = env.job_queue;
-		jq.enqueue(&mut db, JobCommand::SyncBranch(1))
-			.await
-			.unwrap();
-		jq.enqueue_with_priority(&mut db, JobCommand::SyncBranch(2), 120)
-			.await
-			.unwrap();
-		jq.enqueue(&mut db, JobCommand::SyncBranch(3))
-			.await
-			.unwrap();
-		drop(db);
-		assert_eq!(
-			jq.fetch_and_start().await.unwrap().unwrap().command,
-			JobCommand::SyncBranch(2)
-		);
-		assert_eq!(
-			jq.fetch_and_start().await.unwrap().unwrap().command,
-			JobCommand::SyncBranch(1)
-		);
-		assert_eq!(
-			jq.fetch_and_start().await.unwrap().unwrap().command,
-			JobCommand::SyncBranch(3)
-		);
-		assert!(jq.fetch_and_start().await.unwrap().is_none());
+/// Order of jobs with the same priority.
+///
+/// Jobs enqueued with [`EnqueueOptions::front`] are always claimed first.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum JobOrdering {
+	/// First in, first out.
+	#[default]
+	Fifo,
+	/// Last in, first out.
+	///
+	/// This is useful when the most recent request matters most.
+	Lifo,
+}
+
+impl Default for JobQueueConfig {
+	fn default() -> Self {
+		Self {
+			timeouts: BTreeMap::new(),
+			default_timeout: None,
+			handler_timeouts_ms: BTreeMap::new(),
+			default_handler_timeout_ms: None,
+			tenant_weights: BTreeMap::new(),
+			default_tenant_weight: default_tenant_weight(),
+			data_encoding: JobDataEncoding::default(),
+			max_attempts: default_max_attempts(),
+			compress_threshold: None,
+			offload_threshold: None,
+			compression: CompressionCodec::default(),
+			slow_claim_threshold_ms: None,
+			ordering: JobOrdering::default(),
+			retry_delay: 0,
+			max_retry_delay: default_max_retry_delay(),
+			retry_jitter: RetryJitter::default(),
+			unknown_kinds: UnknownKindPolicy::default(),
+			default_priority: default_priority(),
+			on_finish: FinishPolicy::default(),
+			stale_threshold: None,
+			empty_debounce: 0,
+			max_pending: None,
+			queue_full_retry_after: default_queue_full_retry_after(),
+			max_claims_per_worker: None,
+			check_indexes: false,
+			callback_hosts: BTreeSet::new(),
+			callback_secret: None,
+			callback_retries: default_callback_retries(),
+			callback_retry_delay_ms: default_callback_retry_delay_ms(),
+			worker_timeout: None,
+		}
 	}
+}
 
-	#[tokio::test]
-	async fn test_finish() {
-		let env = test_env().await;
-		let jq = env.job_queue;
+fn default_max_attempts() -> u32 {
+	3
+}
 
-		let mut db = env.database.get().await.unwrap();
-		jq.enqueue(&mut db, JobCommand::SyncBranch(1))
-			.await
-			.unwrap();
-		drop(db);
+fn default_max_retry_delay() -> u64 {
+	3600
+}
 
-		let id = jq.fetch_and_start().await.unwrap().unwrap().id;
+fn default_queue_full_retry_after() -> u64 {
+	30
+}
 
-		let mut db = env.database.get().await.unwrap();
-		jq.finish_job(&mut db, id).await.unwrap();
-		assert_eq!(
-			db.get_result::<_, i64>(dsl::job_queue.count())
-				.await
-				.unwrap(),
-			0
-		);
-		drop(db);
+fn default_callback_retries() -> u32 {
+	3
+}
 
-		assert!(jq.fetch_and_start().await.unwrap().is_none());
+fn default_callback_retry_delay_ms() -> u64 {
+	1000
+}
+
+fn default_tenant_weight() -> u32 {
+	1
+}
+
+fn default_priority() -> u16 {
+	Priority::Normal.value()
+}
+
+/// Encoding of job data in the database.
+///
+/// Jobs are always decoded according to how they were stored,
+/// so the encoding can be changed at any time.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum JobDataEncoding {
+	/// Stored as JSON in the `data` column.
+	#[default]
+	Json,
+	/// Stored as MessagePack in the `data_bin` column.
+	MessagePack,
+	/// Stored as CBOR in the `data_bin` column.
+	Cbor,
+}
+
+/// Codec of compressed job data.
+///
+/// Compressed data is identified by the magic bytes of the codec.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum CompressionCodec {
+	#[default]
+	Zstd,
+	Gzip,
+}
+
+impl CompressionCodec {
+	const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+	const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+	fn compress(self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+		match self {
+			Self::Zstd => zstd::encode_all(data, 0),
+			Self::Gzip => {
+				let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+				encoder.write_all(data)?;
+				encoder.finish()
+			}
+		}
+	}
+
+	/// Decompresses data with the codec identified by its magic bytes.
+	fn decompress(data: &[u8]) -> std::io::Result<Vec<u8>> {
+		if data.starts_with(Self::ZSTD_MAGIC) {
+			zstd::decode_all(data)
+		} else if data.starts_with(Self::GZIP_MAGIC) {
+			let mut decoded = Vec::new();
+			GzDecoder::new(data).read_to_end(&mut decoded)?;
+			Ok(decoded)
+		} else {
+			Err(std::io::Error::new(
+				std::io::ErrorKind::InvalidData,
+				"unknown compression codec",
+			))
+		}
+	}
+}
+
+/// Values of `data`, `data_bin` and `compressed` columns.
+type SqlJobData = (XJsonVal, Option<Vec<u8>>, bool);
+
+/// Key of references to offloaded payloads in the `data` column.
+///
+/// Job data with only this key is a reference to be loaded from the
+/// [PayloadStore]. Such references are returned as is when listing jobs.
+const PAYLOAD_REF: &str = "$payload";
+
+/// Returns the key of an offloaded payload, if `data` is a reference to it.
+fn payload_key(data: &serde_json::Value) -> Option<&str> {
+	match data {
+		serde_json::Value::Object(object) if object.len() == 1 => {
+			object.get(PAYLOAD_REF).and_then(|key| key.as_str())
+		}
+		_ => None,
+	}
+}
+
+impl JobDataEncoding {
+	/// Format byte of JSON in `data_bin`.
+	const FORMAT_JSON: u8 = 0;
+	/// Format byte of MessagePack in `data_bin`.
+	const FORMAT_MSGPACK: u8 = 1;
+	/// Format byte of CBOR in `data_bin`.
+	const FORMAT_CBOR: u8 = 2;
+
+	/// Encodes job data into values of `data`, `data_bin` and `compressed` columns.
+	///
+	/// The data is compressed if `compress` is `Some(true)`, or if `compress`
+	/// is `None` and the encoded data is not smaller than `threshold`.
+	fn encode(
+		&self,
+		value: serde_json::Value,
+		compress: Option<bool>,
+		threshold: Option<usize>,
+		codec: CompressionCodec,
+	) -> Result<SqlJobData> {
+		let data = match self {
+			JobDataEncoding::Json => {
+				let mut data = vec![Self::FORMAT_JSON];
+				serde_json::to_writer(&mut data, &value)?;
+				data
+			}
+			JobDataEncoding::MessagePack => {
+				let mut data = vec![Self::FORMAT_MSGPACK];
+				rmp_serde::encode::write(&mut data, &value)
+					.map_err(|error| JobQueueError::DataEncoding(error.to_string()))?;
+				data
+			}
+			JobDataEncoding::Cbor => {
+				let mut data = vec![Self::FORMAT_CBOR];
+				ciborium::into_writer(&value, &mut data)
+					.map_err(|error| JobQueueError::DataEncoding(error.to_string()))?;
+				data
+			}
+		};
+
+		let compress =
+			compress.unwrap_or_else(|| threshold.is_some_and(|threshold| data.len() >= threshold));
+		if compress {
+			let data = codec
+				.compress(&data)
+				.map_err(|error| JobQueueError::DataEncoding(error.to_string()))?;
+			Ok((XJsonVal(serde_json::Value::Null), Some(data), true))
+		} else if *self == JobDataEncoding::Json {
+			Ok((XJsonVal(value), None, false))
+		} else {
+			Ok((XJsonVal(serde_json::Value::Null), Some(data), false))
+		}
+	}
+
+	/// Decodes job data from values of `data`, `data_bin` and `compressed` columns.
+	fn decode((data, data_bin, compressed): SqlJobData) -> Result<serde_json::Value> {
+		let Some(mut data_bin) = data_bin else {
+			return Ok(data.0);
+		};
+		if compressed {
+			data_bin = CompressionCodec::decompress(&data_bin)
+				.map_err(|error| JobQueueError::DataEncoding(error.to_string()))?;
+		}
+		match data_bin.split_first() {
+			Some((&Self::FORMAT_JSON, value)) => Ok(serde_json::from_slice(value)?),
+			Some((&Self::FORMAT_MSGPACK, value)) => Ok(rmp_serde::from_slice(value)
+				.map_err(|error| JobQueueError::DataEncoding(error.to_string()))?),
+			Some((&Self::FORMAT_CBOR, value)) => Ok(ciborium::from_reader(value)
+				.map_err(|error| JobQueueError::DataEncoding(error.to_string()))?),
+			Some((format, _)) => {
+				Err(JobQueueError::DataEncoding(format!("unknown format {}", format)).into())
+			}
+			None => Err(JobQueueError::DataEncoding("empty binary data".to_string()).into()),
+		}
+	}
+}
+
+/// Generator of job IDs.
+///
+/// Jobs with the same priority are ordered by ID,
+/// so generated IDs should be monotonically increasing.
+pub trait IdGenerator
+where
+	Self: Send + Sync + Debug,
+{
+	fn generate(&self) -> JobRef;
+}
+
+/// Generates UUID v7 as job IDs.
+///
+/// This is the default [IdGenerator].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UuidV7Generator;
+
+impl IdGenerator for UuidV7Generator {
+	fn generate(&self) -> JobRef {
+		Uuid::now_v7()
+	}
+}
+
+/// Source of the current time.
+///
+/// Time-dependent logic of [JobQueue] reads time from a clock,
+/// so that tests can advance time without sleeping.
+pub trait Clock
+where
+	Self: Send + Sync + Debug,
+{
+	/// Returns the current time in UTC.
+	fn now(&self) -> PrimitiveDateTime;
+}
+
+/// Reads time from the system.
+///
+/// This is the default [Clock].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+	fn now(&self) -> PrimitiveDateTime {
+		let time = OffsetDateTime::now_utc();
+		PrimitiveDateTime::new(time.date(), time.time())
+	}
+}
+
+/// Observer of events of [JobQueue], such as for alerting.
+///
+/// All methods do nothing by default.
+pub trait JobObserver
+where
+	Self: Send + Sync + Debug,
+{
+	/// Called when the oldest pending job exceeds the stale threshold.
+	///
+	/// See [JobQueue::set_stale_threshold].
+	fn on_stale(&self, oldest_age: time::Duration) {
+		let _ = oldest_age;
+	}
+
+	/// Called when the queue becomes empty, e.g. to scale workers to zero.
+	///
+	/// See [JobQueue::check_empty].
+	fn on_queue_empty(&self) {}
+
+	/// Called when a job is enqueued into an empty queue.
+	fn on_queue_nonempty(&self) {}
+}
+
+/// Ignores all events.
+///
+/// This is the default [JobObserver].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopObserver;
+
+impl JobObserver for NoopObserver {}
+
+/// Admission policy of jobs, run before jobs are persisted, including
+/// continuations enqueued with [JobQueue::continue_job].
+///
+/// Interceptors run in the order they are added with
+/// [JobQueue::with_interceptor], each seeing changes made by previous ones.
+pub trait EnqueueInterceptor
+where
+	Self: Send + Sync + Debug,
+{
+	/// Checks a job about to be enqueued, possibly rewriting its command or options.
+	///
+	/// Returning an error rejects the job, and no later interceptors are run.
+	fn intercept(
+		&self,
+		job: &mut JobCommand,
+		options: &mut EnqueueOptions,
+	) -> Result<(), JobQueueError>;
+}
+
+/// Indexes checked by [JobQueue::check_indexes], with statements to create
+/// them on PostgreSQL and SQLite.
+const EXPECTED_INDEXES: [(&str, &str, &str); 2] = [
+	(
+		"job_queue_id",
+		r#"CREATE UNIQUE INDEX "job_queue_id" ON "job_queue" ("id");"#,
+		"CREATE UNIQUE INDEX `job_queue_id` ON `job_queue` (`id`);",
+	),
+	(
+		"job_queue_poll",
+		r#"CREATE INDEX "job_queue_poll" ON "job_queue" ("kind", ("started_at" IS NULL), "priority" DESC, "seq");"#,
+		"CREATE INDEX `job_queue_poll` ON `job_queue` (`kind`, (`started_at` IS NULL), `priority` DESC, `seq`);",
+	),
+];
+
+/// State of [JobQueue::check_empty].
+#[derive(Debug, Default)]
+struct EmptyState {
+	/// Whether the queue was last reported empty, or `None` if never reported.
+	empty: Option<bool>,
+	/// Time since which the queue has been observed empty.
+	empty_since: Option<PrimitiveDateTime>,
+}
+
+/// State of [JobQueue::check_stale].
+#[derive(Debug)]
+struct StaleState {
+	threshold: Option<time::Duration>,
+	/// Whether the threshold has been exceeded and not recovered.
+	alerting: bool,
+}
+
+#[derive(Debug)]
+pub struct JobQueue {
+	db: Arc<DatabaseService>,
+	config: JobQueueConfig,
+	id_generator: Box<dyn IdGenerator>,
+	notifier: Box<dyn JobNotifier>,
+	payload_store: Box<dyn PayloadStore>,
+	callback_sender: Arc<dyn CallbackSender>,
+	clock: Box<dyn Clock>,
+	observer: Box<dyn JobObserver>,
+	interceptors: Vec<Box<dyn EnqueueInterceptor>>,
+	stale: Mutex<StaleState>,
+	empty: Mutex<EmptyState>,
+	tenant_passes: Mutex<TenantPasses>,
+	draining: AtomicBool,
+}
+
+/// Pass advanced by claiming a job of a tenant with weight 1.
+const TENANT_STRIDE: u64 = 1 << 20;
+
+/// State of stride scheduling of claims across tenants.
+///
+/// The tenant with the lowest pass is claimed from next, and its pass
+/// advances inversely proportional to its weight.
+#[derive(Debug, Default)]
+struct TenantPasses {
+	/// Pass of the most recently picked tenant.
+	current: u64,
+	passes: HashMap<String, u64>,
+}
+
+impl JobQueue {
+	pub fn new(db: Arc<DatabaseService>, config: JobQueueConfig) -> Self {
+		let stale = StaleState {
+			threshold: config
+				.stale_threshold
+				.map(|secs| time::Duration::seconds(secs as i64)),
+			alerting: false,
+		};
+		Self {
+			db,
+			config,
+			id_generator: Box::new(UuidV7Generator),
+			notifier: Box::new(LocalJobNotifier::new()),
+			payload_store: Box::new(InlinePayloadStore),
+			callback_sender: Arc::new(HttpCallbackSender::new()),
+			clock: Box::new(SystemClock),
+			observer: Box::new(NoopObserver),
+			interceptors: Vec::new(),
+			stale: Mutex::new(stale),
+			empty: Mutex::new(EmptyState::default()),
+			tenant_passes: Mutex::new(TenantPasses::default()),
+			draining: AtomicBool::new(false),
+		}
+	}
+
+	/// Returns the configuration of this queue.
+	pub fn config(&self) -> &JobQueueConfig {
+		&self.config
+	}
+
+	/// Replaces the generator of job IDs.
+	pub fn with_id_generator<G: IdGenerator + 'static>(mut self, generator: G) -> Self {
+		self.id_generator = Box::new(generator);
+		self
+	}
+
+	/// Replaces the clock.
+	///
+	/// By default, a [SystemClock] is used.
+	pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+		self.clock = Box::new(clock);
+		self
+	}
+
+	/// Returns the clock of time-dependent logic.
+	pub fn clock(&self) -> &dyn Clock {
+		self.clock.as_ref()
+	}
+
+	/// Replaces the notifier of enqueued jobs.
+	///
+	/// By default, a [LocalJobNotifier] is used.
+	pub fn with_notifier<N: JobNotifier + 'static>(mut self, notifier: N) -> Self {
+		self.notifier = Box::new(notifier);
+		self
+	}
+
+	/// Replaces the store of large job payloads.
+	///
+	/// Payloads are offloaded only with [`JobQueueConfig::offload_threshold`] set.
+	pub fn with_payload_store<S: PayloadStore + 'static>(mut self, store: S) -> Self {
+		self.payload_store = Box::new(store);
+		self
+	}
+
+	/// Replaces the sender of completion callbacks.
+	///
+	/// By default, a [HttpCallbackSender] is used.
+	pub fn with_callback_sender<S: CallbackSender + 'static>(mut self, sender: S) -> Self {
+		self.callback_sender = Arc::new(sender);
+		self
+	}
+
+	/// Returns the notifier of enqueued jobs, for workers to subscribe to.
+	pub fn notifier(&self) -> &dyn JobNotifier {
+		self.notifier.as_ref()
+	}
+
+	/// Replaces the observer of queue events.
+	///
+	/// By default, a [NoopObserver] is used.
+	pub fn with_observer<O: JobObserver + 'static>(mut self, observer: O) -> Self {
+		self.observer = Box::new(observer);
+		self
+	}
+
+	/// Appends an interceptor to the chain run before enqueuing jobs.
+	pub fn with_interceptor<I: EnqueueInterceptor + 'static>(mut self, interceptor: I) -> Self {
+		self.interceptors.push(Box::new(interceptor));
+		self
+	}
+
+	/// Sets the maximum age of the oldest pending job, before
+	/// [JobObserver::on_stale] is called by [JobQueue::check_stale].
+	pub fn set_stale_threshold(&self, threshold: time::Duration) {
+		self.stale.lock().unwrap().threshold = Some(threshold);
+	}
+
+	/// Checks the age of the oldest pending job against the stale threshold.
+	///
+	/// [JobObserver::on_stale] is called once when the threshold is exceeded.
+	/// To avoid flapping, it is not called again until the age has dropped
+	/// below half of the threshold.
+	/// This does nothing if no threshold is set.
+	///
+	/// Returns the age of the oldest pending job, if any.
+	pub async fn check_stale(&self) -> Result<Option<time::Duration>> {
+		let Some(threshold) = self.stale.lock().unwrap().threshold else {
+			return Ok(None);
+		};
+		let mut conn = self.db.get().await?;
+		let oldest = conn
+			.get_result::<_, XUuidVal>(
+				dsl::job_queue
+					.filter(dsl::started_at.is_null())
+					.order(dsl::id.asc())
+					.select(dsl::id)
+					.limit(1),
+			)
+			.await
+			.optional()?;
+		drop(conn);
+		let age = oldest
+			.and_then(|id| uuid_v7_time(id.0))
+			.map(|enqueued_at| self.clock.now() - enqueued_at);
+
+		let mut stale = self.stale.lock().unwrap();
+		let fire = match age {
+			Some(age) if age > threshold => !std::mem::replace(&mut stale.alerting, true),
+			Some(age) if age * 2 >= threshold => false,
+			_ => {
+				stale.alerting = false;
+				false
+			}
+		};
+		drop(stale);
+		if let (true, Some(age)) = (fire, age) {
+			warn!(%age, %threshold, "oldest pending job exceeds the stale threshold");
+			self.observer.on_stale(age);
+		}
+		Ok(age)
+	}
+
+	/// Checks if the queue has become empty, with neither pending nor running jobs.
+	///
+	/// [JobObserver::on_queue_empty] is called once the queue has been observed
+	/// empty for [`JobQueueConfig::empty_debounce`], so that short gaps between
+	/// jobs are not reported. It is not called again until a job is enqueued,
+	/// when [JobObserver::on_queue_nonempty] is called.
+	///
+	/// Returns whether the queue is empty.
+	pub async fn check_empty(&self) -> Result<bool> {
+		let mut conn = self.db.get().await?;
+		let is_empty = conn
+			.get_result::<_, XUuidVal>(dsl::job_queue.select(dsl::id).limit(1))
+			.await
+			.optional()?
+			.is_none();
+		drop(conn);
+		let now = self.clock.now();
+		let debounce = time::Duration::seconds(self.config.empty_debounce as i64);
+
+		let mut state = self.empty.lock().unwrap();
+		if !is_empty {
+			state.empty_since = None;
+			let fire = state.empty.replace(false) == Some(true);
+			drop(state);
+			if fire {
+				// enqueued by another process
+				info!("job queue becomes nonempty");
+				self.observer.on_queue_nonempty();
+			}
+			return Ok(false);
+		}
+		let since = *state.empty_since.get_or_insert(now);
+		let fire = now - since >= debounce && state.empty != Some(true);
+		if fire {
+			state.empty = Some(true);
+		}
+		drop(state);
+		if fire {
+			info!("job queue becomes empty");
+			self.observer.on_queue_empty();
+		}
+		Ok(true)
+	}
+
+	/// Stops accepting new jobs.
+	///
+	/// After draining, enqueueing fails with [JobQueueError::Draining],
+	/// and no more pending jobs are started.
+	/// Running jobs can still be finished or failed.
+	pub fn drain(&self) {
+		if !self.draining.swap(true, atomic::Ordering::SeqCst) {
+			info!("draining job queue");
+		}
+	}
+
+	/// Checks if the job queue is draining.
+	pub fn is_draining(&self) -> bool {
+		self.draining.load(atomic::Ordering::SeqCst)
+	}
+
+	/// Enqueues a job with [`JobQueueConfig::default_priority`].
+	pub async fn enqueue(&self, conn: &mut BoxedSqlConn, job: JobCommand) -> Result<JobRef> {
+		self.enqueue_with_priority(conn, job, self.config.default_priority)
+			.await
+	}
+
+	pub async fn enqueue_with_priority(
+		&self,
+		conn: &mut BoxedSqlConn,
+		job: JobCommand,
+		priority: u16,
+	) -> Result<JobRef> {
+		let options = EnqueueOptions {
+			priority: Some(priority),
+			..Default::default()
+		};
+		Ok(self.enqueue_with(conn, job, options).await?.id())
+	}
+
+	/// Enqueues a job with the priority of a [Priority] tier.
+	pub async fn enqueue_with_tier(
+		&self,
+		conn: &mut BoxedSqlConn,
+		job: JobCommand,
+		tier: Priority,
+	) -> Result<JobRef> {
+		self.enqueue_with_priority(conn, job, tier.value()).await
+	}
+
+	/// Enqueues a job before pending jobs of the same priority, with
+	/// [`JobQueueConfig::default_priority`].
+	///
+	/// Unlike a higher priority, this does not affect the ordering of jobs
+	/// of other priorities.
+	pub async fn enqueue_front(&self, conn: &mut BoxedSqlConn, job: JobCommand) -> Result<JobRef> {
+		let options = EnqueueOptions {
+			front: true,
+			..Default::default()
+		};
+		Ok(self.enqueue_with(conn, job, options).await?.id())
+	}
+
+	/// Enqueues a job with options.
+	///
+	/// With a dedup or idempotency key, this reports whether an existing job
+	/// was matched instead.
+	pub async fn enqueue_with(
+		&self,
+		conn: &mut BoxedSqlConn,
+		job: JobCommand,
+		options: EnqueueOptions,
+	) -> Result<EnqueueOutcome> {
+		let (kind, outcome) = self.insert_job(conn, job, options).await?;
+		if let EnqueueOutcome::Enqueued { id } = outcome {
+			self.notify_enqueued(conn, &kind, id).await;
+		}
+		Ok(outcome)
+	}
+
+	/// Runs interceptors and inserts a job, without notifying workers.
+	///
+	/// Returns the kind of the job, along with the outcome.
+	async fn insert_job(
+		&self,
+		conn: &mut BoxedSqlConn,
+		mut job: JobCommand,
+		mut options: EnqueueOptions,
+	) -> Result<(KString, EnqueueOutcome)> {
+		if self.is_draining() {
+			return Err(JobQueueError::Draining.into());
+		}
+		// interceptors see the priority the job would be enqueued with
+		options.priority.get_or_insert(self.config.default_priority);
+		for interceptor in &self.interceptors {
+			interceptor.intercept(&mut job, &mut options)?;
+		}
+		if let Some(url) = &options.callback_url {
+			job_callback::check_url(url, &self.config.callback_hosts)?;
+		}
+		let id = self.id_generator.generate();
+		let branch = job.branch();
+		let trace_context = options
+			.trace_context
+			.or_else(TraceContext::current)
+			.map(|context| context.to_string());
+		let (kind, job_data) = job.serialize()?;
+		let (job_data, job_data_bin, compressed) =
+			self.encode_data(id, job_data, options.compress).await?;
+		let offloaded = payload_key(&job_data.0).map(str::to_string);
+
+		let result = conn
+			.retry_on_serialization(async |conn| {
+				// the ID is new, so no job depends on this job and dependencies
+				// never form a cycle
+				if let Some(key) = &options.idempotency_key {
+					let claimed = conn
+						.execute(
+							insert_into(job_idempotency::table)
+								.values((
+									job_idempotency::idempotency_key.eq(key),
+									job_idempotency::job.eq(XUuidVal(id)),
+									job_idempotency::created_at.eq(self.clock.now()),
+								))
+								.on_conflict(job_idempotency::idempotency_key)
+								.do_nothing(),
+						)
+						.await? != 0;
+					if !claimed {
+						let existing = conn
+							.get_result::<_, XUuidVal>(
+								job_idempotency::table
+									.filter(job_idempotency::idempotency_key.eq(key))
+									.select(job_idempotency::job),
+							)
+							.await?;
+						return Ok((existing.0, false));
+					}
+				}
+
+				if let Some(max_pending) = self.config.max_pending {
+					let pending = conn
+						.get_result::<_, i64>(
+							dsl::job_queue.filter(dsl::started_at.is_null()).count(),
+						)
+						.await?;
+					if pending as u64 >= max_pending {
+						warn!(pending, "rejected enqueuing into a full job queue");
+						let retry_after = self.config.queue_full_retry_after;
+						return Err(JobQueueError::QueueFull(retry_after).into());
+					}
+				}
+
+				let priority =
+					priority_into_sql(options.priority.unwrap_or(self.config.default_priority));
+				let seq = if options.front {
+					let first = conn
+						.get_result::<_, Option<i64>>(
+							dsl::job_queue
+								.filter(dsl::priority.eq(priority).and(dsl::started_at.is_null()))
+								.select(diesel::dsl::min(dsl::seq)),
+						)
+						.await?;
+					first.unwrap_or_default().min(0) - 1
+				} else {
+					0
+				};
+
+				// with a conflicting dedup key, nothing is inserted or returned
+				let inserted = conn
+					.get_result::<_, XUuidVal>(
+						insert_into(dsl::job_queue)
+							.values((
+								dsl::id.eq(XUuidVal(id)),
+								dsl::kind.eq(kind.as_str()),
+								dsl::data.eq(job_data.clone()),
+								dsl::data_bin.eq(job_data_bin.clone()),
+								dsl::compressed.eq(compressed),
+								dsl::priority.eq(priority),
+								dsl::seq.eq(seq),
+								dsl::branch.eq(branch),
+								dsl::dedup_key.eq(&options.dedup_key),
+								dsl::trace_context.eq(&trace_context),
+								dsl::tenant.eq(options.tenant.as_deref().unwrap_or_default()),
+								dsl::callback_url.eq(&options.callback_url),
+							))
+							.on_conflict(dsl::dedup_key)
+							.do_nothing()
+							.returning(dsl::id),
+					)
+					.await
+					.optional()?;
+				let Some(id) = inserted else {
+					let existing = conn
+						.get_result::<_, XUuidVal>(
+							dsl::job_queue
+								.filter(dsl::dedup_key.eq(&options.dedup_key))
+								.select(dsl::id),
+						)
+						.await?;
+					return Ok((existing.0, false));
+				};
+
+				for dependency in &options.depends_on {
+					let exists = conn
+						.get_result::<_, i64>(
+							dsl::job_queue
+								.filter(dsl::id.eq(XUuidVal(*dependency)))
+								.count(),
+						)
+						.await? != 0;
+					if !exists {
+						let failed = conn
+							.get_result::<_, i64>(
+								job_dead_letter::table
+									.filter(job_dead_letter::id.eq(XUuidVal(*dependency)))
+									.count(),
+							)
+							.await? != 0;
+						if failed {
+							return Err(JobQueueError::DependencyFailed(*dependency).into());
+						}
+						// already finished
+						continue;
+					}
+					conn.execute(insert_into(job_dependency::table).values((
+						job_dependency::job.eq(id),
+						job_dependency::depends_on.eq(XUuidVal(*dependency)),
+					)))
+					.await?;
+				}
+
+				Ok((id.0, true))
+			})
+			.await;
+		// the offloaded payload is not referenced unless the job is inserted
+		if let Some(key) = offloaded.filter(|_| !matches!(result, Ok((_, true)))) {
+			let deleted = self.payload_store.delete(&key).await;
+			if let Err(error) = deleted {
+				warn!(%id, key, %error, "failed to delete unreferenced job payload");
+			}
+		}
+		let (id, inserted) = result?;
+		if !inserted {
+			info!(%kind, %id, "job with the same key is already enqueued");
+			return Ok((kind, EnqueueOutcome::Deduplicated { id }));
+		}
+		info!(%kind, %id, "enqueued job");
+		Ok((kind, EnqueueOutcome::Enqueued { id }))
+	}
+
+	/// Notifies workers of a job enqueued on `conn`.
+	async fn notify_enqueued(&self, conn: &mut BoxedSqlConn, kind: &str, id: JobRef) {
+		if let Err(error) = self.notifier.notify(conn, kind).await {
+			// workers poll periodically anyway
+			warn!(%kind, %id, %error, "failed to notify job workers");
+		}
+
+		let mut state = self.empty.lock().unwrap();
+		state.empty_since = None;
+		let fire = state.empty.replace(false) != Some(false);
+		drop(state);
+		if fire {
+			info!("job queue becomes nonempty");
+			self.observer.on_queue_nonempty();
+		}
+	}
+
+	/// Rejects dependencies which would form a cycle with the job `id`.
+	async fn check_dependency_cycle(
+		conn: &mut BoxedSqlConn,
+		id: JobRef,
+		depends_on: &[JobRef],
+	) -> Result<()> {
+		let mut visited = HashSet::new();
+		let mut queue = depends_on.to_vec();
+		while let Some(job) = queue.pop() {
+			if job == id {
+				return Err(JobQueueError::DependencyCycle(id).into());
+			}
+			if !visited.insert(job) {
+				continue;
+			}
+			let dependencies = conn
+				.load::<_, XUuidVal>(
+					job_dependency::table
+						.filter(job_dependency::job.eq(XUuidVal(job)))
+						.select(job_dependency::depends_on),
+				)
+				.await?;
+			queue.extend(dependencies.into_iter().map(|job| job.0));
+		}
+		Ok(())
+	}
+
+	pub async fn fetch_and_start(&self) -> Result<Option<Job>> {
+		self.fetch_and_start_with(&ClaimOptions::default()).await
+	}
+
+	/// Fetches and starts a pending job, only considering jobs of `kinds`.
+	///
+	/// If `kinds` is `None`, jobs of all kinds are considered.
+	pub async fn fetch_and_start_kinds(&self, kinds: Option<&[KString]>) -> Result<Option<Job>> {
+		self.fetch_and_start_with(&ClaimOptions {
+			kinds,
+			..Default::default()
+		})
+		.await
+	}
+
+	/// Fetches and starts a pending job matching `options`.
+	pub async fn fetch_and_start_with(&self, options: &ClaimOptions<'_>) -> Result<Option<Job>> {
+		let started = Instant::now();
+		let result = self.claim(options).await;
+		self.warn_if_slow("claim loop", started.elapsed());
+		result
+	}
+
+	/// Fetches and starts up to `n` pending jobs in a transaction.
+	///
+	/// This saves round trips for workers of fast jobs. Each job is claimed
+	/// as with [JobQueue::fetch_and_start], and must be finished or failed
+	/// independently.
+	pub async fn fetch_and_start_batch(&self, n: usize) -> Result<Vec<Job>> {
+		self.fetch_and_start_batch_with(n, &ClaimOptions::default())
+			.await
+	}
+
+	/// Fetches and starts up to `n` pending jobs matching the options in a transaction.
+	///
+	/// The transaction is retried on serialization failures and deadlocks,
+	/// see [BoxedSqlConn::retry_on_serialization].
+	pub async fn fetch_and_start_batch_with(
+		&self,
+		n: usize,
+		options: &ClaimOptions<'_>,
+	) -> Result<Vec<Job>> {
+		let started = Instant::now();
+		let mut conn = self.db.get().await?;
+		let result = conn
+			.retry_on_serialization(async |conn| {
+				let mut jobs = Vec::with_capacity(n);
+				while jobs.len() < n {
+					match self.claim_with(conn, options).await? {
+						Some(job) => jobs.push(job),
+						None => break,
+					}
+				}
+				Ok(jobs)
+			})
+			.await;
+		self.warn_if_slow("batch claim", started.elapsed());
+		result
+	}
+
+	/// Picks the tenant to claim a job from among `tenants` with pending jobs,
+	/// and advances its pass.
+	fn pick_tenant(&self, tenants: &[String]) -> Option<String> {
+		let mut state = self.tenant_passes.lock().unwrap();
+		let TenantPasses { current, passes } = &mut *state;
+		// tenants without pending jobs for a while do not gain credits,
+		// so that they do not claim in bursts when they return
+		let tenant = tenants.iter().min_by_key(|tenant| {
+			let pass = passes.get(*tenant).copied().unwrap_or_default();
+			(pass.max(*current), *tenant)
+		})?;
+		let weight = self
+			.config
+			.tenant_weights
+			.get(tenant.as_str())
+			.copied()
+			.unwrap_or(self.config.default_tenant_weight)
+			.max(1);
+		let pass = passes.entry(tenant.clone()).or_default();
+		*current = (*pass).max(*current);
+		*pass = *current + TENANT_STRIDE / weight as u64;
+		Some(tenant.clone())
+	}
+
+	async fn claim(&self, options: &ClaimOptions<'_>) -> Result<Option<Job>> {
+		let mut conn = self.db.get().await?;
+		self.claim_with(&mut conn, options).await
+	}
+
+	async fn claim_with(
+		&self,
+		conn: &mut BoxedSqlConn,
+		options: &ClaimOptions<'_>,
+	) -> Result<Option<Job>> {
+		if self.is_draining() {
+			return Ok(None);
+		}
+		let all_kinds = options.kinds.is_none();
+		let kinds = options.kinds.unwrap_or_default();
+		let min_priority = options.min_priority;
+		let quarantine = self.config.unknown_kinds == UnknownKindPolicy::Quarantine;
+		let fair = !self.config.tenant_weights.is_empty();
+
+		if let (Some(worker), Some(cap)) = (options.worker, self.config.max_claims_per_worker) {
+			let held = conn
+				.get_result::<_, i64>(
+					dsl::job_queue
+						.filter(dsl::claimed_by.eq(worker))
+						.filter(dsl::started_at.is_not_null())
+						.count(),
+				)
+				.await?;
+			if held >= i64::from(cap) {
+				debug!(worker, held, "withheld claims from worker at its cap");
+				return Ok(None);
+			}
+		}
+
+		loop {
+			let time = self.clock.now();
+
+			// find a pending job, of which all dependencies are finished
+			// priorities are stored as non-negative SMALLINTs, see `priority_into_sql`,
+			// so ordering them descending claims higher ones first
+			// for jobs with the same priority, we order them with ID.
+			// because ID are UUID v7, this is equivalent to ordering with
+			// insertion time, ascending or descending as configured
+			let query_started = Instant::now();
+			let eligible = || {
+				dsl::job_queue
+					.filter(dsl::started_at.is_null())
+					.filter(
+						dsl::next_attempt_at
+							.is_null()
+							.or(dsl::next_attempt_at.le(time)),
+					)
+					.filter(dsl::id.ne_all(job_dependency::table.select(job_dependency::job)))
+					.filter(
+						dsl::kind
+							.eq_any(kinds.iter().map(KString::as_str))
+							.or(all_kinds.into_sql::<Bool>()),
+					)
+					.filter(
+						dsl::priority
+							.ge(min_priority.unwrap_or_default())
+							.or(min_priority.is_none().into_sql::<Bool>()),
+					)
+					.filter(
+						dsl::kind
+							.eq_any(JobCommand::KINDS.iter().copied())
+							.or(quarantine.into_sql::<Bool>()),
+					)
+			};
+			// with fair sharing, the tenant to claim from is chosen first
+			let tenant = if fair {
+				let tenants = conn
+					.load::<_, String>(eligible().select(dsl::tenant).distinct())
+					.await?;
+				self.pick_tenant(&tenants).unwrap_or_default()
+			} else {
+				String::new()
+			};
+			let query = || {
+				eligible()
+					.filter(
+						dsl::tenant
+							.eq(tenant.as_str())
+							.or((!fair).into_sql::<Bool>()),
+					)
+					.limit(1)
+					.select((
+						dsl::id,
+						dsl::kind,
+						(dsl::data, dsl::data_bin, dsl::compressed),
+						dsl::attempts,
+						dsl::trace_context,
+					))
+			};
+			let result = match self.config.ordering {
+				JobOrdering::Fifo => {
+					conn.get_result::<_, SqlClaimedJob>(query().order((
+						dsl::priority.desc(),
+						dsl::seq.asc(),
+						dsl::id.asc(),
+					)))
+					.await
+				}
+				JobOrdering::Lifo => {
+					conn.get_result::<_, SqlClaimedJob>(query().order((
+						dsl::priority.desc(),
+						dsl::seq.asc(),
+						dsl::id.desc(),
+					)))
+					.await
+				}
+			}
+			.optional()?;
+			self.warn_if_slow("claim query", query_started.elapsed());
+			if let Some((id, kind, data, attempts, trace_context)) = result {
+				let cols = conn
+					.execute(
+						update(dsl::job_queue)
+							.filter(dsl::id.eq(id).and(dsl::started_at.is_null()))
+							.set((dsl::started_at.eq(time), dsl::claimed_by.eq(options.worker))),
+					)
+					.await?;
+				#[cfg(test)]
+				assert!(cols != 0);
+				if cols == 0 {
+					warn!(%id, "SQL lightweight job queue polling hit contented");
+					continue;
+				}
+				info!(%id, "polled lightweight job");
+				conn.execute(insert_into(job_attempt::table).values((
+					job_attempt::job.eq(id),
+					job_attempt::attempt.eq(attempts + 1),
+					job_attempt::started_at.eq(time),
+					job_attempt::outcome.eq(AttemptOutcome::Running as i16),
+				)))
+				.await?;
+				if !JobCommand::KINDS.contains(&kind.as_str()) {
+					warn!(%id, %kind, "quarantined job of unknown kind");
+					self.dead_letter(
+						conn,
+						id.0,
+						"unknown job kind",
+						Some(JobErrorCode::Validation),
+					)
+					.await?;
+					continue;
+				}
+				// failures of the payload store are not faults of the job,
+				// so the claim is undone and they are returned instead of
+				// dead-lettering the job
+				let data = match JobDataEncoding::decode(data) {
+					Ok(data) => match self.load_payload(data).await {
+						Ok(data) => Ok(data),
+						Err(error) => {
+							warn!(%id, %error, "failed to load job payload, unclaiming the job");
+							self.unclaim(conn, id.0).await?;
+							return Err(error);
+						}
+					},
+					Err(error) => Err(error),
+				};
+				let cmd = data.and_then(|data| Ok(JobCommand::deserialize(&kind, data)?));
+				match cmd {
+					Ok(cmd) => {
+						return Ok(Some(Job {
+							id: id.0,
+							command: cmd,
+							trace_context: trace_context.as_deref().and_then(TraceContext::parse),
+						}));
+					}
+					Err(error) => {
+						// a poison job should not block the worker
+						warn!(%id, %kind, %error, "failed to deserialize job");
+						self.dead_letter(
+							conn,
+							id.0,
+							truncate_error(&error.to_string()),
+							Some(JobErrorCode::Validation),
+						)
+						.await?;
+						continue;
+					}
+				}
+			} else {
+				return Ok(None);
+			}
+		}
+	}
+
+	/// Warns if an operation took longer than [`JobQueueConfig::slow_claim_threshold_ms`].
+	///
+	/// Returns `true` if the warning is emitted.
+	fn warn_if_slow(&self, operation: &str, elapsed: std::time::Duration) -> bool {
+		let Some(threshold) = self.config.slow_claim_threshold_ms else {
+			return false;
+		};
+		let elapsed_ms = elapsed.as_millis() as u64;
+		if elapsed_ms < threshold {
+			return false;
+		}
+		warn!(
+			operation,
+			elapsed_ms,
+			threshold_ms = threshold,
+			"slow job claim"
+		);
+		true
+	}
+
+	/// Returns information about a job in the queue.
+	///
+	/// Finished jobs are not in the queue.
+	pub async fn get_job(&self, id: JobRef) -> Result<Option<JobInfo>> {
+		let mut conn = self.db.get().await?;
+
+		let job = conn
+			.get_result::<_, SqlJobInfo>(dsl::job_queue.filter(dsl::id.eq(XUuidVal(id))).select((
+				dsl::kind,
+				(dsl::data, dsl::data_bin, dsl::compressed),
+				dsl::priority,
+				dsl::started_at,
+				dsl::attempts,
+				dsl::last_error,
+				dsl::trace_context,
+				dsl::claimed_by,
+			)))
+			.await
+			.optional()?;
+		job.map(|job| job_info(id, job)).transpose()
+	}
+
+	/// Explains why a job would or would not be claimed now.
+	///
+	/// Returns `None` if the job is not in the queue.
+	pub async fn explain_claim(&self, id: JobRef) -> Result<Option<ClaimExplanation>> {
+		let mut conn = self.db.get().await?;
+
+		let job = conn
+			.get_result::<_, (
+				String,
+				Option<PrimitiveDateTime>,
+				Option<String>,
+				Option<PrimitiveDateTime>,
+			)>(dsl::job_queue.filter(dsl::id.eq(XUuidVal(id))).select((
+				dsl::kind,
+				dsl::started_at,
+				dsl::claimed_by,
+				dsl::next_attempt_at,
+			)))
+			.await
+			.optional()?;
+		let Some((kind, started_at, claimed_by, next_attempt_at)) = job else {
+			return Ok(None);
+		};
+
+		if started_at.is_some() {
+			return Ok(Some(ClaimExplanation::Running { claimed_by }));
+		}
+		if self.is_draining() {
+			return Ok(Some(ClaimExplanation::Draining));
+		}
+		let depends_on = conn
+			.load::<_, XUuidVal>(
+				job_dependency::table
+					.filter(job_dependency::job.eq(XUuidVal(id)))
+					.select(job_dependency::depends_on)
+					.order(job_dependency::depends_on),
+			)
+			.await?;
+		if !depends_on.is_empty() {
+			return Ok(Some(ClaimExplanation::DependenciesPending {
+				depends_on: depends_on.into_iter().map(|id| id.0).collect(),
+			}));
+		}
+		if let Some(next_attempt_at) = next_attempt_at.filter(|time| *time > self.clock.now()) {
+			return Ok(Some(ClaimExplanation::RetryScheduled { next_attempt_at }));
+		}
+		if !JobCommand::KINDS.contains(&kind.as_str())
+			&& self.config.unknown_kinds == UnknownKindPolicy::Skip
+		{
+			return Ok(Some(ClaimExplanation::UnknownKind));
+		}
+		Ok(Some(ClaimExplanation::Claimable))
+	}
+
+	/// Lists jobs matching a filter, in the order of enqueuing.
+	pub async fn list_jobs(&self, filter: &JobFilter) -> Result<Vec<JobInfo>> {
+		filter.validate()?;
+		let current = self.clock.now();
+		// IDs are UUID v7, so ages are compared with the timestamps in IDs
+		let enqueued_after = filter
+			.max_age
+			.map_or(Uuid::nil(), |age| uuid_v7_bound(current - age));
+		let enqueued_before = filter
+			.min_age
+			.map_or(Uuid::max(), |age| uuid_v7_bound(current - age));
+		let status = filter.status;
+
+		let mut conn = self.db.get().await?;
+		let jobs = conn
+			.load::<_, (XUuidVal, SqlJobInfo)>(
+				dsl::job_queue
+					.filter(
+						dsl::kind
+							.eq(filter.kind.as_deref().unwrap_or_default())
+							.or(filter.kind.is_none().into_sql::<Bool>()),
+					)
+					.filter(
+						dsl::started_at
+							.is_null()
+							.or((status != Some(JobStatus::Pending)).into_sql::<Bool>()),
+					)
+					.filter(
+						dsl::started_at
+							.is_not_null()
+							.or((status != Some(JobStatus::Running)).into_sql::<Bool>()),
+					)
+					.filter(dsl::priority.ge(filter.min_priority.unwrap_or(i16::MIN)))
+					.filter(dsl::priority.le(filter.max_priority.unwrap_or(i16::MAX)))
+					.filter(
+						dsl::branch
+							.eq(filter.branch.unwrap_or_default())
+							.or(filter.branch.is_none().into_sql::<Bool>()),
+					)
+					.filter(dsl::id.ge(XUuidVal(enqueued_after)))
+					.filter(dsl::id.lt(XUuidVal(enqueued_before)))
+					.filter(
+						dsl::id
+							.gt(XUuidVal(filter.after.unwrap_or_default()))
+							.or(filter.after.is_none().into_sql::<Bool>()),
+					)
+					.order(dsl::id.asc())
+					.limit(filter.limit as i64)
+					.select((
+						dsl::id,
+						(
+							dsl::kind,
+							(dsl::data, dsl::data_bin, dsl::compressed),
+							dsl::priority,
+							dsl::started_at,
+							dsl::attempts,
+							dsl::last_error,
+							dsl::trace_context,
+							dsl::claimed_by,
+						),
+					)),
+			)
+			.await?;
+		jobs.into_iter()
+			.map(|(id, job)| job_info(id.0, job))
+			.collect()
+	}
+
+	/// Checks if the precondition of a command holds.
+	///
+	/// Commands without preconditions always pass.
+	pub async fn check_guard(&self, conn: &mut BoxedSqlConn, command: &JobCommand) -> Result<bool> {
+		let Some(guard) = command.guard() else {
+			return Ok(true);
+		};
+		match guard {
+			JobGuard::BranchEnabled(id) => Ok(conn
+				.get_result::<_, i64>(
+					branch::table
+						.filter(branch::id.eq(id))
+						.filter(branch::deleted_at.is_null())
+						.filter(branch::enabled)
+						.count(),
+				)
+				.await? != 0),
+		}
+	}
+
+	pub async fn finish_job(&self, conn: &mut BoxedSqlConn, id: JobRef) -> Result<()> {
+		let callback = self
+			.finish_with(conn, id, AttemptOutcome::Succeeded, None)
+			.await?;
+		self.send_callback(callback);
+		Ok(())
+	}
+
+	/// Finishes a started job, and enqueues `next` to continue its work.
+	///
+	/// This is for jobs processed in chunks, such as [JobCommand::SyncBranch]
+	/// with a cursor. The continuation keeps the priority and dedup key of
+	/// the finished job. Both happen in a transaction, so the work is neither
+	/// lost nor duplicated, and the continuation is never deduplicated
+	/// against the job it continues.
+	pub async fn continue_job(
+		&self,
+		conn: &mut BoxedSqlConn,
+		id: JobRef,
+		next: JobCommand,
+	) -> Result<EnqueueOutcome> {
+		let (kind, outcome) = conn
+			.transaction::<_, crate::BackendError, _>(async |conn| {
+				let (priority, dedup_key, tenant, callback_url) = conn
+					.get_result::<_, (i16, Option<String>, String, Option<String>)>(
+						dsl::job_queue
+							.filter(dsl::id.eq(XUuidVal(id)).and(dsl::started_at.is_not_null()))
+							.select((
+								dsl::priority,
+								dsl::dedup_key,
+								dsl::tenant,
+								dsl::callback_url,
+							)),
+					)
+					.await
+					.optional()?
+					.ok_or(JobQueueError::JobAborted(id))?;
+				// the callback is passed on, and sent when the last continuation finishes
+				self.finish_with(conn, id, AttemptOutcome::Succeeded, None)
+					.await?;
+				let options = EnqueueOptions {
+					priority: Some(priority as u16),
+					dedup_key,
+					tenant: Some(tenant).filter(|tenant| !tenant.is_empty()),
+					callback_url,
+					..Default::default()
+				};
+				self.insert_job(conn, next, options).await
+			})
+			.await?;
+		if let EnqueueOutcome::Enqueued { id: next } = outcome {
+			debug!(%id, %next, "continued job");
+			self.notify_enqueued(conn, &kind, next).await;
+		}
+		Ok(outcome)
+	}
+
+	/// Finishes a started job without executing it, as its precondition failed.
+	pub async fn skip_job(&self, conn: &mut BoxedSqlConn, id: JobRef) -> Result<()> {
+		let callback = self
+			.finish_with(
+				conn,
+				id,
+				AttemptOutcome::Skipped,
+				Some("skipped: precondition failed"),
+			)
+			.await?;
+		self.send_callback(callback);
+		info!(%id, "skipped job of which precondition failed");
+		Ok(())
+	}
+
+	/// Removes a started job, and records the outcome of its running attempt.
+	///
+	/// The job is archived before removal with [FinishPolicy::Archive].
+	async fn finish_with(
+		&self,
+		conn: &mut BoxedSqlConn,
+		id: JobRef,
+		outcome: AttemptOutcome,
+		error: Option<&str>,
+	) -> Result<Option<PendingCallback>> {
+		conn.transaction::<_, crate::BackendError, _>(async |conn| {
+			if self.config.on_finish == FinishPolicy::Archive {
+				self.archive(conn, id, outcome).await?;
+			}
+			let deleted = conn
+				.get_result::<_, (String, Option<BranchRef>, Option<String>)>(
+					delete(dsl::job_queue)
+						.filter(dsl::id.eq(XUuidVal(id)).and(dsl::started_at.is_not_null()))
+						.returning((dsl::kind, dsl::branch, dsl::callback_url)),
+				)
+				.await
+				.optional()?;
+			let Some((kind, branch, callback_url)) = deleted else {
+				warn!(%id, "job has been aborted or finished by another worker");
+				return Err(JobQueueError::JobAborted(id).into());
+			};
+			self.close_attempt(conn, id, outcome, error, None).await?;
+			if outcome == AttemptOutcome::Succeeded {
+				Self::record_sync_error(conn, &kind, branch, None).await?;
+			}
+
+			// release dependent jobs
+			conn.execute(
+				delete(job_dependency::table).filter(job_dependency::depends_on.eq(XUuidVal(id))),
+			)
+			.await?;
+			Ok(callback_url.map(|url| PendingCallback {
+				url,
+				body: CallbackBody {
+					id,
+					kind,
+					outcome: outcome.into(),
+					error: error.map(str::to_string),
+				},
+			}))
+		})
+		.await
+	}
+
+	/// Sends a completion callback in the background, if any.
+	fn send_callback(&self, callback: Option<PendingCallback>) {
+		if let Some(callback) = callback {
+			job_callback::spawn_delivery(
+				self.callback_sender.clone(),
+				callback,
+				self.config.callback_secret.as_deref(),
+				self.config.callback_retries,
+				std::time::Duration::from_millis(self.config.callback_retry_delay_ms),
+			);
+		}
+	}
+
+	/// Caches the outcome of a [JobCommand::SyncBranch] job on its branch.
+	///
+	/// The error of the branch is cleared if `error` is `None`.
+	/// Jobs of other kinds are ignored.
+	async fn record_sync_error(
+		conn: &mut BoxedSqlConn,
+		kind: &str,
+		branch: Option<BranchRef>,
+		error: Option<(&str, Option<JobErrorCode>)>,
+	) -> Result<()> {
+		let Some(id) = branch.filter(|_| kind == "SyncBranch") else {
+			return Ok(());
+		};
+		conn.execute(update(branch::table).filter(branch::id.eq(id)).set((
+			branch::last_error.eq(error.map(|(error, _)| error)),
+			branch::last_error_code.eq(error.and_then(|(_, code)| code).map(|code| code as i16)),
+		)))
+		.await?;
+		Ok(())
+	}
+
+	/// Encodes job data into values of `data`, `data_bin` and `compressed` columns.
+	///
+	/// Data not smaller than [`JobQueueConfig::offload_threshold`] is offloaded
+	/// to the payload store, leaving a reference in the `data` column.
+	async fn encode_data(
+		&self,
+		id: JobRef,
+		data: serde_json::Value,
+		compress: Option<bool>,
+	) -> Result<SqlJobData> {
+		if let Some(threshold) = self.config.offload_threshold {
+			let payload = serde_json::to_vec(&data)?;
+			if payload.len() >= threshold {
+				let size = payload.len();
+				if let Some(key) = self.payload_store.put(id, payload).await? {
+					debug!(%id, size, key, "offloaded job payload");
+					let reference = serde_json::json!({ PAYLOAD_REF: key });
+					return Ok((XJsonVal(reference), None, false));
+				}
+			}
+		}
+		self.config.data_encoding.encode(
+			data,
+			compress,
+			self.config.compress_threshold,
+			self.config.compression,
+		)
+	}
+
+	/// Loads job data offloaded by [JobQueue::encode_data].
+	///
+	/// Data kept in the queue is returned as is.
+	async fn load_payload(&self, data: serde_json::Value) -> Result<serde_json::Value> {
+		let Some(key) = payload_key(&data) else {
+			return Ok(data);
+		};
+		let payload = self.payload_store.get(key).await?;
+		Ok(serde_json::from_slice(&payload)?)
+	}
+
+	/// Copies a started job into the history table.
+	async fn archive(
+		&self,
+		conn: &mut BoxedSqlConn,
+		id: JobRef,
+		outcome: AttemptOutcome,
+	) -> Result<()> {
+		let job = conn
+			.get_result::<_, (String, SqlJobData, i16)>(
+				dsl::job_queue
+					.filter(dsl::id.eq(XUuidVal(id)).and(dsl::started_at.is_not_null()))
+					.select((
+						dsl::kind,
+						(dsl::data, dsl::data_bin, dsl::compressed),
+						dsl::priority,
+					)),
+			)
+			.await
+			.optional()?;
+		// missing jobs are reported by the caller
+		let Some((kind, (data, data_bin, compressed), priority)) = job else {
+			return Ok(());
+		};
+
+		conn.execute(insert_into(job_history::table).values((
+			job_history::id.eq(XUuidVal(id)),
+			job_history::kind.eq(kind),
+			job_history::data.eq(data),
+			job_history::data_bin.eq(data_bin),
+			job_history::compressed.eq(compressed),
+			job_history::priority.eq(priority),
+			job_history::outcome.eq(outcome as i16),
+			job_history::finished_at.eq(self.clock.now()),
+		)))
+		.await?;
+		Ok(())
+	}
+
+	/// Runs a started job with `handler`, then finishes or fails it by the result.
+	///
+	/// Panics of `handler` are caught and fail the job with a `panicked` reason,
+	/// so that a bad job never kills the worker or stays started forever.
+	/// Handlers running longer than the timeout of the kind, see
+	/// [`JobQueueConfig::handler_timeouts_ms`], are cancelled and fail the job
+	/// with a `timed out` reason.
+	///
+	/// Returns `true` if the job succeeded.
+	pub async fn run_job<F, E>(&self, job: &Job, handler: F) -> Result<bool>
+	where
+		F: Future<Output = std::result::Result<(), E>>,
+		E: Display,
+	{
+		let id = job.id;
+		let kind = job.command.kind();
+		let timeout = self
+			.config
+			.handler_timeouts_ms
+			.get(kind)
+			.copied()
+			.or(self.config.default_handler_timeout_ms)
+			.map(std::time::Duration::from_millis);
+		let started = Instant::now();
+		let handler = AssertUnwindSafe(handler).catch_unwind();
+		let result = match timeout {
+			Some(timeout) => tokio::time::timeout(timeout, handler).await.ok(),
+			None => Some(handler.await),
+		};
+
+		let error = match result {
+			None => {
+				warn!(%id, kind, elapsed = ?started.elapsed(), "job handler timed out");
+				Some("timed out".to_string())
+			}
+			Some(Ok(Ok(()))) => None,
+			Some(Ok(Err(error))) => Some(format!("{error:#}")),
+			Some(Err(payload)) => {
+				let message = payload
+					.downcast_ref::<&str>()
+					.copied()
+					.or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+					.unwrap_or("unknown payload");
+				Some(format!("panicked: {message}"))
+			}
+		};
+
+		let mut conn = self.db.get().await?;
+		match error {
+			None => {
+				self.finish_job(&mut conn, id).await?;
+				Ok(true)
+			}
+			Some(error) => {
+				error!(%id, error, "job failed");
+				self.fail_job(&mut conn, id, &error).await?;
+				Ok(false)
+			}
+		}
+	}
+
+	/// Finishes a job, like [JobQueue::finish_job], but succeeds if the job is gone.
+	///
+	/// This is for workers retrying a finish, of which the first call may have
+	/// succeeded before a transient error.
+	pub async fn finish_job_idempotent(&self, conn: &mut BoxedSqlConn, id: JobRef) -> Result<()> {
+		match self.finish_job(conn, id).await {
+			Err(crate::BackendError::JobQueueError(JobQueueError::JobAborted(_))) => {
+				debug!(%id, "job has already been finished");
+				Ok(())
+			}
+			result => result,
+		}
+	}
+
+	/// Cancels all pending jobs of some branches.
+	///
+	/// Started jobs are not affected.
+	/// Jobs depending on cancelled jobs are released.
+	/// Returns the count of cancelled jobs.
+	pub async fn cancel_pending_for_branches(
+		&self,
+		conn: &mut BoxedSqlConn,
+		branches: &[BranchRef],
+	) -> Result<usize> {
+		conn.transaction::<usize, crate::BackendError, _>(async |conn| {
+			let jobs = conn
+				.load::<_, XUuidVal>(
+					dsl::job_queue
+						.filter(dsl::branch.eq_any(branches))
+						.filter(dsl::started_at.is_null())
+						.select(dsl::id),
+				)
+				.await?;
+			if jobs.is_empty() {
+				return Ok(0);
+			}
+
+			conn.execute(delete(dsl::job_queue).filter(dsl::id.eq_any(&jobs)))
+				.await?;
+			conn.execute(
+				delete(job_dependency::table).filter(
+					job_dependency::job
+						.eq_any(&jobs)
+						.or(job_dependency::depends_on.eq_any(&jobs)),
+				),
+			)
+			.await?;
+			info!(count = jobs.len(), "cancelled pending jobs of branches");
+			Ok(jobs.len())
+		})
+		.await
+	}
+
+	/// Sets the priority of all pending jobs of a branch, e.g. when it becomes urgent.
+	///
+	/// Started jobs are not affected.
+	/// Returns the count of updated jobs.
+	pub async fn boost_branch(
+		&self,
+		conn: &mut BoxedSqlConn,
+		branch: BranchRef,
+		priority: impl Into<u16>,
+	) -> Result<usize> {
+		let priority = priority.into();
+		let count = conn
+			.execute(
+				update(dsl::job_queue)
+					.filter(dsl::branch.eq(branch))
+					.filter(dsl::started_at.is_null())
+					.set(dsl::priority.eq(priority_into_sql(priority))),
+			)
+			.await?;
+		info!(branch, priority, count, "boosted pending jobs of branch");
+		Ok(count)
+	}
+
+	/// Replaces the command of a pending job, keeping its ID, priority and dependencies.
+	///
+	/// This is for migrating jobs enqueued with commands of older versions.
+	/// Returns [JobQueueError::AlreadyStarted] if the job has started,
+	/// or [JobQueueError::JobAborted] if it does not exist.
+	pub async fn rewrite_pending(
+		&self,
+		conn: &mut BoxedSqlConn,
+		id: JobRef,
+		new: JobCommand,
+	) -> Result<()> {
+		let branch = new.branch();
+		let (kind, data) = new.serialize()?;
+		let (data, data_bin, compressed) = self.encode_data(id, data, None).await?;
+
+		conn.transaction::<(), crate::BackendError, _>(async |conn| {
+			let started = conn
+				.get_result::<_, Option<PrimitiveDateTime>>(
+					dsl::job_queue
+						.filter(dsl::id.eq(XUuidVal(id)))
+						.select(dsl::started_at),
+				)
+				.await
+				.optional()?
+				.ok_or(JobQueueError::JobAborted(id))?;
+			if started.is_some() {
+				return Err(JobQueueError::AlreadyStarted(id).into());
+			}
+
+			conn.execute(
+				update(dsl::job_queue)
+					.filter(dsl::id.eq(XUuidVal(id)))
+					.filter(dsl::started_at.is_null())
+					.set((
+						dsl::kind.eq(kind.as_str()),
+						dsl::data.eq(data),
+						dsl::data_bin.eq(data_bin),
+						dsl::compressed.eq(compressed),
+						dsl::branch.eq(branch),
+					)),
+			)
+			.await?;
+			Ok(())
+		})
+		.await?;
+		info!(%id, %kind, "rewrote pending job");
+
+		Ok(())
+	}
+
+	/// Fails an attempt of a started job.
+	///
+	/// The job is put back into the queue to be retried, or moved to the
+	/// dead-letter queue if it has been attempted for
+	/// [`JobQueueConfig::max_attempts`] times.
+	/// The error is truncated to [`MAX_ERROR_LEN`] bytes.
+	///
+	/// Returns `true` if the job will be retried.
+	pub async fn fail_job(&self, conn: &mut BoxedSqlConn, id: JobRef, error: &str) -> Result<bool> {
+		self.fail_with(conn, id, error, None).await
+	}
+
+	/// Fails an attempt of a started job with a categorized error.
+	///
+	/// This is the same as [JobQueue::fail_job], except that the code of
+	/// the failure is also recorded in the attempt and the dead-letter queue.
+	pub async fn fail_job_with(
+		&self,
+		conn: &mut BoxedSqlConn,
+		id: JobRef,
+		failure: &JobFailure,
+	) -> Result<bool> {
+		self.fail_with(conn, id, &failure.message, Some(failure.code))
+			.await
+	}
+
+	async fn fail_with(
+		&self,
+		conn: &mut BoxedSqlConn,
+		id: JobRef,
+		error: &str,
+		code: Option<JobErrorCode>,
+	) -> Result<bool> {
+		let error = truncate_error(error);
+
+		let retry = conn
+			.transaction::<bool, crate::BackendError, _>(async |conn| {
+				let (attempts, kind, branch) = conn
+					.get_result::<_, (i32, String, Option<BranchRef>)>(
+						update(dsl::job_queue)
+							.filter(dsl::id.eq(XUuidVal(id)).and(dsl::started_at.is_not_null()))
+							.set((
+								dsl::attempts.eq(dsl::attempts + 1),
+								dsl::last_error.eq(error),
+								dsl::started_at.eq(None::<PrimitiveDateTime>),
+								dsl::claimed_by.eq(None::<String>),
+							))
+							.returning((dsl::attempts, dsl::kind, dsl::branch)),
+					)
+					.await
+					.optional()?
+					.ok_or(JobQueueError::JobAborted(id))?;
+				self.close_attempt(conn, id, AttemptOutcome::Failed, Some(error), code)
+					.await?;
+				Self::record_sync_error(conn, &kind, branch, Some((error, code))).await?;
+
+				if attempts as u32 >= self.config.max_attempts {
+					self.dead_letter(conn, id, error, code).await?;
+					Ok(false)
+				} else {
+					let next_attempt_at = self
+						.retry_delay(attempts as u32)
+						.map(|delay| self.clock.now() + delay);
+					conn.execute(
+						update(dsl::job_queue)
+							.filter(dsl::id.eq(XUuidVal(id)))
+							.set(dsl::next_attempt_at.eq(next_attempt_at)),
+					)
+					.await?;
+					Ok(true)
+				}
+			})
+			.await?;
+		if retry {
+			warn!(%id, error, "job failed, will be retried");
+		}
+		Ok(retry)
+	}
+
+	/// Returns the delay before retrying a job failed for `attempts` times.
+	///
+	/// Returns `None` if retries are not delayed.
+	fn retry_delay(&self, attempts: u32) -> Option<time::Duration> {
+		if self.config.retry_delay == 0 {
+			return None;
+		}
+		let delay = self
+			.config
+			.retry_delay
+			.saturating_mul(1 << attempts.saturating_sub(1).min(32))
+			.min(self.config.max_retry_delay);
+		let delay = self.config.retry_jitter.apply(delay.saturating_mul(1000));
+		Some(time::Duration::milliseconds(delay as i64))
+	}
+
+	/// Returns a claimed job to the queue, as if it had not been claimed.
+	///
+	/// The running attempt is removed instead of being recorded.
+	async fn unclaim(&self, conn: &mut BoxedSqlConn, id: JobRef) -> Result<()> {
+		conn.transaction::<(), crate::BackendError, _>(async |conn| {
+			let attempts = conn
+				.get_result::<_, i32>(
+					update(dsl::job_queue)
+						.filter(dsl::id.eq(XUuidVal(id)).and(dsl::started_at.is_not_null()))
+						.set((
+							dsl::started_at.eq(None::<PrimitiveDateTime>),
+							dsl::claimed_by.eq(None::<String>),
+						))
+						.returning(dsl::attempts),
+				)
+				.await
+				.optional()?
+				.ok_or(JobQueueError::NotClaimed(id))?;
+			conn.execute(
+				delete(job_attempt::table)
+					.filter(job_attempt::job.eq(XUuidVal(id)))
+					.filter(job_attempt::attempt.eq(attempts + 1)),
+			)
+			.await?;
+			Ok(())
+		})
+		.await
+	}
+
+	/// Returns a claimed job to the queue without charging an attempt.
+	///
+	/// This is for jobs claimed ahead of processing and never handed to a
+	/// handler, such as prefetched jobs on shutdown.
+	pub async fn unclaim_job(&self, id: JobRef) -> Result<()> {
+		let mut conn = self.db.get().await?;
+		self.unclaim(&mut conn, id).await?;
+		info!(%id, "unclaimed job");
+		Ok(())
+	}
+
+	/// Releases a claimed job, so that it can be fetched again.
+	///
+	/// This is for jobs of which workers are known to be dead.
+	/// The running attempt is recorded as failed with `reason`, but the job
+	/// is never dead-lettered for it. `actor` is the principal releasing
+	/// the job, if known.
+	pub async fn release_job(&self, id: JobRef, reason: &str, actor: Option<&str>) -> Result<()> {
+		let mut conn = self.db.get().await?;
+		let error = truncate_error(reason);
+
+		conn.transaction::<(), crate::BackendError, _>(async |conn| {
+			// the attempt counter is increased, so that the next attempt gets a new number
+			let cols = conn
+				.execute(
+					update(dsl::job_queue)
+						.filter(dsl::id.eq(XUuidVal(id)).and(dsl::started_at.is_not_null()))
+						.set((
+							dsl::attempts.eq(dsl::attempts + 1),
+							dsl::last_error.eq(error),
+							dsl::started_at.eq(None::<PrimitiveDateTime>),
+							dsl::claimed_by.eq(None::<String>),
+						)),
+				)
+				.await?;
+			if cols == 0 {
+				return Err(JobQueueError::NotClaimed(id).into());
+			}
+			self.close_attempt(conn, id, AttemptOutcome::Failed, Some(error), None)
+				.await
+		})
+		.await?;
+		warn!(target: "audit", %id, reason, ?actor, "released claimed job");
+
+		Ok(())
+	}
+
+	/// Enqueues a new job with the same command as a finished or dead-lettered one.
+	///
+	/// Jobs finished successfully can be replayed only if archived with
+	/// [FinishPolicy::Archive]. The original record is left untouched, and
+	/// the new job starts with no attempts. If `priority` is `None`, the
+	/// priority of the original job is used.
+	///
+	/// Returns the ID of the new job.
+	pub async fn replay_job(&self, id: JobRef, priority: Option<u16>) -> Result<JobRef> {
+		let mut conn = self.db.get().await?;
+		let mut job = conn
+			.get_result::<_, (String, SqlJobData, i16)>(
+				job_history::table
+					.filter(job_history::id.eq(XUuidVal(id)))
+					.select((
+						job_history::kind,
+						(
+							job_history::data,
+							job_history::data_bin,
+							job_history::compressed,
+						),
+						job_history::priority,
+					)),
+			)
+			.await
+			.optional()?;
+		if job.is_none() {
+			job = conn
+				.get_result::<_, (String, SqlJobData, i16)>(
+					job_dead_letter::table
+						.filter(job_dead_letter::id.eq(XUuidVal(id)))
+						.select((
+							job_dead_letter::kind,
+							(
+								job_dead_letter::data,
+								job_dead_letter::data_bin,
+								job_dead_letter::compressed,
+							),
+							job_dead_letter::priority,
+						)),
+				)
+				.await
+				.optional()?;
+		}
+		let (kind, data, original) = job.ok_or(JobQueueError::NotInHistory(id))?;
+
+		let data = self.load_payload(JobDataEncoding::decode(data)?).await?;
+		let command = JobCommand::deserialize(&kind, data)?;
+		let priority = priority.unwrap_or(original.max(0) as u16);
+		let replayed = self
+			.enqueue_with_priority(&mut conn, command, priority)
+			.await?;
+		info!(target: "audit", %id, %replayed, priority, "replayed job");
+		Ok(replayed)
+	}
+
+	/// Releases jobs claimed by workers of which heartbeats have expired.
+	///
+	/// Workers without a heartbeat for [`JobQueueConfig::worker_timeout`]
+	/// are considered lost, and are unregistered. Their jobs can then be
+	/// claimed again, as with [JobQueue::release_job].
+	///
+	/// Returns the count of released jobs.
+	pub async fn reclaim_lost_jobs(&self) -> Result<usize> {
+		let Some(timeout) = self.config.worker_timeout else {
+			return Ok(0);
+		};
+		let deadline = self.clock.now() - time::Duration::seconds(timeout as i64);
+
+		let mut conn = self.db.get().await?;
+		let workers = conn
+			.load::<_, String>(
+				job_worker::table
+					.filter(job_worker::heartbeat_at.lt(deadline))
+					.select(job_worker::id),
+			)
+			.await?;
+		if workers.is_empty() {
+			return Ok(0);
+		}
+		let jobs = conn
+			.load::<_, XUuidVal>(
+				dsl::job_queue
+					.filter(dsl::started_at.is_not_null())
+					.filter(dsl::claimed_by.eq_any(&workers))
+					.select(dsl::id),
+			)
+			.await?;
+		conn.execute(delete(job_worker::table).filter(job_worker::id.eq_any(&workers)))
+			.await?;
+		drop(conn);
+
+		let mut count = 0;
+		for id in jobs {
+			match self.release_job(id.0, "worker lost", None).await {
+				Ok(()) => count += 1,
+				// finished or failed since loaded
+				Err(crate::BackendError::JobQueueError(JobQueueError::NotClaimed(_))) => {}
+				Err(error) => return Err(error),
+			}
+		}
+		warn!(?workers, count, "reclaimed jobs of lost workers");
+		Ok(count)
+	}
+
+	/// Records the outcome of the running attempt of a job, if any.
+	async fn close_attempt(
+		&self,
+		conn: &mut BoxedSqlConn,
+		id: JobRef,
+		outcome: AttemptOutcome,
+		error: Option<&str>,
+		error_code: Option<JobErrorCode>,
+	) -> Result<()> {
+		conn.execute(
+			update(job_attempt::table)
+				.filter(job_attempt::job.eq(XUuidVal(id)))
+				.filter(job_attempt::outcome.eq(AttemptOutcome::Running as i16))
+				.set((
+					job_attempt::finished_at.eq(self.clock.now()),
+					job_attempt::outcome.eq(outcome as i16),
+					job_attempt::error.eq(error),
+					job_attempt::error_code.eq(error_code.map(|code| code as i16)),
+				)),
+		)
+		.await?;
+		Ok(())
+	}
+
+	/// Returns all attempts of a job, in order.
+	///
+	/// Attempts of finished jobs are also returned.
+	pub async fn get_attempts(&self, id: JobRef) -> Result<Vec<JobAttempt>> {
+		let mut conn = self.db.get().await?;
+
+		let attempts = conn
+			.load::<_, (
+				i32,
+				PrimitiveDateTime,
+				Option<PrimitiveDateTime>,
+				i16,
+				Option<String>,
+				Option<i16>,
+			)>(
+				job_attempt::table
+					.filter(job_attempt::job.eq(XUuidVal(id)))
+					.order(job_attempt::attempt.asc())
+					.select((
+						job_attempt::attempt,
+						job_attempt::started_at,
+						job_attempt::finished_at,
+						job_attempt::outcome,
+						job_attempt::error,
+						job_attempt::error_code,
+					)),
+			)
+			.await?;
+		Ok(attempts
+			.into_iter()
+			.map(
+				|(attempt, started_at, finished_at, outcome, error, error_code)| JobAttempt {
+					attempt: attempt as u32,
+					started_at,
+					finished_at,
+					outcome: AttemptOutcome::from(outcome),
+					error,
+					error_code: error_code.map(JobErrorCode::from),
+				},
+			)
+			.collect())
+	}
+
+	/// Moves a job to the dead-letter queue.
+	///
+	/// Returns `false` if the job does not exist.
+	async fn dead_letter(
+		&self,
+		conn: &mut BoxedSqlConn,
+		id: JobRef,
+		error: &str,
+		error_code: Option<JobErrorCode>,
+	) -> Result<bool> {
+		let failed_at = self.clock.now();
+
+		let moved = conn
+			.transaction::<_, crate::BackendError, _>(async |conn| {
+				let job = conn
+					.get_result::<_, (String, SqlJobData, i16, Option<String>)>(
+						dsl::job_queue.filter(dsl::id.eq(XUuidVal(id))).select((
+							dsl::kind,
+							(dsl::data, dsl::data_bin, dsl::compressed),
+							dsl::priority,
+							dsl::callback_url,
+						)),
+					)
+					.await
+					.optional()?;
+				let Some((kind, (data, data_bin, compressed), priority, callback_url)) = job else {
+					return Ok(None);
+				};
+				self.close_attempt(conn, id, AttemptOutcome::Failed, Some(error), error_code)
+					.await?;
+
+				conn.execute(insert_into(job_dead_letter::table).values((
+					job_dead_letter::id.eq(XUuidVal(id)),
+					job_dead_letter::kind.eq(&kind),
+					job_dead_letter::data.eq(data),
+					job_dead_letter::data_bin.eq(data_bin),
+					job_dead_letter::compressed.eq(compressed),
+					job_dead_letter::priority.eq(priority),
+					job_dead_letter::error.eq(error),
+					job_dead_letter::error_code.eq(error_code.map(|code| code as i16)),
+					job_dead_letter::failed_at.eq(failed_at),
+				)))
+				.await?;
+				conn.execute(delete(dsl::job_queue).filter(dsl::id.eq(XUuidVal(id))))
+					.await?;
+
+				// dependents can never become eligible, so they fail along
+				let dependents = conn
+					.load::<_, XUuidVal>(
+						job_dependency::table
+							.filter(job_dependency::depends_on.eq(XUuidVal(id)))
+							.select(job_dependency::job),
+					)
+					.await?;
+				conn.execute(
+					delete(job_dependency::table).filter(
+						job_dependency::job
+							.eq(XUuidVal(id))
+							.or(job_dependency::depends_on.eq(XUuidVal(id))),
+					),
+				)
+				.await?;
+				let reason = format!("dependency {id} failed");
+				for dependent in dependents {
+					Box::pin(self.dead_letter(conn, dependent.0, &reason, error_code)).await?;
+				}
+
+				Ok(Some(callback_url.map(|url| PendingCallback {
+					url,
+					body: CallbackBody {
+						id,
+						kind,
+						outcome: CallbackOutcome::Failed,
+						error: Some(error.to_string()),
+					},
+				})))
+			})
+			.await?;
+		let Some(callback) = moved else {
+			return Ok(false);
+		};
+		warn!(%id, error, "moved job to dead-letter queue");
+		self.send_callback(callback);
+		Ok(true)
+	}
+
+	/// Fails all jobs running longer than the timeout of their kind, or
+	/// [`JobQueueConfig::default_timeout`] for kinds without timeouts.
+	///
+	/// Timed out jobs are moved to the dead-letter queue.
+	/// Returns the count of failed jobs.
+	///
+	/// This is about runaway jobs, and should be called periodically
+	/// by a supervisor.
+	pub async fn fail_timed_out(&self) -> Result<usize> {
+		let mut conn = self.db.get().await?;
+		let current = self.clock.now();
+
+		let mut count = 0;
+		for (kind, timeout) in &self.config.timeouts {
+			let deadline = current - time::Duration::seconds(*timeout as i64);
+			let jobs = conn
+				.load::<_, XUuidVal>(
+					dsl::job_queue
+						.filter(dsl::kind.eq(kind.as_str()))
+						.filter(dsl::started_at.lt(deadline))
+						.select(dsl::id),
+				)
+				.await?;
+			for id in jobs {
+				warn!(%id, %kind, timeout, "job exceeded the maximum running time");
+				if self.dead_letter(&mut conn, id.0, "timed out", None).await? {
+					count += 1;
+				}
+			}
+		}
+		if let Some(timeout) = self.config.default_timeout {
+			let deadline = current - time::Duration::seconds(timeout as i64);
+			let jobs = conn
+				.load::<_, (XUuidVal, String)>(
+					dsl::job_queue
+						.filter(dsl::kind.ne_all(self.config.timeouts.keys().map(KString::as_str)))
+						.filter(dsl::started_at.lt(deadline))
+						.select((dsl::id, dsl::kind)),
+				)
+				.await?;
+			for (id, kind) in jobs {
+				warn!(%id, %kind, timeout, "job exceeded the default maximum running time");
+				if self.dead_letter(&mut conn, id.0, "timed out", None).await? {
+					count += 1;
+				}
+			}
+		}
+		Ok(count)
+	}
+
+	/// Runs `VACUUM ANALYZE` on the job tables to reclaim space from churn.
+	///
+	/// This is [JobQueue::maintain] with vacuuming, which is only supported
+	/// on PostgreSQL. Returns `false` if tables were only analyzed.
+	pub async fn vacuum(&self) -> Result<bool> {
+		Ok(self.maintain(true).await?.vacuumed)
+	}
+
+	/// Checks if indexes used to claim jobs exist, returning names of missing ones.
+	///
+	/// Claiming filters pending jobs by kind and orders them by priority, which
+	/// scans the whole queue without these indexes. A warning with the
+	/// statement to create each missing index is logged.
+	pub async fn check_indexes(&self) -> Result<Vec<&'static str>> {
+		#[derive(QueryableByName)]
+		struct IndexName {
+			#[diesel(sql_type = Text)]
+			name: String,
+		}
+
+		let mut conn = self.db.get().await?;
+		let postgres = matches!(*conn, BoxedSqlConn::Pg(_));
+		let names: Vec<IndexName> = if postgres {
+			conn.load(sql_query(
+				"SELECT \"indexname\" AS \"name\" FROM \"pg_indexes\" \
+				WHERE \"tablename\" = 'job_queue'",
+			))
+			.await?
+		} else {
+			conn.load(sql_query(
+				"SELECT `name` FROM `sqlite_master` \
+				WHERE `type` = 'index' AND `tbl_name` = 'job_queue'",
+			))
+			.await?
+		};
+
+		let mut missing = Vec::new();
+		for (name, create_pg, create_sqlite) in EXPECTED_INDEXES {
+			if names.iter().any(|index| index.name == *name) {
+				continue;
+			}
+			let create = if postgres { create_pg } else { create_sqlite };
+			warn!(index = name, create, "index of job queue is missing");
+			missing.push(name);
+		}
+		Ok(missing)
+	}
+
+	/// Repairs priorities of pending jobs wrapped into negative values,
+	/// by clamping them to [i16::MAX].
+	///
+	/// Priorities above [i16::MAX] were once wrapped when stored, so
+	/// negative ones were meant to be high, but are claimed after all others.
+	/// Returns the count of repaired jobs.
+	pub async fn repair_priorities(&self) -> Result<usize> {
+		let mut conn = self.db.get().await?;
+		let count = conn
+			.execute(
+				update(dsl::job_queue)
+					.filter(dsl::priority.lt(0))
+					.set(dsl::priority.eq(i16::MAX)),
+			)
+			.await?;
+		if count != 0 {
+			warn!(target: "audit", count, "repaired wrapped job priorities");
+		}
+		Ok(count)
+	}
+
+	/// Refreshes planner statistics of the job tables with `ANALYZE`,
+	/// and optionally reclaims space with `VACUUM` on PostgreSQL.
+	///
+	/// Tables are processed one at a time with plain (not `FULL`) statements,
+	/// which do not block concurrent reads and writes of workers.
+	/// `VACUUM` is skipped on SQLite, where it rewrites the whole database
+	/// under an exclusive lock.
+	pub async fn maintain(&self, vacuum: bool) -> Result<MaintenanceReport> {
+		let mut conn = self.db.get().await?;
+		let vacuum = vacuum && matches!(*conn, BoxedSqlConn::Pg(_));
+		let command = if vacuum { "VACUUM ANALYZE" } else { "ANALYZE" };
+
+		let mut tables = Vec::with_capacity(MAINTAINED_TABLES.len());
+		for table in MAINTAINED_TABLES {
+			let started = Instant::now();
+			conn.batch_execute(&format!("{command} {table}")).await?;
+			tables.push((*table, started.elapsed()));
+		}
+		info!(vacuum, "maintained job tables");
+		Ok(MaintenanceReport {
+			vacuumed: vacuum,
+			tables,
+		})
+	}
+
+	/// Exports all pending jobs in the order of IDs, for moving the queue
+	/// to another database with [JobQueue::import].
+	///
+	/// Running jobs are not exported, so workers should be stopped first.
+	pub async fn export(&self) -> Result<Vec<ExportedJob>> {
+		let mut conn = self.db.get().await?;
+		let jobs: Vec<SqlExportedJob> = conn
+			.load_select(
+				dsl::job_queue
+					.filter(dsl::started_at.is_null())
+					.order(dsl::id.asc()),
+			)
+			.await?;
+		let mut dependencies = HashMap::<JobRef, Vec<JobRef>>::new();
+		for (job, depends_on) in conn
+			.load::<_, (XUuidVal, XUuidVal)>(
+				job_dependency::table.select((job_dependency::job, job_dependency::depends_on)),
+			)
+			.await?
+		{
+			dependencies.entry(job.0).or_default().push(depends_on.0);
+		}
+
+		let mut exported = Vec::with_capacity(jobs.len());
+		for job in jobs {
+			let id = job.id.0;
+			let data = JobDataEncoding::decode((job.data, job.data_bin, job.compressed))?;
+			exported.push(ExportedJob {
+				id,
+				kind: job.kind,
+				data: self.load_payload(data).await?,
+				priority: job.priority as u16,
+				created_at: uuid_v7_time(id).map(PrimitiveDateTime::assume_utc),
+				branch: job.branch,
+				dedup_key: job.dedup_key,
+				trace_context: job.trace_context,
+				depends_on: dependencies.remove(&id).unwrap_or_default(),
+				tenant: Some(job.tenant).filter(|tenant| !tenant.is_empty()),
+				attempts: job.attempts as u32,
+				last_error: job.last_error,
+				next_attempt_at: job.next_attempt_at.map(PrimitiveDateTime::assume_utc),
+				callback_url: job.callback_url,
+				seq: job.seq,
+			});
+		}
+		info!(count = exported.len(), "exported pending jobs");
+		Ok(exported)
+	}
+
+	/// Imports jobs exported by [JobQueue::export] in a transaction.
+	///
+	/// IDs are preserved, so the order of claiming is also preserved.
+	/// Data is encoded with the configuration of this queue.
+	/// Fails without importing anything if any ID already exists, or if
+	/// dependencies form a cycle.
+	pub async fn import(&self, jobs: Vec<ExportedJob>) -> Result<()> {
+		let count = jobs.len();
+		let mut conn = self.db.get().await?;
+		conn.transaction::<(), crate::BackendError, _>(async |conn| {
+			for job in &jobs {
+				let (data, data_bin, compressed) =
+					self.encode_data(job.id, job.data.clone(), None).await?;
+				conn.execute(insert_into(dsl::job_queue).values((
+					dsl::id.eq(XUuidVal(job.id)),
+					dsl::kind.eq(&job.kind),
+					dsl::data.eq(data),
+					dsl::data_bin.eq(data_bin),
+					dsl::compressed.eq(compressed),
+					dsl::priority.eq(priority_into_sql(job.priority)),
+					dsl::branch.eq(job.branch),
+					dsl::dedup_key.eq(&job.dedup_key),
+					dsl::trace_context.eq(&job.trace_context),
+					dsl::tenant.eq(job.tenant.as_deref().unwrap_or_default()),
+					dsl::attempts.eq(job.attempts as i32),
+					dsl::last_error.eq(&job.last_error),
+					dsl::next_attempt_at.eq(job.next_attempt_at.map(|time| {
+						let time = time.to_offset(UtcOffset::UTC);
+						PrimitiveDateTime::new(time.date(), time.time())
+					})),
+					dsl::callback_url.eq(&job.callback_url),
+					dsl::seq.eq(job.seq),
+				)))
+				.await?;
+			}
+			// dependencies may refer to jobs later in the list
+			for job in &jobs {
+				for dependency in &job.depends_on {
+					conn.execute(insert_into(job_dependency::table).values((
+						job_dependency::job.eq(XUuidVal(job.id)),
+						job_dependency::depends_on.eq(XUuidVal(*dependency)),
+					)))
+					.await?;
+				}
+			}
+			for job in &jobs {
+				Self::check_dependency_cycle(conn, job.id, &job.depends_on).await?;
+			}
+			Ok(())
+		})
+		.await?;
+		info!(count, "imported jobs");
+		Ok(())
+	}
+
+	/// Deletes idempotency keys created before `before`.
+	///
+	/// Producers redelivering with pruned keys enqueue new jobs.
+	/// Returns the count of deleted keys.
+	pub async fn prune_idempotency_keys(&self, before: PrimitiveDateTime) -> Result<usize> {
+		let mut conn = self.db.get().await?;
+		let count = conn
+			.execute(delete(job_idempotency::table).filter(job_idempotency::created_at.lt(before)))
+			.await?;
+		info!(count, "pruned job idempotency keys");
+		Ok(count)
+	}
+
+	/// Finds jobs of `kind`, of which data has `value` at the object path `path`.
+	///
+	/// On PostgreSQL, this is a containment query served by a GIN index,
+	/// so arrays match if they contain all elements of `value`.
+	/// On SQLite, the value at `path` must be equal to `value`.
+	///
+	/// Jobs with binary encoded or compressed data are never found.
+	pub async fn find_by_data(
+		&self,
+		kind: &str,
+		path: &[&str],
+		value: serde_json::Value,
+	) -> Result<Vec<JobRef>> {
+		#[derive(QueryableByName)]
+		struct JobId {
+			#[diesel(sql_type = XUuid)]
+			id: XUuidVal,
+		}
+
+		let mut conn = self.db.get().await?;
+		let ids: Vec<JobId> = if matches!(*conn, BoxedSqlConn::Pg(_)) {
+			let document = path
+				.iter()
+				.rev()
+				.fold(value, |value, key| serde_json::json!({ *key: value }));
+			conn.load(
+				sql_query(
+					r#"SELECT "id" FROM "job_queue" WHERE "kind" = $1 AND "data" @> $2::jsonb ORDER BY "id""#,
+				)
+				.bind::<Text, _>(kind.to_owned())
+				.bind::<Text, _>(document.to_string()),
+			)
+			.await?
+		} else {
+			let json_path = path.iter().fold(String::from("$"), |json_path, key| {
+				format!("{json_path}.\"{key}\"")
+			});
+			conn.load(
+				sql_query(
+					"SELECT `id` FROM `job_queue` WHERE `kind` = ? \
+					AND json_extract(`data`, ?) = json_extract(?, '$') ORDER BY `id`",
+				)
+				.bind::<Text, _>(kind.to_owned())
+				.bind::<Text, _>(json_path)
+				.bind::<Text, _>(value.to_string()),
+			)
+			.await?
+		};
+		Ok(ids.into_iter().map(|id| id.id.0).collect())
+	}
+
+	/// Returns the count of pending jobs of each priority.
+	pub async fn priority_histogram(&self) -> Result<BTreeMap<i16, usize>> {
+		let mut conn = self.db.get().await?;
+		let buckets = conn
+			.load::<_, (i16, i64)>(
+				dsl::job_queue
+					.filter(dsl::started_at.is_null())
+					.group_by(dsl::priority)
+					.select((dsl::priority, count_star())),
+			)
+			.await?;
+		Ok(buckets
+			.into_iter()
+			.map(|(priority, count)| (priority, count as usize))
+			.collect())
+	}
+
+	/// Returns the count of jobs finished in each minute of the last `window`.
+	///
+	/// Jobs are counted from the history table by any outcome, so only jobs
+	/// finished with [FinishPolicy::Archive] are included.
+	///
+	/// Keys are the start of each minute, from the minute `window` ago to the current one.
+	/// Minutes without finished jobs are included with a count of zero.
+	pub async fn throughput(
+		&self,
+		window: time::Duration,
+	) -> Result<BTreeMap<PrimitiveDateTime, usize>> {
+		let current = self.clock.now();
+		let since = truncate_to_minute(current - window);
+
+		let mut conn = self.db.get().await?;
+		let finished = conn
+			.load::<_, PrimitiveDateTime>(
+				job_history::table
+					.filter(job_history::finished_at.ge(since))
+					.select(job_history::finished_at),
+			)
+			.await?;
+
+		let mut buckets = BTreeMap::new();
+		let mut minute = since;
+		while minute <= current {
+			buckets.insert(minute, 0);
+			minute += time::Duration::MINUTE;
+		}
+		for time in finished {
+			if let Some(count) = buckets.get_mut(&truncate_to_minute(time)) {
+				*count += 1;
+			}
+		}
+		Ok(buckets)
+	}
+
+	/// Summarizes retries of jobs in the queue, with the top `kinds` failing kinds.
+	pub async fn retry_stats(&self, kinds: usize) -> Result<RetryStats> {
+		let mut conn = self.db.get().await?;
+		let attempts = conn
+			.load::<_, (i32, i64)>(
+				dsl::job_queue
+					.group_by(dsl::attempts)
+					.select((dsl::attempts, count_star())),
+			)
+			.await?;
+		let mut top_kinds = conn
+			.load::<_, (String, Option<i64>)>(
+				dsl::job_queue
+					.filter(dsl::attempts.gt(0))
+					.group_by(dsl::kind)
+					.select((dsl::kind, diesel::dsl::sum(dsl::attempts))),
+			)
+			.await?
+			.into_iter()
+			.map(|(kind, retries)| (kind, retries.unwrap_or_default() as u64))
+			.collect::<Vec<_>>();
+		top_kinds.sort_by(|(a_kind, a), (b_kind, b)| b.cmp(a).then_with(|| a_kind.cmp(b_kind)));
+		top_kinds.truncate(kinds);
+
+		Ok(RetryStats {
+			attempts: attempts
+				.into_iter()
+				.map(|(attempts, count)| (attempts as u32 + 1, count as usize))
+				.collect(),
+			top_kinds,
+		})
+	}
+
+	/// Returns the approximate count of jobs not started yet, up to `max`.
+	pub async fn count_pending(&self, max: usize) -> Result<usize> {
+		let mut conn = self.db.get().await?;
+
+		// a LIMIT on COUNT(*) limits the single result row instead of the rows
+		// counted, so the jobs are selected and counted here
+		let ids = conn
+			.load::<_, XUuidVal>(
+				dsl::job_queue
+					.select(dsl::id)
+					.filter(dsl::started_at.is_null())
+					.limit(max.try_into().unwrap_or(i64::MAX)),
+			)
+			.await?;
+		Ok(ids.len())
+	}
+
+	/// Registers a worker, or records a heartbeat of a registered one.
+	///
+	/// Workers should call this periodically, so that operators can tell
+	/// whether the holder of a job is alive.
+	pub async fn register_worker(&self, id: &str) -> Result<()> {
+		let mut conn = self.db.get().await?;
+		let time = self.clock.now();
+		conn.execute(
+			insert_into(job_worker::table)
+				.values((
+					job_worker::id.eq(id),
+					job_worker::registered_at.eq(time),
+					job_worker::heartbeat_at.eq(time),
+				))
+				.on_conflict(job_worker::id)
+				.do_update()
+				.set(job_worker::heartbeat_at.eq(time)),
+		)
+		.await?;
+		debug!(worker = id, "worker heartbeat");
+		Ok(())
+	}
+
+	/// Removes a registered worker.
+	///
+	/// Jobs claimed by the worker are not affected.
+	pub async fn unregister_worker(&self, id: &str) -> Result<()> {
+		let mut conn = self.db.get().await?;
+		conn.execute(delete(job_worker::table).filter(job_worker::id.eq(id)))
+			.await?;
+		info!(worker = id, "unregistered worker");
+		Ok(())
+	}
+
+	/// Lists registered workers with counts of their running jobs, ordered by ID.
+	pub async fn list_workers(&self) -> Result<Vec<WorkerInfo>> {
+		let mut conn = self.db.get().await?;
+		let workers = conn
+			.load::<_, (String, PrimitiveDateTime, PrimitiveDateTime)>(
+				job_worker::table.order(job_worker::id.asc()).select((
+					job_worker::id,
+					job_worker::registered_at,
+					job_worker::heartbeat_at,
+				)),
+			)
+			.await?;
+		let running = conn
+			.load::<_, (Option<String>, i64)>(
+				dsl::job_queue
+					.filter(dsl::started_at.is_not_null())
+					.filter(dsl::claimed_by.is_not_null())
+					.group_by(dsl::claimed_by)
+					.select((dsl::claimed_by, count_star())),
+			)
+			.await?
+			.into_iter()
+			.filter_map(|(worker, count)| Some((worker?, count as usize)))
+			.collect::<HashMap<_, _>>();
+
+		Ok(workers
+			.into_iter()
+			.map(|(id, registered_at, heartbeat_at)| WorkerInfo {
+				running: running.get(&id).copied().unwrap_or_default(),
+				id,
+				registered_at,
+				heartbeat_at,
+			})
+			.collect())
+	}
+}
+
+fn truncate_to_minute(time: PrimitiveDateTime) -> PrimitiveDateTime {
+	time.replace_time(time::Time::from_hms(time.hour(), time.minute(), 0).unwrap())
+}
+
+fn job_info(
+	id: JobRef,
+	(kind, data, priority, started_at, attempts, last_error, trace_context, claimed_by): SqlJobInfo,
+) -> Result<JobInfo> {
+	Ok(JobInfo {
+		id,
+		kind,
+		data: JobDataEncoding::decode(data)?,
+		priority: priority as u16,
+		started_at,
+		attempts: attempts as u32,
+		last_error,
+		trace_context: trace_context.as_deref().and_then(TraceContext::parse),
+		claimed_by,
+	})
+}
+
+/// Returns the smallest UUID v7 with the timestamp of `time`.
+fn uuid_v7_bound(time: PrimitiveDateTime) -> Uuid {
+	let millis = (time.assume_utc().unix_timestamp_nanos() / 1_000_000).max(0) as u64;
+	let mut bytes = [0; 16];
+	bytes[..6].copy_from_slice(&millis.to_be_bytes()[2..]);
+	Uuid::from_bytes(bytes)
+}
+
+/// Returns the timestamp of a UUID v7.
+fn uuid_v7_time(id: Uuid) -> Option<PrimitiveDateTime> {
+	let (secs, nanos) = id.get_timestamp()?.to_unix();
+	let time = OffsetDateTime::from_unix_timestamp(secs as i64).ok()?
+		+ time::Duration::nanoseconds(nanos.into());
+	Some(PrimitiveDateTime::new(time.date(), time.time()))
+}
+
+/// Converts a priority into the stored `SMALLINT`, saturating at [i16::MAX].
+///
+/// A plain cast would wrap priorities above [i16::MAX] into negative ones,
+/// which are claimed after all others.
+fn priority_into_sql(priority: u16) -> i16 {
+	priority.min(i16::MAX as u16) as i16
+}
+
+/// Maximum length of errors of failed jobs, in bytes.
+pub const MAX_ERROR_LEN: usize = 1024;
+
+/// Truncates an error to [`MAX_ERROR_LEN`] bytes, at a character boundary.
+fn truncate_error(error: &str) -> &str {
+	if error.len() <= MAX_ERROR_LEN {
+		return error;
+	}
+	let mut end = MAX_ERROR_LEN;
+	while !error.is_char_boundary(end) {
+		end -= 1;
+	}
+	&error[..end]
+}
+
+#[derive(Debug, Error)]
+pub enum JobQueueError {
+	#[error("job {0} has been aborted")]
+	JobAborted(JobRef),
+	#[error("dependencies of job {0} form a cycle")]
+	DependencyCycle(JobRef),
+	#[error("dependency {0} has been dead-lettered")]
+	DependencyFailed(JobRef),
+	#[error("failed to encode or decode job data: {0}")]
+	DataEncoding(String),
+	#[error("job notifier error: {0}")]
+	Notifier(String),
+	#[error("job queue is draining")]
+	Draining,
+	#[error("job {0} is not claimed")]
+	NotClaimed(JobRef),
+	#[error("job {0} has already started")]
+	AlreadyStarted(JobRef),
+	#[error("invalid job filter: {0}")]
+	InvalidFilter(String),
+	#[error("invalid job command: {0}")]
+	InvalidCommand(String),
+	#[error("job {0} is neither in history nor dead-lettered")]
+	NotInHistory(JobRef),
+	/// The queue has reached [`JobQueueConfig::max_pending`].
+	///
+	/// This holds the time in seconds to wait before retrying.
+	#[error("job queue is full, retry after {0} seconds")]
+	QueueFull(u64),
+	/// The job is rejected by an [EnqueueInterceptor].
+	#[error("job is rejected: {0}")]
+	Rejected(String),
+	#[error("callback URL is not allowed: {0}")]
+	CallbackNotAllowed(String),
+}
+
+#[cfg(test)]
+mod test {
+	use std::{
+		collections::{BTreeMap, HashSet},
+		sync::{
+			Arc, Mutex,
+			atomic::{AtomicU64, Ordering},
+		},
+	};
+
+	use diesel::{ExpressionMethods, QueryDsl, insert_into, update};
+	use fabricia_common_model::priority::{Priority, parse_priority};
+	use kstring::KString;
+	use serde::Deserialize;
+	use serde_json::json;
+	use time::{Duration, OffsetDateTime, PrimitiveDateTime};
+	use uuid::Uuid;
+
+	use crate::{
+		BackendError, BackendServices,
+		branch::{BranchConfigInfo, BranchRef},
+		db::{
+			schema::{job_dead_letter, job_history, job_queue::dsl},
+			utils::{XJsonVal, XUuidVal},
+		},
+		job_queue::{
+			AttemptOutcome, ClaimExplanation, ClaimOptions, Clock, CompressionCodec,
+			EnqueueInterceptor, EnqueueOptions, EnqueueOutcome, FinishPolicy, IdGenerator, Job,
+			JobCommand, JobCommandBuilder, JobDataEncoding, JobErrorCode, JobFailure, JobFilter,
+			JobObserver, JobOrdering, JobQueue, JobQueueConfig, JobQueueError, JobRef, JobStatus,
+			MAINTAINED_TABLES, MAX_ERROR_LEN, RetryJitter, RetryStats, UnknownKindPolicy,
+		},
+		test::{TestEnv, test_env},
+		trace::TraceContext,
+	};
+
+	#[tokio::test]
+	async fn test_enqueue() {
+		let env = test_env().await;
+		let mut db = env.database.get().await.unwrap();
+		env.job_queue
+			.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+	}
+
+	#[tokio::test]
+	async fn test_enqueue_fetch() {
+		let env = test_env().await;
+		let mut db = env.database.get().await.unwrap();
+		let jq = env.job_queue;
+		jq.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+		jq.enqueue_with_priority(&mut db, JobCommand::sync_branch(2), 120)
+			.await
+			.unwrap();
+		jq.enqueue(&mut db, JobCommand::sync_branch(3))
+			.await
+			.unwrap();
+		drop(db);
+		assert_eq!(
+			jq.fetch_and_start().await.unwrap().unwrap().command,
+			JobCommand::sync_branch(2)
+		);
+		assert_eq!(
+			jq.fetch_and_start().await.unwrap().unwrap().command,
+			JobCommand::sync_branch(1)
+		);
+		assert_eq!(
+			jq.fetch_and_start().await.unwrap().unwrap().command,
+			JobCommand::sync_branch(3)
+		);
+		assert!(jq.fetch_and_start().await.unwrap().is_none());
+	}
+
+	#[tokio::test]
+	async fn test_finish() {
+		let env = test_env().await;
+		let jq = env.job_queue;
+
+		let mut db = env.database.get().await.unwrap();
+		jq.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+		drop(db);
+
+		let id = jq.fetch_and_start().await.unwrap().unwrap().id;
+
+		let mut db = env.database.get().await.unwrap();
+		jq.finish_job(&mut db, id).await.unwrap();
+		assert_eq!(
+			db.get_result::<_, i64>(dsl::job_queue.count())
+				.await
+				.unwrap(),
+			0
+		);
+		drop(db);
+
+		assert!(jq.fetch_and_start().await.unwrap().is_none());
+	}
+
+	#[tokio::test]
+	async fn test_fail_timed_out() {
+		let env = test_env().await;
+		let jq = JobQueue::new(
+			env.database.clone(),
+			JobQueueConfig {
+				timeouts: BTreeMap::from([(KString::from_static("SyncBranch"), 60)]),
+				..Default::default()
+			},
+		);
+
+		let mut db = env.database.get().await.unwrap();
+		jq.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+		drop(db);
+		jq.fetch_and_start().await.unwrap().unwrap();
+		assert_eq!(jq.fail_timed_out().await.unwrap(), 0);
+
+		// pretend that the job has been running for two minutes
+		let mut db = env.database.get().await.unwrap();
+		let time = OffsetDateTime::now_utc() - Duration::minutes(2);
+		db.execute(
+			update(dsl::job_queue)
+				.set(dsl::started_at.eq(PrimitiveDateTime::new(time.date(), time.time()))),
+		)
+		.await
+		.unwrap();
+		drop(db);
+
+		assert_eq!(jq.fail_timed_out().await.unwrap(), 1);
+		let mut db = env.database.get().await.unwrap();
+		assert_eq!(
+			db.get_result::<_, i64>(dsl::job_queue.count())
+				.await
+				.unwrap(),
+			0
+		);
+		assert_eq!(
+			db.get_result::<_, String>(job_dead_letter::table.select(job_dead_letter::error))
+				.await
+				.unwrap(),
+			"timed out"
+		);
+	}
+
+	#[tokio::test]
+	async fn test_vacuum_unsupported() {
+		let env = test_env().await;
+		assert!(!env.job_queue.vacuum().await.unwrap());
+	}
+
+	/// Records ages of stale alerts.
+	#[derive(Debug, Clone, Default)]
+	struct StaleRecorder(Arc<Mutex<Vec<Duration>>>);
+
+	impl JobObserver for StaleRecorder {
+		fn on_stale(&self, oldest_age: Duration) {
+			self.0.lock().unwrap().push(oldest_age);
+		}
+	}
+
+	#[tokio::test]
+	async fn test_stale_alert() {
+		let env = test_env().await;
+		let recorder = StaleRecorder::default();
+		let jq = JobQueue::new(env.database.clone(), Default::default())
+			.with_clock(ManualClock::new())
+			.with_observer(recorder.clone());
+
+		let mut db = env.database.get().await.unwrap();
+		let backdated = Uuid::new_v7(uuid::Timestamp::from_unix(
+			uuid::NoContext,
+			(OffsetDateTime::now_utc() - Duration::hours(1)).unix_timestamp() as u64,
+			0,
+		));
+		db.execute(insert_into(dsl::job_queue).values((
+			dsl::id.eq(XUuidVal(backdated)),
+			dsl::kind.eq("SyncBranch"),
+			dsl::data.eq(XJsonVal(json!({ "branch": 1 }))),
+			dsl::priority.eq(100),
+		)))
+		.await
+		.unwrap();
+		drop(db);
+
+		// no threshold is set
+		assert_eq!(jq.check_stale().await.unwrap(), None);
+		jq.set_stale_threshold(Duration::hours(2));
+		let age = jq.check_stale().await.unwrap().unwrap();
+		assert!(age >= Duration::hours(1));
+		assert!(recorder.0.lock().unwrap().is_empty());
+
+		// crossed, and only alerted once while it stays stale
+		jq.set_stale_threshold(Duration::minutes(30));
+		for _ in 0..3 {
+			jq.check_stale().await.unwrap();
+		}
+		assert_eq!(recorder.0.lock().unwrap().clone(), [age]);
+
+		// recovered after the job is started, and alerted again when crossed
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		assert_eq!(jq.check_stale().await.unwrap(), None);
+		jq.release_job(job.id, "worker lost", None).await.unwrap();
+		jq.check_stale().await.unwrap();
+		assert_eq!(recorder.0.lock().unwrap().len(), 2);
+	}
+
+	/// Records edges of emptiness of the queue.
+	#[derive(Debug, Clone, Default)]
+	struct EmptyRecorder(Arc<Mutex<Vec<bool>>>);
+
+	impl JobObserver for EmptyRecorder {
+		fn on_queue_empty(&self) {
+			self.0.lock().unwrap().push(true);
+		}
+
+		fn on_queue_nonempty(&self) {
+			self.0.lock().unwrap().push(false);
+		}
+	}
+
+	#[tokio::test]
+	async fn test_empty_edges() {
+		let env = test_env().await;
+		let recorder = EmptyRecorder::default();
+		let clock = ManualClock::new();
+		let config = JobQueueConfig {
+			empty_debounce: 60,
+			..Default::default()
+		};
+		let jq = JobQueue::new(env.database.clone(), config)
+			.with_clock(clock.clone())
+			.with_observer(recorder.clone());
+
+		let mut db = env.database.get().await.unwrap();
+		for branch in [1, 2] {
+			jq.enqueue(&mut db, JobCommand::sync_branch(branch))
+				.await
+				.unwrap();
+		}
+		drop(db);
+		assert!(!jq.check_empty().await.unwrap());
+		assert_eq!(recorder.0.lock().unwrap().clone(), [false]);
+
+		for _ in 0..2 {
+			let job = jq.fetch_and_start().await.unwrap().unwrap();
+			let mut db = env.database.get().await.unwrap();
+			jq.finish_job(&mut db, job.id).await.unwrap();
+			drop(db);
+			jq.check_empty().await.unwrap();
+		}
+		// debounced until the queue stays empty
+		assert!(jq.check_empty().await.unwrap());
+		assert_eq!(recorder.0.lock().unwrap().clone(), [false]);
+		clock.advance(Duration::minutes(1));
+		for _ in 0..3 {
+			assert!(jq.check_empty().await.unwrap());
+		}
+		assert_eq!(recorder.0.lock().unwrap().clone(), [false, true]);
+	}
+
+	#[tokio::test]
+	async fn test_export_import() {
+		let env = test_env().await;
+		let mut db = env.database.get().await.unwrap();
+		let sync = env
+			.job_queue
+			.enqueue_with_priority(&mut db, JobCommand::sync_branch(1), 100)
+			.await
+			.unwrap();
+		for (branch, priority) in [(2, 200), (3, 100)] {
+			let options = EnqueueOptions {
+				priority: Some(priority),
+				depends_on: vec![sync],
+				..Default::default()
+			};
+			env.job_queue
+				.enqueue_with(&mut db, JobCommand::sync_branch(branch), options)
+				.await
+				.unwrap();
+		}
+		let options = EnqueueOptions {
+			front: true,
+			..Default::default()
+		};
+		let retried = env
+			.job_queue
+			.enqueue_with(&mut db, JobCommand::sync_branch(4), options)
+			.await
+			.unwrap()
+			.id();
+		let time = OffsetDateTime::from_unix_timestamp(1_900_000_000).unwrap();
+		db.execute(
+			update(dsl::job_queue)
+				.filter(dsl::id.eq(XUuidVal(retried)))
+				.set((
+					dsl::attempts.eq(2),
+					dsl::last_error.eq("timed out"),
+					dsl::next_attempt_at.eq(PrimitiveDateTime::new(time.date(), time.time())),
+					dsl::callback_url.eq("http://127.0.0.1/done"),
+				)),
+		)
+		.await
+		.unwrap();
+		drop(db);
+
+		let exported = env.job_queue.export().await.unwrap();
+		assert_eq!(exported.len(), 4);
+		assert!(exported.iter().all(|job| job.created_at.is_some()));
+		assert_eq!(exported[1].depends_on, [sync]);
+		let job = &exported[3];
+		assert_eq!(job.id, retried);
+		assert_eq!(
+			(job.attempts, job.last_error.as_deref(), job.seq),
+			(2, Some("timed out"), -1)
+		);
+		assert_eq!(job.next_attempt_at, Some(time));
+		assert_eq!(job.callback_url.as_deref(), Some("http://127.0.0.1/done"));
+
+		let target = test_env().await;
+		target.job_queue.import(exported.clone()).await.unwrap();
+		assert_eq!(target.job_queue.export().await.unwrap(), exported);
+		// importing again conflicts
+		assert!(target.job_queue.import(exported.clone()).await.is_err());
+
+		let mut claimed = Vec::new();
+		for _ in 0..3 {
+			let job = target.job_queue.fetch_and_start().await.unwrap().unwrap();
+			claimed.push(job.command.clone());
+			let mut db = target.database.get().await.unwrap();
+			target.job_queue.finish_job(&mut db, job.id).await.unwrap();
+		}
+		assert_eq!(claimed, [1, 2, 3].map(JobCommand::sync_branch),);
+
+		// imported dependencies may form a cycle
+		let mut cycle = exported[1..3].to_vec();
+		cycle[0].depends_on = vec![cycle[1].id];
+		cycle[1].depends_on = vec![cycle[0].id];
+		let target = test_env().await;
+		assert!(matches!(
+			target.job_queue.import(cycle).await,
+			Err(BackendError::JobQueueError(JobQueueError::DependencyCycle(
+				_
+			)))
+		));
+		assert!(target.job_queue.export().await.unwrap().is_empty());
+	}
+
+	#[tokio::test]
+	async fn test_count_pending() {
+		let env = test_env().await;
+		let mut db = env.database.get().await.unwrap();
+		for branch in 0..3 {
+			env.job_queue
+				.enqueue(&mut db, JobCommand::sync_branch(branch))
+				.await
+				.unwrap();
+		}
+		drop(db);
+		env.job_queue.fetch_and_start().await.unwrap().unwrap();
+
+		// started jobs are not counted, and counting stops at `max`
+		assert_eq!(env.job_queue.count_pending(10).await.unwrap(), 2);
+		assert_eq!(env.job_queue.count_pending(1).await.unwrap(), 1);
+	}
+
+	#[tokio::test]
+	async fn test_maintain() {
+		let env = test_env().await;
+		let mut db = env.database.get().await.unwrap();
+		for branch in 0..16 {
+			env.job_queue
+				.enqueue(&mut db, JobCommand::sync_branch(branch))
+				.await
+				.unwrap();
+		}
+		drop(db);
+
+		let report = env.job_queue.maintain(true).await.unwrap();
+		assert!(!report.vacuumed);
+		assert_eq!(
+			report
+				.tables
+				.iter()
+				.map(|(table, _)| *table)
+				.collect::<Vec<_>>(),
+			MAINTAINED_TABLES
+		);
+		assert_eq!(env.job_queue.count_pending(100).await.unwrap(), 16);
+	}
+
+	#[derive(Debug, Default)]
+	struct SequentialIdGenerator(AtomicU64);
+
+	impl IdGenerator for SequentialIdGenerator {
+		fn generate(&self) -> JobRef {
+			Uuid::from_u128(self.0.fetch_add(1, Ordering::Relaxed) as u128 + 1)
+		}
+	}
+
+	#[tokio::test]
+	async fn test_id_generator() {
+		let env = test_env().await;
+		let jq = JobQueue::new(env.database.clone(), Default::default())
+			.with_id_generator(SequentialIdGenerator::default());
+
+		let mut db = env.database.get().await.unwrap();
+		for branch in 1..=3 {
+			let id = jq
+				.enqueue(&mut db, JobCommand::sync_branch(branch))
+				.await
+				.unwrap();
+			assert_eq!(id, Uuid::from_u128(branch as u128));
+		}
+		drop(db);
+
+		for expected in 1..=3 {
+			let job = jq.fetch_and_start().await.unwrap().unwrap();
+			assert_eq!(job.id, Uuid::from_u128(expected));
+			assert_eq!(job.command, JobCommand::sync_branch(expected as i64));
+		}
+	}
+
+	#[tokio::test]
+	async fn test_dependency() {
+		let env = test_env().await;
+		let jq = env.job_queue;
+
+		let mut db = env.database.get().await.unwrap();
+		let sync = jq
+			.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+		let options = EnqueueOptions {
+			priority: Some(200),
+			depends_on: vec![sync],
+			..Default::default()
+		};
+		jq.enqueue_with(&mut db, JobCommand::sync_branch(2), options)
+			.await
+			.unwrap();
+		drop(db);
+
+		// the dependent job is not eligible, despite of its higher priority
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		assert_eq!(job.command, JobCommand::sync_branch(1));
+		assert!(jq.fetch_and_start().await.unwrap().is_none());
+
+		let mut db = env.database.get().await.unwrap();
+		jq.finish_job(&mut db, job.id).await.unwrap();
+		drop(db);
+		assert_eq!(
+			jq.fetch_and_start().await.unwrap().unwrap().command,
+			JobCommand::sync_branch(2)
+		);
+	}
+
+	#[tokio::test]
+	async fn test_failed_dependency() {
+		let env = test_env().await;
+		let config = JobQueueConfig {
+			max_attempts: 1,
+			..Default::default()
+		};
+		let jq = JobQueue::new(env.database.clone(), config);
+
+		let mut db = env.database.get().await.unwrap();
+		let sync = jq
+			.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+		let depends_on = |id| EnqueueOptions {
+			depends_on: vec![id],
+			..Default::default()
+		};
+		let dependent = jq
+			.enqueue_with(&mut db, JobCommand::sync_branch(2), depends_on(sync))
+			.await
+			.unwrap()
+			.id();
+		let transitive = jq
+			.enqueue_with(&mut db, JobCommand::sync_branch(3), depends_on(dependent))
+			.await
+			.unwrap()
+			.id();
+		drop(db);
+
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		let mut db = env.database.get().await.unwrap();
+		assert!(!jq.fail_job(&mut db, job.id, "broken").await.unwrap());
+		drop(db);
+
+		// dependents are dead-lettered along with the dependency
+		assert!(jq.fetch_and_start().await.unwrap().is_none());
+		for id in [dependent, transitive] {
+			assert!(jq.get_job(id).await.unwrap().is_none());
+		}
+		let mut db = env.database.get().await.unwrap();
+		for id in [dependent, transitive] {
+			let error = db
+				.get_result::<_, String>(
+					job_dead_letter::table
+						.filter(job_dead_letter::id.eq(XUuidVal(id)))
+						.select(job_dead_letter::error),
+				)
+				.await
+				.unwrap();
+			assert!(error.starts_with("dependency "));
+		}
+
+		// new dependents of the failed job are rejected
+		let error = jq
+			.enqueue_with(&mut db, JobCommand::sync_branch(4), depends_on(sync))
+			.await
+			.unwrap_err();
+		assert!(matches!(
+			error,
+			BackendError::JobQueueError(JobQueueError::DependencyFailed(id)) if id == sync
+		));
+	}
+
+	#[tokio::test]
+	async fn test_message_pack_encoding() {
+		let env = test_env().await;
+		let config = JobQueueConfig {
+			data_encoding: JobDataEncoding::MessagePack,
+			..Default::default()
+		};
+		let jq = JobQueue::new(env.database.clone(), config);
+
+		let mut db = env.database.get().await.unwrap();
+		// written as JSON before the encoding is changed
+		let json_job = env
+			.job_queue
+			.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+		let binary_job = jq
+			.enqueue(&mut db, JobCommand::sync_branch(2))
+			.await
+			.unwrap();
+		let data_bin = db
+			.get_result::<_, Option<Vec<u8>>>(
+				dsl::job_queue
+					.filter(dsl::id.eq(XUuidVal(binary_job)))
+					.select(dsl::data_bin),
+			)
+			.await
+			.unwrap();
+		assert!(data_bin.is_some());
+		drop(db);
+
+		assert_eq!(
+			jq.get_job(binary_job).await.unwrap().unwrap().data,
+			json!({ "branch": 2 })
+		);
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		assert_eq!(
+			(job.id, job.command),
+			(json_job, JobCommand::sync_branch(1))
+		);
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		assert_eq!(
+			(job.id, job.command),
+			(binary_job, JobCommand::sync_branch(2))
+		);
+	}
+
+	#[test]
+	fn test_message_pack_round_trip() {
+		let value = json!({
+			"branch": "stable",
+			"packages": ["bash", "coreutils", "glibc", "linux-kernel"],
+			"targets": { "amd64": true, "arm64": true, "loongarch64": false },
+			"priority": 100,
+		});
+
+		let (data, data_bin, compressed) = JobDataEncoding::MessagePack
+			.encode(value.clone(), None, None, CompressionCodec::Zstd)
+			.unwrap();
+		let data_bin = data_bin.unwrap();
+		assert!(!compressed);
+		assert!(data_bin.len() < serde_json::to_vec(&value).unwrap().len());
+		assert_eq!(
+			JobDataEncoding::decode((data, Some(data_bin), compressed)).unwrap(),
+			value
+		);
+	}
+
+	#[test]
+	fn test_encoding_round_trip() {
+		let command = JobCommand::SyncBranch {
+			branch: 7,
+			cursor: Some("rev-42".to_string()),
+		};
+		let (kind, value) = command.serialize().unwrap();
+		for encoding in [
+			JobDataEncoding::Json,
+			JobDataEncoding::MessagePack,
+			JobDataEncoding::Cbor,
+		] {
+			for compress in [false, true] {
+				let data = encoding
+					.encode(value.clone(), Some(compress), None, CompressionCodec::Zstd)
+					.unwrap();
+				assert_eq!(
+					data.1.is_some(),
+					compress || encoding != JobDataEncoding::Json
+				);
+				let decoded = JobDataEncoding::decode(data).unwrap();
+				assert_eq!(JobCommand::deserialize(&kind, decoded).unwrap(), command);
+			}
+		}
+	}
+
+	#[tokio::test]
+	async fn test_fail_job() {
+		let env = test_env().await;
+		let jq = env.job_queue;
+
+		let mut db = env.database.get().await.unwrap();
+		let id = jq
+			.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+		drop(db);
+
+		for attempt in 1..=3 {
+			let job = jq.fetch_and_start().await.unwrap().unwrap();
+			assert_eq!(job.id, id);
+			let mut db = env.database.get().await.unwrap();
+			let retry = jq
+				.fail_job(&mut db, id, &format!("failure {}", attempt))
+				.await
+				.unwrap();
+			drop(db);
+
+			if attempt < 3 {
+				assert!(retry);
+				let info = jq.get_job(id).await.unwrap().unwrap();
+				assert_eq!(info.attempts, attempt);
+				assert_eq!(info.last_error, Some(format!("failure {}", attempt)));
+				assert!(info.started_at.is_none());
+			} else {
+				assert!(!retry);
+				assert!(jq.get_job(id).await.unwrap().is_none());
+			}
+		}
+	}
+
+	async fn finish_with_policy(on_finish: FinishPolicy) -> Vec<(XUuidVal, i16)> {
+		let env = test_env().await;
+		let config = JobQueueConfig {
+			on_finish,
+			..Default::default()
+		};
+		let jq = JobQueue::new(env.database.clone(), config);
+
+		let mut db = env.database.get().await.unwrap();
+		for branch in [1, 2] {
+			jq.enqueue(&mut db, JobCommand::sync_branch(branch))
+				.await
+				.unwrap();
+		}
+		drop(db);
+		let finished = jq.fetch_and_start().await.unwrap().unwrap();
+		let skipped = jq.fetch_and_start().await.unwrap().unwrap();
+		let mut db = env.database.get().await.unwrap();
+		jq.finish_job(&mut db, finished.id).await.unwrap();
+		jq.skip_job(&mut db, skipped.id).await.unwrap();
+
+		assert_eq!(
+			db.get_result::<_, i64>(dsl::job_queue.count())
+				.await
+				.unwrap(),
+			0
+		);
+		let mut history = db
+			.load::<_, (XUuidVal, i16)>(
+				job_history::table.select((job_history::id, job_history::outcome)),
+			)
+			.await
+			.unwrap();
+		history.sort_by_key(|(_, outcome)| *outcome);
+		if on_finish == FinishPolicy::Archive {
+			assert_eq!(
+				history,
+				[
+					(XUuidVal(finished.id), AttemptOutcome::Succeeded as i16),
+					(XUuidVal(skipped.id), AttemptOutcome::Skipped as i16),
+				]
+			);
+		}
+		history
+	}
+
+	#[tokio::test]
+	async fn test_finish_archive() {
+		assert_eq!(finish_with_policy(FinishPolicy::Archive).await.len(), 2);
+	}
+
+	#[tokio::test]
+	async fn test_finish_delete() {
+		assert!(finish_with_policy(FinishPolicy::Delete).await.is_empty());
+	}
+
+	#[tokio::test]
+	async fn test_workers() {
+		let env = test_env().await;
+		let clock = ManualClock::new();
+		let jq = JobQueue::new(env.database.clone(), Default::default()).with_clock(clock.clone());
+
+		let mut db = env.database.get().await.unwrap();
+		for branch in 0..4 {
+			jq.enqueue(&mut db, JobCommand::sync_branch(branch))
+				.await
+				.unwrap();
+		}
+		drop(db);
+
+		jq.register_worker("worker-a").await.unwrap();
+		jq.register_worker("worker-b").await.unwrap();
+		for (worker, count) in [("worker-a", 2), ("worker-b", 1)] {
+			let options = ClaimOptions {
+				worker: Some(worker),
+				..Default::default()
+			};
+			let jobs = jq
+				.fetch_and_start_batch_with(count, &options)
+				.await
+				.unwrap();
+			assert_eq!(jobs.len(), count);
+			for job in jobs {
+				let info = jq.get_job(job.id).await.unwrap().unwrap();
+				assert_eq!(info.claimed_by.as_deref(), Some(worker));
+			}
+		}
+		// claimed without a worker
+		jq.fetch_and_start().await.unwrap().unwrap();
+
+		clock.advance(Duration::seconds(30));
+		jq.register_worker("worker-b").await.unwrap();
+
+		let workers = jq.list_workers().await.unwrap();
+		assert_eq!(
+			workers
+				.iter()
+				.map(|worker| (
+					worker.id.as_str(),
+					worker.running,
+					worker.heartbeat_at - worker.registered_at
+				))
+				.collect::<Vec<_>>(),
+			[
+				("worker-a", 2, Duration::ZERO),
+				("worker-b", 1, Duration::seconds(30)),
+			]
+		);
+
+		jq.unregister_worker("worker-a").await.unwrap();
+		assert_eq!(jq.list_workers().await.unwrap().len(), 1);
+	}
+
+	#[tokio::test]
+	async fn test_rewrite_pending() {
+		let env = test_env().await;
+		let jq = env.job_queue;
+
+		let mut db = env.database.get().await.unwrap();
+		let id = jq
+			.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+		jq.rewrite_pending(&mut db, id, JobCommand::sync_branch(2))
+			.await
+			.unwrap();
+		drop(db);
+
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		assert_eq!(job.id, id);
+		assert_eq!(job.command, JobCommand::sync_branch(2));
+
+		let mut db = env.database.get().await.unwrap();
+		assert!(matches!(
+			jq.rewrite_pending(&mut db, id, JobCommand::sync_branch(3))
+				.await,
+			Err(BackendError::JobQueueError(JobQueueError::AlreadyStarted(
+				_
+			)))
+		));
+		jq.finish_job(&mut db, id).await.unwrap();
+		assert!(matches!(
+			jq.rewrite_pending(&mut db, id, JobCommand::sync_branch(3))
+				.await,
+			Err(BackendError::JobQueueError(JobQueueError::JobAborted(_)))
+		));
+	}
+
+	#[tokio::test]
+	async fn test_fail_job_with() {
+		let env = test_env().await;
+		let config = JobQueueConfig {
+			max_attempts: 2,
+			..Default::default()
+		};
+		let jq = JobQueue::new(env.database.clone(), config);
+
+		let mut db = env.database.get().await.unwrap();
+		let id = jq
+			.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+		drop(db);
+
+		let failures = [
+			JobFailure::new(JobErrorCode::Network, "connection reset"),
+			JobFailure::new(JobErrorCode::Validation, "invalid manifest"),
+		];
+		for failure in &failures {
+			jq.fetch_and_start().await.unwrap().unwrap();
+			let mut db = env.database.get().await.unwrap();
+			jq.fail_job_with(&mut db, id, failure).await.unwrap();
+		}
+
+		let attempts = jq.get_attempts(id).await.unwrap();
+		assert_eq!(
+			attempts
+				.iter()
+				.map(|attempt| (attempt.error_code, attempt.error.as_deref()))
+				.collect::<Vec<_>>(),
+			[
+				(Some(JobErrorCode::Network), Some("connection reset")),
+				(Some(JobErrorCode::Validation), Some("invalid manifest")),
+			]
+		);
+
+		let mut db = env.database.get().await.unwrap();
+		let (error, code) = db
+			.get_result::<_, (String, Option<i16>)>(
+				job_dead_letter::table
+					.select((job_dead_letter::error, job_dead_letter::error_code)),
+			)
+			.await
+			.unwrap();
+		assert_eq!(error, "invalid manifest");
+		assert_eq!(code.map(JobErrorCode::from), Some(JobErrorCode::Validation));
+	}
+
+	#[tokio::test]
+	async fn test_fail_job_truncate_error() {
+		let env = test_env().await;
+		let jq = env.job_queue;
+
+		let mut db = env.database.get().await.unwrap();
+		let id = jq
+			.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+		drop(db);
+		jq.fetch_and_start().await.unwrap().unwrap();
+		let mut db = env.database.get().await.unwrap();
+		// multi-byte characters are not split
+		jq.fail_job(&mut db, id, &"é".repeat(MAX_ERROR_LEN))
+			.await
+			.unwrap();
+		drop(db);
+
+		let last_error = jq.get_job(id).await.unwrap().unwrap().last_error.unwrap();
+		assert!(last_error.len() <= MAX_ERROR_LEN);
+		assert!("é".repeat(MAX_ERROR_LEN).starts_with(&last_error));
+	}
+
+	#[tokio::test]
+	async fn test_fetch_poison_job() {
+		let env = test_env().await;
+		let jq = env.job_queue;
+
+		let mut db = env.database.get().await.unwrap();
+		let poison = Uuid::now_v7();
+		db.execute(insert_into(dsl::job_queue).values((
+			dsl::id.eq(XUuidVal(poison)),
+			dsl::kind.eq("SyncBranch"),
+			dsl::data.eq(XJsonVal(json!("not a branch"))),
+			dsl::priority.eq(200),
+		)))
+		.await
+		.unwrap();
+		let valid = jq
+			.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+		drop(db);
+
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		assert_eq!(job.id, valid);
+		assert!(jq.get_job(poison).await.unwrap().is_none());
+
+		let mut db = env.database.get().await.unwrap();
+		assert_eq!(
+			db.get_result::<_, XUuidVal>(job_dead_letter::table.select(job_dead_letter::id))
+				.await
+				.unwrap(),
+			XUuidVal(poison)
+		);
+	}
+
+	#[tokio::test]
+	async fn test_fetch_unknown_kind() {
+		for policy in [UnknownKindPolicy::Skip, UnknownKindPolicy::Quarantine] {
+			let env = test_env().await;
+			let jq = JobQueue::new(
+				env.database.clone(),
+				JobQueueConfig {
+					unknown_kinds: policy,
+					..Default::default()
+				},
+			);
+
+			let mut db = env.database.get().await.unwrap();
+			let unknown = Uuid::now_v7();
+			db.execute(insert_into(dsl::job_queue).values((
+				dsl::id.eq(XUuidVal(unknown)),
+				dsl::kind.eq("FromTheFuture"),
+				dsl::data.eq(XJsonVal(json!({}))),
+				dsl::priority.eq(200),
+			)))
+			.await
+			.unwrap();
+			let valid = jq
+				.enqueue(&mut db, JobCommand::sync_branch(1))
+				.await
+				.unwrap();
+			drop(db);
+
+			let job = jq.fetch_and_start().await.unwrap().unwrap();
+			assert_eq!(job.id, valid);
+			assert!(jq.fetch_and_start().await.unwrap().is_none());
+
+			let unknown_job = jq.get_job(unknown).await.unwrap();
+			match policy {
+				UnknownKindPolicy::Skip => {
+					assert!(unknown_job.unwrap().started_at.is_none());
+				}
+				UnknownKindPolicy::Quarantine => {
+					assert!(unknown_job.is_none());
+					let mut db = env.database.get().await.unwrap();
+					assert_eq!(
+						db.get_result::<_, XUuidVal>(
+							job_dead_letter::table.select(job_dead_letter::id)
+						)
+						.await
+						.unwrap(),
+						XUuidVal(unknown)
+					);
+				}
+			}
+		}
+	}
+
+	#[tokio::test]
+	async fn test_compression() {
+		let env = test_env().await;
+		let config = JobQueueConfig {
+			compress_threshold: Some(1024),
+			..Default::default()
+		};
+		let jq = JobQueue::new(env.database.clone(), config);
+
+		let mut db = env.database.get().await.unwrap();
+		let small = jq
+			.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+		// large payloads are not producible with current commands
+		let value = json!({ "packages": vec!["package-with-a-long-name"; 1000] });
+		let (data, data_bin, compressed) = JobDataEncoding::Json
+			.encode(value.clone(), None, Some(1024), CompressionCodec::Zstd)
+			.unwrap();
+		let large = Uuid::now_v7();
+		db.execute(insert_into(dsl::job_queue).values((
+			dsl::id.eq(XUuidVal(large)),
+			dsl::kind.eq("Large"),
+			dsl::data.eq(data),
+			dsl::data_bin.eq(data_bin),
+			dsl::compressed.eq(compressed),
+			dsl::priority.eq(100),
+		)))
+		.await
+		.unwrap();
+
+		let stored = |id| {
+			dsl::job_queue
+				.filter(dsl::id.eq(XUuidVal(id)))
+				.select((dsl::data_bin, dsl::compressed))
+		};
+		let (data_bin, compressed) = db
+			.get_result::<_, (Option<Vec<u8>>, bool)>(stored(small))
+			.await
+			.unwrap();
+		assert!(data_bin.is_none() && !compressed);
+		let (data_bin, compressed) = db
+			.get_result::<_, (Option<Vec<u8>>, bool)>(stored(large))
+			.await
+			.unwrap();
+		assert!(compressed);
+		assert!(data_bin.unwrap().len() < serde_json::to_vec(&value).unwrap().len());
+		drop(db);
+
+		assert_eq!(jq.get_job(large).await.unwrap().unwrap().data, value);
+	}
+
+	#[test]
+	fn test_compression_codec() {
+		// large payloads are not producible with current commands
+		let value = json!({ "packages": vec!["package-with-a-long-name"; 1000] });
+		let (zstd_data, zstd_data_bin, compressed) = JobDataEncoding::Json
+			.encode(value.clone(), None, Some(1024), CompressionCodec::Zstd)
+			.unwrap();
+		assert!(compressed);
+		let zstd_data_bin = zstd_data_bin.unwrap();
+		assert!(zstd_data_bin.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]));
+
+		let (gzip_data, gzip_data_bin, compressed) = JobDataEncoding::Json
+			.encode(value.clone(), None, Some(1024), CompressionCodec::Gzip)
+			.unwrap();
+		assert!(compressed);
+		let gzip_data_bin = gzip_data_bin.unwrap();
+		assert!(gzip_data_bin.starts_with(&[0x1f, 0x8b]));
+		assert!(gzip_data_bin.len() < serde_json::to_vec(&value).unwrap().len());
+
+		// rows compressed with different codecs coexist
+		assert_eq!(
+			JobDataEncoding::decode((zstd_data, Some(zstd_data_bin), true)).unwrap(),
+			value
+		);
+		assert_eq!(
+			JobDataEncoding::decode((gzip_data, Some(gzip_data_bin), true)).unwrap(),
+			value
+		);
+	}
+
+	#[tokio::test]
+	async fn test_compression_gzip() {
+		let env = test_env().await;
+		let config = JobQueueConfig {
+			compress_threshold: Some(0),
+			compression: CompressionCodec::Gzip,
+			..Default::default()
+		};
+		let jq = JobQueue::new(env.database.clone(), config);
+
+		let mut db = env.database.get().await.unwrap();
+		let id = jq
+			.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+		let (data_bin, compressed) = db
+			.get_result::<_, (Option<Vec<u8>>, bool)>(
+				dsl::job_queue
+					.filter(dsl::id.eq(XUuidVal(id)))
+					.select((dsl::data_bin, dsl::compressed)),
+			)
+			.await
+			.unwrap();
+		assert!(compressed);
+		assert!(data_bin.unwrap().starts_with(&[0x1f, 0x8b]));
+		drop(db);
+
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		assert_eq!(job.command, JobCommand::sync_branch(1));
+	}
+
+	#[tokio::test]
+	async fn test_compression_on_request() {
+		let env = test_env().await;
+		let jq = env.job_queue;
+
+		let mut db = env.database.get().await.unwrap();
+		let options = EnqueueOptions {
+			compress: Some(true),
+			..Default::default()
+		};
+		let id = jq
+			.enqueue_with(&mut db, JobCommand::sync_branch(1), options)
+			.await
+			.unwrap()
+			.id();
+		let compressed = db
+			.get_result::<_, bool>(
+				dsl::job_queue
+					.filter(dsl::id.eq(XUuidVal(id)))
+					.select(dsl::compressed),
+			)
+			.await
+			.unwrap();
+		assert!(compressed);
+		drop(db);
+
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		assert_eq!(job.command, JobCommand::sync_branch(1));
+	}
+
+	#[tokio::test]
+	async fn test_fetch_min_priority() {
+		let env = test_env().await;
+		let jq = env.job_queue;
+
+		let mut db = env.database.get().await.unwrap();
+		let low = jq
+			.enqueue_with_priority(&mut db, JobCommand::sync_branch(1), 50)
+			.await
+			.unwrap();
+		drop(db);
+
+		let urgent = ClaimOptions {
+			min_priority: Some(200),
+			..Default::default()
+		};
+		assert!(jq.fetch_and_start_with(&urgent).await.unwrap().is_none());
+
+		let mut db = env.database.get().await.unwrap();
+		let high = jq
+			.enqueue_with_priority(&mut db, JobCommand::sync_branch(2), 200)
+			.await
+			.unwrap();
+		drop(db);
+		assert_eq!(
+			jq.fetch_and_start_with(&urgent).await.unwrap().unwrap().id,
+			high
+		);
+		assert!(jq.fetch_and_start_with(&urgent).await.unwrap().is_none());
+
+		// general workers still claim low-priority jobs
+		assert_eq!(jq.fetch_and_start().await.unwrap().unwrap().id, low);
+	}
+
+	#[tokio::test]
+	async fn test_fetch_kinds() {
+		let env = test_env().await;
+		let jq = env.job_queue;
+
+		let mut db = env.database.get().await.unwrap();
+		jq.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+		let other = Uuid::now_v7();
+		db.execute(insert_into(dsl::job_queue).values((
+			dsl::id.eq(XUuidVal(other)),
+			dsl::kind.eq("Other"),
+			dsl::data.eq(XJsonVal(json!(null))),
+			dsl::priority.eq(200),
+		)))
+		.await
+		.unwrap();
+		drop(db);
+
+		// the job of higher priority is skipped
+		let kinds = [KString::from_static("SyncBranch")];
+		assert!(
+			jq.fetch_and_start_kinds(Some(&[KString::from_static("Unknown")]))
+				.await
+				.unwrap()
+				.is_none()
+		);
+		let job = jq
+			.fetch_and_start_kinds(Some(&kinds))
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(job.command, JobCommand::sync_branch(1));
+		assert!(
+			jq.fetch_and_start_kinds(Some(&kinds))
+				.await
+				.unwrap()
+				.is_none()
+		);
+		assert!(
+			jq.get_job(other)
+				.await
+				.unwrap()
+				.unwrap()
+				.started_at
+				.is_none()
+		);
+	}
+
+	#[tokio::test]
+	async fn test_default_priority() {
+		let env = test_env().await;
+		let config = JobQueueConfig {
+			default_priority: 50,
+			..Default::default()
+		};
+		let jq = JobQueue::new(env.database.clone(), config);
+
+		let mut db = env.database.get().await.unwrap();
+		let low = jq
+			.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+		let high = jq
+			.enqueue_with_priority(&mut db, JobCommand::sync_branch(2), 100)
+			.await
+			.unwrap();
+		// options without a priority also use the default
+		let options = EnqueueOptions {
+			dedup_key: Some("sync-3".to_string()),
+			..Default::default()
+		};
+		let deduped = jq
+			.enqueue_with(&mut db, JobCommand::sync_branch(3), options)
+			.await
+			.unwrap()
+			.id();
+		drop(db);
+
+		assert_eq!(jq.get_job(low).await.unwrap().unwrap().priority, 50);
+		assert_eq!(jq.get_job(deduped).await.unwrap().unwrap().priority, 50);
+		assert_eq!(jq.fetch_and_start().await.unwrap().unwrap().id, high);
+		assert_eq!(jq.fetch_and_start().await.unwrap().unwrap().id, low);
+	}
+
+	#[tokio::test]
+	async fn test_warn_if_slow() {
+		let env = test_env().await;
+		let config = JobQueueConfig {
+			slow_claim_threshold_ms: Some(100),
+			..Default::default()
+		};
+		let jq = JobQueue::new(env.database.clone(), config);
+
+		assert!(!jq.warn_if_slow("claim query", std::time::Duration::from_millis(99)));
+		assert!(jq.warn_if_slow("claim query", std::time::Duration::from_millis(100)));
+		assert!(
+			!env.job_queue
+				.warn_if_slow("claim query", std::time::Duration::MAX)
+		);
+
+		// with a zero threshold, every claim is slow
+		let config = JobQueueConfig {
+			slow_claim_threshold_ms: Some(0),
+			..Default::default()
+		};
+		let jq = JobQueue::new(env.database.clone(), config);
+		let mut db = env.database.get().await.unwrap();
+		jq.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+		drop(db);
+		assert!(jq.fetch_and_start().await.unwrap().is_some());
+	}
+
+	#[tokio::test]
+	async fn test_attempts() {
+		let env = test_env().await;
+		let jq = env.job_queue;
+
+		let mut db = env.database.get().await.unwrap();
+		let id = jq
+			.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+		drop(db);
+		for attempt in 1..=2 {
+			jq.fetch_and_start().await.unwrap().unwrap();
+			let mut db = env.database.get().await.unwrap();
+			jq.fail_job(&mut db, id, &format!("failure {}", attempt))
+				.await
+				.unwrap();
+		}
+		jq.fetch_and_start().await.unwrap().unwrap();
+		let mut db = env.database.get().await.unwrap();
+		jq.finish_job(&mut db, id).await.unwrap();
+		drop(db);
+
+		let attempts = jq.get_attempts(id).await.unwrap();
+		assert_eq!(
+			attempts
+				.iter()
+				.map(|attempt| (attempt.attempt, attempt.outcome, attempt.error.as_deref()))
+				.collect::<Vec<_>>(),
+			vec![
+				(1, AttemptOutcome::Failed, Some("failure 1")),
+				(2, AttemptOutcome::Failed, Some("failure 2")),
+				(3, AttemptOutcome::Succeeded, None),
+			]
+		);
+		assert!(attempts.iter().all(|attempt| attempt.finished_at.is_some()));
+	}
+
+	#[tokio::test]
+	async fn test_skip_job_of_disabled_branch() {
+		let env = TestEnv::builder().with_branches(["main"]).build().await;
+		let jq = &env.job_queue;
+		let branch = env.branch.find_id_or_err("main").await.unwrap();
+
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		assert_eq!(job.command, JobCommand::sync_branch(branch));
+		let mut db = env.database.get().await.unwrap();
+		assert!(jq.check_guard(&mut db, &job.command).await.unwrap());
+		drop(db);
+
+		// disabled after the job is started
+		let info = BranchConfigInfo {
+			enabled: Some(false),
+			..Default::default()
+		};
+		env.branch.update_config(branch, &info, None).await.unwrap();
+		let mut db = env.database.get().await.unwrap();
+		assert!(!jq.check_guard(&mut db, &job.command).await.unwrap());
+		jq.skip_job(&mut db, job.id).await.unwrap();
+		drop(db);
+
+		assert!(jq.get_job(job.id).await.unwrap().is_none());
+		let attempts = jq.get_attempts(job.id).await.unwrap();
+		assert_eq!(attempts.len(), 1);
+		assert_eq!(attempts[0].outcome, AttemptOutcome::Skipped);
+		assert_eq!(
+			attempts[0].error.as_deref(),
+			Some("skipped: precondition failed")
+		);
+	}
+
+	#[tokio::test]
+	async fn test_run_job_panic() {
+		let env = test_env().await;
+		let jq = env.job_queue;
+
+		let mut db = env.database.get().await.unwrap();
+		let id = jq
+			.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+		drop(db);
+
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		let handler = async {
+			if job.id == id {
+				panic!("bad job");
+			}
+			Ok::<_, String>(())
+		};
+		assert!(!jq.run_job(&job, handler).await.unwrap());
+		let info = jq.get_job(id).await.unwrap().unwrap();
+		assert!(info.started_at.is_none());
+		assert_eq!(info.last_error.as_deref(), Some("panicked: bad job"));
+
+		// the worker keeps running jobs
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		assert!(
+			jq.run_job(&job, async { Ok::<_, String>(()) })
+				.await
+				.unwrap()
+		);
+		assert!(jq.get_job(id).await.unwrap().is_none());
+	}
+
+	#[tokio::test]
+	async fn test_max_claims_per_worker() {
+		let env = test_env().await;
+		let config = JobQueueConfig {
+			max_claims_per_worker: Some(2),
+			..Default::default()
+		};
+		let jq = JobQueue::new(env.database.clone(), config);
+		let mut db = env.database.get().await.unwrap();
+		for branch in 1..=4 {
+			jq.enqueue(&mut db, JobCommand::sync_branch(branch))
+				.await
+				.unwrap();
+		}
+		drop(db);
+
+		let fast = ClaimOptions {
+			worker: Some("fast"),
+			..Default::default()
+		};
+		let slow = ClaimOptions {
+			worker: Some("slow"),
+			..Default::default()
+		};
+		let jobs = jq.fetch_and_start_batch_with(3, &fast).await.unwrap();
+		assert_eq!(jobs.len(), 2);
+		assert!(jq.fetch_and_start_with(&fast).await.unwrap().is_none());
+		assert!(jq.fetch_and_start_with(&slow).await.unwrap().is_some());
+
+		// finishing a job frees a slot
+		let mut db = env.database.get().await.unwrap();
+		jq.finish_job(&mut db, jobs[0].id).await.unwrap();
+		drop(db);
+		assert!(jq.fetch_and_start_with(&fast).await.unwrap().is_some());
+	}
+
+	#[tokio::test]
+	async fn test_max_pending() {
+		let env = test_env().await;
+		let config = JobQueueConfig {
+			max_pending: Some(2),
+			..Default::default()
+		};
+		let jq = JobQueue::new(env.database.clone(), config);
+
+		let mut db = env.database.get().await.unwrap();
+		for branch in [1, 2] {
+			jq.enqueue(&mut db, JobCommand::sync_branch(branch))
+				.await
+				.unwrap();
+		}
+		assert!(matches!(
+			jq.enqueue(&mut db, JobCommand::sync_branch(3))
+				.await
+				.unwrap_err(),
+			BackendError::JobQueueError(JobQueueError::QueueFull(30))
+		));
+		drop(db);
+
+		// running jobs are not counted
+		jq.fetch_and_start().await.unwrap().unwrap();
+		let mut db = env.database.get().await.unwrap();
+		jq.enqueue(&mut db, JobCommand::sync_branch(3))
+			.await
+			.unwrap();
+	}
+
+	#[tokio::test]
+	async fn test_boost_branch() {
+		let env = test_env().await;
+		let jq = &env.job_queue;
+		let mut db = env.database.get().await.unwrap();
+		let other = jq
+			.enqueue_with_priority(&mut db, JobCommand::sync_branch(1), 150u16)
+			.await
+			.unwrap();
+		let mut boosted = Vec::new();
+		for cursor in ["a", "b", "c"] {
+			let command = JobCommand::SyncBranch {
+				branch: 2,
+				cursor: Some(cursor.to_string()),
+			};
+			boosted.push(jq.enqueue(&mut db, command).await.unwrap());
+		}
+		drop(db);
+
+		// started jobs are not boosted
+		let started = jq.fetch_and_start().await.unwrap().unwrap();
+		assert_eq!(started.id, other);
+		let mut db = env.database.get().await.unwrap();
+		let other = jq
+			.enqueue_with_priority(&mut db, JobCommand::sync_branch(3), 150u16)
+			.await
+			.unwrap();
+		assert_eq!(jq.boost_branch(&mut db, 2, 200u16).await.unwrap(), 3);
+		drop(db);
+
+		for id in &boosted {
+			let info = jq.get_job(*id).await.unwrap().unwrap();
+			assert_eq!(info.priority, 200);
+		}
+		let claimed = jq.fetch_and_start_batch(4).await.unwrap();
+		let claimed = claimed.iter().map(|job| job.id).collect::<Vec<_>>();
+		assert_eq!(claimed[..3], boosted);
+		assert_eq!(claimed[3], other);
+	}
+
+	#[derive(Debug)]
+	struct RejectBranch(BranchRef);
+
+	impl EnqueueInterceptor for RejectBranch {
+		fn intercept(
+			&self,
+			job: &mut JobCommand,
+			_options: &mut EnqueueOptions,
+		) -> Result<(), JobQueueError> {
+			if job.branch() == Some(self.0) {
+				return Err(JobQueueError::Rejected(format!(
+					"branch {} is quarantined",
+					self.0
+				)));
+			}
+			Ok(())
+		}
+	}
+
+	#[derive(Debug)]
+	struct BumpPriority;
+
+	impl EnqueueInterceptor for BumpPriority {
+		fn intercept(
+			&self,
+			_job: &mut JobCommand,
+			options: &mut EnqueueOptions,
+		) -> Result<(), JobQueueError> {
+			options.priority = options.priority.max(Some(Priority::High.value()));
+			Ok(())
+		}
+	}
+
+	#[tokio::test]
+	async fn test_interceptors() {
+		let env = test_env().await;
+		let jq = JobQueue::new(env.database.clone(), JobQueueConfig::default())
+			.with_interceptor(RejectBranch(2))
+			.with_interceptor(BumpPriority);
+		let mut db = env.database.get().await.unwrap();
+
+		let id = jq
+			.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+		let error = jq
+			.enqueue(&mut db, JobCommand::sync_branch(2))
+			.await
+			.unwrap_err();
+		assert!(matches!(
+			error,
+			BackendError::JobQueueError(JobQueueError::Rejected(_))
+		));
+		drop(db);
+
+		let jobs = jq.list_jobs(&JobFilter::default()).await.unwrap();
+		assert_eq!(jobs.len(), 1);
+		assert_eq!(jobs[0].id, id);
+		assert_eq!(jobs[0].priority, Priority::High.value());
+
+		// continuations are intercepted as well
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		let mut db = env.database.get().await.unwrap();
+		let error = jq
+			.continue_job(&mut db, job.id, JobCommand::sync_branch(2))
+			.await
+			.unwrap_err();
+		assert!(matches!(
+			error,
+			BackendError::JobQueueError(JobQueueError::Rejected(_))
+		));
+		drop(db);
+		// the rejected continuation does not finish the job
+		let info = jq.get_job(id).await.unwrap().unwrap();
+		assert!(info.started_at.is_some());
+	}
+
+	#[tokio::test]
+	async fn test_check_indexes() {
+		let env = test_env().await;
+		assert!(env.job_queue.check_indexes().await.unwrap().is_empty());
+
+		let mut db = env.database.get().await.unwrap();
+		db.batch_execute("DROP INDEX `job_queue_poll`")
+			.await
+			.unwrap();
+		drop(db);
+		assert_eq!(
+			env.job_queue.check_indexes().await.unwrap(),
+			["job_queue_poll"]
+		);
+	}
+
+	#[tokio::test]
+	async fn test_repair_priorities() {
+		let env = test_env().await;
+		let mut db = env.database.get().await.unwrap();
+		let wrapped = env
+			.job_queue
+			.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+		let normal = env
+			.job_queue
+			.enqueue_with_priority(&mut db, JobCommand::sync_branch(2), 200u16)
+			.await
+			.unwrap();
+		// as stored by a plain cast of 65000
+		db.execute(
+			update(dsl::job_queue)
+				.filter(dsl::id.eq(XUuidVal(wrapped)))
+				.set(dsl::priority.eq(65000u16 as i16)),
+		)
+		.await
+		.unwrap();
+		drop(db);
+
+		assert_eq!(env.job_queue.repair_priorities().await.unwrap(), 1);
+		let info = env.job_queue.get_job(wrapped).await.unwrap().unwrap();
+		assert_eq!(info.priority, i16::MAX as u16);
+		let info = env.job_queue.get_job(normal).await.unwrap().unwrap();
+		assert_eq!(info.priority, 200);
+		assert_eq!(env.job_queue.repair_priorities().await.unwrap(), 0);
+
+		// the repaired job is claimed first, as it was meant to be
+		let job = env.job_queue.fetch_and_start().await.unwrap().unwrap();
+		assert_eq!(job.id, wrapped);
+
+		// priorities above the range are clamped when enqueued
+		let mut db = env.database.get().await.unwrap();
+		let id = env
+			.job_queue
+			.enqueue_with_priority(&mut db, JobCommand::sync_branch(3), u16::MAX)
+			.await
+			.unwrap();
+		drop(db);
+		let info = env.job_queue.get_job(id).await.unwrap().unwrap();
+		assert_eq!(info.priority, i16::MAX as u16);
+	}
+
+	#[tokio::test]
+	async fn test_replay_job() {
+		let env = test_env().await;
+		let config = JobQueueConfig {
+			on_finish: FinishPolicy::Archive,
+			..Default::default()
+		};
+		let jq = JobQueue::new(env.database.clone(), config);
+
+		let mut db = env.database.get().await.unwrap();
+		let command = JobCommand::SyncBranch {
+			branch: 1,
+			cursor: Some("abc".to_string()),
+		};
+		let id = jq
+			.enqueue_with_priority(&mut db, command.clone(), 150u16)
+			.await
+			.unwrap();
+		drop(db);
+		assert!(matches!(
+			jq.replay_job(id, None).await.unwrap_err(),
+			BackendError::JobQueueError(JobQueueError::NotInHistory(_))
+		));
+
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		let mut db = env.database.get().await.unwrap();
+		jq.finish_job(&mut db, job.id).await.unwrap();
+		drop(db);
+
+		let replayed = jq.replay_job(id, None).await.unwrap();
+		assert_ne!(replayed, id);
+		let info = jq.get_job(replayed).await.unwrap().unwrap();
+		assert!(info.started_at.is_none());
+		assert_eq!(info.priority, 150);
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		assert_eq!(job.id, replayed);
+		assert_eq!(job.command, command);
+	}
+
+	#[tokio::test]
+	async fn test_replay_dead_letter() {
+		let env = test_env().await;
+		let config = JobQueueConfig {
+			max_attempts: 1,
+			..Default::default()
+		};
+		let jq = JobQueue::new(env.database.clone(), config);
+
+		let mut db = env.database.get().await.unwrap();
+		let command = JobCommand::sync_branch(1);
+		let id = jq
+			.enqueue_with_priority(&mut db, command.clone(), 150u16)
+			.await
+			.unwrap();
+		drop(db);
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		let mut db = env.database.get().await.unwrap();
+		assert!(!jq.fail_job(&mut db, job.id, "boom").await.unwrap());
+		drop(db);
+		assert!(jq.get_job(id).await.unwrap().is_none());
+
+		let replayed = jq.replay_job(id, Some(300)).await.unwrap();
+		assert_ne!(replayed, id);
+		let info = jq.get_job(replayed).await.unwrap().unwrap();
+		assert!(info.started_at.is_none());
+		assert_eq!(info.attempts, 0);
+		assert_eq!(info.priority, 300);
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		assert_eq!(job.id, replayed);
+		assert_eq!(job.command, command);
+
+		// the dead letter is kept
+		let mut db = env.database.get().await.unwrap();
+		assert_eq!(
+			db.get_result::<_, XUuidVal>(job_dead_letter::table.select(job_dead_letter::id))
+				.await
+				.unwrap(),
+			XUuidVal(id)
+		);
+	}
+
+	#[tokio::test]
+	async fn test_tenant_weights() {
+		let env = test_env().await;
+		let config = JobQueueConfig {
+			tenant_weights: BTreeMap::from([(KString::from_static("big"), 3)]),
+			..Default::default()
+		};
+		let jq = JobQueue::new(env.database.clone(), config);
+
+		let mut db = env.database.get().await.unwrap();
+		for branch in 0..20 {
+			for tenant in ["big", "small"] {
+				let options = EnqueueOptions {
+					tenant: Some(tenant.to_string()),
+					..Default::default()
+				};
+				jq.enqueue_with(&mut db, JobCommand::sync_branch(branch), options)
+					.await
+					.unwrap();
+			}
+		}
+		drop(db);
+
+		let claimed = jq.fetch_and_start_batch(16).await.unwrap();
+		assert_eq!(claimed.len(), 16);
+		let mut db = env.database.get().await.unwrap();
+		let tenants = db
+			.load::<_, String>(
+				dsl::job_queue
+					.filter(dsl::started_at.is_not_null())
+					.select(dsl::tenant),
+			)
+			.await
+			.unwrap();
+		let big = tenants.iter().filter(|tenant| *tenant == "big").count();
+		assert_eq!(big, 12);
+		assert_eq!(tenants.len() - big, 4);
+	}
+
+	#[tokio::test]
+	async fn test_priority_tiers() {
+		for (tier, value) in [
+			(Priority::Low, 50),
+			(Priority::Normal, 100),
+			(Priority::High, 200),
+			(Priority::Critical, 500),
+		] {
+			assert_eq!(tier.value(), value);
+			assert_eq!(tier.name().parse::<Priority>(), Ok(tier));
+		}
+		assert_eq!(parse_priority("120"), Ok(120));
+		assert_eq!(parse_priority("High"), Ok(200));
+		assert!(parse_priority("urgent").is_err());
+
+		let env = test_env().await;
+		let mut db = env.database.get().await.unwrap();
+		let tier = "critical".parse::<Priority>().unwrap();
+		let id = env
+			.job_queue
+			.enqueue_with_tier(&mut db, JobCommand::sync_branch(1), tier)
+			.await
+			.unwrap();
+		drop(db);
+		let info = env.job_queue.get_job(id).await.unwrap().unwrap();
+		assert_eq!(info.priority, 500);
+
+		let config: BranchConfigInfo =
+			serde_json::from_value(json!({ "priority": "low" })).unwrap();
+		assert_eq!(config.priority, Some(50));
+		let config: BranchConfigInfo = serde_json::from_value(json!({ "priority": 75 })).unwrap();
+		assert_eq!(config.priority, Some(75));
+		assert!(
+			serde_json::from_value::<BranchConfigInfo>(json!({ "priority": "urgent" })).is_err()
+		);
+	}
+
+	#[test]
+	fn test_command_builder() {
+		let command = JobCommandBuilder::sync_branch()
+			.branch(1)
+			.cursor("abc")
+			.build()
+			.unwrap();
+		assert_eq!(
+			command,
+			JobCommand::SyncBranch {
+				branch: 1,
+				cursor: Some("abc".to_string()),
+			}
+		);
+		assert_eq!(
+			JobCommandBuilder::new("SyncBranch")
+				.branch(2)
+				.build()
+				.unwrap(),
+			JobCommand::sync_branch(2)
+		);
+
+		for builder in [
+			JobCommandBuilder::sync_branch(),
+			JobCommandBuilder::sync_branch().branch(0),
+			JobCommandBuilder::sync_branch().branch(1).cursor(""),
+			JobCommandBuilder::new("BuildBranch").branch(1),
+		] {
+			assert!(matches!(
+				builder.build(),
+				Err(JobQueueError::InvalidCommand(_))
+			));
+		}
+	}
+
+	#[tokio::test]
+	async fn test_run_job_timeout() {
+		let env = test_env().await;
+		let config = JobQueueConfig {
+			handler_timeouts_ms: BTreeMap::from([(KString::from_static("SyncBranch"), 50)]),
+			..Default::default()
+		};
+		let jq = JobQueue::new(env.database.clone(), config);
+
+		let mut db = env.database.get().await.unwrap();
+		let id = jq
+			.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+		drop(db);
+
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		let handler = async {
+			tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+			Ok::<_, String>(())
+		};
+		assert!(!jq.run_job(&job, handler).await.unwrap());
+		let info = jq.get_job(id).await.unwrap().unwrap();
+		assert!(info.started_at.is_none());
+		assert_eq!(info.attempts, 1);
+		assert_eq!(info.last_error.as_deref(), Some("timed out"));
+
+		// the job is retried, and the worker keeps running jobs
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		assert_eq!(job.id, id);
+		assert!(
+			jq.run_job(&job, async { Ok::<_, String>(()) })
+				.await
+				.unwrap()
+		);
+	}
+
+	#[tokio::test]
+	async fn test_fetch_batch() {
+		let env = TestEnv::builder()
+			.with_jobs((1..=4).map(JobCommand::sync_branch))
+			.build()
+			.await;
+		let jq = &env.job_queue;
+
+		let jobs = jq.fetch_and_start_batch(3).await.unwrap();
+		assert_eq!(jobs.len(), 3);
+		let ids = jobs.iter().map(|job| job.id).collect::<HashSet<_>>();
+		assert_eq!(ids.len(), 3);
+		for id in &ids {
+			let info = jq.get_job(*id).await.unwrap().unwrap();
+			assert!(info.started_at.is_some());
+		}
+
+		// jobs are finished independently
+		let mut db = env.database.get().await.unwrap();
+		jq.finish_job(&mut db, jobs[0].id).await.unwrap();
+		assert!(jq.fail_job(&mut db, jobs[1].id, "failure").await.unwrap());
+		drop(db);
+
+		let jobs = jq.fetch_and_start_batch(3).await.unwrap();
+		assert_eq!(jobs.len(), 2);
+		assert!(jq.fetch_and_start_batch(3).await.unwrap().is_empty());
+	}
+
+	#[tokio::test]
+	async fn test_lifo() {
+		let env = test_env().await;
+		let config = JobQueueConfig {
+			ordering: JobOrdering::Lifo,
+			..Default::default()
+		};
+		let jq = JobQueue::new(env.database.clone(), config)
+			.with_id_generator(SequentialIdGenerator::default());
+
+		let mut db = env.database.get().await.unwrap();
+		for branch in 1..=3 {
+			jq.enqueue(&mut db, JobCommand::sync_branch(branch))
+				.await
+				.unwrap();
+		}
+		drop(db);
+
+		for expected in (1..=3).rev() {
+			assert_eq!(
+				jq.fetch_and_start().await.unwrap().unwrap().command,
+				JobCommand::sync_branch(expected)
+			);
+		}
+	}
+
+	#[test]
+	fn test_job_accessors() {
+		#[derive(Debug, PartialEq, Eq, Deserialize)]
+		struct SyncBranchData {
+			branch: BranchRef,
+			cursor: Option<String>,
+		}
+
+		let job = Job {
+			id: Uuid::nil(),
+			command: JobCommand::SyncBranch {
+				branch: 3,
+				cursor: Some("abc".to_string()),
+			},
+			trace_context: None,
+		};
+		assert_eq!(job.as_sync_branch(), Some(3));
+		assert_eq!(
+			job.payload::<SyncBranchData>().unwrap(),
+			SyncBranchData {
+				branch: 3,
+				cursor: Some("abc".to_string()),
+			}
+		);
+		assert!(job.payload::<Vec<String>>().is_err());
+	}
+
+	#[tokio::test]
+	async fn test_legacy_sync_branch_data() {
+		assert_eq!(
+			JobCommand::deserialize("SyncBranch", json!(3)).unwrap(),
+			JobCommand::sync_branch(3)
+		);
+
+		// jobs enqueued before cursors were added are still processed
+		let env = test_env().await;
+		let jq = &env.job_queue;
+		let mut db = env.database.get().await.unwrap();
+		let job = jq
+			.enqueue(&mut db, JobCommand::sync_branch(3))
+			.await
+			.unwrap();
+		db.execute(
+			update(dsl::job_queue)
+				.filter(dsl::id.eq(XUuidVal(job)))
+				.set(dsl::data.eq(XJsonVal(json!(3)))),
+		)
+		.await
+		.unwrap();
+		drop(db);
+		let started = jq.fetch_and_start().await.unwrap().unwrap();
+		assert_eq!(started.id, job);
+		assert_eq!(started.command, JobCommand::sync_branch(3));
+	}
+
+	#[tokio::test]
+	async fn test_enqueue_front() {
+		let env = test_env().await;
+		let jq = JobQueue::new(env.database.clone(), JobQueueConfig::default())
+			.with_id_generator(SequentialIdGenerator::default());
+
+		let mut db = env.database.get().await.unwrap();
+		for branch in 1..=2 {
+			jq.enqueue(&mut db, JobCommand::sync_branch(branch))
+				.await
+				.unwrap();
+		}
+		jq.enqueue_with_priority(&mut db, JobCommand::sync_branch(3), 150)
+			.await
+			.unwrap();
+		jq.enqueue_front(&mut db, JobCommand::sync_branch(4))
+			.await
+			.unwrap();
+		jq.enqueue_front(&mut db, JobCommand::sync_branch(5))
+			.await
+			.unwrap();
+		drop(db);
+
+		// higher priorities still come first
+		for expected in [3, 5, 4, 1, 2] {
+			assert_eq!(
+				jq.fetch_and_start().await.unwrap().unwrap().command,
+				JobCommand::sync_branch(expected)
+			);
+		}
+	}
+
+	#[tokio::test]
+	async fn test_continue_job() {
+		let env = test_env().await;
+		let jq = env.job_queue;
+
+		let mut db = env.database.get().await.unwrap();
+		let options = EnqueueOptions {
+			priority: Some(150),
+			dedup_key: Some("sync-1".to_string()),
+			..Default::default()
+		};
+		jq.enqueue_with(&mut db, JobCommand::sync_branch(1), options.clone())
+			.await
+			.unwrap();
+		drop(db);
+
+		// the first chunk re-enqueues the sync with an advanced cursor
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		let next = JobCommand::SyncBranch {
+			branch: 1,
+			cursor: Some("chunk-2".to_string()),
+		};
+		let mut db = env.database.get().await.unwrap();
+		let outcome = jq
+			.continue_job(&mut db, job.id, next.clone())
+			.await
+			.unwrap();
+		let EnqueueOutcome::Enqueued { id } = outcome else {
+			panic!("expected a continuation, got {outcome:?}");
+		};
+		assert_ne!(id, job.id);
+
+		// a new sync is coalesced into the continuation
+		let outcome = jq
+			.enqueue_with(&mut db, JobCommand::sync_branch(1), options)
+			.await
+			.unwrap();
+		assert_eq!(outcome, EnqueueOutcome::Deduplicated { id });
+		drop(db);
+		assert_eq!(jq.get_job(id).await.unwrap().unwrap().priority, 150);
+
+		// the last chunk finishes
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		assert_eq!((job.id, &job.command), (id, &next));
+		let mut db = env.database.get().await.unwrap();
+		jq.finish_job(&mut db, job.id).await.unwrap();
+		drop(db);
+		assert!(jq.fetch_and_start().await.unwrap().is_none());
+	}
+
+	#[tokio::test]
+	async fn test_enqueue_outcome() {
+		let env = test_env().await;
+		let jq = env.job_queue;
+
+		let mut db = env.database.get().await.unwrap();
+		let options = EnqueueOptions {
+			dedup_key: Some("sync-1".to_string()),
+			..Default::default()
+		};
+		let first = jq
+			.enqueue_with(&mut db, JobCommand::sync_branch(1), options.clone())
+			.await
+			.unwrap();
+		let EnqueueOutcome::Enqueued { id } = first else {
+			panic!("expected a new job, got {first:?}");
+		};
+		let second = jq
+			.enqueue_with(&mut db, JobCommand::sync_branch(1), options)
+			.await
+			.unwrap();
+		assert_eq!(second, EnqueueOutcome::Deduplicated { id });
+	}
+
+	#[tokio::test]
+	async fn test_dedup_key() {
+		let env = test_env().await;
+		let jq = env.job_queue;
+
+		let tasks = (0..8).map(|_| {
+			let jq = jq.clone();
+			let database = env.database.clone();
+			tokio::spawn(async move {
+				let mut db = database.get().await.unwrap();
+				let options = EnqueueOptions {
+					dedup_key: Some("sync-1".to_string()),
+					..Default::default()
+				};
+				jq.enqueue_with(&mut db, JobCommand::sync_branch(1), options)
+					.await
+					.unwrap()
+					.id()
+			})
+		});
+		let ids = futures::future::try_join_all(tasks).await.unwrap();
+		assert!(ids.iter().all(|id| *id == ids[0]));
+
+		let mut db = env.database.get().await.unwrap();
+		assert_eq!(
+			db.get_result::<_, i64>(dsl::job_queue.count())
+				.await
+				.unwrap(),
+			1
+		);
+		drop(db);
+
+		// the key is released after the job finishes
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		let mut db = env.database.get().await.unwrap();
+		jq.finish_job(&mut db, job.id).await.unwrap();
+		let options = EnqueueOptions {
+			dedup_key: Some("sync-1".to_string()),
+			..Default::default()
+		};
+		let id = jq
+			.enqueue_with(&mut db, JobCommand::sync_branch(1), options)
+			.await
+			.unwrap();
+		assert!(id.is_enqueued());
+		assert_ne!(id.id(), ids[0]);
+	}
+
+	#[tokio::test]
+	async fn test_drain() {
+		let env = test_env().await;
+		let mut db = env.database.get().await.unwrap();
+		env.job_queue
+			.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+
+		env.job_queue.drain();
+		assert!(matches!(
+			env.job_queue
+				.enqueue(&mut db, JobCommand::sync_branch(2))
+				.await,
+			Err(BackendError::JobQueueError(JobQueueError::Draining))
+		));
+		drop(db);
+		assert!(env.job_queue.fetch_and_start().await.unwrap().is_none());
+	}
+
+	#[tokio::test]
+	async fn test_idempotency_key() {
+		let env = test_env().await;
+		let mut db = env.database.get().await.unwrap();
+		let options = EnqueueOptions {
+			idempotency_key: Some("webhook-1".to_string()),
+			..Default::default()
+		};
+		let first = env
+			.job_queue
+			.enqueue_with(&mut db, JobCommand::sync_branch(1), options.clone())
+			.await
+			.unwrap();
+		let second = env
+			.job_queue
+			.enqueue_with(&mut db, JobCommand::sync_branch(1), options.clone())
+			.await
+			.unwrap();
+		assert_eq!(second, EnqueueOutcome::Deduplicated { id: first.id() });
+		assert_eq!(
+			db.get_result::<_, i64>(dsl::job_queue.count())
+				.await
+				.unwrap(),
+			1
+		);
+		drop(db);
+
+		// unlike dedup keys, finished jobs are not enqueued again
+		let job = env.job_queue.fetch_and_start().await.unwrap().unwrap();
+		let mut db = env.database.get().await.unwrap();
+		env.job_queue.finish_job(&mut db, job.id).await.unwrap();
+		let third = env
+			.job_queue
+			.enqueue_with(&mut db, JobCommand::sync_branch(1), options.clone())
+			.await
+			.unwrap();
+		assert_eq!(third, EnqueueOutcome::Deduplicated { id: first.id() });
+		assert_eq!(
+			db.get_result::<_, i64>(dsl::job_queue.count())
+				.await
+				.unwrap(),
+			0
+		);
+	}
+
+	#[tokio::test]
+	async fn test_priority_histogram() {
+		let env = test_env().await;
+		let mut db = env.database.get().await.unwrap();
+		for (branch, priority) in [(1, 50), (2, 100), (3, 100), (4, 120), (5, 120), (6, 120)] {
+			env.job_queue
+				.enqueue_with_priority(&mut db, JobCommand::sync_branch(branch), priority)
+				.await
+				.unwrap();
+		}
+		drop(db);
+		// started jobs are not pending
+		env.job_queue.fetch_and_start().await.unwrap().unwrap();
+
+		assert_eq!(
+			env.job_queue.priority_histogram().await.unwrap(),
+			BTreeMap::from([(50, 1), (100, 2), (120, 2)])
+		);
+	}
+
+	#[tokio::test]
+	async fn test_find_by_data() {
+		let env = test_env().await;
+		let mut db = env.database.get().await.unwrap();
+		let mut ids = Vec::new();
+		for data in [
+			json!({ "branch": { "id": 5, "name": "main" } }),
+			json!({ "branch": { "id": 6, "name": "stable" } }),
+			json!({ "branch": { "id": 5, "name": "main" }, "full": true }),
+		] {
+			let id = Uuid::now_v7();
+			db.execute(insert_into(dsl::job_queue).values((
+				dsl::id.eq(XUuidVal(id)),
+				dsl::kind.eq("Test"),
+				dsl::data.eq(XJsonVal(data)),
+				dsl::priority.eq(100),
+			)))
+			.await
+			.unwrap();
+			ids.push(id);
+		}
+		env.job_queue
+			.enqueue(&mut db, JobCommand::sync_branch(5))
+			.await
+			.unwrap();
+		drop(db);
+
+		let jq = &env.job_queue;
+		assert_eq!(
+			jq.find_by_data("Test", &["branch", "id"], json!(5))
+				.await
+				.unwrap(),
+			[ids[0], ids[2]]
+		);
+		assert_eq!(
+			jq.find_by_data("Test", &["branch", "name"], json!("stable"))
+				.await
+				.unwrap(),
+			[ids[1]]
+		);
+		assert!(
+			jq.find_by_data("Test", &["branch", "id"], json!(7))
+				.await
+				.unwrap()
+				.is_empty()
+		);
+		assert_eq!(
+			jq.find_by_data("SyncBranch", &["branch"], json!(5))
+				.await
+				.unwrap()
+				.len(),
+			1
+		);
+	}
+
+	#[tokio::test]
+	async fn test_trace_context() {
+		let env = test_env().await;
+		let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+		let context = TraceContext::parse(traceparent).unwrap();
+
+		let mut db = env.database.get().await.unwrap();
+		let id = context
+			.scope(env.job_queue.enqueue(&mut db, JobCommand::sync_branch(1)))
+			.await
+			.unwrap();
+		drop(db);
+
+		let info = env.job_queue.get_job(id).await.unwrap().unwrap();
+		assert_eq!(info.trace_context, Some(context));
+		let job = env.job_queue.fetch_and_start().await.unwrap().unwrap();
+		assert_eq!(
+			job.trace_context.unwrap().trace_id(),
+			"4bf92f3577b34da6a3ce929d0e0e4736"
+		);
+	}
+
+	#[tokio::test]
+	async fn test_release_job() {
+		let env = test_env().await;
+		let jq = env.job_queue;
+		let mut db = env.database.get().await.unwrap();
+		let id = jq
+			.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+		drop(db);
+
+		assert!(matches!(
+			jq.release_job(id, "worker is dead", Some("ops")).await,
+			Err(BackendError::JobQueueError(JobQueueError::NotClaimed(_)))
+		));
+		assert_eq!(jq.fetch_and_start().await.unwrap().unwrap().id, id);
+		assert!(jq.fetch_and_start().await.unwrap().is_none());
+
+		jq.release_job(id, "worker is dead", Some("ops"))
+			.await
+			.unwrap();
+		assert_eq!(jq.fetch_and_start().await.unwrap().unwrap().id, id);
+
+		let attempts = jq.get_attempts(id).await.unwrap();
+		assert_eq!(attempts.len(), 2);
+		assert_eq!(attempts[0].outcome, AttemptOutcome::Failed);
+		assert_eq!(attempts[0].error.as_deref(), Some("worker is dead"));
+		assert_eq!(attempts[1].attempt, 2);
+		assert_eq!(attempts[1].outcome, AttemptOutcome::Running);
+	}
+
+	#[tokio::test]
+	async fn test_finish_job_idempotent() {
+		let env = test_env().await;
+		let jq = env.job_queue;
+		let mut db = env.database.get().await.unwrap();
+		jq.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+		drop(db);
+
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		let mut db = env.database.get().await.unwrap();
+		jq.finish_job_idempotent(&mut db, job.id).await.unwrap();
+		jq.finish_job_idempotent(&mut db, job.id).await.unwrap();
+		assert!(matches!(
+			jq.finish_job(&mut db, job.id).await,
+			Err(BackendError::JobQueueError(JobQueueError::JobAborted(_)))
+		));
+	}
+
+	/// Clock advanced manually.
+	#[derive(Debug, Clone)]
+	struct ManualClock(Arc<Mutex<PrimitiveDateTime>>);
+
+	impl ManualClock {
+		fn new() -> Self {
+			let time = OffsetDateTime::now_utc();
+			Self(Arc::new(Mutex::new(PrimitiveDateTime::new(
+				time.date(),
+				time.time(),
+			))))
+		}
+
+		fn advance(&self, duration: Duration) {
+			*self.0.lock().unwrap() += duration;
+		}
+	}
+
+	impl Clock for ManualClock {
+		fn now(&self) -> PrimitiveDateTime {
+			*self.0.lock().unwrap()
+		}
+	}
+
+	#[tokio::test]
+	async fn test_explain_claim() {
+		let env = test_env().await;
+		let clock = ManualClock::new();
+		let config = JobQueueConfig {
+			retry_delay: 60,
+			..Default::default()
+		};
+		let jq = JobQueue::new(env.database.clone(), config).with_clock(clock.clone());
+		assert_eq!(jq.explain_claim(Uuid::now_v7()).await.unwrap(), None);
+
+		let mut db = env.database.get().await.unwrap();
+		let sync = jq
+			.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+		let options = EnqueueOptions {
+			depends_on: vec![sync],
+			..Default::default()
+		};
+		let dependent = jq
+			.enqueue_with(&mut db, JobCommand::sync_branch(2), options)
+			.await
+			.unwrap()
+			.id();
+		let unknown = Uuid::now_v7();
+		db.execute(insert_into(dsl::job_queue).values((
+			dsl::id.eq(XUuidVal(unknown)),
+			dsl::kind.eq("FromTheFuture"),
+			dsl::data.eq(XJsonVal(json!({}))),
+			dsl::priority.eq(100),
+		)))
+		.await
+		.unwrap();
+		drop(db);
+
+		assert_eq!(
+			jq.explain_claim(sync).await.unwrap(),
+			Some(ClaimExplanation::Claimable)
+		);
+		assert_eq!(
+			jq.explain_claim(dependent).await.unwrap(),
+			Some(ClaimExplanation::DependenciesPending {
+				depends_on: vec![sync]
+			})
+		);
+		assert_eq!(
+			jq.explain_claim(unknown).await.unwrap(),
+			Some(ClaimExplanation::UnknownKind)
+		);
+
+		let options = ClaimOptions {
+			worker: Some("worker-1"),
+			..Default::default()
+		};
+		let job = jq.fetch_and_start_with(&options).await.unwrap().unwrap();
+		assert_eq!(job.id, sync);
+		assert_eq!(
+			jq.explain_claim(sync).await.unwrap(),
+			Some(ClaimExplanation::Running {
+				claimed_by: Some("worker-1".to_string())
+			})
+		);
+
+		let mut db = env.database.get().await.unwrap();
+		assert!(jq.fail_job(&mut db, sync, "boom").await.unwrap());
+		drop(db);
+		assert!(matches!(
+			jq.explain_claim(sync).await.unwrap(),
+			Some(ClaimExplanation::RetryScheduled { .. })
+		));
+		clock.advance(Duration::minutes(2));
+		assert_eq!(
+			jq.explain_claim(sync).await.unwrap(),
+			Some(ClaimExplanation::Claimable)
+		);
+
+		jq.drain();
+		assert_eq!(
+			jq.explain_claim(sync).await.unwrap(),
+			Some(ClaimExplanation::Draining)
+		);
+	}
+
+	#[tokio::test]
+	async fn test_fail_timed_out_with_clock() {
+		let env = test_env().await;
+		let clock = ManualClock::new();
+		let jq = JobQueue::new(
+			env.database.clone(),
+			JobQueueConfig {
+				timeouts: BTreeMap::from([(KString::from_static("SyncBranch"), 60)]),
+				..Default::default()
+			},
+		)
+		.with_clock(clock.clone());
+
+		let mut db = env.database.get().await.unwrap();
+		jq.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+		drop(db);
+		jq.fetch_and_start().await.unwrap().unwrap();
+
+		clock.advance(Duration::seconds(59));
+		assert_eq!(jq.fail_timed_out().await.unwrap(), 0);
+		clock.advance(Duration::seconds(2));
+		assert_eq!(jq.fail_timed_out().await.unwrap(), 1);
+	}
+
+	#[tokio::test]
+	async fn test_reclaim_lost_jobs() {
+		let env = test_env().await;
+		let clock = ManualClock::new();
+		let config = JobQueueConfig {
+			worker_timeout: Some(60),
+			..Default::default()
+		};
+		let jq = JobQueue::new(env.database.clone(), config).with_clock(clock.clone());
+
+		let mut db = env.database.get().await.unwrap();
+		for branch in [1, 2] {
+			jq.enqueue(&mut db, JobCommand::sync_branch(branch))
+				.await
+				.unwrap();
+		}
+		drop(db);
+		for worker in ["alive", "lost"] {
+			jq.register_worker(worker).await.unwrap();
+			let options = ClaimOptions {
+				worker: Some(worker),
+				..Default::default()
+			};
+			jq.fetch_and_start_with(&options).await.unwrap().unwrap();
+		}
+
+		clock.advance(Duration::seconds(59));
+		jq.register_worker("alive").await.unwrap();
+		assert_eq!(jq.reclaim_lost_jobs().await.unwrap(), 0);
+
+		// the lease of the lost worker expires without sleeping
+		clock.advance(Duration::seconds(2));
+		assert_eq!(jq.reclaim_lost_jobs().await.unwrap(), 1);
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		assert_eq!(job.command, JobCommand::sync_branch(2));
+		assert_eq!(
+			jq.list_workers()
+				.await
+				.unwrap()
+				.into_iter()
+				.map(|worker| worker.id)
+				.collect::<Vec<_>>(),
+			["alive"]
+		);
+	}
+
+	#[tokio::test]
+	async fn test_custom_config() {
+		let env = test_env().await;
+		let clock = ManualClock::new();
+		let config = JobQueueConfig {
+			timeouts: BTreeMap::from([(KString::from_static("Other"), 3600)]),
+			default_timeout: Some(30),
+			max_attempts: 1,
+			..Default::default()
+		};
+		let jq = JobQueue::new(env.database.clone(), config.clone()).with_clock(clock.clone());
+		assert_eq!(jq.config(), &config);
+
+		let mut db = env.database.get().await.unwrap();
+		for branch in [1, 2] {
+			jq.enqueue(&mut db, JobCommand::sync_branch(branch))
+				.await
+				.unwrap();
+		}
+		drop(db);
+
+		// a short lease from the default timeout
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		clock.advance(Duration::seconds(31));
+		assert_eq!(jq.fail_timed_out().await.unwrap(), 1);
+		assert!(jq.get_job(job.id).await.unwrap().is_none());
+
+		// a single attempt
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		let mut db = env.database.get().await.unwrap();
+		assert!(!jq.fail_job(&mut db, job.id, "failure").await.unwrap());
+	}
+
+	#[tokio::test]
+	async fn test_retry_stats() {
+		let env = test_env().await;
+		let mut db = env.database.get().await.unwrap();
+		for (kind, attempts) in [
+			("SyncBranch", 0),
+			("SyncBranch", 0),
+			("SyncBranch", 1),
+			("SyncBranch", 2),
+			("Build", 2),
+			("Build", 2),
+			("Fetch", 1),
+		] {
+			db.execute(insert_into(dsl::job_queue).values((
+				dsl::id.eq(XUuidVal(Uuid::now_v7())),
+				dsl::kind.eq(kind),
+				dsl::data.eq(XJsonVal(json!({}))),
+				dsl::priority.eq(100),
+				dsl::attempts.eq(attempts),
+			)))
+			.await
+			.unwrap();
+		}
+		drop(db);
+
+		assert_eq!(
+			env.job_queue.retry_stats(2).await.unwrap(),
+			RetryStats {
+				attempts: BTreeMap::from([(1, 2), (2, 2), (3, 3)]),
+				top_kinds: vec![("Build".to_string(), 4), ("SyncBranch".to_string(), 3)],
+			}
+		);
+	}
+
+	#[tokio::test]
+	async fn test_throughput() {
+		let env = test_env().await;
+		let clock = ManualClock::new();
+		let jq = JobQueue::new(env.database.clone(), Default::default()).with_clock(clock.clone());
+		let current = clock.now();
+
+		let mut db = env.database.get().await.unwrap();
+		let history = [
+			(Duration::seconds(0), AttemptOutcome::Succeeded),
+			(Duration::minutes(1), AttemptOutcome::Succeeded),
+			(Duration::minutes(1), AttemptOutcome::Succeeded),
+			(Duration::minutes(1), AttemptOutcome::Failed),
+			(Duration::minutes(3), AttemptOutcome::Skipped),
+			// outside of the window
+			(Duration::minutes(10), AttemptOutcome::Succeeded),
+		];
+		for (ago, outcome) in history {
+			db.execute(insert_into(job_history::table).values((
+				job_history::id.eq(XUuidVal(Uuid::now_v7())),
+				job_history::kind.eq("SyncBranch"),
+				job_history::data.eq(XJsonVal(json!({ "branch": 1 }))),
+				job_history::priority.eq(100),
+				job_history::outcome.eq(outcome as i16),
+				job_history::finished_at.eq(current - ago),
+			)))
+			.await
+			.unwrap();
+		}
+		drop(db);
+
+		// failed and skipped jobs are finished too
+		let buckets = jq.throughput(Duration::minutes(5)).await.unwrap();
+		let counts = buckets.values().copied().collect::<Vec<_>>();
+		assert_eq!(counts, [0, 0, 1, 0, 3, 1]);
+		let minutes = buckets.keys().collect::<Vec<_>>();
+		assert!(
+			minutes
+				.windows(2)
+				.all(|pair| *pair[1] - *pair[0] == Duration::MINUTE)
+		);
+		assert!(minutes.iter().all(|minute| minute.second() == 0));
+	}
+
+	#[tokio::test]
+	async fn test_retry_jitter() {
+		let env = test_env().await;
+		let clock = ManualClock::new();
+		let jq = JobQueue::new(
+			env.database.clone(),
+			JobQueueConfig {
+				retry_delay: 3600,
+				retry_jitter: RetryJitter::Equal,
+				..Default::default()
+			},
+		)
+		.with_clock(clock.clone());
+
+		let mut db = env.database.get().await.unwrap();
+		for branch in [1, 2] {
+			jq.enqueue(&mut db, JobCommand::sync_branch(branch))
+				.await
+				.unwrap();
+		}
+		drop(db);
+		let jobs = [
+			jq.fetch_and_start().await.unwrap().unwrap(),
+			jq.fetch_and_start().await.unwrap().unwrap(),
+		];
+		let mut db = env.database.get().await.unwrap();
+		for job in &jobs {
+			assert!(jq.fail_job(&mut db, job.id, "failure").await.unwrap());
+		}
+
+		let failed_at = clock.now();
+		let mut next_attempts = Vec::new();
+		for job in &jobs {
+			let next_attempt_at = db
+				.get_result::<_, Option<PrimitiveDateTime>>(
+					dsl::job_queue
+						.filter(dsl::id.eq(XUuidVal(job.id)))
+						.select(dsl::next_attempt_at),
+				)
+				.await
+				.unwrap()
+				.unwrap();
+			assert!(next_attempt_at >= failed_at + Duration::minutes(30));
+			assert!(next_attempt_at <= failed_at + Duration::hours(1));
+			next_attempts.push(next_attempt_at);
+		}
+		assert_ne!(next_attempts[0], next_attempts[1]);
+		drop(db);
+
+		// retries are delayed
+		assert!(jq.fetch_and_start().await.unwrap().is_none());
+		clock.advance(Duration::hours(1));
+		assert!(jq.fetch_and_start().await.unwrap().is_some());
+		assert!(jq.fetch_and_start().await.unwrap().is_some());
+	}
+
+	/// Seeds jobs for listing, and returns IDs of them.
+	///
+	/// 1. `sync_branch(1)` with priority 100, running
+	/// 2. `sync_branch(2)` with priority 200
+	/// 3. `Other` with priority 50, enqueued an hour ago
+	async fn seed_list_jobs(env: &BackendServices) -> [JobRef; 3] {
+		let jq = &env.job_queue;
+		let mut db = env.database.get().await.unwrap();
+		let old = Uuid::new_v7(uuid::Timestamp::from_unix(
+			uuid::NoContext,
+			(OffsetDateTime::now_utc() - Duration::hours(1)).unix_timestamp() as u64,
+			0,
+		));
+		db.execute(insert_into(dsl::job_queue).values((
+			dsl::id.eq(XUuidVal(old)),
+			dsl::kind.eq("Other"),
+			dsl::data.eq(XJsonVal(json!({}))),
+			dsl::priority.eq(50),
+		)))
+		.await
+		.unwrap();
+		let running = jq
+			.enqueue_with_priority(&mut db, JobCommand::sync_branch(1), 100)
+			.await
+			.unwrap();
+		let pending = jq
+			.enqueue_with_priority(&mut db, JobCommand::sync_branch(2), 200)
+			.await
+			.unwrap();
+		drop(db);
+		let kinds = [KString::from_static("SyncBranch")];
+		let options = ClaimOptions {
+			kinds: Some(&kinds),
+			..Default::default()
+		};
+		// the one with higher priority is claimed first
+		jq.fetch_and_start_with(&options).await.unwrap().unwrap();
+		jq.fetch_and_start_with(&options).await.unwrap().unwrap();
+		let mut db = env.database.get().await.unwrap();
+		jq.fail_job(&mut db, pending, "failure").await.unwrap();
+		drop(db);
+		[running, pending, old]
+	}
+
+	async fn list_ids(jq: &JobQueue, filter: JobFilter) -> Vec<JobRef> {
+		jq.list_jobs(&filter)
+			.await
+			.unwrap()
+			.into_iter()
+			.map(|job| job.id)
+			.collect()
+	}
+
+	#[tokio::test]
+	async fn test_list_jobs() {
+		let env = test_env().await;
+		let [running, pending, old] = seed_list_jobs(&env).await;
+		let jq = &env.job_queue;
+
+		assert_eq!(
+			list_ids(jq, JobFilter::default()).await,
+			[old, running, pending]
+		);
+		let filter = JobFilter {
+			kind: Some("SyncBranch".to_string()),
+			..Default::default()
+		};
+		assert_eq!(list_ids(jq, filter).await, [running, pending]);
+		let filter = JobFilter {
+			status: Some(JobStatus::Running),
+			..Default::default()
+		};
+		assert_eq!(list_ids(jq, filter).await, [running]);
+		let filter = JobFilter {
+			status: Some(JobStatus::Pending),
+			..Default::default()
+		};
+		assert_eq!(list_ids(jq, filter).await, [old, pending]);
+		let filter = JobFilter {
+			min_priority: Some(100),
+			..Default::default()
+		};
+		assert_eq!(list_ids(jq, filter).await, [running, pending]);
+		let filter = JobFilter {
+			max_priority: Some(100),
+			..Default::default()
+		};
+		assert_eq!(list_ids(jq, filter).await, [old, running]);
+		let filter = JobFilter {
+			branch: Some(2),
+			..Default::default()
+		};
+		assert_eq!(list_ids(jq, filter).await, [pending]);
+		let filter = JobFilter {
+			min_age: Some(Duration::minutes(30)),
+			..Default::default()
+		};
+		assert_eq!(list_ids(jq, filter).await, [old]);
+		let filter = JobFilter {
+			max_age: Some(Duration::minutes(30)),
+			..Default::default()
+		};
+		assert_eq!(list_ids(jq, filter).await, [running, pending]);
+		let filter = JobFilter {
+			limit: 1,
+			..Default::default()
+		};
+		assert_eq!(list_ids(jq, filter).await, [old]);
+		let filter = JobFilter {
+			limit: 1,
+			after: Some(old),
+			..Default::default()
+		};
+		assert_eq!(list_ids(jq, filter).await, [running]);
+	}
+
+	#[tokio::test]
+	async fn test_list_jobs_combined() {
+		let env = test_env().await;
+		let [_, pending, _] = seed_list_jobs(&env).await;
+
+		let filter = JobFilter {
+			kind: Some("SyncBranch".to_string()),
+			status: Some(JobStatus::Pending),
+			min_priority: Some(150),
+			max_age: Some(Duration::minutes(30)),
+			..Default::default()
+		};
+		let jobs = env.job_queue.list_jobs(&filter).await.unwrap();
+		assert_eq!(jobs.len(), 1);
+		assert_eq!(jobs[0].id, pending);
+		assert_eq!(jobs[0].attempts, 1);
+	}
+
+	#[tokio::test]
+	async fn test_list_jobs_invalid() {
+		let env = test_env().await;
+		let filter = JobFilter {
+			min_priority: Some(200),
+			max_priority: Some(100),
+			..Default::default()
+		};
+		assert!(matches!(
+			env.job_queue.list_jobs(&filter).await,
+			Err(BackendError::JobQueueError(JobQueueError::InvalidFilter(_)))
+		));
+		let filter = JobFilter {
+			min_age: Some(Duration::hours(1)),
+			max_age: Some(Duration::minutes(1)),
+			..Default::default()
+		};
+		assert!(matches!(
+			env.job_queue.list_jobs(&filter).await,
+			Err(BackendError::JobQueueError(JobQueueError::InvalidFilter(_)))
+		));
 	}
 }
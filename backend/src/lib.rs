@@ -4,21 +4,28 @@ use std::sync::Arc;
 
 use branch::{BranchError, BranchService};
 use bus::{BackendBusFactory, BoxedBusService};
-use config::BackendConfig;
+use config::{BackendConfig, ConfigError};
 use db::service::{DatabaseError, DatabaseService};
+use job_notifier::PgJobNotifier;
 use job_queue::{JobQueue, JobQueueError};
 use redis::{RedisError, RedisService};
 use target::TargetService;
 use thiserror::Error;
+use tracing::info;
 
 pub mod branch;
 pub mod bus;
 pub mod config;
 pub mod db;
+pub mod job_callback;
+pub mod job_notifier;
+pub mod job_prefetch;
 pub mod job_queue;
 pub mod package;
+pub mod payload_store;
 pub mod redis;
 pub mod target;
+pub mod trace;
 
 /// Service container for Fabricia backends.
 ///
@@ -40,13 +47,28 @@ impl BackendServices {
 	where
 		Bus: BackendBusFactory,
 	{
+		config.validate()?;
 		let config = Arc::new(config);
 		let target = Arc::new(TargetService::new(&config.target)?);
 		let redis = Arc::new(RedisService::new(&config.redis).await?);
 		let database = Arc::new(DatabaseService::new(&config.database, &redis).await?);
+		if config.database.warmup_connections > 0 {
+			database.warmup(config.database.warmup_connections).await?;
+		}
 		let bus = Arc::new(bus.construct(redis.clone()).await?);
-		let job_queue = Arc::new(JobQueue::new(database.clone()));
-		let branch = Arc::new(BranchService::new(database.clone(), job_queue.clone()));
+		let mut job_queue = JobQueue::new(database.clone(), config.job_queue.clone());
+		if database.is_postgres() {
+			job_queue = job_queue.with_notifier(PgJobNotifier::new(database.clone()));
+		}
+		if config.job_queue.check_indexes {
+			job_queue.check_indexes().await?;
+		}
+		let job_queue = Arc::new(job_queue);
+		let branch = Arc::new(BranchService::new(
+			database.clone(),
+			job_queue.clone(),
+			config.branch.clone(),
+		));
 		let services = Self {
 			config,
 			target,
@@ -59,6 +81,20 @@ impl BackendServices {
 
 		Ok(services)
 	}
+
+	/// Shuts down services gracefully.
+	///
+	/// The job queue is drained, pending notifications are flushed,
+	/// and the database pool is closed after all connections are returned,
+	/// or [`DatabaseConfig::close_timeout`](db::service::DatabaseConfig::close_timeout) elapses.
+	#[tracing::instrument(skip(self))]
+	pub async fn shutdown(&self) -> Result<()> {
+		self.job_queue.drain();
+		self.job_queue.notifier().flush().await?;
+		self.database.close().await?;
+		info!("backend services shut down");
+		Ok(())
+	}
 }
 
 /// Backend errors.
@@ -74,6 +110,15 @@ pub enum BackendError {
 	JobQueueError(#[from] JobQueueError),
 	#[error(transparent)]
 	BranchError(#[from] BranchError),
+	#[error(transparent)]
+	ConfigError(#[from] ConfigError),
+}
+
+impl BackendError {
+	/// Checks if the failed operation may succeed on retry.
+	pub fn is_retryable(&self) -> bool {
+		matches!(self, Self::DatabaseError(error) if error.is_retryable())
+	}
 }
 
 /// A specialized [`Result`] for backend errors.
@@ -81,47 +126,145 @@ pub type Result<T, E = BackendError> = std::result::Result<T, E>;
 
 impl From<diesel::result::Error> for BackendError {
 	fn from(value: diesel::result::Error) -> Self {
-		Self::DatabaseError(DatabaseError::QueryError(value))
+		Self::DatabaseError(value.into())
 	}
 }
 
 #[cfg(test)]
 pub(crate) mod test {
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
 	use crate::redis::RedisConfig;
 	use bus::{BackendBusMessage, BackendBusService, C2ABusMessage};
-	use db::service::DatabaseConfig;
+	use db::service::{DatabaseConfig, DatabaseError};
 	use futures::{
 		FutureExt,
 		future::{BoxFuture, ready},
 	};
 	use target::*;
 
-	use crate::*;
+	use crate::{
+		branch::{BranchConfigInfo, BranchQuery},
+		job_queue::JobCommand,
+		*,
+	};
 
 	pub async fn test_env() -> BackendServices {
-		let config = BackendConfig {
-			database: DatabaseConfig {
-				url: "sqlite://:memory:".to_string(),
-				max_connections: 1,
-			},
-			redis: RedisConfig {
-				url: "redis://127.0.0.1".to_string(),
-				max_connections: 1,
-			},
-			target: vec![
-				TargetConfig {
-					name: "arch1".into(),
-					arch: None,
+		TestEnv::builder().build().await
+	}
+
+	/// Returns the URL of a new SQLite database file, for tests using more
+	/// than one connection, as each connection to `:memory:` opens a separate
+	/// database.
+	pub fn temp_database_url() -> String {
+		static COUNTER: AtomicUsize = AtomicUsize::new(0);
+		let path = std::env::temp_dir().join(format!(
+			"fabricia-test-{}-{}.db",
+			std::process::id(),
+			COUNTER.fetch_add(1, Ordering::Relaxed)
+		));
+		_ = std::fs::remove_file(&path);
+		format!("sqlite://{}", path.display())
+	}
+
+	/// Test environment seeded declaratively.
+	pub struct TestEnv;
+
+	impl TestEnv {
+		pub fn builder() -> TestEnvBuilder {
+			TestEnvBuilder::default()
+		}
+	}
+
+	type ConfigFn = Box<dyn FnOnce(&mut BackendConfig)>;
+
+	#[derive(Default)]
+	pub struct TestEnvBuilder {
+		config: Vec<ConfigFn>,
+		branches: Vec<(String, BranchConfigInfo)>,
+		jobs: Vec<JobCommand>,
+	}
+
+	impl TestEnvBuilder {
+		/// Customizes the configuration of services.
+		pub fn with_config<F: FnOnce(&mut BackendConfig) + 'static>(mut self, f: F) -> Self {
+			self.config.push(Box::new(f));
+			self
+		}
+
+		/// Tracks branches with the default configuration.
+		///
+		/// Tracking a branch also enqueues a synchronization job.
+		pub fn with_branches<I, S>(mut self, names: I) -> Self
+		where
+			I: IntoIterator<Item = S>,
+			S: Into<String>,
+		{
+			self.branches.extend(
+				names
+					.into_iter()
+					.map(|name| (name.into(), BranchConfigInfo::default())),
+			);
+			self
+		}
+
+		/// Tracks a branch with a configuration.
+		pub fn with_branch<S: Into<String>>(mut self, name: S, info: BranchConfigInfo) -> Self {
+			self.branches.push((name.into(), info));
+			self
+		}
+
+		/// Enqueues jobs, after tracking branches.
+		pub fn with_jobs<I: IntoIterator<Item = JobCommand>>(mut self, jobs: I) -> Self {
+			self.jobs.extend(jobs);
+			self
+		}
+
+		pub async fn build(self) -> BackendServices {
+			let mut config = Self::config();
+			for f in self.config {
+				f(&mut config);
+			}
+			let env = BackendServices::new(config, TestingBusFactory)
+				.await
+				.unwrap();
+			for (name, info) in self.branches {
+				env.branch.track(&name, info, None).await.unwrap();
+			}
+			let mut db = env.database.get().await.unwrap();
+			for job in self.jobs {
+				env.job_queue.enqueue(&mut db, job).await.unwrap();
+			}
+			drop(db);
+			env
+		}
+
+		pub fn config() -> BackendConfig {
+			BackendConfig {
+				database: DatabaseConfig {
+					url: "sqlite://:memory:".to_string(),
+					max_connections: 1,
+					warmup_connections: 0,
+					close_timeout: 1,
 				},
-				TargetConfig {
-					name: "arch2".into(),
-					arch: Some("testarch2".into()),
+				redis: RedisConfig {
+					url: "redis://127.0.0.1".to_string(),
+					max_connections: 1,
 				},
-			],
-		};
-		BackendServices::new(config, TestingBusFactory)
-			.await
-			.unwrap()
+				target: vec![
+					TargetConfig {
+						name: "arch1".into(),
+						arch: None,
+					},
+					TargetConfig {
+						name: "arch2".into(),
+						arch: Some("testarch2".into()),
+					},
+				],
+				job_queue: Default::default(),
+				branch: Default::default(),
+			}
+		}
 	}
 
 	#[derive(Debug)]
@@ -139,7 +282,7 @@ pub(crate) mod test {
 		}
 	}
 
-	struct TestingBusFactory;
+	pub struct TestingBusFactory;
 
 	impl BackendBusFactory for TestingBusFactory {
 		fn construct(self, _: Arc<RedisService>) -> BoxFuture<'static, Result<BoxedBusService>> {
@@ -152,4 +295,112 @@ pub(crate) mod test {
 		let env = test_env().await;
 		assert!(env.job_queue.fetch_and_start().await.unwrap().is_none());
 	}
+
+	#[tokio::test]
+	async fn test_shutdown() {
+		let env = test_env().await;
+		let mut db = env.database.get().await.unwrap();
+		env.job_queue
+			.enqueue(&mut db, JobCommand::sync_branch(1))
+			.await
+			.unwrap();
+		drop(db);
+
+		env.shutdown().await.unwrap();
+		assert!(env.job_queue.is_draining());
+		assert!(env.database.is_closed());
+		assert!(env.database.get().await.is_err());
+	}
+
+	#[tokio::test]
+	async fn test_shutdown_timeout() {
+		let env = test_env().await;
+		let db = env.database.get().await.unwrap();
+
+		// connections in use do not block shutdown forever
+		assert!(matches!(
+			env.shutdown().await,
+			Err(BackendError::DatabaseError(DatabaseError::CloseTimeout(1)))
+		));
+		assert!(env.database.is_closed());
+		drop(db);
+		assert!(env.database.get().await.is_err());
+	}
+
+	#[tokio::test]
+	async fn test_env_builder() {
+		let env = TestEnv::builder()
+			.with_branches(["main", "stable"])
+			.with_jobs([JobCommand::sync_branch(1)])
+			.build()
+			.await;
+
+		let ids = env.branch.list_ids(&BranchQuery::default()).await.unwrap();
+		assert_eq!(
+			ids,
+			[
+				env.branch.find_id_or_err("main").await.unwrap(),
+				env.branch.find_id_or_err("stable").await.unwrap(),
+			]
+		);
+		// two synchronization jobs of tracking, and the seeded one
+		let mut jobs = 0;
+		while env.job_queue.fetch_and_start().await.unwrap().is_some() {
+			jobs += 1;
+		}
+		assert_eq!(jobs, 3);
+	}
+
+	#[tokio::test]
+	async fn test_env_builder_branch_config() {
+		let env = TestEnv::builder()
+			.with_branch(
+				"main",
+				BranchConfigInfo {
+					priority: Some(120),
+					..Default::default()
+				},
+			)
+			.with_branch(
+				"feat",
+				BranchConfigInfo {
+					base: Some("main".into()),
+					enabled: Some(false),
+					..Default::default()
+				},
+			)
+			.build()
+			.await;
+
+		let main = env.branch.find_id_or_err("main").await.unwrap();
+		assert_eq!(
+			env.branch.get_config(main).await.unwrap().priority,
+			Some(120)
+		);
+		let feat = env.branch.find_id_or_err("feat").await.unwrap();
+		let config = env.branch.get_config(feat).await.unwrap();
+		assert_eq!(config.base.as_deref(), Some("main"));
+		assert_eq!(config.enabled, Some(false));
+	}
+
+	#[tokio::test]
+	async fn test_env_builder_config() {
+		let env = TestEnv::builder()
+			.with_config(|config| config.job_queue.max_attempts = 1)
+			.with_branches(["main"])
+			.build()
+			.await;
+
+		let job = env.job_queue.fetch_and_start().await.unwrap().unwrap();
+		let mut db = env.database.get().await.unwrap();
+		// the only attempt is failed, so the job is dead-lettered
+		assert!(
+			!env.job_queue
+				.fail_job(&mut db, job.id, "failure")
+				.await
+				.unwrap()
+		);
+		drop(db);
+		assert!(env.job_queue.get_job(job.id).await.unwrap().is_none());
+	}
 }
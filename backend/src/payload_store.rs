@@ -0,0 +1,200 @@
+//! External storage of large job payloads.
+
+use std::fmt::Debug;
+
+use futures::{
+	FutureExt,
+	future::{BoxFuture, ready},
+};
+
+use crate::{Result, job_queue::JobRef};
+
+/// Stores payloads of large jobs outside of the database.
+///
+/// When the data of a job is not smaller than
+/// [`JobQueueConfig::offload_threshold`](crate::job_queue::JobQueueConfig::offload_threshold),
+/// it is passed to [PayloadStore::put], and only the returned key is kept
+/// in the queue. The payload is loaded with [PayloadStore::get] when the job
+/// is claimed.
+///
+/// Payloads of enqueued jobs are never deleted by the queue, since finished
+/// jobs may still be referenced from the history and the dead-letter queue.
+/// Stores should expire them instead, e.g. with lifecycle rules of buckets.
+/// Only payloads of jobs which are not enqueued after all, e.g. as they are
+/// deduplicated, are deleted with [PayloadStore::delete].
+pub trait PayloadStore
+where
+	Self: Send + Sync + Debug,
+{
+	/// Stores the payload of job `id`.
+	///
+	/// Returns the key to load the payload, or `None` to keep it inline.
+	fn put(&self, id: JobRef, payload: Vec<u8>) -> BoxFuture<'_, Result<Option<String>>>;
+
+	/// Loads a payload stored with [PayloadStore::put].
+	fn get(&self, key: &str) -> BoxFuture<'_, Result<Vec<u8>>>;
+
+	/// Deletes a payload stored with [PayloadStore::put], which is not
+	/// referenced by any job.
+	fn delete(&self, key: &str) -> BoxFuture<'_, Result<()>>;
+}
+
+/// [PayloadStore] keeping all payloads inline in the queue.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InlinePayloadStore;
+
+impl PayloadStore for InlinePayloadStore {
+	fn put(&self, _: JobRef, _: Vec<u8>) -> BoxFuture<'_, Result<Option<String>>> {
+		ready(Ok(None)).boxed()
+	}
+
+	fn get(&self, key: &str) -> BoxFuture<'_, Result<Vec<u8>>> {
+		let error = format!("payload {key} is not stored inline");
+		ready(Err(
+			crate::job_queue::JobQueueError::DataEncoding(error).into()
+		))
+		.boxed()
+	}
+
+	fn delete(&self, _: &str) -> BoxFuture<'_, Result<()>> {
+		ready(Ok(())).boxed()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::{
+		collections::HashMap,
+		sync::{Arc, Mutex},
+	};
+
+	use diesel::{ExpressionMethods, QueryDsl};
+	use futures::{
+		FutureExt,
+		future::{BoxFuture, ready},
+	};
+	use serde_json::json;
+
+	use crate::{
+		Result,
+		db::{
+			schema::job_queue::dsl,
+			utils::{XJsonVal, XUuidVal},
+		},
+		job_queue::{
+			EnqueueOptions, EnqueueOutcome, JobCommand, JobQueue, JobQueueConfig, JobQueueError,
+			JobRef,
+		},
+		payload_store::PayloadStore,
+		test::test_env,
+	};
+
+	#[derive(Debug, Default, Clone)]
+	struct MemoryPayloadStore {
+		payloads: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+	}
+
+	impl PayloadStore for MemoryPayloadStore {
+		fn put(&self, id: JobRef, payload: Vec<u8>) -> BoxFuture<'_, Result<Option<String>>> {
+			let key = format!("jobs/{id}");
+			self.payloads.lock().unwrap().insert(key.clone(), payload);
+			ready(Ok(Some(key))).boxed()
+		}
+
+		fn get(&self, key: &str) -> BoxFuture<'_, Result<Vec<u8>>> {
+			let payload = self.payloads.lock().unwrap().get(key).cloned();
+			let result = payload.ok_or_else(|| {
+				JobQueueError::DataEncoding(format!("missing payload {key}")).into()
+			});
+			ready(result).boxed()
+		}
+
+		fn delete(&self, key: &str) -> BoxFuture<'_, Result<()>> {
+			self.payloads.lock().unwrap().remove(key);
+			ready(Ok(())).boxed()
+		}
+	}
+
+	#[tokio::test]
+	async fn test_offload_payload() {
+		let env = test_env().await;
+		let store = MemoryPayloadStore::default();
+		let config = JobQueueConfig {
+			offload_threshold: Some(64),
+			..Default::default()
+		};
+		let jq = JobQueue::new(env.database.clone(), config).with_payload_store(store.clone());
+
+		let small = JobCommand::sync_branch(1);
+		let large = JobCommand::SyncBranch {
+			branch: 2,
+			cursor: Some("x".repeat(256)),
+		};
+		let mut db = env.database.get().await.unwrap();
+		let small_id = jq.enqueue(&mut db, small.clone()).await.unwrap();
+		let large_id = jq.enqueue(&mut db, large.clone()).await.unwrap();
+
+		// only the large payload is offloaded, leaving a reference
+		assert_eq!(store.payloads.lock().unwrap().len(), 1);
+		let data = db
+			.get_result::<_, XJsonVal>(
+				dsl::job_queue
+					.filter(dsl::id.eq(XUuidVal(large_id)))
+					.select(dsl::data),
+			)
+			.await
+			.unwrap();
+		assert_eq!(data.0, json!({ "$payload": format!("jobs/{large_id}") }));
+		drop(db);
+
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		assert_eq!((job.id, job.command), (small_id, small));
+		let job = jq.fetch_and_start().await.unwrap().unwrap();
+		assert_eq!((job.id, job.command), (large_id, large));
+	}
+
+	#[tokio::test]
+	async fn test_unreferenced_payload() {
+		let env = test_env().await;
+		let store = MemoryPayloadStore::default();
+		let config = JobQueueConfig {
+			offload_threshold: Some(64),
+			..Default::default()
+		};
+		let jq = JobQueue::new(env.database.clone(), config).with_payload_store(store.clone());
+		let large = JobCommand::SyncBranch {
+			branch: 1,
+			cursor: Some("x".repeat(256)),
+		};
+		let options = || EnqueueOptions {
+			dedup_key: Some("large".to_string()),
+			..Default::default()
+		};
+
+		let mut db = env.database.get().await.unwrap();
+		let id = jq
+			.enqueue_with(&mut db, large.clone(), options())
+			.await
+			.unwrap()
+			.id();
+		let outcome = jq
+			.enqueue_with(&mut db, large.clone(), options())
+			.await
+			.unwrap();
+		assert_eq!(outcome, EnqueueOutcome::Deduplicated { id });
+		// the payload of the deduplicated job is deleted
+		assert_eq!(
+			store.payloads.lock().unwrap().keys().collect::<Vec<_>>(),
+			[&format!("jobs/{id}")]
+		);
+		drop(db);
+
+		// a job of which payload cannot be loaded stays pending
+		store.payloads.lock().unwrap().clear();
+		assert!(jq.fetch_and_start().await.is_err());
+		let info = jq.get_job(id).await.unwrap().unwrap();
+		assert!(info.started_at.is_none());
+		assert_eq!(info.attempts, 0);
+		assert!(jq.get_attempts(id).await.unwrap().is_empty());
+	}
+}
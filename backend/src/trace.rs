@@ -0,0 +1,126 @@
+//! Propagation of W3C trace context from requests to jobs.
+//!
+//! See <https://www.w3.org/TR/trace-context/>.
+
+use std::fmt::Display;
+
+/// W3C trace context of an operation, as in the `traceparent` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceContext {
+	trace_id: [u8; 16],
+	parent_id: [u8; 8],
+	flags: u8,
+}
+
+tokio::task_local! {
+	static CURRENT: TraceContext;
+}
+
+impl TraceContext {
+	/// Name of the HTTP header carrying the trace context.
+	pub const HEADER: &str = "traceparent";
+
+	/// Parses a `traceparent` value.
+	///
+	/// Returns `None` for unsupported versions and invalid values.
+	pub fn parse(traceparent: &str) -> Option<Self> {
+		let mut parts = traceparent.trim().split('-');
+		let version = parts.next()?;
+		if version != "00" {
+			return None;
+		}
+		let mut context = Self {
+			trace_id: [0; 16],
+			parent_id: [0; 8],
+			flags: 0,
+		};
+		hex::decode_to_slice(parts.next()?, &mut context.trace_id).ok()?;
+		hex::decode_to_slice(parts.next()?, &mut context.parent_id).ok()?;
+		let mut flags = [0];
+		hex::decode_to_slice(parts.next()?, &mut flags).ok()?;
+		context.flags = flags[0];
+		if parts.next().is_some() || context.trace_id == [0; 16] || context.parent_id == [0; 8] {
+			return None;
+		}
+		Some(context)
+	}
+
+	/// Starts a new sampled trace.
+	pub fn new_root() -> Self {
+		Self {
+			trace_id: rand::random(),
+			parent_id: rand::random(),
+			flags: 0x01,
+		}
+	}
+
+	/// Returns the context of a child operation in the same trace.
+	pub fn child(&self) -> Self {
+		Self {
+			parent_id: rand::random(),
+			..*self
+		}
+	}
+
+	/// Returns the trace ID in lowercase hex.
+	pub fn trace_id(&self) -> String {
+		hex::encode(self.trace_id)
+	}
+
+	/// Returns the ID of the current operation in lowercase hex.
+	pub fn span_id(&self) -> String {
+		hex::encode(self.parent_id)
+	}
+
+	/// Returns the trace context of the current task, if any.
+	pub fn current() -> Option<Self> {
+		CURRENT.try_with(|context| *context).ok()
+	}
+
+	/// Runs `future` with this as the trace context of the current task.
+	///
+	/// Jobs enqueued in `future` continue this trace.
+	pub async fn scope<F: Future>(self, future: F) -> F::Output {
+		CURRENT.scope(self, future).await
+	}
+}
+
+impl Display for TraceContext {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"00-{}-{}-{:02x}",
+			self.trace_id(),
+			self.span_id(),
+			self.flags
+		)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::trace::TraceContext;
+
+	#[test]
+	fn test_parse() {
+		let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+		let context = TraceContext::parse(traceparent).unwrap();
+		assert_eq!(context.trace_id(), "4bf92f3577b34da6a3ce929d0e0e4736");
+		assert_eq!(context.span_id(), "00f067aa0ba902b7");
+		assert_eq!(context.to_string(), traceparent);
+
+		let child = context.child();
+		assert_eq!(child.trace_id(), context.trace_id());
+		assert_ne!(child.span_id(), context.span_id());
+
+		assert!(
+			TraceContext::parse("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+				.is_none()
+		);
+		assert!(
+			TraceContext::parse("00-00000000000000000000000000000000-00f067aa0ba902b7-01")
+				.is_none()
+		);
+		assert!(TraceContext::parse("00-4bf92f3577b34da6-00f067aa0ba902b7-01").is_none());
+	}
+}
@@ -1,5 +1,11 @@
+use std::collections::BTreeSet;
+
+use kstring::KString;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use crate::priority::Priority;
+
 /// State of a branch.
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -26,7 +32,7 @@ pub enum BranchStatus {
 }
 
 /// Tracking rules indicating how should we track packages in a branch.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum TrackingMode {
 	/// Tracking all changed (in comparison with base branch) packages automatically.
@@ -34,3 +40,172 @@ pub enum TrackingMode {
 	/// Do not track any packages.
 	Unmanaged,
 }
+
+/// Configuration of a branch.
+///
+/// Unset fields are left unchanged on updates.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Default, JsonSchema)]
+pub struct BranchConfigInfo {
+	/// Name of the base branch of this branch.
+	///
+	/// Set this to empty string to remove base branch.
+	#[schemars(with = "Option<String>")]
+	pub base: Option<KString>,
+	/// Priority of jobs of the branch.
+	///
+	/// This may be given either as a number or a [Priority] tier name.
+	#[serde(default, deserialize_with = "crate::priority::deserialize_option")]
+	#[schemars(schema_with = "crate::priority::option_schema")]
+	pub priority: Option<u16>,
+	pub tracking_mode: Option<TrackingMode>,
+	/// Disabled branches are kept tracked, but not built.
+	pub enabled: Option<bool>,
+	/// Labels of the branch, either plain tags or `key=value` pairs.
+	///
+	/// When set, replaces all existing labels.
+	pub labels: Option<BTreeSet<String>>,
+}
+
+impl BranchConfigInfo {
+	/// Returns the configuration with all fields set to their default values.
+	pub fn defaults() -> Self {
+		Self::default().or_defaults()
+	}
+
+	/// Fills unset fields with their default values.
+	///
+	/// The returned configuration, when written, fully replaces the current one.
+	pub fn or_defaults(self) -> Self {
+		Self {
+			base: Some(self.base.unwrap_or_default()),
+			priority: Some(self.priority.unwrap_or(Priority::Normal.value())),
+			tracking_mode: Some(self.tracking_mode.unwrap_or(TrackingMode::Auto)),
+			enabled: Some(self.enabled.unwrap_or(true)),
+			labels: Some(self.labels.unwrap_or_default()),
+		}
+	}
+
+	/// Checks if all fields set in `expected` are equal to the ones in `self`.
+	pub fn matches(&self, expected: &BranchConfigInfo) -> bool {
+		fn field_matches<T: PartialEq>(current: &Option<T>, expected: &Option<T>) -> bool {
+			expected.is_none() || current == expected
+		}
+
+		field_matches(&self.base, &expected.base)
+			&& field_matches(&self.priority, &expected.priority)
+			&& field_matches(&self.tracking_mode, &expected.tracking_mode)
+			&& field_matches(&self.enabled, &expected.enabled)
+			&& field_matches(&self.labels, &expected.labels)
+	}
+
+	/// Validates fields set in this configuration.
+	///
+	/// Configurations with errors must be rejected,
+	/// while warnings are only reported to users.
+	pub fn validate(&self) -> ConfigValidation {
+		let mut validation = ConfigValidation::default();
+		if let Some(base) = &self.base {
+			if !base.is_empty() && !is_valid_branch_name(base) {
+				validation
+					.errors
+					.push(format!("base branch name {base:?} is invalid"));
+			}
+			if !base.is_empty() && self.tracking_mode == Some(TrackingMode::Unmanaged) {
+				validation
+					.warnings
+					.push("base branch is unused in unmanaged tracking mode".to_string());
+			}
+		}
+		if let Some(priority) = self.priority.filter(|priority| *priority > 1000) {
+			validation
+				.warnings
+				.push(format!("priority {priority} is unusually high"));
+		}
+		for label in self.labels.iter().flatten() {
+			if !is_valid_label(label) {
+				validation
+					.errors
+					.push(format!("label {label:?} is invalid"));
+			}
+		}
+		validation
+	}
+}
+
+/// Result of [BranchConfigInfo::validate].
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+pub struct ConfigValidation {
+	/// Problems rejecting the configuration.
+	pub errors: Vec<String>,
+	/// Problems not blocking the configuration from being saved.
+	pub warnings: Vec<String>,
+}
+
+impl ConfigValidation {
+	pub fn is_valid(&self) -> bool {
+		self.errors.is_empty()
+	}
+}
+
+/// Checks if a branch name is a valid Git reference name.
+///
+/// This is a subset of rules of `git check-ref-format`.
+pub fn is_valid_branch_name(name: &str) -> bool {
+	!name.is_empty()
+		&& !name.starts_with(['-', '/', '.'])
+		&& !name.ends_with(['/', '.'])
+		&& !name.ends_with(".lock")
+		&& !name.contains("..")
+		&& !name.contains("//")
+		&& !name.contains("@{")
+		&& !name.chars().any(|ch| {
+			ch.is_whitespace()
+				|| ch.is_control()
+				|| matches!(ch, '~' | '^' | ':' | '?' | '*' | '[' | '\\')
+		})
+}
+
+/// Checks if a label is a plain tag or a `key=value` pair.
+///
+/// Both tags, keys and values may only contain ASCII alphanumerics, `.`, `_` and `-`.
+pub fn is_valid_label(label: &str) -> bool {
+	fn is_valid_part(part: &str) -> bool {
+		!part.is_empty()
+			&& part.len() <= 63
+			&& part
+				.chars()
+				.all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '.' | '_' | '-'))
+	}
+
+	match label.split_once('=') {
+		Some((key, value)) => is_valid_part(key) && is_valid_part(value),
+		None => is_valid_part(label),
+	}
+}
+
+/// Filter selecting a set of branches.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BranchFilter {
+	/// Branches with any of the names.
+	Names(Vec<KString>),
+	/// Branches with any of the IDs.
+	Refs(Vec<i64>),
+	/// Branches of which names start with the prefix.
+	NamePrefix(String),
+}
+
+/// Body of a branch configuration update request.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum BranchConfigUpdate {
+	/// Applies `desired` only if the current configuration matches `expected`.
+	///
+	/// The comparison is done with [BranchConfigInfo::matches].
+	CompareAndSwap {
+		expected: BranchConfigInfo,
+		desired: BranchConfigInfo,
+	},
+	/// Merges the given fields into the current configuration.
+	Merge(BranchConfigInfo),
+}
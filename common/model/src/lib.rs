@@ -1,2 +1,3 @@
 /// Common models for Fabricia.
 pub mod branch;
+pub mod priority;
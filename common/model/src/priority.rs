@@ -0,0 +1,148 @@
+use std::{fmt, str::FromStr};
+
+use schemars::{Schema, SchemaGenerator, json_schema};
+use serde::{
+	Deserialize, Deserializer, Serialize,
+	de::{self, Visitor},
+};
+
+/// Named tier of priorities.
+///
+/// Priorities are stored as numbers, and tiers are names of common values.
+/// Numbers between tiers remain available for fine-grained control.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+	Low,
+	/// The default priority of branches and jobs.
+	Normal,
+	High,
+	Critical,
+}
+
+impl Priority {
+	/// All tiers, from the lowest to the highest.
+	pub const ALL: [Priority; 4] = [Self::Low, Self::Normal, Self::High, Self::Critical];
+
+	/// Returns the numeric priority of the tier.
+	pub const fn value(self) -> u16 {
+		match self {
+			Self::Low => 50,
+			Self::Normal => 100,
+			Self::High => 200,
+			Self::Critical => 500,
+		}
+	}
+
+	pub const fn name(self) -> &'static str {
+		match self {
+			Self::Low => "low",
+			Self::Normal => "normal",
+			Self::High => "high",
+			Self::Critical => "critical",
+		}
+	}
+}
+
+impl From<Priority> for u16 {
+	fn from(value: Priority) -> Self {
+		value.value()
+	}
+}
+
+impl fmt::Display for Priority {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(self.name())
+	}
+}
+
+impl FromStr for Priority {
+	type Err = ParsePriorityError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Self::ALL
+			.into_iter()
+			.find(|tier| tier.name().eq_ignore_ascii_case(s))
+			.ok_or_else(|| ParsePriorityError(s.to_string()))
+	}
+}
+
+/// Error of parsing an unknown [Priority] tier name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsePriorityError(pub String);
+
+impl fmt::Display for ParsePriorityError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "unknown priority tier {:?}", self.0)
+	}
+}
+
+impl std::error::Error for ParsePriorityError {}
+
+/// Parses a priority given either as a number or a tier name.
+pub fn parse_priority(s: &str) -> Result<u16, ParsePriorityError> {
+	s.parse()
+		.or_else(|_| s.parse::<Priority>().map(Priority::value))
+}
+
+/// Deserializes an optional priority given either as a number or a tier name.
+///
+/// Use with `#[serde(default, deserialize_with = "...")]`.
+/// Priorities are always serialized as numbers.
+pub fn deserialize_option<'de, D: Deserializer<'de>>(
+	deserializer: D,
+) -> Result<Option<u16>, D::Error> {
+	struct PriorityVisitor;
+
+	impl<'de> Visitor<'de> for PriorityVisitor {
+		type Value = Option<u16>;
+
+		fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+			f.write_str("a priority number or tier name")
+		}
+
+		fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+			Ok(None)
+		}
+
+		fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+			Ok(None)
+		}
+
+		fn visit_some<D: Deserializer<'de>>(
+			self,
+			deserializer: D,
+		) -> Result<Self::Value, D::Error> {
+			deserializer.deserialize_any(self)
+		}
+
+		fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+			u16::try_from(v)
+				.map(Some)
+				.map_err(|_| E::invalid_value(de::Unexpected::Unsigned(v), &self))
+		}
+
+		fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+			u16::try_from(v)
+				.map(Some)
+				.map_err(|_| E::invalid_value(de::Unexpected::Signed(v), &self))
+		}
+
+		fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+			parse_priority(v).map(Some).map_err(E::custom)
+		}
+	}
+
+	deserializer.deserialize_option(PriorityVisitor)
+}
+
+/// JSON schema of optional priorities accepted by [deserialize_option].
+pub fn option_schema(_: &mut SchemaGenerator) -> Schema {
+	json_schema!({
+		"anyOf": [
+			{ "type": "integer", "minimum": 0, "maximum": u16::MAX },
+			{ "type": "string", "enum": Priority::ALL.map(Priority::name) },
+			{ "type": "null" },
+		]
+	})
+}
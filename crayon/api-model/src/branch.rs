@@ -1,5 +1,10 @@
+use std::collections::{BTreeMap, BTreeSet};
+
 use fabricia_common_model::branch::{BranchStatus, TrackingMode};
 use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::job::ApiJobErrorCode;
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct ApiBranchInfo {
@@ -10,4 +15,84 @@ pub struct ApiBranchInfo {
 	pub tracking_mode: TrackingMode,
 	pub commit: Option<String>,
 	pub packages: u32,
+	pub enabled: bool,
+	/// Labels of the branch, either plain tags or `key=value` pairs.
+	#[serde(default)]
+	pub labels: BTreeSet<String>,
+	/// Time when the branch is tracked.
+	///
+	/// This is null for branches tracked before auditing.
+	#[serde(with = "time::serde::rfc3339::option")]
+	pub created_at: Option<OffsetDateTime>,
+	/// Time when the configuration of the branch is last updated.
+	#[serde(with = "time::serde::rfc3339::option")]
+	pub updated_at: Option<OffsetDateTime>,
+	/// Principal tracking the branch, if known.
+	pub created_by: Option<String>,
+	/// Principal last updating the configuration of the branch, if known.
+	pub updated_by: Option<String>,
+	/// Most recent failure of synchronizing the branch.
+	///
+	/// This is null if the most recent synchronization has succeeded.
+	#[serde(default)]
+	pub last_error: Option<ApiBranchSyncError>,
+}
+
+/// A failure of synchronizing a branch.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ApiBranchSyncError {
+	/// Category of the failure, or null if it is not categorized.
+	pub code: Option<ApiJobErrorCode>,
+	pub message: String,
+}
+
+/// Query parameters of listing branches.
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+pub struct ApiBranchListQuery {
+	pub enabled: Option<bool>,
+	pub sync_status: Option<ApiSyncStatus>,
+	/// Only lists branches with the label.
+	pub label: Option<String>,
+	pub limit: Option<u32>,
+	#[serde(default)]
+	pub offset: u32,
+	/// Cursor of the page, from [`ApiPageInfo::next_cursor`](crate::page::ApiPageInfo::next_cursor).
+	pub cursor: Option<String>,
+	/// Comma-separated fields of [ApiBranchInfo] to return, see [ApiBranchGetQuery::fields].
+	pub fields: Option<String>,
+}
+
+/// Query parameters of getting a branch.
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+pub struct ApiBranchGetQuery {
+	/// Comma-separated fields of [ApiBranchInfo] to return, e.g. `name,status`.
+	///
+	/// If unset, all fields are returned. Unknown fields are rejected with
+	/// `400 Bad Request`.
+	pub fields: Option<String>,
+}
+
+/// Synchronization state of a branch, without details.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApiSyncStatus {
+	Dirty,
+	Ready,
+	Failed,
+	Suspended,
+}
+
+/// Result of updating configurations of multiple branches.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ApiBranchBulkUpdateResult {
+	/// Count of updated branches.
+	pub updated: u64,
+	/// Errors of branches left unchanged, by name.
+	pub failures: BTreeMap<String, String>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ApiBranchDeleteResult {
+	/// Count of deleted branches.
+	pub deleted: u64,
 }
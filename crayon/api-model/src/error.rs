@@ -0,0 +1,10 @@
+use serde::{Deserialize, Serialize};
+
+/// Body of error responses with machine-readable codes.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ApiErrorBody {
+	/// Stable code of the error, e.g. `job_not_claimed`.
+	pub code: String,
+	/// Human-readable message of the error.
+	pub message: String,
+}
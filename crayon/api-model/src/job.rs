@@ -0,0 +1,157 @@
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ApiJobInfo {
+	pub id: Uuid,
+	pub kind: String,
+	pub data: serde_json::Value,
+	pub priority: u16,
+	/// Started time of the job.
+	///
+	/// This is null when the job is pending.
+	#[serde(with = "time::serde::rfc3339::option")]
+	pub started_at: Option<OffsetDateTime>,
+	/// Count of failed attempts.
+	pub attempts: u32,
+	/// Error of the most recent failed attempt.
+	pub last_error: Option<String>,
+	/// Worker running the job, or null if unknown.
+	#[serde(default)]
+	pub claimed_by: Option<String>,
+}
+
+/// Reason of a job being claimed or not.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+pub enum ApiClaimExplanation {
+	/// The job would be claimed by workers accepting its kind.
+	Claimable,
+	/// The job has been claimed.
+	Running { claimed_by: Option<String> },
+	/// The queue is draining.
+	Draining,
+	/// The job has failed, and is not retried until the time.
+	RetryScheduled {
+		#[serde(with = "time::serde::rfc3339")]
+		next_attempt_at: OffsetDateTime,
+	},
+	/// The job waits for these jobs to finish.
+	DependenciesPending { depends_on: Vec<Uuid> },
+	/// The job is of a kind unknown to the server.
+	UnknownKind,
+}
+
+/// A worker claiming jobs.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ApiWorkerInfo {
+	pub id: String,
+	#[serde(with = "time::serde::rfc3339")]
+	pub registered_at: OffsetDateTime,
+	/// Time of the most recent heartbeat.
+	#[serde(with = "time::serde::rfc3339")]
+	pub heartbeat_at: OffsetDateTime,
+	/// Count of jobs being run by the worker.
+	pub running: u64,
+}
+
+/// Query parameters of listing jobs.
+///
+/// Unset fields match all jobs.
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+pub struct ApiJobListQuery {
+	pub kind: Option<String>,
+	pub status: Option<ApiJobStatus>,
+	/// Minimum priority, inclusive.
+	pub min_priority: Option<i16>,
+	/// Maximum priority, inclusive.
+	pub max_priority: Option<i16>,
+	/// Name of the branch the jobs work on.
+	pub branch: Option<String>,
+	/// Minimum time in seconds since the jobs were enqueued.
+	pub min_age: Option<u32>,
+	/// Maximum time in seconds since the jobs were enqueued.
+	pub max_age: Option<u32>,
+	/// Maximum count of jobs to list, 100 by default.
+	pub limit: Option<u32>,
+	/// Cursor of the page, from [`ApiPageInfo::next_cursor`](crate::page::ApiPageInfo::next_cursor).
+	pub cursor: Option<String>,
+}
+
+/// Query parameters of replaying a job.
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+pub struct ApiJobReplayQuery {
+	/// Priority of the new job, or that of the original job if unset.
+	pub priority: Option<u16>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApiJobStatus {
+	Pending,
+	Running,
+}
+
+/// An attempt of a job.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ApiJobAttempt {
+	/// Number of this attempt, starting from 1.
+	pub attempt: u32,
+	#[serde(with = "time::serde::rfc3339")]
+	pub started_at: OffsetDateTime,
+	/// Finished time of this attempt.
+	///
+	/// This is null when the attempt is running.
+	#[serde(with = "time::serde::rfc3339::option")]
+	pub finished_at: Option<OffsetDateTime>,
+	pub outcome: ApiAttemptOutcome,
+	pub error: Option<String>,
+	/// Category of the error, or null if the failure is not categorized.
+	#[serde(default)]
+	pub error_code: Option<ApiJobErrorCode>,
+}
+
+/// Category of a job failure.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApiJobErrorCode {
+	Internal,
+	Network,
+	Validation,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApiAttemptOutcome {
+	Running,
+	Succeeded,
+	Failed,
+	/// The job was not executed, as its precondition did not hold.
+	Skipped,
+}
+
+/// Query parameters of maintaining job tables.
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+pub struct ApiJobMaintenanceQuery {
+	/// Whether to also vacuum the tables, false by default.
+	///
+	/// This is ignored on SQLite.
+	#[serde(default)]
+	pub vacuum: bool,
+}
+
+/// Result of maintaining job tables.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ApiJobMaintenanceReport {
+	/// Whether the tables were vacuumed in addition to being analyzed.
+	pub vacuumed: bool,
+	/// Time in milliseconds spent on each table.
+	pub tables: Vec<ApiTableMaintenance>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ApiTableMaintenance {
+	pub table: String,
+	pub elapsed_ms: u64,
+}
@@ -1,4 +1,9 @@
 pub mod branch;
+pub mod error;
+pub mod job;
+pub mod meta;
+pub mod page;
+pub mod stats;
 
 /// Git object ID.
 ///
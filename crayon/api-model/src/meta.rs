@@ -0,0 +1,14 @@
+use serde::{Deserialize, Serialize};
+
+/// Version and build information of a Crayon instance.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ApiVersionInfo {
+	/// Version of the Crayon crate.
+	pub version: String,
+	/// Git commit the instance is built from.
+	pub git_commit: Option<String>,
+	/// Build time, in seconds since the Unix epoch.
+	pub build_timestamp: Option<u64>,
+	/// Version of the latest applied database migration.
+	pub schema_version: Option<String>,
+}
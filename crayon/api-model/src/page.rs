@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+/// A page of a list.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ApiPage<T> {
+	pub items: Vec<T>,
+	pub page: ApiPageInfo,
+}
+
+/// Pagination of an [ApiPage].
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+pub struct ApiPageInfo {
+	/// Cursor to pass to get the next page, or null on the last page.
+	pub next_cursor: Option<String>,
+	/// Count of items on all pages.
+	///
+	/// This may be approximate, and is omitted if counting is expensive.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub total: Option<u64>,
+}
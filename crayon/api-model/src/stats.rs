@@ -0,0 +1,39 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+/// Query parameters of job throughput.
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+pub struct ApiThroughputQuery {
+	/// Length of the window, such as `30m` or `1h`.
+	///
+	/// Defaults to one hour.
+	pub window: Option<String>,
+}
+
+/// Summary of retries of jobs in the queue.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ApiRetryStats {
+	/// Count of jobs by the number of their next or running attempt.
+	pub attempts: BTreeMap<u32, u64>,
+	/// Kinds with the most failed attempts.
+	pub top_kinds: Vec<ApiKindRetries>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ApiKindRetries {
+	pub kind: String,
+	/// Count of failed attempts of pending and running jobs.
+	pub retries: u64,
+}
+
+/// Count of jobs finished in a minute.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ApiThroughputBucket {
+	/// Start of the minute.
+	#[serde(with = "time::serde::rfc3339")]
+	pub start: OffsetDateTime,
+	/// Count of jobs finished by any outcome.
+	pub finished: u64,
+}
@@ -0,0 +1,44 @@
+use reqwest::StatusCode;
+use thiserror::Error;
+
+/// Errors of [crate::CrayonClient].
+#[derive(Debug, Error)]
+pub enum ClientError {
+	#[error("HTTP error: {0}")]
+	Http(#[from] reqwest::Error),
+	/// The server responded with an error status.
+	///
+	/// `code` is set if the server responded with a machine-readable code.
+	#[error("api error ({status}): {message}")]
+	Api {
+		status: StatusCode,
+		code: Option<String>,
+		message: String,
+	},
+	#[error("invalid response: {0}")]
+	InvalidResponse(String),
+	#[error("invalid base URL: {0}")]
+	InvalidUrl(String),
+}
+
+impl ClientError {
+	/// Returns the status code of API errors.
+	pub fn status(&self) -> Option<StatusCode> {
+		match self {
+			Self::Api { status, .. } => Some(*status),
+			Self::Http(error) => error.status(),
+			_ => None,
+		}
+	}
+
+	/// Returns the machine-readable code of API errors, if any.
+	pub fn code(&self) -> Option<&str> {
+		match self {
+			Self::Api { code, .. } => code.as_deref(),
+			_ => None,
+		}
+	}
+}
+
+/// A specialized [`Result`] for client errors.
+pub type Result<T, E = ClientError> = std::result::Result<T, E>;
@@ -0,0 +1,298 @@
+//! Typed client of the Crayon API.
+
+use fabricia_common_model::branch::{BranchConfigInfo, BranchConfigUpdate, BranchFilter};
+use fabricia_crayon_api_model::{
+	branch::{ApiBranchBulkUpdateResult, ApiBranchDeleteResult, ApiBranchInfo, ApiBranchListQuery},
+	error::ApiErrorBody,
+	job::{
+		ApiClaimExplanation, ApiJobAttempt, ApiJobInfo, ApiJobListQuery, ApiJobMaintenanceQuery,
+		ApiJobMaintenanceReport, ApiJobReplayQuery, ApiWorkerInfo,
+	},
+	meta::ApiVersionInfo,
+	page::ApiPage,
+};
+use reqwest::{
+	Method, RequestBuilder, Response, Url,
+	header::{CONTENT_TYPE, LOCATION},
+};
+use serde::de::DeserializeOwned;
+use uuid::Uuid;
+
+pub use error::{ClientError, Result};
+
+mod error;
+
+/// Client of a Crayon instance.
+#[derive(Debug, Clone)]
+pub struct CrayonClient {
+	http: reqwest::Client,
+	base: Url,
+}
+
+impl CrayonClient {
+	/// Creates a client with the base URL of the API.
+	///
+	/// For example, `http://127.0.0.1:8000/api/v0`.
+	pub fn new(base: &str) -> Result<Self> {
+		Self::with_client(reqwest::Client::new(), base)
+	}
+
+	/// Creates a client with a customized HTTP client.
+	pub fn with_client(http: reqwest::Client, base: &str) -> Result<Self> {
+		let base = Url::parse(base).map_err(|error| ClientError::InvalidUrl(error.to_string()))?;
+		if base.cannot_be_a_base() {
+			return Err(ClientError::InvalidUrl(base.to_string()));
+		}
+		Ok(Self { http, base })
+	}
+
+	/// Builds a request to the API path of `segments`.
+	///
+	/// Segments are percent-encoded, so branch names may contain slashes.
+	fn request(&self, method: Method, segments: &[&str]) -> RequestBuilder {
+		let mut url = self.base.clone();
+		url.path_segments_mut()
+			.expect("checked on construction")
+			.pop_if_empty()
+			.extend(segments);
+		self.http.request(method, url)
+	}
+
+	pub async fn version(&self) -> Result<ApiVersionInfo> {
+		let response = self.request(Method::GET, &["version"]).send().await?;
+		parse_json(response).await
+	}
+
+	/// Lists a page of branches matching the query.
+	pub async fn list_branches(
+		&self,
+		query: &ApiBranchListQuery,
+	) -> Result<ApiPage<ApiBranchInfo>> {
+		let response = self
+			.request(Method::GET, &["branch"])
+			.query(query)
+			.send()
+			.await?;
+		parse_json(response).await
+	}
+
+	pub async fn get_branch(&self, name: &str) -> Result<ApiBranchInfo> {
+		let response = self.request(Method::GET, &["branch", name]).send().await?;
+		parse_json(response).await
+	}
+
+	/// Returns the default configuration of new branches.
+	pub async fn branch_defaults(&self) -> Result<BranchConfigInfo> {
+		let response = self
+			.request(Method::GET, &["branch", "defaults"])
+			.send()
+			.await?;
+		parse_json(response).await
+	}
+
+	/// Returns the JSON schema of branch configurations.
+	pub async fn branch_config_schema(&self) -> Result<serde_json::Value> {
+		let response = self
+			.request(Method::GET, &["branch", "config-schema"])
+			.send()
+			.await?;
+		parse_json(response).await
+	}
+
+	/// Tracks a new branch.
+	pub async fn create_branch(
+		&self,
+		name: &str,
+		info: &BranchConfigInfo,
+	) -> Result<ApiBranchInfo> {
+		let response = self
+			.request(Method::PUT, &["branch", name])
+			.json(info)
+			.send()
+			.await?;
+		parse_json(response).await
+	}
+
+	pub async fn update_branch_config(
+		&self,
+		name: &str,
+		update: &BranchConfigUpdate,
+	) -> Result<ApiBranchInfo> {
+		let response = self
+			.request(Method::PATCH, &["branch", name])
+			.json(update)
+			.send()
+			.await?;
+		parse_json(response).await
+	}
+
+	/// Applies a JSON Patch (RFC 6902) to the configuration of a branch.
+	///
+	/// Nothing is changed if any operation fails, and failed `test`
+	/// operations are reported with 409 Conflict.
+	pub async fn patch_branch_config(
+		&self,
+		name: &str,
+		patch: &serde_json::Value,
+	) -> Result<ApiBranchInfo> {
+		let response = self
+			.request(Method::PATCH, &["branch", name])
+			.header(CONTENT_TYPE, "application/json-patch+json")
+			.json(patch)
+			.send()
+			.await?;
+		parse_json(response).await
+	}
+
+	/// Applies a JSON Merge Patch to configurations of all branches matching the query.
+	pub async fn update_branches(
+		&self,
+		query: &ApiBranchListQuery,
+		patch: &serde_json::Value,
+	) -> Result<ApiBranchBulkUpdateResult> {
+		let response = self
+			.request(Method::PATCH, &["branch"])
+			.query(query)
+			.header(CONTENT_TYPE, "application/merge-patch+json")
+			.json(patch)
+			.send()
+			.await?;
+		parse_json(response).await
+	}
+
+	pub async fn delete_branch(&self, name: &str) -> Result<()> {
+		let response = self
+			.request(Method::DELETE, &["branch", name])
+			.send()
+			.await?;
+		check_status(response).await?;
+		Ok(())
+	}
+
+	/// Deletes all branches matching a filter.
+	pub async fn delete_branches(&self, filter: &BranchFilter) -> Result<ApiBranchDeleteResult> {
+		let response = self
+			.request(Method::POST, &["branch", "delete"])
+			.json(filter)
+			.send()
+			.await?;
+		parse_json(response).await
+	}
+
+	/// Enqueues a synchronization job for a branch.
+	///
+	/// Returns the ID of the created job.
+	pub async fn sync_branch(&self, name: &str) -> Result<Uuid> {
+		let response = self
+			.request(Method::POST, &["branch", name, "sync"])
+			.send()
+			.await?;
+		job_id_from_location(check_status(response).await?)
+	}
+
+	/// Enqueues a new job with the same command as a finished or dead-lettered job.
+	///
+	/// If `priority` is `None`, the priority of the original job is used.
+	/// Returns the ID of the created job.
+	pub async fn replay_job(&self, id: Uuid, priority: Option<u16>) -> Result<Uuid> {
+		let response = self
+			.request(Method::POST, &["job", &id.to_string(), "replay"])
+			.query(&ApiJobReplayQuery { priority })
+			.send()
+			.await?;
+		job_id_from_location(check_status(response).await?)
+	}
+
+	/// Lists jobs matching the query.
+	pub async fn list_jobs(&self, query: &ApiJobListQuery) -> Result<ApiPage<ApiJobInfo>> {
+		let response = self
+			.request(Method::GET, &["job"])
+			.query(query)
+			.send()
+			.await?;
+		parse_json(response).await
+	}
+
+	pub async fn get_job(&self, id: Uuid) -> Result<ApiJobInfo> {
+		let response = self
+			.request(Method::GET, &["job", &id.to_string()])
+			.send()
+			.await?;
+		parse_json(response).await
+	}
+
+	/// Explains why a job would or would not be claimed now.
+	pub async fn explain_job(&self, id: Uuid) -> Result<ApiClaimExplanation> {
+		let response = self
+			.request(Method::GET, &["job", &id.to_string(), "explain"])
+			.send()
+			.await?;
+		parse_json(response).await
+	}
+
+	/// Returns the attempt history of a job.
+	pub async fn get_job_attempts(&self, id: Uuid) -> Result<Vec<ApiJobAttempt>> {
+		let response = self
+			.request(Method::GET, &["job", &id.to_string(), "attempts"])
+			.send()
+			.await?;
+		parse_json(response).await
+	}
+
+	/// Analyzes the job tables, and vacuums them if `vacuum` is set.
+	pub async fn maintain_jobs(&self, vacuum: bool) -> Result<ApiJobMaintenanceReport> {
+		let response = self
+			.request(Method::POST, &["admin", "maintenance", "jobs"])
+			.query(&ApiJobMaintenanceQuery { vacuum })
+			.send()
+			.await?;
+		parse_json(response).await
+	}
+
+	/// Lists registered workers.
+	pub async fn list_workers(&self) -> Result<Vec<ApiWorkerInfo>> {
+		let response = self.request(Method::GET, &["workers"]).send().await?;
+		parse_json(response).await
+	}
+}
+
+/// Converts error responses into [ClientError::Api].
+async fn check_status(response: Response) -> Result<Response> {
+	let status = response.status();
+	if status.is_success() {
+		Ok(response)
+	} else {
+		let message = response.text().await?;
+		Err(match serde_json::from_str::<ApiErrorBody>(&message) {
+			Ok(body) => ClientError::Api {
+				status,
+				code: Some(body.code),
+				message: body.message,
+			},
+			Err(_) => ClientError::Api {
+				status,
+				code: None,
+				message,
+			},
+		})
+	}
+}
+
+async fn parse_json<T: DeserializeOwned>(response: Response) -> Result<T> {
+	let body = check_status(response).await?.bytes().await?;
+	serde_json::from_slice(&body).map_err(|error| ClientError::InvalidResponse(error.to_string()))
+}
+
+/// Parses the ID of a created job from the `Location` header.
+fn job_id_from_location(response: Response) -> Result<Uuid> {
+	let location = response
+		.headers()
+		.get(LOCATION)
+		.and_then(|location| location.to_str().ok())
+		.ok_or_else(|| ClientError::InvalidResponse("missing job location".to_string()))?;
+	location
+		.rsplit('/')
+		.next()
+		.and_then(|id| Uuid::parse_str(id).ok())
+		.ok_or_else(|| ClientError::InvalidResponse(format!("invalid job location: {location}")))
+}
@@ -0,0 +1,31 @@
+use std::{
+	env,
+	process::Command,
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+fn main() {
+	let commit = Command::new("git")
+		.args(["rev-parse", "HEAD"])
+		.output()
+		.ok()
+		.filter(|output| output.status.success())
+		.and_then(|output| String::from_utf8(output.stdout).ok());
+	if let Some(commit) = commit {
+		println!("cargo:rustc-env=FABRICIA_GIT_COMMIT={}", commit.trim());
+	}
+
+	// respect SOURCE_DATE_EPOCH for reproducible builds
+	let timestamp = env::var("SOURCE_DATE_EPOCH").ok().or_else(|| {
+		SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.ok()
+			.map(|time| time.as_secs().to_string())
+	});
+	if let Some(timestamp) = timestamp {
+		println!("cargo:rustc-env=FABRICIA_BUILD_TIMESTAMP={}", timestamp);
+	}
+
+	println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+	println!("cargo:rerun-if-changed=../../.git/HEAD");
+}
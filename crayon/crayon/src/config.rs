@@ -1,14 +1,23 @@
+use std::collections::{BTreeMap, BTreeSet};
+
 use fabricia_backend::{
-	config::BackendConfig, db::service::DatabaseConfig, redis::RedisConfig, target::TargetConfig,
+	branch::BranchConfig, config::BackendConfig, db::service::DatabaseConfig,
+	job_queue::JobQueueConfig, redis::RedisConfig, target::TargetConfig,
 };
 use serde::{Deserialize, Serialize};
 
+use crate::routes::API_BASE;
+
 #[derive(Debug, PartialEq, Eq, Clone, Hash, Deserialize, Serialize)]
 pub struct CrayonConfig {
 	pub web: WebConfig,
 	pub database: DatabaseConfig,
 	pub redis: RedisConfig,
 	pub target: Vec<TargetConfig>,
+	#[serde(default)]
+	pub job_queue: JobQueueConfig,
+	#[serde(default)]
+	pub branch: BranchConfig,
 }
 
 impl TryFrom<CrayonConfig> for BackendConfig {
@@ -19,6 +28,8 @@ impl TryFrom<CrayonConfig> for BackendConfig {
 			database: config.database,
 			redis: config.redis,
 			target: config.target,
+			job_queue: config.job_queue,
+			branch: config.branch,
 		})
 	}
 }
@@ -31,4 +42,42 @@ pub struct WebConfig {
 	/// - `unix://crayon.socket`
 	/// - `tcp://127.0.0.1:8000`
 	pub listen: String,
+	/// Maximum size of request bodies in bytes.
+	///
+	/// Larger requests are rejected with `413 Payload Too Large`.
+	#[serde(default = "default_body_limit")]
+	pub body_limit: usize,
+	/// Path to mount the API under, e.g. `/api/v1` behind a reverse proxy.
+	///
+	/// This must start with `/`, and must not end with `/`.
+	/// URLs returned by the API, e.g. in `Location` headers, include it.
+	#[serde(default = "default_api_base")]
+	pub api_base: String,
+	/// API keys accepted in `Authorization: Bearer <key>` headers, by name.
+	///
+	/// Names of keys are recorded as principals, e.g. in `created_by` of
+	/// branches. If empty, requests are not authenticated.
+	#[serde(default)]
+	pub api_keys: BTreeMap<String, String>,
+	/// Names of API keys granted the administrative scope.
+	///
+	/// Administrative operations, e.g. maintenance of the job tables,
+	/// are rejected with `403 Forbidden` for other keys. If empty, they
+	/// are rejected for all clients, unless [`WebConfig::open_admin`] is set.
+	#[serde(default)]
+	pub admin_keys: BTreeSet<String>,
+	/// Allows administrative operations for all authenticated clients.
+	///
+	/// Without `api_keys`, this opens them to anyone, so it should only be
+	/// set behind a trusted proxy or in development.
+	#[serde(default)]
+	pub open_admin: bool,
+}
+
+fn default_body_limit() -> usize {
+	256 * 1024
+}
+
+fn default_api_base() -> String {
+	API_BASE.to_string()
 }
@@ -4,13 +4,15 @@ use anyhow::{Result, bail};
 use bus::CrayonBusFactory;
 use clap::Parser;
 use config::CrayonConfig;
-use fabricia_backend::BackendServices;
+use fabricia_backend::{BackendServices, config::BackendConfig};
 use tokio::net::{TcpListener, UnixListener};
 use tracing::info;
 
 mod bus;
 mod config;
 mod routes;
+#[cfg(test)]
+mod test;
 
 #[derive(clap::Parser)]
 struct Args {
@@ -29,7 +31,8 @@ async fn main() -> Result<()> {
 	)?;
 
 	let config_path = &args.config;
-	let config = toml::from_str::<CrayonConfig>(&fs::read_to_string(config_path)?)?;
+	let mut config = toml::from_str::<CrayonConfig>(&fs::read_to_string(config_path)?)?;
+	BackendConfig::override_from_env(&mut config.database, &mut config.redis);
 	info!("loaded configuration from file: {:?}", config_path);
 
 	info!("initializing backend services ...");
@@ -44,6 +47,7 @@ async fn main() -> Result<()> {
 	tokio::spawn(bus::handle_bus_message(services.clone()));
 
 	let listen_addr = services.config.web.listen.clone();
+	let backend = services.backend.clone();
 	let router = routes::make_router(services)?;
 	if let Some(path) = listen_addr.strip_prefix("unix://") {
 		let path = PathBuf::from(path);
@@ -52,18 +56,29 @@ async fn main() -> Result<()> {
 
 		let listener = UnixListener::bind(&path)?;
 		info!("listening on UDS: {:?}", path);
-		axum::serve(listener, router).await?;
+		axum::serve(listener, router)
+			.with_graceful_shutdown(shutdown_signal())
+			.await?;
 	} else if let Some(addr) = listen_addr.strip_prefix("tcp://") {
 		let listener = TcpListener::bind(addr).await?;
 		info!("listening on TCP {}", listener.local_addr()?);
-		axum::serve(listener, router).await.unwrap();
+		axum::serve(listener, router)
+			.with_graceful_shutdown(shutdown_signal())
+			.await
+			.unwrap();
 	} else {
 		bail!("unsupported web.listen schema")
 	}
 
+	info!("shutting down ...");
+	backend.shutdown().await?;
 	Ok(())
 }
 
+async fn shutdown_signal() {
+	_ = tokio::signal::ctrl_c().await;
+}
+
 #[derive(Debug, Clone)]
 pub struct CrayonServices {
 	pub config: CrayonConfig,
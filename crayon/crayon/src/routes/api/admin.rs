@@ -0,0 +1,49 @@
+use axum::{
+	Json,
+	extract::{Query, State},
+	http::StatusCode,
+};
+use fabricia_crayon_api_model::job::{
+	ApiJobMaintenanceQuery, ApiJobMaintenanceReport, ApiTableMaintenance,
+};
+
+use crate::CrayonServices;
+
+use super::{auth::AdminRequired, error::ApiResult};
+
+/// Vacuums and analyzes the job tables, like [maintain_jobs] with `vacuum` set.
+pub async fn vacuum(
+	AdminRequired(_): AdminRequired,
+	State(services): State<CrayonServices>,
+) -> ApiResult<(StatusCode, &'static str)> {
+	if services.backend.job_queue.maintain(true).await?.vacuumed {
+		Ok((StatusCode::OK, "job tables vacuumed"))
+	} else {
+		Ok((
+			StatusCode::OK,
+			"vacuum is only supported on PostgreSQL, job tables were only analyzed",
+		))
+	}
+}
+
+/// Analyzes the job tables, and vacuums them if requested.
+///
+/// This does not block workers, so it is safe to run on a busy queue.
+pub async fn maintain_jobs(
+	AdminRequired(_): AdminRequired,
+	State(services): State<CrayonServices>,
+	Query(query): Query<ApiJobMaintenanceQuery>,
+) -> ApiResult<Json<ApiJobMaintenanceReport>> {
+	let report = services.backend.job_queue.maintain(query.vacuum).await?;
+	Ok(Json(ApiJobMaintenanceReport {
+		vacuumed: report.vacuumed,
+		tables: report
+			.tables
+			.into_iter()
+			.map(|(table, elapsed)| ApiTableMaintenance {
+				table: table.to_string(),
+				elapsed_ms: elapsed.as_millis() as u64,
+			})
+			.collect(),
+	}))
+}
@@ -1,21 +1,88 @@
-use axum::{extract::FromRequestParts, http::request::Parts};
+use axum::{
+	extract::FromRequestParts,
+	http::{header::AUTHORIZATION, request::Parts},
+};
+
+use crate::CrayonServices;
 
 use super::error::ApiError;
 
+/// Authenticates the client with [`WebConfig::api_keys`](crate::config::WebConfig::api_keys).
+///
+/// Returns the name of the API key, or `None` if no keys are configured.
+fn authenticate(parts: &Parts, services: &CrayonServices) -> Result<Option<String>, ApiError> {
+	let keys = &services.config.web.api_keys;
+	if keys.is_empty() {
+		return Ok(None);
+	}
+	let key = parts
+		.headers
+		.get(AUTHORIZATION)
+		.and_then(|value| value.to_str().ok())
+		.and_then(|value| value.strip_prefix("Bearer "))
+		.ok_or(ApiError::AuthRequired)?;
+	keys.iter()
+		.find(|(_, expected)| constant_time_eq(expected.as_bytes(), key.as_bytes()))
+		.map(|(name, _)| Some(name.clone()))
+		.ok_or(ApiError::AuthRequired)
+}
+
+/// Compares secrets without returning early on the first different byte.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+	a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
 pub struct AuthRequired;
 
-impl<S> FromRequestParts<S> for AuthRequired
-where
-	S: Send + Sync,
-{
+impl FromRequestParts<CrayonServices> for AuthRequired {
+	type Rejection = ApiError;
+
+	async fn from_request_parts(
+		parts: &mut Parts,
+		state: &CrayonServices,
+	) -> Result<Self, Self::Rejection> {
+		authenticate(parts, state)?;
+		Ok(Self)
+	}
+}
+
+/// Principal of the authorized client, recorded in audit fields.
+///
+/// This is the name of the API key, or `None` if no keys are configured.
+pub struct Principal(pub Option<String>);
+
+impl FromRequestParts<CrayonServices> for Principal {
+	type Rejection = ApiError;
+
+	async fn from_request_parts(
+		parts: &mut Parts,
+		state: &CrayonServices,
+	) -> Result<Self, Self::Rejection> {
+		Ok(Self(authenticate(parts, state)?))
+	}
+}
+
+/// Requires the client to be authorized for administrative operations.
+///
+/// The API key must be listed in
+/// [`WebConfig::admin_keys`](crate::config::WebConfig::admin_keys),
+/// unless [`WebConfig::open_admin`](crate::config::WebConfig::open_admin) is set.
+///
+/// Carries the principal of the client like [Principal].
+pub struct AdminRequired(pub Option<String>);
+
+impl FromRequestParts<CrayonServices> for AdminRequired {
 	type Rejection = ApiError;
 
-	async fn from_request_parts(_parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-		// TODO: implement authorization
-		if true {
-			Ok(Self)
-		} else {
-			Err(ApiError::AuthRequired)
+	async fn from_request_parts(
+		parts: &mut Parts,
+		state: &CrayonServices,
+	) -> Result<Self, Self::Rejection> {
+		let web = &state.config.web;
+		match authenticate(parts, state)? {
+			principal if web.open_admin => Ok(Self(principal)),
+			Some(name) if web.admin_keys.contains(&name) => Ok(Self(Some(name))),
+			_ => Err(ApiError::AdminRequired),
 		}
 	}
 }
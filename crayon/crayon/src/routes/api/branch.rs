@@ -1,41 +1,134 @@
-use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
 
 use axum::{
 	Json,
-	extract::{Path, State},
-	http::StatusCode,
+	body::Bytes,
+	extract::{FromRequestParts, Path, Query, State},
+	http::{
+		HeaderMap, HeaderName, HeaderValue, StatusCode,
+		header::{CONTENT_TYPE, ETAG, LAST_MODIFIED, LOCATION, WARNING},
+		request::Parts,
+	},
+	response::AppendHeaders,
 };
 use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, Queryable, Selectable};
 use fabricia_backend::{
-	branch::{BranchConfigInfo, SqlBranchStatus, SqlTrackingMode},
+	branch::{
+		BranchConfigInfo, BranchConfigUpdate, BranchFilter, BranchQuery, SqlBranchStatus,
+		SqlTrackingMode,
+	},
 	db::{
-		schema::{self, branch::dsl},
+		schema::{self, branch::dsl, branch_label},
 		service::SqlConnRef,
 		utils::WherePredicate,
 	},
 };
-use fabricia_common_model::branch::TrackingMode;
-use fabricia_crayon_api_model::branch::*;
-use serde::{Deserialize, Serialize};
+use fabricia_common_model::branch::{TrackingMode, is_valid_branch_name};
+use fabricia_crayon_api_model::{
+	branch::*,
+	page::{ApiPage, ApiPageInfo},
+};
+use json_patch::Patch;
+use schemars::{Schema, schema_for};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use time::{OffsetDateTime, PrimitiveDateTime, UtcOffset};
 
 use crate::CrayonServices;
 
 use super::{
-	auth::AuthRequired,
-	error::{ApiError, ApiResult, OptionExt},
+	auth::{AuthRequired, Principal},
+	error::{ApiError, ApiResult},
+	job::{error_code_into_api, job_location},
 };
 
+/// Lists branches matching the query.
+///
+/// Branches are paginated in the order of names, and cursors are
+/// names of the last branches of pages.
+/// Items only contain the requested fields, see [ApiBranchGetQuery::fields].
 pub async fn list_branches(
 	State(services): State<CrayonServices>,
-) -> ApiResult<Json<HashMap<String, ApiBranchInfo>>> {
+	Query(query): Query<ApiBranchListQuery>,
+) -> ApiResult<Json<ApiPage<serde_json::Value>>> {
+	let limit = query.limit;
+	let fields = query.fields.clone();
+	let query = branch_query(query);
+	let ids = services.backend.branch.list_ids(&query).await?;
+	let total = services.backend.branch.count(&query).await?;
+
 	let mut db = services.backend.database.get().await?;
-	let result: Vec<SqlApiBranchInfo> = db.load_select(dsl::branch).await?;
-	let mut output = HashMap::with_capacity(result.len());
+	let result: Vec<SqlApiBranchInfo> = db
+		.load_select(
+			dsl::branch
+				.filter(dsl::id.eq_any(ids))
+				.order(dsl::name.asc()),
+		)
+		.await?;
+	let mut items = Vec::with_capacity(result.len());
 	for info in result {
-		output.insert(info.name.clone(), info.into_api(&mut db).await?);
+		items.push(info.into_api(&mut db).await?);
+	}
+
+	let next_cursor = match (items.last(), limit) {
+		(Some(last), Some(limit)) if items.len() >= limit as usize => Some(last.name.clone()),
+		_ => None,
+	};
+	let items = items
+		.iter()
+		.map(|item| select_fields(item, fields.as_deref()))
+		.collect::<ApiResult<_>>()?;
+	Ok(Json(ApiPage {
+		items,
+		page: ApiPageInfo {
+			next_cursor,
+			total: Some(total as u64),
+		},
+	}))
+}
+
+fn branch_query(query: ApiBranchListQuery) -> BranchQuery {
+	BranchQuery {
+		enabled: query.enabled,
+		status: query.sync_status.map(|status| match status {
+			ApiSyncStatus::Dirty => SqlBranchStatus::Dirty,
+			ApiSyncStatus::Ready => SqlBranchStatus::Ready,
+			ApiSyncStatus::Failed => SqlBranchStatus::Error,
+			ApiSyncStatus::Suspended => SqlBranchStatus::Suspended,
+		}),
+		label: query.label,
+		limit: query.limit,
+		offset: query.offset,
+		after: query.cursor,
 	}
+}
 
-	Ok(Json(output))
+/// Applies a JSON Merge Patch to all branches matching the query.
+///
+/// At least one of `enabled`, `sync_status` and `label` must be set,
+/// so that a missing filter does not update every branch by accident.
+/// Branches failing validation are left unchanged and reported.
+pub async fn update_branches(
+	Principal(actor): Principal,
+	State(services): State<CrayonServices>,
+	Query(query): Query<ApiBranchListQuery>,
+	body: Bytes,
+) -> ApiResult<Json<ApiBranchBulkUpdateResult>> {
+	if query.enabled.is_none() && query.sync_status.is_none() && query.label.is_none() {
+		return Err(ApiError::CustomRef(
+			StatusCode::UNPROCESSABLE_ENTITY,
+			"a filter of branches is required",
+		));
+	}
+	let patch = parse_body::<serde_json::Value>(&body)?;
+	let result = services
+		.backend
+		.branch
+		.merge_patch_many(&branch_query(query), &patch, actor.as_deref())
+		.await?;
+	Ok(Json(ApiBranchBulkUpdateResult {
+		updated: result.updated as u64,
+		failures: result.failures,
+	}))
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Queryable, Selectable)]
@@ -43,6 +136,7 @@ pub async fn list_branches(
 #[diesel(check_for_backend(diesel::pg::Pg))]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
 pub struct SqlApiBranchInfo {
+	id: i64,
 	name: String,
 	base: Option<i64>,
 	status: i16,
@@ -51,6 +145,13 @@ pub struct SqlApiBranchInfo {
 	tracking: i16,
 	commit: Option<Vec<u8>>,
 	total_srcpkgs: i32,
+	enabled: bool,
+	created_at: Option<PrimitiveDateTime>,
+	updated_at: Option<PrimitiveDateTime>,
+	created_by: Option<String>,
+	updated_by: Option<String>,
+	last_error: Option<String>,
+	last_error_code: Option<i16>,
 }
 
 impl SqlApiBranchInfo {
@@ -70,6 +171,15 @@ impl SqlApiBranchInfo {
 		let status = SqlBranchStatus::from(self.status).into_common(self.status_msg);
 		let tracking_mode = TrackingMode::from(SqlTrackingMode::from(self.tracking));
 		let commit = self.commit.map(hex::encode);
+		let labels = db
+			.load::<_, String>(
+				branch_label::table
+					.filter(branch_label::branch.eq(self.id))
+					.select(branch_label::label),
+			)
+			.await?
+			.into_iter()
+			.collect();
 		Ok(ApiBranchInfo {
 			name: self.name.clone(),
 			base,
@@ -78,16 +188,127 @@ impl SqlApiBranchInfo {
 			tracking_mode,
 			commit,
 			packages: self.total_srcpkgs as u32,
+			enabled: self.enabled,
+			labels,
+			created_at: self.created_at.map(|time| time.assume_utc()),
+			updated_at: self.updated_at.map(|time| time.assume_utc()),
+			created_by: self.created_by,
+			updated_by: self.updated_by,
+			last_error: self.last_error.map(|message| ApiBranchSyncError {
+				code: self
+					.last_error_code
+					.map(|code| error_code_into_api(code.into())),
+				message,
+			}),
 		})
 	}
 }
 
+/// Name of the branch in the path of a request.
+///
+/// Names which are not valid Git reference names are rejected with
+/// 400 Bad Request and the code `invalid_branch_name`, rather than looked up.
+pub struct BranchName(pub String);
+
+impl<S> FromRequestParts<S> for BranchName
+where
+	S: Send + Sync,
+{
+	type Rejection = ApiError;
+
+	async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+		let Path(name) = Path::<String>::from_request_parts(parts, state)
+			.await
+			.map_err(|rejection| {
+				ApiError::CustomString(rejection.status(), rejection.body_text())
+			})?;
+		if !is_valid_branch_name(&name) {
+			return Err(ApiError::Coded(
+				StatusCode::BAD_REQUEST,
+				"invalid_branch_name",
+				format!("branch name {name:?} is invalid"),
+			));
+		}
+		Ok(Self(name))
+	}
+}
+
+fn branch_not_found() -> ApiError {
+	ApiError::Coded(
+		StatusCode::NOT_FOUND,
+		"branch_not_found",
+		"branch not found".to_string(),
+	)
+}
+
+/// Returns information of a branch, with only the requested fields.
+///
+/// This also serves `HEAD` requests, with the same status and headers.
+/// `Last-Modified` is omitted for branches tracked before auditing.
 pub async fn get_branch(
 	State(services): State<CrayonServices>,
-	Path(name): Path<String>,
-) -> ApiResult<Json<ApiBranchInfo>> {
+	BranchName(name): BranchName,
+	Query(query): Query<ApiBranchGetQuery>,
+) -> ApiResult<(
+	AppendHeaders<Vec<(HeaderName, String)>>,
+	Json<serde_json::Value>,
+)> {
 	let mut db = services.backend.database.get().await?;
-	get_branch_info(&mut db, dsl::name.eq(name)).await
+	let Json(info) = get_branch_info(&mut db, dsl::name.eq(name)).await?;
+	let body = select_fields(&info, query.fields.as_deref())?;
+	let mut headers = vec![(ETAG, entity_tag(&body)?)];
+	if let Some(updated_at) = info.updated_at {
+		headers.push((LAST_MODIFIED, http_date(updated_at)));
+	}
+	Ok((AppendHeaders(headers), Json(body)))
+}
+
+/// Formats a time as an HTTP date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+fn http_date(time: OffsetDateTime) -> String {
+	let time = time.to_offset(UtcOffset::UTC);
+	format!(
+		"{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+		&time.weekday().to_string()[..3],
+		time.day(),
+		&time.month().to_string()[..3],
+		time.year(),
+		time.hour(),
+		time.minute(),
+		time.second(),
+	)
+}
+
+/// Serializes a response body with only the comma-separated `fields`.
+///
+/// All fields are kept if `fields` is `None`.
+fn select_fields<T: Serialize>(body: &T, fields: Option<&str>) -> ApiResult<serde_json::Value> {
+	match (fields, serde_json::to_value(body)?) {
+		(Some(fields), serde_json::Value::Object(mut object)) => {
+			let mut selected = serde_json::Map::new();
+			for field in fields
+				.split(',')
+				.map(str::trim)
+				.filter(|field| !field.is_empty())
+			{
+				let value = object.remove(field).ok_or_else(|| {
+					ApiError::CustomString(
+						StatusCode::BAD_REQUEST,
+						format!("unknown field {field}"),
+					)
+				})?;
+				selected.insert(field.to_string(), value);
+			}
+			Ok(serde_json::Value::Object(selected))
+		}
+		(_, value) => Ok(value),
+	}
+}
+
+/// Computes a weak entity tag of a response body.
+fn entity_tag<T: Serialize>(body: &T) -> ApiResult<String> {
+	let mut hasher = DefaultHasher::new();
+	serde_json::to_vec(body)?.hash(&mut hasher);
+	Ok(format!("W/\"{:016x}\"", hasher.finish()))
 }
 
 async fn get_branch_info<F: WherePredicate<dsl::branch>>(
@@ -95,17 +316,34 @@ async fn get_branch_info<F: WherePredicate<dsl::branch>>(
 	filter: F,
 ) -> ApiResult<Json<ApiBranchInfo>> {
 	let result: SqlApiBranchInfo = db
-		.load_one_select(dsl::branch.limit(1).filter(filter))
-		.await?;
+		.load_one_select(
+			dsl::branch
+				.limit(1)
+				.filter(filter)
+				.filter(dsl::deleted_at.is_null()),
+		)
+		.await
+		.optional()?
+		.ok_or_else(branch_not_found)?;
 	Ok(Json(result.into_api(db).await?))
 }
 
+/// Returns the default configuration of new branches.
+pub async fn branch_defaults() -> Json<BranchConfigInfo> {
+	Json(BranchConfigInfo::defaults())
+}
+
+/// Returns the JSON schema of branch configurations.
+pub async fn config_schema() -> Json<Schema> {
+	Json(schema_for!(BranchConfigInfo))
+}
+
 pub async fn new_branch(
-	AuthRequired: AuthRequired,
+	Principal(actor): Principal,
 	State(services): State<CrayonServices>,
-	Path(name): Path<String>,
+	BranchName(name): BranchName,
 	Json(info): Json<BranchConfigInfo>,
-) -> ApiResult<(StatusCode, Json<ApiBranchInfo>)> {
+) -> ApiResult<(StatusCode, HeaderMap, Json<ApiBranchInfo>)> {
 	let branch = &services.backend.branch;
 	if branch.find_id(&name).await?.is_some() {
 		return Err(ApiError::CustomRef(
@@ -114,31 +352,52 @@ pub async fn new_branch(
 		));
 	}
 
-	branch.track(&name, info).await?;
+	let warnings = branch.track(&name, info, actor.as_deref()).await?;
 
 	let mut db = services.backend.database.get().await?;
 	Ok((
 		StatusCode::CREATED,
+		warning_headers(&warnings),
 		get_branch_info(&mut db, dsl::name.eq(name)).await?,
 	))
 }
 
 pub async fn update_branch_config(
-	AuthRequired: AuthRequired,
+	Principal(actor): Principal,
 	State(services): State<CrayonServices>,
-	Path(name): Path<String>,
-	Json(info): Json<BranchConfigInfo>,
-) -> ApiResult<(StatusCode, Json<ApiBranchInfo>)> {
+	BranchName(name): BranchName,
+	headers: HeaderMap,
+	body: Bytes,
+) -> ApiResult<(StatusCode, HeaderMap, Json<ApiBranchInfo>)> {
 	let branch = &services.backend.branch;
-	let id = branch
-		.find_id(&name)
-		.await?
-		.or_api_error(StatusCode::NOT_FOUND, "branch not found")?;
-	branch.update_config(id, &info).await?;
+	let id = branch.find_id(&name).await?.ok_or_else(branch_not_found)?;
+	let warnings = match media_type(&headers) {
+		Some("application/json-patch+json") => {
+			let patch = parse_body::<Patch>(&body)?;
+			branch.patch_config(id, &patch, actor.as_deref()).await?
+		}
+		Some("application/merge-patch+json") => {
+			let patch = parse_body::<serde_json::Value>(&body)?;
+			branch
+				.merge_patch_config(id, &patch, actor.as_deref())
+				.await?
+		}
+		_ => match parse_body::<BranchConfigUpdate>(&body)? {
+			BranchConfigUpdate::CompareAndSwap { expected, desired } => {
+				branch
+					.update_config_cas(id, &expected, &desired, actor.as_deref())
+					.await?
+			}
+			BranchConfigUpdate::Merge(info) => {
+				branch.update_config(id, &info, actor.as_deref()).await?
+			}
+		},
+	};
 
 	let mut db = services.backend.database.get().await?;
 	Ok((
 		StatusCode::ACCEPTED,
+		warning_headers(&warnings),
 		get_branch_info(&mut db, dsl::name.eq(name)).await?,
 	))
 }
@@ -146,13 +405,81 @@ pub async fn update_branch_config(
 pub async fn delete_branch(
 	AuthRequired: AuthRequired,
 	State(services): State<CrayonServices>,
-	Path(name): Path<String>,
+	BranchName(name): BranchName,
 ) -> ApiResult<(StatusCode, &'static str)> {
 	let branch = &services.backend.branch;
-	let id = branch
-		.find_id(name)
-		.await?
-		.or_api_error(StatusCode::NOT_FOUND, "branch not found")?;
+	let id = branch.find_id(name).await?.ok_or_else(branch_not_found)?;
 	branch.untrack(id).await?;
 	Ok((StatusCode::ACCEPTED, "branch deleted"))
 }
+
+/// Restores a deleted branch within the grace period.
+pub async fn restore_branch(
+	AuthRequired: AuthRequired,
+	State(services): State<CrayonServices>,
+	BranchName(name): BranchName,
+) -> ApiResult<(StatusCode, Json<ApiBranchInfo>)> {
+	services.backend.branch.restore(&name).await?;
+
+	let mut db = services.backend.database.get().await?;
+	Ok((
+		StatusCode::ACCEPTED,
+		get_branch_info(&mut db, dsl::name.eq(name)).await?,
+	))
+}
+
+/// Deletes all branches matching a filter.
+///
+/// Pending jobs of deleted branches are cancelled.
+pub async fn delete_branches(
+	AuthRequired: AuthRequired,
+	State(services): State<CrayonServices>,
+	Json(filter): Json<BranchFilter>,
+) -> ApiResult<Json<ApiBranchDeleteResult>> {
+	let deleted = services.backend.branch.delete_many(&filter).await?;
+	Ok(Json(ApiBranchDeleteResult {
+		deleted: deleted as u64,
+	}))
+}
+
+/// Enqueues a synchronization job for a branch.
+///
+/// Responds with the location of the created job.
+pub async fn sync_branch(
+	AuthRequired: AuthRequired,
+	State(services): State<CrayonServices>,
+	BranchName(name): BranchName,
+) -> ApiResult<(StatusCode, [(HeaderName, String); 1], &'static str)> {
+	let branch = &services.backend.branch;
+	let id = branch.find_id(&name).await?.ok_or_else(branch_not_found)?;
+	let job = branch.sync(id).await?;
+	Ok((
+		StatusCode::ACCEPTED,
+		[(LOCATION, job_location(&services, job))],
+		"branch synchronization enqueued",
+	))
+}
+
+/// Builds `Warning` headers echoing back configuration warnings.
+fn warning_headers(warnings: &[String]) -> HeaderMap {
+	let mut headers = HeaderMap::new();
+	for warning in warnings {
+		let value = format!("299 - {:?}", warning);
+		if let Ok(value) = HeaderValue::try_from(value) {
+			headers.append(WARNING, value);
+		}
+	}
+	headers
+}
+
+/// Returns the media type of the request body, without parameters.
+fn media_type(headers: &HeaderMap) -> Option<&str> {
+	let content_type = headers.get(CONTENT_TYPE)?.to_str().ok()?;
+	content_type.split(';').next().map(str::trim)
+}
+
+fn parse_body<T: DeserializeOwned>(body: &Bytes) -> ApiResult<T> {
+	serde_json::from_slice(body).map_err(|error| {
+		ApiError::CustomString(StatusCode::UNPROCESSABLE_ENTITY, error.to_string())
+	})
+}
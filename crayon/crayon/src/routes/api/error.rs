@@ -1,8 +1,12 @@
 use axum::{
-	http::StatusCode,
+	Json,
+	http::{HeaderValue, StatusCode, header::RETRY_AFTER},
 	response::{AppendHeaders, IntoResponse, Response},
 };
-use fabricia_backend::BackendError;
+use fabricia_backend::{
+	BackendError, branch::BranchError, db::service::DatabaseError, job_queue::JobQueueError,
+};
+use fabricia_crayon_api_model::error::ApiErrorBody;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -14,9 +18,14 @@ pub enum ApiError {
 	CustomRef(StatusCode, &'static str),
 	#[error("api error: {1}")]
 	CustomString(StatusCode, String),
+	/// Error responded with an [ApiErrorBody], with a stable code.
+	#[error("api error: {2}")]
+	Coded(StatusCode, &'static str, String),
 
 	#[error("authentication is required")]
 	AuthRequired,
+	#[error("administrative scope is required")]
+	AdminRequired,
 }
 
 impl IntoResponse for ApiError {
@@ -25,6 +34,12 @@ impl IntoResponse for ApiError {
 			(status, message).into_response()
 		} else if let ApiError::CustomString(status, message) = self {
 			(status, message).into_response()
+		} else if let ApiError::Coded(status, code, message) = self {
+			let body = ApiErrorBody {
+				code: code.to_string(),
+				message,
+			};
+			(status, Json(body)).into_response()
 		} else if let ApiError::AuthRequired = self {
 			(
 				StatusCode::UNAUTHORIZED,
@@ -32,12 +47,83 @@ impl IntoResponse for ApiError {
 				"authentication is required",
 			)
 				.into_response()
+		} else if let ApiError::AdminRequired = self {
+			(StatusCode::FORBIDDEN, self.to_string()).into_response()
+		} else if let ApiError::BackendError(BackendError::BranchError(error)) = self {
+			let status = match error {
+				BranchError::BranchNameNotFound(_) | BranchError::BranchNotFound(_) => {
+					StatusCode::NOT_FOUND
+				}
+				BranchError::ConfigConflict(_)
+				| BranchError::PatchTestFailed(_)
+				| BranchError::DeletedBranchExists(_) => StatusCode::CONFLICT,
+				BranchError::InvalidPatch(_) | BranchError::InvalidConfig(_) => {
+					StatusCode::UNPROCESSABLE_ENTITY
+				}
+			};
+			(status, error.to_string()).into_response()
+		} else if let ApiError::BackendError(BackendError::JobQueueError(error)) = self {
+			let (status, code) = job_queue_error_code(&error);
+			let body = ApiErrorBody {
+				code: code.to_string(),
+				message: error.to_string(),
+			};
+			let mut response = (status, Json(body)).into_response();
+			if let JobQueueError::QueueFull(retry_after) = error {
+				// clients should back off instead of retrying immediately
+				response
+					.headers_mut()
+					.insert(RETRY_AFTER, HeaderValue::from(retry_after));
+			}
+			response
+		} else if let ApiError::BackendError(BackendError::DatabaseError(
+			error @ (DatabaseError::NotFound
+			| DatabaseError::UniqueViolation(_)
+			| DatabaseError::ForeignKeyViolation(_)
+			| DatabaseError::SerializationFailure(_)
+			| DatabaseError::Deadlock(_)),
+		)) = self
+		{
+			let status = match error {
+				DatabaseError::NotFound => StatusCode::NOT_FOUND,
+				DatabaseError::SerializationFailure(_) | DatabaseError::Deadlock(_) => {
+					StatusCode::SERVICE_UNAVAILABLE
+				}
+				_ => StatusCode::CONFLICT,
+			};
+			(status, error.to_string()).into_response()
 		} else {
 			(StatusCode::INTERNAL_SERVER_ERROR, self.to_string()).into_response()
 		}
 	}
 }
 
+/// Returns the HTTP status and the stable code of a job queue error.
+///
+/// Codes are part of the API, and must not be changed once released.
+pub(crate) fn job_queue_error_code(error: &JobQueueError) -> (StatusCode, &'static str) {
+	match error {
+		JobQueueError::JobAborted(_) => (StatusCode::CONFLICT, "job_aborted"),
+		JobQueueError::DependencyCycle(_) => (StatusCode::UNPROCESSABLE_ENTITY, "dependency_cycle"),
+		JobQueueError::DependencyFailed(_) => {
+			(StatusCode::UNPROCESSABLE_ENTITY, "dependency_failed")
+		}
+		JobQueueError::DataEncoding(_) => (StatusCode::INTERNAL_SERVER_ERROR, "data_encoding"),
+		JobQueueError::Notifier(_) => (StatusCode::INTERNAL_SERVER_ERROR, "notifier_error"),
+		JobQueueError::Draining => (StatusCode::SERVICE_UNAVAILABLE, "queue_draining"),
+		JobQueueError::NotClaimed(_) => (StatusCode::CONFLICT, "job_not_claimed"),
+		JobQueueError::InvalidFilter(_) => (StatusCode::UNPROCESSABLE_ENTITY, "invalid_filter"),
+		JobQueueError::InvalidCommand(_) => (StatusCode::UNPROCESSABLE_ENTITY, "invalid_command"),
+		JobQueueError::AlreadyStarted(_) => (StatusCode::CONFLICT, "job_already_started"),
+		JobQueueError::NotInHistory(_) => (StatusCode::NOT_FOUND, "job_not_in_history"),
+		JobQueueError::QueueFull(_) => (StatusCode::SERVICE_UNAVAILABLE, "queue_full"),
+		JobQueueError::Rejected(_) => (StatusCode::FORBIDDEN, "job_rejected"),
+		JobQueueError::CallbackNotAllowed(_) => {
+			(StatusCode::UNPROCESSABLE_ENTITY, "callback_not_allowed")
+		}
+	}
+}
+
 impl<T: Into<BackendError>> From<T> for ApiError {
 	fn from(value: T) -> Self {
 		Self::BackendError(value.into())
@@ -81,3 +167,100 @@ impl<T> OptionExt<T> for Option<T> {
 		}
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use axum::{http::StatusCode, response::IntoResponse};
+	use fabricia_backend::job_queue::{JobQueueError, JobRef};
+	use fabricia_crayon_api_model::error::ApiErrorBody;
+
+	use super::ApiError;
+
+	#[tokio::test]
+	async fn test_job_queue_error_codes() {
+		let id = JobRef::nil();
+		let cases = [
+			(
+				JobQueueError::JobAborted(id),
+				StatusCode::CONFLICT,
+				"job_aborted",
+			),
+			(
+				JobQueueError::DependencyCycle(id),
+				StatusCode::UNPROCESSABLE_ENTITY,
+				"dependency_cycle",
+			),
+			(
+				JobQueueError::DependencyFailed(id),
+				StatusCode::UNPROCESSABLE_ENTITY,
+				"dependency_failed",
+			),
+			(
+				JobQueueError::DataEncoding("bad".to_string()),
+				StatusCode::INTERNAL_SERVER_ERROR,
+				"data_encoding",
+			),
+			(
+				JobQueueError::Notifier("closed".to_string()),
+				StatusCode::INTERNAL_SERVER_ERROR,
+				"notifier_error",
+			),
+			(
+				JobQueueError::Draining,
+				StatusCode::SERVICE_UNAVAILABLE,
+				"queue_draining",
+			),
+			(
+				JobQueueError::NotClaimed(id),
+				StatusCode::CONFLICT,
+				"job_not_claimed",
+			),
+			(
+				JobQueueError::InvalidFilter("bad".to_string()),
+				StatusCode::UNPROCESSABLE_ENTITY,
+				"invalid_filter",
+			),
+			(
+				JobQueueError::InvalidCommand("bad".to_string()),
+				StatusCode::UNPROCESSABLE_ENTITY,
+				"invalid_command",
+			),
+			(
+				JobQueueError::AlreadyStarted(id),
+				StatusCode::CONFLICT,
+				"job_already_started",
+			),
+			(
+				JobQueueError::NotInHistory(id),
+				StatusCode::NOT_FOUND,
+				"job_not_in_history",
+			),
+			(
+				JobQueueError::QueueFull(30),
+				StatusCode::SERVICE_UNAVAILABLE,
+				"queue_full",
+			),
+			(
+				JobQueueError::Rejected("bad".to_string()),
+				StatusCode::FORBIDDEN,
+				"job_rejected",
+			),
+			(
+				JobQueueError::CallbackNotAllowed("bad".to_string()),
+				StatusCode::UNPROCESSABLE_ENTITY,
+				"callback_not_allowed",
+			),
+		];
+		for (error, status, code) in cases {
+			let message = error.to_string();
+			let response = ApiError::from(error).into_response();
+			assert_eq!(response.status(), status);
+			let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+				.await
+				.unwrap();
+			let body = serde_json::from_slice::<ApiErrorBody>(&body).unwrap();
+			assert_eq!(body.code, code);
+			assert_eq!(body.message, message);
+		}
+	}
+}
@@ -0,0 +1,229 @@
+use axum::{
+	Json,
+	extract::{Path, Query, State},
+	http::{HeaderName, StatusCode, header::LOCATION},
+};
+use fabricia_backend::job_queue::{
+	AttemptOutcome, ClaimExplanation, JobAttempt, JobErrorCode, JobFilter, JobInfo, JobRef,
+	JobStatus, WorkerInfo,
+};
+use fabricia_crayon_api_model::{
+	job::{
+		ApiAttemptOutcome, ApiClaimExplanation, ApiJobAttempt, ApiJobErrorCode, ApiJobInfo,
+		ApiJobListQuery, ApiJobReplayQuery, ApiJobStatus, ApiWorkerInfo,
+	},
+	page::{ApiPage, ApiPageInfo},
+};
+use time::Duration;
+
+use crate::CrayonServices;
+
+use super::{
+	auth::AdminRequired,
+	error::{ApiError, ApiResult, OptionExt},
+};
+
+/// Lists jobs matching the query, in the order of enqueuing.
+///
+/// Cursors are IDs of the last jobs of pages. Totals are omitted,
+/// as counting jobs in a large queue is expensive.
+pub async fn list_jobs(
+	State(services): State<CrayonServices>,
+	Query(query): Query<ApiJobListQuery>,
+) -> ApiResult<Json<ApiPage<ApiJobInfo>>> {
+	let after = query
+		.cursor
+		.as_deref()
+		.map(JobRef::parse_str)
+		.transpose()
+		.ok()
+		.or_api_error(StatusCode::UNPROCESSABLE_ENTITY, "invalid cursor")?;
+	let branch = match &query.branch {
+		Some(name) => Some(
+			services
+				.backend
+				.branch
+				.find_id(name)
+				.await?
+				.or_api_error(StatusCode::UNPROCESSABLE_ENTITY, "branch not found")?,
+		),
+		None => None,
+	};
+	let filter = JobFilter {
+		kind: query.kind,
+		status: query.status.map(|status| match status {
+			ApiJobStatus::Pending => JobStatus::Pending,
+			ApiJobStatus::Running => JobStatus::Running,
+		}),
+		min_priority: query.min_priority,
+		max_priority: query.max_priority,
+		branch,
+		min_age: query.min_age.map(|age| Duration::seconds(age.into())),
+		max_age: query.max_age.map(|age| Duration::seconds(age.into())),
+		limit: query.limit.unwrap_or(JobFilter::default().limit),
+		after,
+	};
+	let jobs = services.backend.job_queue.list_jobs(&filter).await?;
+	let next_cursor = match jobs.last() {
+		Some(last) if jobs.len() >= filter.limit as usize => Some(last.id.to_string()),
+		_ => None,
+	};
+	Ok(Json(ApiPage {
+		items: jobs.into_iter().map(into_api).collect(),
+		page: ApiPageInfo {
+			next_cursor,
+			total: None,
+		},
+	}))
+}
+
+pub async fn get_job(
+	State(services): State<CrayonServices>,
+	Path(id): Path<JobRef>,
+) -> ApiResult<Json<ApiJobInfo>> {
+	let job = services
+		.backend
+		.job_queue
+		.get_job(id)
+		.await?
+		.or_api_error(StatusCode::NOT_FOUND, "job not found")?;
+	Ok(Json(into_api(job)))
+}
+
+fn into_api(job: JobInfo) -> ApiJobInfo {
+	ApiJobInfo {
+		id: job.id,
+		kind: job.kind,
+		data: job.data,
+		priority: job.priority,
+		started_at: job.started_at.map(|time| time.assume_utc()),
+		attempts: job.attempts,
+		last_error: job.last_error,
+		claimed_by: job.claimed_by,
+	}
+}
+
+/// Explains why a job would or would not be claimed now.
+pub async fn explain_job(
+	State(services): State<CrayonServices>,
+	Path(id): Path<JobRef>,
+) -> ApiResult<Json<ApiClaimExplanation>> {
+	let explanation = services
+		.backend
+		.job_queue
+		.explain_claim(id)
+		.await?
+		.or_api_error(StatusCode::NOT_FOUND, "job not found")?;
+	Ok(Json(match explanation {
+		ClaimExplanation::Claimable => ApiClaimExplanation::Claimable,
+		ClaimExplanation::Running { claimed_by } => ApiClaimExplanation::Running { claimed_by },
+		ClaimExplanation::Draining => ApiClaimExplanation::Draining,
+		ClaimExplanation::RetryScheduled { next_attempt_at } => {
+			ApiClaimExplanation::RetryScheduled {
+				next_attempt_at: next_attempt_at.assume_utc(),
+			}
+		}
+		ClaimExplanation::DependenciesPending { depends_on } => {
+			ApiClaimExplanation::DependenciesPending { depends_on }
+		}
+		ClaimExplanation::UnknownKind => ApiClaimExplanation::UnknownKind,
+	}))
+}
+
+/// Returns the attempt history of a job.
+///
+/// This is also available after the job is finished.
+pub async fn get_job_attempts(
+	State(services): State<CrayonServices>,
+	Path(id): Path<JobRef>,
+) -> ApiResult<Json<Vec<ApiJobAttempt>>> {
+	let job_queue = &services.backend.job_queue;
+	let attempts = job_queue.get_attempts(id).await?;
+	if attempts.is_empty() && job_queue.get_job(id).await?.is_none() {
+		return Err(ApiError::CustomRef(StatusCode::NOT_FOUND, "job not found"));
+	}
+	Ok(Json(attempts.into_iter().map(attempt_into_api).collect()))
+}
+
+fn attempt_into_api(attempt: JobAttempt) -> ApiJobAttempt {
+	ApiJobAttempt {
+		attempt: attempt.attempt,
+		started_at: attempt.started_at.assume_utc(),
+		finished_at: attempt.finished_at.map(|time| time.assume_utc()),
+		outcome: match attempt.outcome {
+			AttemptOutcome::Running => ApiAttemptOutcome::Running,
+			AttemptOutcome::Succeeded => ApiAttemptOutcome::Succeeded,
+			AttemptOutcome::Failed => ApiAttemptOutcome::Failed,
+			AttemptOutcome::Skipped => ApiAttemptOutcome::Skipped,
+		},
+		error: attempt.error,
+		error_code: attempt.error_code.map(error_code_into_api),
+	}
+}
+
+pub fn error_code_into_api(code: JobErrorCode) -> ApiJobErrorCode {
+	match code {
+		JobErrorCode::Internal => ApiJobErrorCode::Internal,
+		JobErrorCode::Network => ApiJobErrorCode::Network,
+		JobErrorCode::Validation => ApiJobErrorCode::Validation,
+	}
+}
+
+/// Releases a claimed job of a dead worker, so that it can be fetched again.
+///
+/// Responds with `409 Conflict` if the job is not claimed.
+pub async fn release_job(
+	AdminRequired(actor): AdminRequired,
+	State(services): State<CrayonServices>,
+	Path(id): Path<JobRef>,
+) -> ApiResult<(StatusCode, &'static str)> {
+	services
+		.backend
+		.job_queue
+		.release_job(id, "released by an administrator", actor.as_deref())
+		.await?;
+	Ok((StatusCode::OK, "job released"))
+}
+
+/// Enqueues a new job with the same command as a finished or dead-lettered job.
+///
+/// Responds with the location of the created job.
+pub async fn replay_job(
+	AdminRequired(_): AdminRequired,
+	State(services): State<CrayonServices>,
+	Path(id): Path<JobRef>,
+	Query(query): Query<ApiJobReplayQuery>,
+) -> ApiResult<(StatusCode, [(HeaderName, String); 1], &'static str)> {
+	let job = services
+		.backend
+		.job_queue
+		.replay_job(id, query.priority)
+		.await?;
+	Ok((
+		StatusCode::ACCEPTED,
+		[(LOCATION, job_location(&services, job))],
+		"job replay enqueued",
+	))
+}
+
+/// Lists registered workers, with counts of jobs they are running.
+pub async fn list_workers(
+	State(services): State<CrayonServices>,
+) -> ApiResult<Json<Vec<ApiWorkerInfo>>> {
+	let workers = services.backend.job_queue.list_workers().await?;
+	Ok(Json(workers.into_iter().map(worker_into_api).collect()))
+}
+
+fn worker_into_api(worker: WorkerInfo) -> ApiWorkerInfo {
+	ApiWorkerInfo {
+		id: worker.id,
+		registered_at: worker.registered_at.assume_utc(),
+		heartbeat_at: worker.heartbeat_at.assume_utc(),
+		running: worker.running as u64,
+	}
+}
+
+/// Returns the URL path of a job resource, for the `Location` header.
+pub fn job_location(services: &CrayonServices, id: JobRef) -> String {
+	format!("{}/job/{}", services.config.web.api_base, id)
+}
@@ -1,24 +1,128 @@
-use axum::{Router, routing::get};
+use axum::{
+	Json, Router,
+	extract::State,
+	http::StatusCode,
+	routing::{get, post},
+};
+use fabricia_crayon_api_model::meta::ApiVersionInfo;
+use tower_http::compression::{
+	CompressionLayer, DefaultPredicate, Predicate, predicate::SizeAbove,
+};
 
 use crate::CrayonServices;
 
+use error::{ApiError, ApiResult};
+
+mod admin;
 pub mod auth;
 mod branch;
 pub mod error;
+mod job;
+mod stats;
 
 pub fn api_router() -> Router<CrayonServices> {
 	Router::new()
-		.route("/", get(handler))
-		.route("/branch", get(branch::list_branches))
+		.route("/", get(super::handler))
+		.route("/version", get(version))
+		.route(
+			"/branch",
+			get(branch::list_branches).patch(branch::update_branches),
+		)
+		.route("/branch/delete", post(branch::delete_branches))
+		.route("/branch/defaults", get(branch::branch_defaults))
+		.route("/branch/config-schema", get(branch::config_schema))
 		.route(
 			"/branch/{branch}",
 			get(branch::get_branch)
+				.head(branch::get_branch)
 				.put(branch::new_branch)
 				.patch(branch::update_branch_config)
 				.delete(branch::delete_branch),
 		)
+		.route("/branch/{branch}/sync", post(branch::sync_branch))
+		.route("/branch/{branch}/restore", post(branch::restore_branch))
+		.route("/job", get(job::list_jobs))
+		.route("/job/{id}", get(job::get_job))
+		.route("/job/{id}/attempts", get(job::get_job_attempts))
+		.route("/job/{id}/explain", get(job::explain_job))
+		.route("/job/{id}/release", post(job::release_job))
+		.route("/job/{id}/replay", post(job::replay_job))
+		.route("/workers", get(job::list_workers))
+		.route("/stats/priorities", get(stats::priorities))
+		.route("/stats/throughput", get(stats::throughput))
+		.route("/stats/retries", get(stats::retries))
+		.route("/admin/maintenance/vacuum", post(admin::vacuum))
+		.route("/admin/maintenance/jobs", post(admin::maintain_jobs))
+		// must be set after all routes, the `Allow` header is set by axum
+		.method_not_allowed_fallback(method_not_allowed)
+		.layer(compression_layer())
+}
+
+/// Compresses responses negotiated with `Accept-Encoding`.
+///
+/// Small responses are not worth compressing. Server-sent events are also
+/// excluded by the default predicate, as compression breaks streaming.
+fn compression_layer() -> CompressionLayer<impl Predicate> {
+	CompressionLayer::new().compress_when(DefaultPredicate::new().and(SizeAbove::new(1024)))
+}
+
+async fn method_not_allowed() -> ApiError {
+	ApiError::CustomRef(StatusCode::METHOD_NOT_ALLOWED, "method not allowed")
 }
 
-async fn handler() -> &'static str {
-	concat!("Fabricia Crayon ", env!("CARGO_PKG_VERSION"))
+async fn version(State(services): State<CrayonServices>) -> ApiResult<Json<ApiVersionInfo>> {
+	Ok(Json(ApiVersionInfo {
+		version: env!("CARGO_PKG_VERSION").to_string(),
+		git_commit: option_env!("FABRICIA_GIT_COMMIT").map(str::to_string),
+		build_timestamp: option_env!("FABRICIA_BUILD_TIMESTAMP").and_then(|time| time.parse().ok()),
+		schema_version: services.backend.database.schema_version().await?,
+	}))
+}
+
+#[cfg(test)]
+mod test {
+	use std::convert::Infallible;
+
+	use axum::{
+		Router,
+		response::sse::{Event, Sse},
+		routing::get,
+	};
+	use tokio::net::TcpListener;
+
+	use super::compression_layer;
+
+	#[tokio::test]
+	async fn test_compression_skips_events() {
+		let router = Router::new()
+			.route(
+				"/events",
+				get(async || {
+					Sse::new(futures::stream::once(async {
+						Ok::<_, Infallible>(Event::default().data("x".repeat(4096)))
+					}))
+				}),
+			)
+			.layer(compression_layer());
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+		let response = reqwest::Client::new()
+			.get(format!("http://{addr}/events"))
+			.header(reqwest::header::ACCEPT_ENCODING, "gzip")
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(
+			response.headers()[reqwest::header::CONTENT_TYPE],
+			"text/event-stream"
+		);
+		assert!(
+			!response
+				.headers()
+				.contains_key(reqwest::header::CONTENT_ENCODING)
+		);
+		assert!(response.text().await.unwrap().contains(&"x".repeat(4096)));
+	}
 }
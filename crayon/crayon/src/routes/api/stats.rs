@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+
+use axum::{
+	Json,
+	extract::{Query, State},
+	http::StatusCode,
+};
+use fabricia_crayon_api_model::stats::{
+	ApiKindRetries, ApiRetryStats, ApiThroughputBucket, ApiThroughputQuery,
+};
+use time::Duration;
+
+use crate::CrayonServices;
+
+use super::error::{ApiError, ApiResult};
+
+/// Returns the count of pending jobs of each priority.
+pub async fn priorities(
+	State(services): State<CrayonServices>,
+) -> ApiResult<Json<BTreeMap<i16, usize>>> {
+	Ok(Json(services.backend.job_queue.priority_histogram().await?))
+}
+
+/// Count of kinds in [ApiRetryStats::top_kinds].
+const TOP_RETRY_KINDS: usize = 10;
+
+/// Returns a summary of retries of jobs in the queue.
+pub async fn retries(State(services): State<CrayonServices>) -> ApiResult<Json<ApiRetryStats>> {
+	let stats = services
+		.backend
+		.job_queue
+		.retry_stats(TOP_RETRY_KINDS)
+		.await?;
+	Ok(Json(ApiRetryStats {
+		attempts: stats
+			.attempts
+			.into_iter()
+			.map(|(attempt, count)| (attempt, count as u64))
+			.collect(),
+		top_kinds: stats
+			.top_kinds
+			.into_iter()
+			.map(|(kind, retries)| ApiKindRetries { kind, retries })
+			.collect(),
+	}))
+}
+
+/// Maximum window of throughput, to bound the count of buckets.
+const MAX_THROUGHPUT_WINDOW: Duration = Duration::days(1);
+
+/// Returns the count of jobs finished in each minute of a window.
+pub async fn throughput(
+	State(services): State<CrayonServices>,
+	Query(query): Query<ApiThroughputQuery>,
+) -> ApiResult<Json<Vec<ApiThroughputBucket>>> {
+	let window = match &query.window {
+		Some(window) => parse_window(window).ok_or_else(|| {
+			ApiError::CustomString(StatusCode::BAD_REQUEST, format!("invalid window: {window}"))
+		})?,
+		None => Duration::HOUR,
+	};
+	if window > MAX_THROUGHPUT_WINDOW {
+		return Err(ApiError::CustomRef(
+			StatusCode::BAD_REQUEST,
+			"window must not exceed one day",
+		));
+	}
+
+	let buckets = services.backend.job_queue.throughput(window).await?;
+	Ok(Json(
+		buckets
+			.into_iter()
+			.map(|(start, finished)| ApiThroughputBucket {
+				start: start.assume_utc(),
+				finished: finished as u64,
+			})
+			.collect(),
+	))
+}
+
+/// Parses windows like `90s`, `30m`, `1h` and `1d`.
+fn parse_window(window: &str) -> Option<Duration> {
+	let (count, unit) = window.split_at_checked(window.len().checked_sub(1)?)?;
+	let count = count.parse::<u32>().ok()?;
+	let unit = match unit {
+		"s" => Duration::SECOND,
+		"m" => Duration::MINUTE,
+		"h" => Duration::HOUR,
+		"d" => Duration::DAY,
+		_ => return None,
+	};
+	Some(unit * count).filter(|window| window.is_positive())
+}
@@ -1,14 +1,34 @@
-use anyhow::Result;
-use axum::{Router, routing::get};
+use anyhow::{Result, bail};
+use axum::{
+	Router,
+	extract::{DefaultBodyLimit, Request},
+	middleware::{self, Next},
+	response::Response,
+	routing::get,
+};
+use fabricia_backend::trace::TraceContext;
+use tracing::{Instrument, info_span};
 
 use crate::CrayonServices;
 
 mod api;
 
+/// Default base path of the API router.
+///
+/// This is configured with [WebConfig::api_base](crate::config::WebConfig::api_base).
+pub const API_BASE: &str = "/api/v0";
+
 pub fn make_router(services: CrayonServices) -> Result<Router> {
+	let body_limit = services.config.web.body_limit;
+	let api_base = services.config.web.api_base.clone();
+	if !api_base.starts_with('/') || api_base.ends_with('/') {
+		bail!("invalid API base path {api_base:?}");
+	}
 	let router = Router::new()
 		.route("/", get(handler))
-		.nest("/api/v0", api::api_router())
+		.nest(&api_base, api::api_router())
+		.layer(DefaultBodyLimit::max(body_limit))
+		.layer(middleware::from_fn(trace_context))
 		.with_state(services);
 
 	Ok(router)
@@ -17,3 +37,24 @@ pub fn make_router(services: CrayonServices) -> Result<Router> {
 async fn handler() -> &'static str {
 	concat!("Fabricia Crayon ", env!("CARGO_PKG_VERSION"))
 }
+
+/// Continues the W3C trace of a request, or starts a new one.
+///
+/// Jobs enqueued while handling the request continue the trace.
+async fn trace_context(request: Request, next: Next) -> Response {
+	let context = request
+		.headers()
+		.get(TraceContext::HEADER)
+		.and_then(|value| value.to_str().ok())
+		.and_then(TraceContext::parse)
+		.map(|parent| parent.child())
+		.unwrap_or_else(TraceContext::new_root);
+	let span = info_span!(
+		"request",
+		trace_id = %context.trace_id(),
+		span_id = %context.span_id(),
+		method = %request.method(),
+		uri = %request.uri(),
+	);
+	context.scope(next.run(request)).instrument(span).await
+}
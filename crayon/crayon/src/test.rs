@@ -0,0 +1,856 @@
+use std::sync::{
+	Arc,
+	atomic::{AtomicUsize, Ordering},
+};
+
+use fabricia_backend::{
+	BackendServices, db::service::DatabaseConfig, redis::RedisConfig, target::TargetConfig,
+};
+use fabricia_common_model::branch::{
+	BranchConfigInfo, BranchConfigUpdate, BranchFilter, TrackingMode,
+};
+use fabricia_crayon_api_model::{
+	branch::{ApiBranchInfo, ApiBranchListQuery},
+	error::ApiErrorBody,
+	job::{ApiClaimExplanation, ApiJobListQuery},
+	meta::ApiVersionInfo,
+	page::ApiPage,
+};
+use fabricia_crayon_client::CrayonClient;
+use serde_json::json;
+use tokio::net::TcpListener;
+
+use crate::{
+	CrayonServices,
+	bus::CrayonBusFactory,
+	config::{CrayonConfig, WebConfig},
+	routes::{self, API_BASE},
+};
+
+/// Starts an in-process server, and returns a client of it.
+async fn test_client() -> CrayonClient {
+	CrayonClient::new(&test_server(|_| {}).await).unwrap()
+}
+
+/// Returns the URL of a new SQLite database file.
+///
+/// In-memory databases are not used, since they are cleared whenever the
+/// pool re-establishes its connection, and only the backend's own tests
+/// migrate pooled connections again.
+fn temp_database_url() -> String {
+	static COUNTER: AtomicUsize = AtomicUsize::new(0);
+	let path = std::env::temp_dir().join(format!(
+		"crayon-test-{}-{}.db",
+		std::process::id(),
+		COUNTER.fetch_add(1, Ordering::Relaxed)
+	));
+	_ = std::fs::remove_file(&path);
+	format!("sqlite://{}", path.display())
+}
+
+/// Starts an in-process server with a customized configuration,
+/// and returns the base URL of the API.
+async fn test_server<F: FnOnce(&mut CrayonConfig)>(configure: F) -> String {
+	let mut config = CrayonConfig {
+		web: WebConfig {
+			listen: "tcp://127.0.0.1:0".to_string(),
+			body_limit: 256 * 1024,
+			api_base: API_BASE.to_string(),
+			api_keys: Default::default(),
+			admin_keys: Default::default(),
+			open_admin: false,
+		},
+		database: DatabaseConfig {
+			url: temp_database_url(),
+			max_connections: 1,
+			warmup_connections: 0,
+			close_timeout: 1,
+		},
+		redis: RedisConfig {
+			url: "redis://127.0.0.1".to_string(),
+			max_connections: 1,
+		},
+		target: vec![TargetConfig {
+			name: "arch1".into(),
+			arch: None,
+		}],
+		job_queue: Default::default(),
+		branch: Default::default(),
+	};
+	configure(&mut config);
+	let api_base = config.web.api_base.clone();
+	let backend = BackendServices::new(config.clone().try_into().unwrap(), CrayonBusFactory)
+		.await
+		.unwrap();
+	let services = CrayonServices {
+		config,
+		backend: Arc::new(backend),
+	};
+
+	let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+	let addr = listener.local_addr().unwrap();
+	let router = routes::make_router(services).unwrap();
+	tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+	format!("http://{addr}{api_base}")
+}
+
+#[tokio::test]
+async fn test_api_base() {
+	let url = test_server(|config| config.web.api_base = "/api/v1".to_string()).await;
+	let client = CrayonClient::new(&url).unwrap();
+	client
+		.create_branch("main", &Default::default())
+		.await
+		.unwrap();
+	assert_eq!(client.get_branch("main").await.unwrap().name, "main");
+
+	let http = reqwest::Client::new();
+	let response = http
+		.post(format!("{url}/branch/main/sync"))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(response.status(), reqwest::StatusCode::ACCEPTED);
+	let location = response.headers()[reqwest::header::LOCATION]
+		.to_str()
+		.unwrap()
+		.to_string();
+	assert!(location.starts_with("/api/v1/job/"));
+	let origin = url.strip_suffix("/api/v1").unwrap();
+	let response = http
+		.get(format!("{origin}{location}"))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+	// the default base is not mounted
+	let response = http
+		.get(format!("{origin}{API_BASE}/version"))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_queue_full() {
+	let url = test_server(|config| config.job_queue.max_pending = Some(1)).await;
+	let client = CrayonClient::new(&url).unwrap();
+	// tracking the branch enqueues a synchronization
+	client
+		.create_branch("main", &Default::default())
+		.await
+		.unwrap();
+
+	let response = reqwest::Client::new()
+		.post(format!("{url}/branch/main/sync"))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+	assert_eq!(response.headers()[reqwest::header::RETRY_AFTER], "30");
+	let body = response.json::<ApiErrorBody>().await.unwrap();
+	assert_eq!(body.code, "queue_full");
+}
+
+#[tokio::test]
+async fn test_client_version() {
+	let client = test_client().await;
+	let info = client.version().await.unwrap();
+	assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+	assert_eq!(
+		info.git_commit.as_deref(),
+		option_env!("FABRICIA_GIT_COMMIT")
+	);
+	assert!(info.schema_version.is_some());
+
+	// build information is null if unavailable
+	let info = serde_json::from_value::<ApiVersionInfo>(json!({
+		"version": "0.1.0",
+		"git_commit": null,
+		"build_timestamp": null,
+		"schema_version": null,
+	}))
+	.unwrap();
+	assert_eq!(info.git_commit, None);
+	assert_eq!(info.build_timestamp, None);
+}
+
+/// Returns a client sending `key` in `Authorization` headers.
+fn key_client(url: &str, key: &str) -> CrayonClient {
+	let mut headers = reqwest::header::HeaderMap::new();
+	headers.insert(
+		reqwest::header::AUTHORIZATION,
+		format!("Bearer {key}").parse().unwrap(),
+	);
+	let http = reqwest::Client::builder()
+		.default_headers(headers)
+		.build()
+		.unwrap();
+	CrayonClient::with_client(http, url).unwrap()
+}
+
+#[tokio::test]
+async fn test_api_keys() {
+	let url = test_server(|config| {
+		config
+			.web
+			.api_keys
+			.insert("alice".to_string(), "secret".to_string());
+	})
+	.await;
+
+	let error = CrayonClient::new(&url)
+		.unwrap()
+		.create_branch("main", &Default::default())
+		.await
+		.unwrap_err();
+	assert_eq!(error.status(), Some(reqwest::StatusCode::UNAUTHORIZED));
+	let response = reqwest::Client::new()
+		.get(format!("{url}/branch/main"))
+		.bearer_auth("wrong")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+	let client = key_client(&url, "secret");
+	client
+		.create_branch("main", &Default::default())
+		.await
+		.unwrap();
+	let info = client.get_branch("main").await.unwrap();
+	assert_eq!(info.created_by.as_deref(), Some("alice"));
+	assert_eq!(info.updated_by.as_deref(), Some("alice"));
+}
+
+#[tokio::test]
+async fn test_client_branch() {
+	let client = test_client().await;
+	let info = BranchConfigInfo {
+		priority: Some(120),
+		..Default::default()
+	};
+	let branch = client.create_branch("feat/a", &info).await.unwrap();
+	assert_eq!(branch.name, "feat/a");
+	assert_eq!(branch.priority, 120);
+	assert_eq!(client.get_branch("feat/a").await.unwrap(), branch);
+
+	let update = BranchConfigUpdate::Merge(BranchConfigInfo {
+		enabled: Some(false),
+		..Default::default()
+	});
+	let branch = client
+		.update_branch_config("feat/a", &update)
+		.await
+		.unwrap();
+	assert!(!branch.enabled);
+
+	let query = ApiBranchListQuery {
+		enabled: Some(false),
+		..Default::default()
+	};
+	let branches = client.list_branches(&query).await.unwrap();
+	assert_eq!(branch_names(&branches), ["feat/a"]);
+
+	client.delete_branch("feat/a").await.unwrap();
+	let error = client.get_branch("feat/a").await.unwrap_err();
+	assert_eq!(error.status().map(|status| status.as_u16()), Some(404));
+}
+
+#[tokio::test]
+async fn test_branch_path() {
+	let client = test_client().await;
+	let error = client.get_branch("missing").await.unwrap_err();
+	assert_eq!(error.status().map(|status| status.as_u16()), Some(404));
+	assert_eq!(error.code(), Some("branch_not_found"));
+
+	for name in ["bad..name", "-flag", "a:b", "trailing.lock"] {
+		let error = client.get_branch(name).await.unwrap_err();
+		assert_eq!(error.status().map(|status| status.as_u16()), Some(400));
+		assert_eq!(error.code(), Some("invalid_branch_name"));
+		let error = client
+			.create_branch(name, &Default::default())
+			.await
+			.unwrap_err();
+		assert_eq!(error.status().map(|status| status.as_u16()), Some(400));
+		assert_eq!(error.code(), Some("invalid_branch_name"));
+	}
+}
+
+#[tokio::test]
+async fn test_field_selection() {
+	let url = test_server(|_| {}).await;
+	let client = CrayonClient::new(&url).unwrap();
+	client
+		.create_branch("main", &Default::default())
+		.await
+		.unwrap();
+
+	let response = reqwest::get(format!("{url}/branch/main?fields=name,status"))
+		.await
+		.unwrap();
+	assert_eq!(response.status().as_u16(), 200);
+	let body = response.json::<serde_json::Value>().await.unwrap();
+	let fields = body.as_object().unwrap().keys().collect::<Vec<_>>();
+	assert_eq!(fields, ["name", "status"]);
+	assert_eq!(body["name"], "main");
+
+	let body = reqwest::get(format!("{url}/branch?fields=name"))
+		.await
+		.unwrap()
+		.json::<serde_json::Value>()
+		.await
+		.unwrap();
+	assert_eq!(body["items"], serde_json::json!([{ "name": "main" }]));
+	assert_eq!(body["page"]["total"], 1);
+
+	let response = reqwest::get(format!("{url}/branch/main?fields=name,bogus"))
+		.await
+		.unwrap();
+	assert_eq!(response.status().as_u16(), 400);
+
+	// without field selection, full information is returned
+	assert_eq!(client.get_branch("main").await.unwrap().name, "main");
+}
+
+fn branch_names(page: &ApiPage<ApiBranchInfo>) -> Vec<&str> {
+	page.items
+		.iter()
+		.map(|branch| branch.name.as_str())
+		.collect()
+}
+
+#[tokio::test]
+async fn test_pagination() {
+	let url = test_server(|_| {}).await;
+	let client = CrayonClient::new(&url).unwrap();
+	for name in ["a", "b", "c"] {
+		client
+			.create_branch(name, &Default::default())
+			.await
+			.unwrap();
+	}
+
+	let body = reqwest::get(format!("{url}/branch?limit=2"))
+		.await
+		.unwrap()
+		.json::<serde_json::Value>()
+		.await
+		.unwrap();
+	assert_eq!(body["items"].as_array().unwrap().len(), 2);
+	assert_eq!(body["page"], json!({ "next_cursor": "b", "total": 3 }));
+
+	let mut query = ApiBranchListQuery {
+		limit: Some(2),
+		..Default::default()
+	};
+	let page = client.list_branches(&query).await.unwrap();
+	assert_eq!(branch_names(&page), ["a", "b"]);
+	query.cursor = page.page.next_cursor;
+	let page = client.list_branches(&query).await.unwrap();
+	assert_eq!(branch_names(&page), ["c"]);
+	assert_eq!(page.page.next_cursor, None);
+	assert_eq!(page.page.total, Some(3));
+
+	// tracking each branch enqueued a synchronization
+	let mut query = ApiJobListQuery {
+		limit: Some(2),
+		..Default::default()
+	};
+	let page = client.list_jobs(&query).await.unwrap();
+	assert_eq!(page.items.len(), 2);
+	assert_eq!(page.page.total, None);
+	assert_eq!(page.page.next_cursor, Some(page.items[1].id.to_string()));
+	query.cursor = page.page.next_cursor;
+	let next = client.list_jobs(&query).await.unwrap();
+	assert_eq!(next.items.len(), 1);
+	assert!(!page.items.contains(&next.items[0]));
+	assert_eq!(next.page.next_cursor, None);
+}
+
+#[tokio::test]
+async fn test_client_branch_config_schema() {
+	let client = test_client().await;
+	let schema = client.branch_config_schema().await.unwrap();
+
+	let properties = schema["properties"].as_object().unwrap();
+	let mut names = properties.keys().map(String::as_str).collect::<Vec<_>>();
+	names.sort();
+	assert_eq!(
+		names,
+		["base", "enabled", "labels", "priority", "tracking_mode"]
+	);
+	// all fields are optional, as unset ones are left unchanged
+	let required = schema["required"].as_array().cloned().unwrap_or_default();
+	assert!(required.is_empty());
+	assert_eq!(
+		properties["enabled"]["description"],
+		"Disabled branches are kept tracked, but not built."
+	);
+
+	// variants are either listed in `enum`, or as `const` of each alternative
+	let tracking_mode = &schema["$defs"]["TrackingMode"];
+	let mut variants = match tracking_mode["enum"].as_array() {
+		Some(variants) => variants.clone(),
+		None => tracking_mode["oneOf"]
+			.as_array()
+			.unwrap()
+			.iter()
+			.map(|variant| variant["const"].clone())
+			.collect(),
+	};
+	variants.sort_by_key(|variant| variant.to_string());
+	assert_eq!(variants, [json!("auto"), json!("unmanaged")]);
+}
+
+#[tokio::test]
+async fn test_config_validation() {
+	let url = test_server(|_| {}).await;
+	let http = reqwest::Client::new();
+
+	// warnings are echoed back in headers, without failing the request
+	let response = http
+		.put(format!("{url}/branch/main"))
+		.json(&json!({ "priority": 2000 }))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(response.status().as_u16(), 201);
+	let warnings = response
+		.headers()
+		.get_all("warning")
+		.iter()
+		.map(|value| value.to_str().unwrap().to_string())
+		.collect::<Vec<_>>();
+	assert_eq!(warnings, [r#"299 - "priority 2000 is unusually high""#]);
+
+	let response = http
+		.put(format!("{url}/branch/stable"))
+		.json(&json!({ "base": "bad..name" }))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(response.status().as_u16(), 422);
+	assert!(response.headers().get("warning").is_none());
+}
+
+#[tokio::test]
+async fn test_client_branch_defaults() {
+	let client = test_client().await;
+	let defaults = client.branch_defaults().await.unwrap();
+	assert_eq!(
+		defaults,
+		BranchConfigInfo {
+			base: Some("".into()),
+			priority: Some(100),
+			tracking_mode: Some(TrackingMode::Auto),
+			enabled: Some(true),
+			labels: Some(Default::default()),
+		}
+	);
+}
+
+#[tokio::test]
+async fn test_client_branch_labels() {
+	let client = test_client().await;
+	for (name, label) in [("main", "stable"), ("feat", "unstable")] {
+		let info = BranchConfigInfo {
+			labels: Some([label.to_string()].into()),
+			..Default::default()
+		};
+		client.create_branch(name, &info).await.unwrap();
+	}
+
+	let query = ApiBranchListQuery {
+		label: Some("stable".to_string()),
+		..Default::default()
+	};
+	let branches = client.list_branches(&query).await.unwrap();
+	assert_eq!(branch_names(&branches), ["main"]);
+	assert_eq!(branches.items[0].labels, ["stable".to_string()].into());
+}
+
+#[tokio::test]
+async fn test_client_patch_branch_config() {
+	let client = test_client().await;
+	let info = BranchConfigInfo {
+		priority: Some(120),
+		labels: Some(["stable".to_string()].into()),
+		..Default::default()
+	};
+	client.create_branch("main", &info).await.unwrap();
+
+	let patch = json!([
+		{ "op": "test", "path": "/priority", "value": 120 },
+		{ "op": "replace", "path": "/priority", "value": 150 },
+		{ "op": "remove", "path": "/labels" },
+	]);
+	let branch = client.patch_branch_config("main", &patch).await.unwrap();
+	assert_eq!(branch.priority, 150);
+	assert!(branch.labels.is_empty());
+
+	let patch = json!([
+		{ "op": "test", "path": "/priority", "value": 120 },
+		{ "op": "replace", "path": "/priority", "value": 200 },
+	]);
+	let error = client
+		.patch_branch_config("main", &patch)
+		.await
+		.unwrap_err();
+	assert_eq!(error.status().map(|status| status.as_u16()), Some(409));
+	assert_eq!(client.get_branch("main").await.unwrap().priority, 150);
+}
+
+#[tokio::test]
+async fn test_client_update_branches() {
+	let client = test_client().await;
+	for (name, label) in [("main", "stable"), ("lts", "stable"), ("feat", "unstable")] {
+		let info = BranchConfigInfo {
+			labels: Some([label.to_string()].into()),
+			..Default::default()
+		};
+		client.create_branch(name, &info).await.unwrap();
+	}
+
+	let query = ApiBranchListQuery {
+		label: Some("stable".to_string()),
+		..Default::default()
+	};
+	let result = client
+		.update_branches(&query, &serde_json::json!({ "priority": 150 }))
+		.await
+		.unwrap();
+	assert_eq!(result.updated, 2);
+	assert!(result.failures.is_empty());
+	for (name, priority) in [("main", 150), ("lts", 150), ("feat", 100)] {
+		assert_eq!(client.get_branch(name).await.unwrap().priority, priority);
+	}
+
+	// a filter is required
+	let error = client
+		.update_branches(&Default::default(), &serde_json::json!({ "priority": 150 }))
+		.await
+		.unwrap_err();
+	assert_eq!(error.status().map(|status| status.as_u16()), Some(422));
+}
+
+#[tokio::test]
+async fn test_client_delete_branches() {
+	let client = test_client().await;
+	for name in ["feat-a", "feat-b", "main"] {
+		client
+			.create_branch(name, &Default::default())
+			.await
+			.unwrap();
+	}
+
+	let filter = BranchFilter::NamePrefix("feat-".to_string());
+	assert_eq!(client.delete_branches(&filter).await.unwrap().deleted, 2);
+	let branches = client.list_branches(&Default::default()).await.unwrap();
+	assert_eq!(branch_names(&branches), ["main"]);
+}
+
+#[tokio::test]
+async fn test_client_job() {
+	let client = test_client().await;
+	client
+		.create_branch("main", &Default::default())
+		.await
+		.unwrap();
+
+	let id = client.sync_branch("main").await.unwrap();
+	let job = client.get_job(id).await.unwrap();
+	assert_eq!(job.id, id);
+	assert_eq!(job.kind, "SyncBranch");
+	assert_eq!(job.claimed_by, None);
+	assert_eq!(
+		client.explain_job(id).await.unwrap(),
+		ApiClaimExplanation::Claimable
+	);
+	assert!(client.get_job_attempts(id).await.unwrap().is_empty());
+	assert!(client.list_workers().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_client_list_jobs() {
+	let client = test_client().await;
+	for name in ["main", "stable"] {
+		client
+			.create_branch(name, &Default::default())
+			.await
+			.unwrap();
+	}
+
+	let query = ApiJobListQuery {
+		branch: Some("stable".to_string()),
+		..Default::default()
+	};
+	let jobs = client.list_jobs(&query).await.unwrap().items;
+	assert_eq!(jobs.len(), 1);
+	assert_eq!(jobs[0].kind, "SyncBranch");
+
+	let query = ApiJobListQuery {
+		min_priority: Some(200),
+		max_priority: Some(100),
+		..Default::default()
+	};
+	let error = client.list_jobs(&query).await.unwrap_err();
+	assert_eq!(error.status().map(|status| status.as_u16()), Some(422));
+}
+
+#[tokio::test]
+async fn test_client_maintain_jobs() {
+	let url = test_server(|config| config.web.open_admin = true).await;
+	let client = CrayonClient::new(&url).unwrap();
+	for name in ["main", "stable"] {
+		client
+			.create_branch(name, &Default::default())
+			.await
+			.unwrap();
+	}
+
+	let report = client.maintain_jobs(true).await.unwrap();
+	assert!(!report.vacuumed);
+	assert!(report.tables.iter().any(|table| table.table == "job_queue"));
+	assert_eq!(
+		client
+			.list_jobs(&Default::default())
+			.await
+			.unwrap()
+			.items
+			.len(),
+		2
+	);
+}
+
+#[tokio::test]
+async fn test_method_not_allowed() {
+	let url = test_server(|_| {}).await;
+	let response = reqwest::Client::new()
+		.post(format!("{url}/branch/main"))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(response.status(), reqwest::StatusCode::METHOD_NOT_ALLOWED);
+	let mut allow = response.headers()[reqwest::header::ALLOW]
+		.to_str()
+		.unwrap()
+		.split(',')
+		.map(str::trim)
+		.collect::<Vec<_>>();
+	allow.sort_unstable();
+	assert_eq!(allow, ["DELETE", "GET", "HEAD", "PATCH", "PUT"]);
+}
+
+#[tokio::test]
+async fn test_head_branch() {
+	let url = test_server(|_| {}).await;
+	let client = CrayonClient::new(&url).unwrap();
+	client
+		.create_branch("main", &Default::default())
+		.await
+		.unwrap();
+
+	let http = reqwest::Client::new();
+	let get = http.get(format!("{url}/branch/main")).send().await.unwrap();
+	let head = http
+		.head(format!("{url}/branch/main"))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(get.status(), reqwest::StatusCode::OK);
+	assert_eq!(head.status(), get.status());
+	for header in [reqwest::header::ETAG, reqwest::header::LAST_MODIFIED] {
+		assert!(get.headers().contains_key(&header));
+		assert_eq!(head.headers()[&header], get.headers()[&header]);
+	}
+	assert!(head.bytes().await.unwrap().is_empty());
+
+	let get = http
+		.get(format!("{url}/branch/missing"))
+		.send()
+		.await
+		.unwrap();
+	let head = http
+		.head(format!("{url}/branch/missing"))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(get.status(), reqwest::StatusCode::NOT_FOUND);
+	assert_eq!(head.status(), get.status());
+	assert!(head.bytes().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_body_limit() {
+	let url = test_server(|config| config.web.body_limit = 1024).await;
+	let client = CrayonClient::new(&url).unwrap();
+	client
+		.create_branch("main", &Default::default())
+		.await
+		.unwrap();
+
+	let http = reqwest::Client::new();
+	let body = serde_json::to_vec(&json!({ "base": "x".repeat(2048) })).unwrap();
+	for request in [
+		http.put(format!("{url}/branch/stable")),
+		http.patch(format!("{url}/branch/main")),
+		http.patch(format!("{url}/branch?enabled=true")),
+	] {
+		let response = request
+			.header(reqwest::header::CONTENT_TYPE, "application/json")
+			.body(body.clone())
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(response.status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+	}
+	// the rejected branch is not created
+	let error = client.get_branch("stable").await.unwrap_err();
+	assert_eq!(error.status(), Some(reqwest::StatusCode::NOT_FOUND));
+}
+
+#[tokio::test]
+async fn test_compression() {
+	let url = test_server(|_| {}).await;
+	let client = CrayonClient::new(&url).unwrap();
+	for i in 0..10 {
+		client
+			.create_branch(&format!("feature-{i}"), &Default::default())
+			.await
+			.unwrap();
+	}
+
+	let http = reqwest::Client::new();
+	let response = http
+		.get(format!("{url}/branch"))
+		.header(reqwest::header::ACCEPT_ENCODING, "gzip")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(response.status(), reqwest::StatusCode::OK);
+	assert_eq!(
+		response.headers()[reqwest::header::CONTENT_ENCODING],
+		"gzip"
+	);
+	let compressed = response.bytes().await.unwrap().len();
+	let plain = http
+		.get(format!("{url}/branch"))
+		.send()
+		.await
+		.unwrap()
+		.bytes()
+		.await
+		.unwrap()
+		.len();
+	assert!(plain > 1024 && compressed < plain);
+
+	// small responses are not compressed
+	let response = http
+		.get(format!("{url}/version"))
+		.header(reqwest::header::ACCEPT_ENCODING, "gzip")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(response.status(), reqwest::StatusCode::OK);
+	assert!(
+		!response
+			.headers()
+			.contains_key(reqwest::header::CONTENT_ENCODING)
+	);
+}
+
+#[tokio::test]
+async fn test_admin_closed_by_default() {
+	let url = test_server(|_| {}).await;
+	let client = CrayonClient::new(&url).unwrap();
+	let error = client.maintain_jobs(false).await.unwrap_err();
+	assert_eq!(error.status(), Some(reqwest::StatusCode::FORBIDDEN));
+	let response = reqwest::Client::new()
+		.post(format!("{url}/admin/maintenance/vacuum"))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+	// other operations are not guarded
+	client
+		.create_branch("main", &Default::default())
+		.await
+		.unwrap();
+}
+
+#[tokio::test]
+async fn test_admin_keys() {
+	let url = test_server(|config| {
+		let web = &mut config.web;
+		web.api_keys
+			.insert("admin".to_string(), "admin-secret".to_string());
+		web.api_keys
+			.insert("user".to_string(), "user-secret".to_string());
+		web.admin_keys.insert("admin".to_string());
+	})
+	.await;
+
+	let user = key_client(&url, "user-secret");
+	let error = user.maintain_jobs(false).await.unwrap_err();
+	assert_eq!(error.status(), Some(reqwest::StatusCode::FORBIDDEN));
+	let response = reqwest::Client::new()
+		.post(format!("{url}/admin/maintenance/vacuum"))
+		.bearer_auth("user-secret")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+	// other operations are still allowed
+	user.create_branch("main", &Default::default())
+		.await
+		.unwrap();
+
+	let admin = key_client(&url, "admin-secret");
+	admin.maintain_jobs(false).await.unwrap();
+	let response = reqwest::Client::new()
+		.post(format!("{url}/admin/maintenance/vacuum"))
+		.bearer_auth("admin-secret")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(response.status(), reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_vacuum_unsupported() {
+	let url = test_server(|config| config.web.open_admin = true).await;
+	let response = reqwest::Client::new()
+		.post(format!("{url}/admin/maintenance/vacuum"))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(response.status(), reqwest::StatusCode::OK);
+	assert_eq!(
+		response.text().await.unwrap(),
+		"vacuum is only supported on PostgreSQL, job tables were only analyzed"
+	);
+}
+
+/// Set `FABRICIA_TEST_POSTGRES_URL` to run this test on a PostgreSQL server.
+#[tokio::test]
+async fn test_vacuum_postgres() {
+	let Ok(url) = std::env::var("FABRICIA_TEST_POSTGRES_URL") else {
+		return;
+	};
+	let url = test_server(move |config| {
+		config.database.url = url;
+		config.web.open_admin = true;
+	})
+	.await;
+	let response = reqwest::Client::new()
+		.post(format!("{url}/admin/maintenance/vacuum"))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(response.status(), reqwest::StatusCode::OK);
+	assert_eq!(response.text().await.unwrap(), "job tables vacuumed");
+}